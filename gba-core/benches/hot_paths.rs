@@ -0,0 +1,235 @@
+//! Microbenchmarks of the paths every frame spends its time in, so a change
+//! can be measured on its own rather than only by `--bench`:
+//!
+//! - the CPU decoding and running mixes of Thumb instructions from ROM
+//! - loads and stores through the MMU in each memory region
+//! - the PPU drawing a frame's scanlines in text, affine and bitmap modes
+//!
+//! ```text
+//! cargo bench -p gba-core
+//! ```
+
+#[macro_use]
+extern crate criterion;
+extern crate gba_core;
+
+use criterion::{black_box, Criterion, Throughput};
+
+use gba_core::mmu::MemoryUnit;
+use gba_core::rom::GameRom;
+use gba_core::{Gba, Options};
+
+/// ALU operations in a loop
+const THUMB_ALU: [u16; 9] = [
+    0x2001, // loop: movs r0, #1
+    0x1809, // adds r1, r1, r0
+    0x1eca, // subs r2, r1, #3
+    0x008b, // lsls r3, r1, #2
+    0x4013, // ands r3, r2
+    0x404b, // eors r3, r1
+    0x434b, // muls r3, r1
+    0x4293, // cmp r3, r2
+    0xe7f6, // b loop
+];
+
+/// Loads and stores to IWRAM through r7, and from ROM
+const THUMB_MEMORY: [u16; 8] = [
+    0x6038, // loop: str r0, [r7]
+    0x6839, // ldr r1, [r7]
+    0x80b9, // strh r1, [r7, #4]
+    0x797a, // ldrb r2, [r7, #5]
+    0xb407, // push {r0-r2}
+    0xbc07, // pop {r0-r2}
+    0x4b00, // ldr r3, [pc]
+    0xe7f7, // b loop
+];
+
+/// Calls, returns and conditional branches
+const THUMB_BRANCH: [u16; 8] = [
+    0xf000, 0xf804, // loop: bl func
+    0x4280, // cmp r0, r0
+    0xd000, // beq skip
+    0x46c0, // nop
+    0xe7f9, // skip: b loop
+    0x3001, // func: adds r0, #1
+    0x4770, // bx lr
+];
+
+/// Instructions run in each iteration of the Thumb benchmarks
+const INSTRUCTIONS: u64 = 1000;
+
+/// PPU events in a frame, three per scanline
+const FRAME_EVENTS: usize = 228 * 3;
+
+/// Boots a ROM of `program` padded to 64K, starting it in Thumb state
+fn boot(program: &[u16]) -> Box<Gba<'static>> {
+    let mut data = vec![0u8; 0x10000];
+    for (bytes, &op) in data.chunks_mut(2).zip(program.iter()) {
+        bytes[0] = op as u8;
+        bytes[1] = (op >> 8) as u8;
+    }
+    let opts = Options {
+        direct_boot: true,
+        entry: Some(0x0800_0001),
+        ..Default::default()
+    };
+    Gba::new(
+        GameRom::from_bytes(&data).unwrap(),
+        Default::default(),
+        &opts,
+    )
+}
+
+fn thumb(c: &mut Criterion) {
+    let mixes: [(&str, &[u16]); 3] = [
+        ("alu", &THUMB_ALU),
+        ("memory", &THUMB_MEMORY),
+        ("branch", &THUMB_BRANCH),
+    ];
+    let mut group = c.benchmark_group("thumb");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    for &(name, program) in mixes.iter() {
+        let mut gba = boot(program);
+        gba.cpu.set_reg(7, 0x0300_0000);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for _ in 0..INSTRUCTIONS {
+                    gba.cpu.cycle();
+                }
+                // Or the cycles would pile up uncounted
+                gba.mmu.take_cycles()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Words accessed in each iteration of the MMU benchmarks
+const WORDS: u32 = 256;
+
+fn mmu(c: &mut Criterion) {
+    // Where each region starts, how many words of it to go over, and
+    // whether it can be written
+    let regions = [
+        ("ewram", 0x0200_0000, WORDS, true),
+        ("iwram", 0x0300_0000, WORDS, true),
+        // Just the BG control, scroll and rotation registers, which have no
+        // side effects
+        ("io", 0x0400_0008, 8, true),
+        ("palette", 0x0500_0000, WORDS, true),
+        ("vram", 0x0600_0000, WORDS, true),
+        ("oam", 0x0700_0000, WORDS, true),
+        ("rom", 0x0800_0000, WORDS, false),
+    ];
+    let mut gba = boot(&[0xe7fe]); // b .
+    let mut group = c.benchmark_group("mmu");
+    group.throughput(Throughput::Elements(WORDS as u64));
+    for &(name, base, words, writable) in regions.iter() {
+        group.bench_function(format!("load32 {}", name), |b| {
+            b.iter(|| {
+                for i in 0..WORDS {
+                    let addr = base + (i % words) * 4;
+                    gba.mmu.wait(addr, 4);
+                    black_box(gba.mmu.load32(addr));
+                }
+                gba.mmu.take_cycles()
+            })
+        });
+        if writable {
+            group.bench_function(format!("store32 {}", name), |b| {
+                b.iter(|| {
+                    for i in 0..WORDS {
+                        let addr = base + (i % words) * 4;
+                        gba.mmu.wait(addr, 4);
+                        gba.mmu.set32(addr, black_box(i));
+                    }
+                    gba.mmu.take_cycles()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Fills `len` bytes from `addr` with a pattern that varies from word to
+/// word, so nothing the PPU draws is uniform
+fn fill(gba: &mut Gba, addr: u32, len: u32, seed: u32) {
+    let mut x = seed | 1;
+    for off in (0..len).step_by(4) {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        gba.mmu.set32(addr + off, x);
+    }
+}
+
+/// Sets up `gba` to draw four text backgrounds and 128 sprites
+fn text_scene(gba: &mut Gba) {
+    // DISPCNT: mode 0, BG0-3 and OBJ on, 1D sprite tiles
+    gba.mmu.set16(0x0400_0000, 0x1f40);
+    for bg in 0..4 {
+        // Tiles at 0x06000000, maps from 0x0600e000, in priority order
+        gba.mmu
+            .set16(0x0400_0008 + bg * 2, (28 + bg as u16) << 8 | bg as u16);
+    }
+    fill(gba, 0x0500_0000, 0x400, 1);
+    fill(gba, 0x0600_0000, 0x8000, 2);
+    for off in (0..0x2000).step_by(2) {
+        gba.mmu.set16(0x0600_e000 + off, (off as u16 / 2) & 0x3ff);
+    }
+    fill(gba, 0x0601_0000, 0x4000, 3);
+    for i in 0..128 {
+        let oam = 0x0700_0000 + i * 8;
+        gba.mmu.set16(oam, (i * 13 % 160) as u16);
+        gba.mmu.set16(oam + 2, (i * 29 % 240) as u16);
+        gba.mmu.set16(oam + 4, i as u16);
+    }
+}
+
+/// Sets up `gba` to draw a rotated affine background
+fn affine_scene(gba: &mut Gba) {
+    // DISPCNT: mode 1, BG2 on
+    gba.mmu.set16(0x0400_0000, 0x0401);
+    // BG2CNT: tiles at 0x06000000, a 256x256 map at 0x0600e000, wrapping
+    gba.mmu.set16(0x0400_000c, 0x7c00);
+    fill(gba, 0x0500_0000, 0x200, 4);
+    fill(gba, 0x0600_0000, 0x1_0000, 5);
+    // BG2PA-PD: a little under 15 degrees
+    gba.mmu.set16(0x0400_0020, 0x00f7);
+    gba.mmu.set16(0x0400_0022, 0x0042);
+    gba.mmu.set16(0x0400_0024, 0xffbe);
+    gba.mmu.set16(0x0400_0026, 0x00f7);
+}
+
+/// Sets up `gba` to draw a mode 3 bitmap
+fn bitmap_scene(gba: &mut Gba) {
+    // DISPCNT: mode 3, BG2 on
+    gba.mmu.set16(0x0400_0000, 0x0403);
+    fill(gba, 0x0600_0000, 240 * 160 * 2, 6);
+}
+
+fn ppu(c: &mut Criterion) {
+    let scenes: [(&str, fn(&mut Gba)); 3] = [
+        ("text", text_scene),
+        ("affine", affine_scene),
+        ("bitmap", bitmap_scene),
+    ];
+    let mut group = c.benchmark_group("ppu");
+    // Per scanline drawn
+    group.throughput(Throughput::Elements(160));
+    for &(name, scene) in scenes.iter() {
+        let mut gba = boot(&[0xe7fe]); // b .
+        scene(&mut gba);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for _ in 0..FRAME_EVENTS {
+                    gba.ppu.event();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, thumb, mmu, ppu);
+criterion_main!(benches);
@@ -0,0 +1,473 @@
+//! Cheat codes for the GameShark, Action Replay and CodeBreaker devices.
+//!
+//! Cheats are read from a file with a `[name]` line starting each cheat,
+//! followed by the kind of device its codes are for, and `off` to have it
+//! start disabled.  The codes follow one per line, as they're printed:
+//!
+//! ```text
+//! # Pokemon Emerald
+//! [Infinite money] gs3
+//! 7BA0C6F2 E1C3A2A0
+//! [Walk through walls] cb off
+//! 82005274 0000
+//! ```
+//!
+//! The kinds are `gs` for GameShark and Action Replay v1 and v2 codes, `gs3`
+//! for GameShark and Action Replay v3, both encrypted as they're published,
+//! and `cb` for CodeBreaker.  Codes write, OR, AND and add to memory, or
+//! compare memory and skip the codes after them when the comparison fails.
+//!
+//! The devices run the codes from a hook in the game, named by a master
+//! code.  Here every enabled cheat runs once at the start of each frame, and
+//! master codes are accepted and ignored.  Codes that patch the ROM become
+//! `RomPatch`es, which the frontend applies to the cartridge while their
+//! cheat is on.  Codes that depend on buttons or change the encryption seeds
+//! aren't supported, nor are encrypted CodeBreaker codes.
+
+use mmu::MemoryUnit;
+use rom::RomPatch;
+use rules::Width;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    /// Any of the bits set
+    And,
+}
+
+impl Cond {
+    fn eval(self, lhs: u32, rhs: u32) -> bool {
+        match self {
+            Cond::Eq => lhs == rhs,
+            Cond::Ne => lhs != rhs,
+            Cond::Lt => lhs < rhs,
+            Cond::Gt => lhs > rhs,
+            Cond::And => lhs & rhs != 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Op {
+    Write(Width, u32, u32),
+    Or(Width, u32, u32),
+    And(Width, u32, u32),
+    Add(Width, u32, u32),
+    /// Skips the number of ops after it if the comparison fails
+    If(Width, u32, Cond, u32, usize),
+    /// Replaces what the CPU reads from the ROM, applied along with the
+    /// cheat rather than run each frame
+    Rom(RomPatch),
+    /// A master code, padding, or the first line of a two line code
+    Nop,
+}
+
+fn write<M: MemoryUnit>(mmu: &mut M, width: Width, addr: u32, val: u32) {
+    match width {
+        Width::Byte => mmu.set8(addr, val as u8),
+        Width::Half => mmu.set16(addr & !1, val as u16),
+        Width::Word => mmu.set32(addr & !3, val),
+    }
+}
+
+/// The TEA decryption the GameShark uses, with `seeds` as the key
+fn decrypt(mut addr: u32, mut val: u32, seeds: &[u32; 4]) -> (u32, u32) {
+    let mut sum: u32 = 0xc6ef_3720;
+    for _ in 0..32 {
+        val = val.wrapping_sub(
+            (addr << 4).wrapping_add(seeds[2])
+                ^ addr.wrapping_add(sum)
+                ^ (addr >> 5).wrapping_add(seeds[3]),
+        );
+        addr = addr.wrapping_sub(
+            (val << 4).wrapping_add(seeds[0])
+                ^ val.wrapping_add(sum)
+                ^ (val >> 5).wrapping_add(seeds[1]),
+        );
+        sum = sum.wrapping_sub(0x9e37_79b9);
+    }
+    (addr, val)
+}
+
+const GS_SEEDS: [u32; 4] = [0x09f4_fbbd, 0x9681_884a, 0x3520_27e9, 0xf3de_e5a7];
+const GS3_SEEDS: [u32; 4] = [0x7aa9_648f, 0x7fae_6994, 0xc0ef_aad5, 0x4271_2c57];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Kind {
+    GameShark,
+    GameShark3,
+    CodeBreaker,
+}
+
+impl Kind {
+    fn parse(s: &str) -> Option<Kind> {
+        match s {
+            "gs" => Some(Kind::GameShark),
+            "gs3" => Some(Kind::GameShark3),
+            "cb" => Some(Kind::CodeBreaker),
+            _ => None,
+        }
+    }
+}
+
+fn unsupported(addr: u32, val: u32) -> String {
+    format!("unsupported code {:08X} {:08X}", addr, val)
+}
+
+/// The GameShark v3 codes starting a ROM patch, whose value is on the next
+/// line.  The device has four patch slots, which are all the same here.
+const GS3_ROM_PATCHES: [u32; 4] = [0x18, 0x1a, 0x1c, 0x1e];
+
+/// Decodes a GameShark v1 or v2 code
+fn gs_op(addr: u32, val: u32) -> Result<Op, String> {
+    if addr == 0xdead_face {
+        return Err("codes changing the encryption seeds aren't supported".to_string());
+    }
+    let target = addr & 0x0fff_ffff;
+    Ok(match addr >> 28 {
+        0x0 => Op::Write(Width::Byte, target, val & 0xff),
+        0x1 => Op::Write(Width::Half, target, val & 0xffff),
+        0x2 => Op::Write(Width::Word, target, val),
+        // The ROM offset in halfwords, and which of v2's patch slots to use
+        0x6 if val >> 24 & 0xcf == 0 => {
+            Op::Rom(RomPatch::Half(target << 1 & 0x1ff_fffe, val as u16))
+        }
+        0xd => Op::If(Width::Half, target, Cond::Eq, val & 0xffff, 1),
+        0xe => Op::If(
+            Width::Half,
+            val & 0x0fff_ffff,
+            Cond::Eq,
+            addr & 0xffff,
+            (addr >> 16 & 0xff) as usize,
+        ),
+        0xf => Op::Nop,
+        _ => return Err(unsupported(addr, val)),
+    })
+}
+
+/// Decodes a GameShark v3 code
+fn gs3_op(addr: u32, val: u32) -> Result<Op, String> {
+    if addr == 0 && val == 0 {
+        return Ok(Op::Nop);
+    }
+    let target = (addr & 0x000f_ffff) | (addr << 4 & 0x0f00_0000);
+    let width = match addr >> 25 & 3 {
+        0 => Width::Byte,
+        1 => Width::Half,
+        2 => Width::Word,
+        _ => return Err(unsupported(addr, val)),
+    };
+    let val = match width {
+        Width::Byte => val & 0xff,
+        Width::Half => val & 0xffff,
+        Width::Word => val,
+    };
+    let cond = match addr & 0x3800_0000 {
+        0 => {
+            return match addr & 0xc000_0000 {
+                0 => Ok(Op::Write(width, target, val)),
+                0x8000_0000 => Ok(Op::Add(width, target, val)),
+                // The hook
+                0xc000_0000 if addr >> 24 == 0xc4 => Ok(Op::Nop),
+                _ => Err(unsupported(addr, val)),
+            };
+        }
+        0x0800_0000 => Cond::Eq,
+        0x1000_0000 => Cond::Ne,
+        0x2800_0000 => Cond::Lt,
+        0x3000_0000 => Cond::Gt,
+        0x3800_0000 => Cond::And,
+        // The signed comparisons
+        _ => return Err(unsupported(addr, val)),
+    };
+    let skip = match addr & 0xc000_0000 {
+        0 => 1,
+        0x4000_0000 => 2,
+        _ => return Err(unsupported(addr, val)),
+    };
+    Ok(Op::If(width, target, cond, val, skip))
+}
+
+/// Decodes a CodeBreaker code
+fn cb_op(addr: u32, val: u32) -> Result<Op, String> {
+    let target = addr & 0x0fff_ffff;
+    let half = val & 0xffff;
+    Ok(match addr >> 28 {
+        0x0 | 0x1 => Op::Nop,
+        0x2 => Op::Or(Width::Half, target, half),
+        0x3 => Op::Write(Width::Byte, target, val & 0xff),
+        0x6 => Op::And(Width::Half, target, half),
+        0x7 => Op::If(Width::Half, target, Cond::Eq, half, 1),
+        0x8 => Op::Write(Width::Half, target, half),
+        0x9 => return Err("encrypted CodeBreaker codes aren't supported".to_string()),
+        0xa => Op::If(Width::Half, target, Cond::Ne, half, 1),
+        0xb => Op::If(Width::Half, target, Cond::Gt, half, 1),
+        0xc => Op::If(Width::Half, target, Cond::Lt, half, 1),
+        0xe => Op::Add(Width::Half, target, half),
+        0xf => Op::If(Width::Half, target, Cond::And, half, 1),
+        _ => return Err(unsupported(addr, val)),
+    })
+}
+
+/// Decodes a code, keeping the ROM offset of a GameShark v3 ROM patch in
+/// `rom_patch` until its value on the next line
+fn parse_code(kind: Kind, line: &str, rom_patch: &mut Option<u32>) -> Result<Op, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let hex =
+        |word: &str| u32::from_str_radix(word, 16).map_err(|_| format!("invalid code '{}'", line));
+    let (addr, val) = match words.len() {
+        2 if words[0].len() == 8 => (hex(words[0])?, hex(words[1])?),
+        _ => return Err(format!("invalid code '{}'", line)),
+    };
+    match kind {
+        Kind::GameShark => {
+            let (addr, val) = decrypt(addr, val, &GS_SEEDS);
+            gs_op(addr, val)
+        }
+        Kind::GameShark3 => {
+            let (addr, val) = decrypt(addr, val, &GS3_SEEDS);
+            if let Some(offset) = rom_patch.take() {
+                return Ok(Op::Rom(RomPatch::Half(offset, addr as u16)));
+            }
+            if addr == 0 && GS3_ROM_PATCHES.contains(&(val >> 24)) {
+                *rom_patch = Some((val & 0xff_ffff) << 1);
+                return Ok(Op::Nop);
+            }
+            gs3_op(addr, val)
+        }
+        Kind::CodeBreaker => cb_op(addr, val),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub enabled: bool,
+    pub ops: Vec<Op>,
+}
+
+impl Cheat {
+    fn apply<M: MemoryUnit>(&self, mmu: &mut M) {
+        let mut ops = self.ops.iter();
+        while let Some(&op) = ops.next() {
+            match op {
+                Op::Write(width, addr, val) => write(mmu, width, addr, val),
+                Op::Or(width, addr, val) => {
+                    let cur = width.read(mmu, addr);
+                    write(mmu, width, addr, cur | val);
+                }
+                Op::And(width, addr, val) => {
+                    let cur = width.read(mmu, addr);
+                    write(mmu, width, addr, cur & val);
+                }
+                Op::Add(width, addr, val) => {
+                    let cur = width.read(mmu, addr);
+                    write(mmu, width, addr, cur.wrapping_add(val));
+                }
+                Op::If(width, addr, cond, val, skip) => {
+                    if !cond.eval(width.read(mmu, addr), val) {
+                        for _ in 0..skip {
+                            ops.next();
+                        }
+                    }
+                }
+                Op::Rom(_) | Op::Nop => (),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Cheats {
+    pub cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    /// Parses a cheat file, ignoring blank lines and `#` comments
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let mut cheats = Vec::new();
+        let mut kind = None;
+        let mut rom_patch = None;
+        let unfinished = "ROM patch code without its value".to_string();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            let err = |err: String| format!("line {}: {}", i + 1, err);
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                if rom_patch.is_some() {
+                    return Err(err(unfinished));
+                }
+                let end = line
+                    .find(']')
+                    .ok_or_else(|| err(format!("unclosed name '{}'", line)))?;
+                let words: Vec<&str> = line[end + 1..].split_whitespace().collect();
+                kind = match words.get(0).and_then(|&word| Kind::parse(word)) {
+                    Some(kind) => Some(kind),
+                    None => return Err(err(format!("no code type for '{}'", line))),
+                };
+                let enabled = match words.get(1) {
+                    None => true,
+                    Some(&"off") => false,
+                    Some(word) => return Err(err(format!("unknown option '{}'", word))),
+                };
+                cheats.push(Cheat {
+                    name: line[1..end].trim().to_string(),
+                    enabled: enabled,
+                    ops: Vec::new(),
+                });
+                continue;
+            }
+            let (kind, cheat) = match (kind, cheats.last_mut()) {
+                (Some(kind), Some(cheat)) => (kind, cheat),
+                _ => return Err(err("code before any cheat's name".to_string())),
+            };
+            cheat
+                .ops
+                .push(parse_code(kind, line, &mut rom_patch).map_err(err)?);
+        }
+        if rom_patch.is_some() {
+            return Err(unfinished);
+        }
+        Ok(Cheats { cheats: cheats })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// The ROM patches of the enabled cheats
+    pub fn rom_patches(&self) -> Vec<RomPatch> {
+        let mut patches = Vec::new();
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            for op in cheat.ops.iter() {
+                if let Op::Rom(patch) = *op {
+                    patches.push(patch);
+                }
+            }
+        }
+        patches
+    }
+
+    /// Runs the enabled cheats' codes against memory
+    pub fn apply<M: MemoryUnit>(&self, mmu: &mut M) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            cheat.apply(mmu);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mmu::ram::{Ram, RamUnit};
+
+    /// The GameShark's encryption, to make codes to test with
+    fn encrypt(mut addr: u32, mut val: u32, seeds: &[u32; 4]) -> String {
+        let mut sum: u32 = 0;
+        for _ in 0..32 {
+            sum = sum.wrapping_add(0x9e37_79b9);
+            addr = addr.wrapping_add(
+                (val << 4).wrapping_add(seeds[0])
+                    ^ val.wrapping_add(sum)
+                    ^ (val >> 5).wrapping_add(seeds[1]),
+            );
+            val = val.wrapping_add(
+                (addr << 4).wrapping_add(seeds[2])
+                    ^ addr.wrapping_add(sum)
+                    ^ (addr >> 5).wrapping_add(seeds[3]),
+            );
+        }
+        format!("{:08X} {:08X}", addr, val)
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let code = encrypt(0x1200_0010, 0x1234, &GS_SEEDS);
+        assert_eq!(
+            Op::Write(Width::Half, 0x0200_0010, 0x1234),
+            parse_code(Kind::GameShark, &code, &mut None).unwrap()
+        );
+        let code = encrypt(0x0820_0010, 0x63, &GS3_SEEDS);
+        assert_eq!(
+            Op::If(Width::Byte, 0x0200_0010, Cond::Eq, 0x63, 1),
+            parse_code(Kind::GameShark3, &code, &mut None).unwrap()
+        );
+        assert!(parse_code(
+            Kind::GameShark,
+            &encrypt(0xdead_face, 0, &GS_SEEDS),
+            &mut None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_rom_patches() {
+        let text = format!(
+            "[v1] gs\n\
+             {}\n\
+             [v3] gs3\n\
+             {}\n\
+             {}\n\
+             [Off] gs off\n\
+             {}\n",
+            encrypt(0x6000_0100, 0x0000_46c0, &GS_SEEDS),
+            encrypt(0, 0x1800_0080, &GS3_SEEDS),
+            encrypt(0x0000_2001, 0, &GS3_SEEDS),
+            encrypt(0x6000_0200, 0x0000_46c0, &GS_SEEDS)
+        );
+        let cheats = Cheats::parse(&text).unwrap();
+        assert_eq!(
+            vec![RomPatch::Half(0x200, 0x46c0), RomPatch::Half(0x100, 0x2001)],
+            cheats.rom_patches()
+        );
+
+        // The value line is missing
+        let text = format!("[v3] gs3\n{}\n", encrypt(0, 0x1a00_0080, &GS3_SEEDS));
+        assert!(Cheats::parse(&text).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut mem = RamUnit { ram: Ram::new(64) };
+        let text = format!(
+            "# comment\n\
+             [Lives] cb\n\
+             7000001C 0000\n\
+             80000010 0063\n\
+             E0000012 0002\n\
+             [Off] gs off\n\
+             {}\n",
+            encrypt(0x0000_0020, 0xff, &GS_SEEDS)
+        );
+        let cheats = Cheats::parse(&text).unwrap();
+        assert_eq!(vec!["Lives", "Off"], {
+            cheats
+                .cheats
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>()
+        });
+
+        cheats.apply(&mut mem);
+        assert_eq!(0x63, mem.load16(0x10));
+        assert_eq!(2, mem.load16(0x12));
+        assert_eq!(0, mem.load8(0x20));
+
+        // The condition fails, so only the add runs
+        mem.set16(0x1c, 1);
+        mem.set16(0x10, 0);
+        cheats.apply(&mut mem);
+        assert_eq!(0, mem.load16(0x10));
+        assert_eq!(4, mem.load16(0x12));
+
+        assert!(Cheats::parse("80000010 0063").is_err());
+        assert!(Cheats::parse("[x] ar\n").is_err());
+        assert!(Cheats::parse("[x] cb\n9ABCDEF0 1234").is_err());
+    }
+}
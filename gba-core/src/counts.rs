@@ -0,0 +1,91 @@
+//! Where the emulated cycles went, for benchmarks: running instructions,
+//! halted waiting for an interrupt, or skipped in an idle loop, and how many
+//! events each component handled.
+
+use scheduler::Event;
+
+const EVENTS: [(Event, &str); 4] = [
+    (Event::Ppu, "PPU"),
+    (Event::Spu, "SPU"),
+    (Event::Timers, "timers"),
+    (Event::Sio, "serial"),
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CycleCounts {
+    pub instructions: u64,
+    /// Cycles taken by instructions, including their memory accesses
+    pub cpu: u64,
+    pub halted: u64,
+    pub idle: u64,
+    /// Events run, in `EVENTS` order
+    pub events: [u64; 4],
+}
+
+impl CycleCounts {
+    /// Counts an event run
+    #[inline]
+    pub fn event(&mut self, event: Event) {
+        let idx = EVENTS.iter().position(|&(e, _)| e == event).unwrap();
+        self.events[idx] += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.cpu + self.halted + self.idle
+    }
+
+    pub fn report(&self) -> Vec<String> {
+        let total = self.total().max(1) as f64;
+        let line = |name: &str, cycles: u64| {
+            format!(
+                "  {:<8} {:>14} cycles {:>6.2}%",
+                name,
+                cycles,
+                cycles as f64 * 100.0 / total
+            )
+        };
+        let mut lines = vec![
+            line("CPU", self.cpu),
+            line("halted", self.halted),
+            line("idle", self.idle),
+        ];
+        if self.instructions > 0 {
+            lines.push(format!(
+                "  {:.2} cycles per instruction, {} instructions",
+                self.cpu as f64 / self.instructions as f64,
+                self.instructions
+            ));
+        }
+        for (&(_, name), &count) in EVENTS.iter().zip(self.events.iter()) {
+            lines.push(format!("  {:<8} {:>14} events", name, count));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut counts = CycleCounts {
+            instructions: 100,
+            cpu: 300,
+            halted: 100,
+            ..Default::default()
+        };
+        counts.event(Event::Spu);
+        counts.event(Event::Spu);
+        assert_eq!([0, 2, 0, 0], counts.events);
+        assert_eq!(400, counts.total());
+
+        let report = counts.report();
+        assert_eq!(
+            format!("  {:<8} {:>14} cycles {:>6.2}%", "CPU", 300, 75.0),
+            report[0]
+        );
+        assert_eq!("  3.00 cycles per instruction, 100 instructions", report[3]);
+        assert_eq!(format!("  {:<8} {:>14} events", "SPU", 2), report[5]);
+    }
+}
@@ -0,0 +1,344 @@
+//! Breakpoints, optionally with conditions checked when the CPU reaches them,
+//! written `<addr> if <cond> && <cond>...`, e.g.
+//!
+//! ```text
+//! update_input if r0 == 0x40
+//! 8000f10 if [0x03001234].h != 0 && hits >= 100
+//! ```
+//!
+//! The address is in hex or a symbol name.  Each condition compares two
+//! operands: a register, `cpsr`, memory as `[addr]` with `.b` or `.h` for a
+//! byte or halfword rather than a word, `hits` for the number of times the
+//! CPU reached the address counting this one, or a number in decimal or `0x`
+//! hex.  The CPU stops only when all of them hold.
+//!
+//! The CPU can also stop on events, see `BreakEvent`.
+
+use std::fmt;
+
+use arm7tdmi_rs::reg::{self, Reg};
+
+use cpu::disasm;
+use symbols::Symbols;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operand {
+    Reg(Reg),
+    /// The value at an address, read as 1, 2 or 4 bytes
+    Mem(u32, u32),
+    Hits,
+    Value(u32),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Longer operators first, so `<=` isn't taken for `<`
+const COMPARES: [(&str, Compare); 6] = [
+    ("==", Compare::Eq),
+    ("!=", Compare::Ne),
+    ("<=", Compare::Le),
+    (">=", Compare::Ge),
+    ("<", Compare::Lt),
+    (">", Compare::Gt),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub cmp: Compare,
+    pub rhs: Operand,
+}
+
+/// Interrupts' names, by their bit in IE and IF
+pub const IRQ_NAMES: [&str; 14] = [
+    "vblank", "hblank", "vcount", "timer0", "timer1", "timer2", "timer3", "serial", "dma0", "dma1",
+    "dma2", "dma3", "keypad", "gamepak",
+];
+
+/// Something the CPU stops after, written `irq`, `swi`, `vblank` or `dma`.
+/// Interrupts, SWIs and DMA can be narrowed to one interrupt, SWI number or
+/// channel with a colon, e.g. `irq:timer0`, `swi:0x0b` or `dma:3`.
+///
+/// The CPU stops once an interrupt or SWI is taken, at the exception
+/// vector, and after the instruction the others happened during.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BreakEvent {
+    Irq(Option<u8>),
+    Swi(Option<u8>),
+    VBlank,
+    Dma(Option<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u32,
+    pub conds: Vec<Condition>,
+    /// The conditions as written, to show them back
+    text: String,
+    /// Times the CPU has reached `addr`
+    pub hits: u32,
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    if s.starts_with("0x") {
+        u32::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn parse_addr(s: &str, symbols: &Symbols) -> Result<u32, String> {
+    let digits = if s.starts_with("0x") { &s[2..] } else { s };
+    u32::from_str_radix(digits, 16).or_else(|_| {
+        symbols
+            .lookup(s)
+            .map(|sym| sym.addr)
+            .ok_or_else(|| format!("'{}' is not an address or symbol", s))
+    })
+}
+
+fn parse_operand(s: &str, symbols: &Symbols) -> Result<Operand, String> {
+    let s = s.trim();
+    if s.starts_with('[') {
+        let (inner, size) = match s.rfind(']') {
+            Some(end) => match &s[end + 1..] {
+                "" => (&s[1..end], 4),
+                ".h" => (&s[1..end], 2),
+                ".b" => (&s[1..end], 1),
+                _ => return Err(format!("'{}' should end in ], ].h or ].b", s)),
+            },
+            None => return Err(format!("'{}' is missing a ]", s)),
+        };
+        return Ok(Operand::Mem(parse_addr(inner.trim(), symbols)?, size));
+    }
+    if s == "hits" {
+        return Ok(Operand::Hits);
+    }
+    if s == "cpsr" {
+        return Ok(Operand::Reg(reg::CPSR));
+    }
+    if let Some(r) = (0..16).find(|&r| disasm::reg_name(r) == s || format!("r{}", r) == s) {
+        return Ok(Operand::Reg(r as Reg));
+    }
+    parse_number(s)
+        .map(Operand::Value)
+        .ok_or_else(|| format!("'{}' is not a register, memory, hits or a number", s))
+}
+
+impl Condition {
+    pub fn parse(s: &str, symbols: &Symbols) -> Result<Condition, String> {
+        let found = COMPARES
+            .iter()
+            .filter_map(|&(op, cmp)| s.find(op).map(|idx| (idx, op, cmp)))
+            .min_by_key(|&(idx, op, _)| (idx, !op.len()));
+        match found {
+            Some((idx, op, cmp)) => Ok(Condition {
+                lhs: parse_operand(&s[..idx], symbols)?,
+                cmp: cmp,
+                rhs: parse_operand(&s[idx + op.len()..], symbols)?,
+            }),
+            None => Err(format!("'{}' has no comparison", s.trim())),
+        }
+    }
+
+    /// Whether the condition holds, with `value` giving each operand's value
+    pub fn holds<F: Fn(Operand) -> u32>(&self, value: F) -> bool {
+        let (lhs, rhs) = (value(self.lhs), value(self.rhs));
+        match self.cmp {
+            Compare::Eq => lhs == rhs,
+            Compare::Ne => lhs != rhs,
+            Compare::Lt => lhs < rhs,
+            Compare::Le => lhs <= rhs,
+            Compare::Gt => lhs > rhs,
+            Compare::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl BreakEvent {
+    pub fn parse(s: &str) -> Result<BreakEvent, String> {
+        let s = s.trim();
+        let (kind, which) = match s.find(':') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+        let number = |max: u32| match which {
+            None => Ok(None),
+            Some(which) => match parse_number(which) {
+                Some(num) if num < max => Ok(Some(num as u8)),
+                _ => Err(format!("'{}' should be a number below {}", which, max)),
+            },
+        };
+        match kind {
+            "irq" => match which.and_then(|which| IRQ_NAMES.iter().position(|&n| n == which)) {
+                Some(bit) => Ok(BreakEvent::Irq(Some(bit as u8))),
+                None => number(IRQ_NAMES.len() as u32).map(BreakEvent::Irq),
+            },
+            "swi" => number(0x100).map(BreakEvent::Swi),
+            "vblank" if which.is_none() => Ok(BreakEvent::VBlank),
+            "dma" => number(4).map(BreakEvent::Dma),
+            _ => Err(format!("'{}' should be irq, swi, vblank or dma", s)),
+        }
+    }
+
+    /// Whether to stop for `event`, which just happened
+    pub fn matches(&self, event: BreakEvent) -> bool {
+        use self::BreakEvent::*;
+        match (*self, event) {
+            (Irq(want), Irq(got)) | (Swi(want), Swi(got)) | (Dma(want), Dma(got)) => {
+                want.is_none() || want == got
+            }
+            (VBlank, VBlank) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for BreakEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BreakEvent::Irq(Some(bit)) => write!(f, "irq:{}", IRQ_NAMES[bit as usize]),
+            BreakEvent::Irq(None) => write!(f, "irq"),
+            BreakEvent::Swi(Some(num)) => write!(f, "swi:{:#04x}", num),
+            BreakEvent::Swi(None) => write!(f, "swi"),
+            BreakEvent::VBlank => write!(f, "vblank"),
+            BreakEvent::Dma(Some(ch)) => write!(f, "dma:{}", ch),
+            BreakEvent::Dma(None) => write!(f, "dma"),
+        }
+    }
+}
+
+impl Breakpoint {
+    /// Stops every time the CPU reaches `addr`
+    pub fn new(addr: u32) -> Self {
+        Breakpoint {
+            addr: addr,
+            conds: Vec::new(),
+            text: String::new(),
+            hits: 0,
+        }
+    }
+
+    pub fn parse(s: &str, symbols: &Symbols) -> Result<Breakpoint, String> {
+        let s = s.trim();
+        let (addr, text) = match s.find(" if ") {
+            Some(idx) => (&s[..idx], s[idx + 4..].trim()),
+            None => (s, ""),
+        };
+        let mut brk = Breakpoint::new(parse_addr(addr.trim(), symbols)?);
+        if !text.is_empty() {
+            brk.conds = text
+                .split("&&")
+                .map(|cond| Condition::parse(cond, symbols))
+                .collect::<Result<_, _>>()?;
+            brk.text = text.to_string();
+        }
+        Ok(brk)
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}", self.addr)?;
+        if !self.text.is_empty() {
+            write!(f, " if {}", self.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use symbols::Symbol;
+
+    #[test]
+    fn test_parse() {
+        let symbols = Symbols::new(vec![Symbol {
+            name: "counter".to_string(),
+            addr: 0x0300_1234,
+            size: 4,
+            thumb: false,
+        }]);
+        let brk = Breakpoint::parse("8000f10", &symbols).unwrap();
+        assert_eq!((0x0800_0f10, 0), (brk.addr, brk.conds.len()));
+        assert_eq!("08000f10", brk.to_string());
+
+        let text = "0x8000f10 if r0 == 0x40 && [counter].h!=0&&hits>=100";
+        let brk = Breakpoint::parse(text, &symbols).unwrap();
+        assert_eq!(
+            vec![
+                Condition {
+                    lhs: Operand::Reg(0),
+                    cmp: Compare::Eq,
+                    rhs: Operand::Value(0x40),
+                },
+                Condition {
+                    lhs: Operand::Mem(0x0300_1234, 2),
+                    cmp: Compare::Ne,
+                    rhs: Operand::Value(0),
+                },
+                Condition {
+                    lhs: Operand::Hits,
+                    cmp: Compare::Ge,
+                    rhs: Operand::Value(100),
+                },
+            ],
+            brk.conds
+        );
+        assert!(brk
+            .to_string()
+            .ends_with(" if r0 == 0x40 && [counter].h!=0&&hits>=100"));
+
+        assert!(Breakpoint::parse("8000f10 if r0", &symbols).is_err());
+        assert!(Breakpoint::parse("8000f10 if r0 == [counter", &symbols).is_err());
+        assert!(Breakpoint::parse("8000f10 if r16 < 3", &symbols).is_err());
+    }
+
+    #[test]
+    fn test_events() {
+        let timer = BreakEvent::parse("irq:timer0").unwrap();
+        assert_eq!(BreakEvent::Irq(Some(3)), timer);
+        assert_eq!(timer, BreakEvent::parse("irq:3").unwrap());
+        assert_eq!("irq:timer0", timer.to_string());
+        assert!(timer.matches(BreakEvent::Irq(Some(3))));
+        assert!(!timer.matches(BreakEvent::Irq(Some(0))));
+
+        let swi = BreakEvent::parse("swi").unwrap();
+        assert!(swi.matches(BreakEvent::Swi(Some(0x0b))));
+        assert!(!swi.matches(BreakEvent::Dma(Some(0))));
+        assert_eq!(
+            "swi:0x0b",
+            BreakEvent::parse("swi:0x0b").unwrap().to_string()
+        );
+
+        assert!(BreakEvent::parse("dma:4").is_err());
+        assert!(BreakEvent::parse("irq:timer4").is_err());
+        assert!(BreakEvent::parse("vblank:1").is_err());
+        assert!(BreakEvent::parse("hblank").is_err());
+    }
+
+    #[test]
+    fn test_holds() {
+        let cond = Condition::parse("sp <= 0x3007f00", &Default::default()).unwrap();
+        assert!(cond.holds(|op| match op {
+            Operand::Reg(13) => 0x0300_7e00,
+            Operand::Value(val) => val,
+            _ => unreachable!(),
+        }));
+        assert!(!cond.holds(|op| match op {
+            Operand::Reg(_) => 0x0300_7f04,
+            Operand::Value(val) => val,
+            _ => unreachable!(),
+        }));
+    }
+}
@@ -0,0 +1,115 @@
+//! A call stack pieced together from the jumps the CPU makes, for
+//! backtraces.  Nothing on the GBA records calls, so it's a heuristic: a jump
+//! that leaves LR pointing after the instruction that made it is a call, as
+//! `bl` and `mov lr, pc` followed by `bx` do, and reaching a return address
+//! on the stack returns to it, however the code got there.  Interrupts and
+//! SWIs are frames of their own, returning to the instruction they came
+//! in before.
+
+use std::mem;
+
+/// Frames kept before the oldest are dropped, enough for any real game
+const MAX_FRAMES: usize = 256;
+
+/// Exception vectors, where SWIs and interrupts enter
+const SWI_VECTOR: u32 = 0x08;
+const IRQ_VECTOR: u32 = 0x18;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrameKind {
+    Call,
+    Swi,
+    Irq,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// The instruction that made the call, or that ran before an interrupt
+    pub call: u32,
+    /// Where the call went
+    pub target: u32,
+    pub ret: u32,
+}
+
+#[derive(Default)]
+pub struct CallStack {
+    /// Outermost first
+    frames: Vec<Frame>,
+    /// The last instruction reached, and its size
+    last: Option<(u32, u32)>,
+}
+
+impl CallStack {
+    /// The calls the CPU is in, outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Called before each instruction runs, with the instruction's address
+    /// and size and LR's value, to account for how the CPU got there
+    pub fn enter(&mut self, pc: u32, size: u32, lr: u32) {
+        let (prev, prev_size) = match mem::replace(&mut self.last, Some((pc, size))) {
+            Some(last) => last,
+            None => return,
+        };
+        let next = prev.wrapping_add(prev_size);
+        if pc == next {
+            return;
+        }
+        if let Some(idx) = self.frames.iter().rposition(|frame| frame.ret == pc) {
+            self.frames.truncate(idx);
+            return;
+        }
+        let (kind, ret) = match pc {
+            SWI_VECTOR => (FrameKind::Swi, lr),
+            // LR is 4 past where the interrupted code carries on
+            IRQ_VECTOR => (FrameKind::Irq, lr.wrapping_sub(4)),
+            _ if lr & !1 == next => (FrameKind::Call, next),
+            _ => return,
+        };
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(Frame {
+            kind: kind,
+            call: prev,
+            target: pc,
+            ret: ret & !1,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_calls() {
+        let mut calls = CallStack::default();
+        calls.enter(0x0800_0100, 4, 0);
+        // bl to 0x08000200
+        calls.enter(0x0800_0200, 4, 0x0800_0104);
+        assert_eq!(1, calls.frames().len());
+        assert_eq!(
+            (0x0800_0100, 0x0800_0104),
+            (calls.frames()[0].call, calls.frames()[0].ret)
+        );
+        // A Thumb bl pair from 0x08000300, after a branch that isn't a call
+        calls.enter(0x0800_0300, 2, 0x0800_0104);
+        calls.enter(0x0800_0302, 2, 0x0800_0104);
+        calls.enter(0x0800_0400, 2, 0x0800_0305);
+        assert_eq!(2, calls.frames().len());
+
+        // An interrupt, then its return
+        calls.enter(0x0800_0402, 2, 0x0800_0305);
+        calls.enter(IRQ_VECTOR, 4, 0x0800_0408);
+        assert_eq!(FrameKind::Irq, calls.frames()[2].kind);
+        calls.enter(0x0800_0404, 2, 0x0800_0305);
+        assert_eq!(2, calls.frames().len());
+
+        // Returning from the outer call unwinds the inner one with it
+        calls.enter(0x0800_0104, 4, 0x0800_0305);
+        assert!(calls.frames().is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+//! Counts of the instructions the CPU runs, by ARM and Thumb instruction
+//! format and by mnemonic, to see which handlers are hot and which are never
+//! run.  Each opcode is counted as it runs, and only sorted into formats and
+//! disassembled for the report.
+
+use std::collections::HashMap;
+
+use super::disasm;
+
+/// The ARM instruction formats, in the order they're told apart
+pub const ARM_FORMATS: [&str; 13] = [
+    "branch and exchange",
+    "multiply",
+    "multiply long",
+    "swap",
+    "halfword transfer",
+    "PSR transfer",
+    "data processing",
+    "undefined",
+    "single data transfer",
+    "block data transfer",
+    "branch",
+    "software interrupt",
+    "coprocessor",
+];
+
+/// The Thumb instruction formats, numbered 1-19 in the ARM7TDMI data sheet,
+/// and undefined instructions
+pub const THUMB_FORMATS: [&str; 20] = [
+    "move shifted register",
+    "add/subtract",
+    "immediate",
+    "ALU",
+    "hi register/branch exchange",
+    "PC-relative load",
+    "load/store register offset",
+    "load/store sign-extended",
+    "load/store immediate offset",
+    "load/store halfword",
+    "SP-relative load/store",
+    "load address",
+    "add offset to SP",
+    "push/pop",
+    "multiple load/store",
+    "conditional branch",
+    "software interrupt",
+    "unconditional branch",
+    "long branch with link",
+    "undefined",
+];
+
+/// The index into `ARM_FORMATS` of `op`'s format, decoded as `disasm` does
+pub fn arm_format(op: u32) -> usize {
+    if op & 0x0fff_fff0 == 0x012f_ff10 {
+        0
+    } else if op & 0x0fc0_00f0 == 0x0000_0090 {
+        1
+    } else if op & 0x0f80_00f0 == 0x0080_0090 {
+        2
+    } else if op & 0x0fb0_0ff0 == 0x0100_0090 {
+        3
+    } else if op & 0x0e00_0090 == 0x0000_0090 && op >> 5 & 3 != 0 {
+        4
+    } else if op & 0x0fbf_0fff == 0x010f_0000 || op & 0x0db0_f000 == 0x0120_f000 {
+        5
+    } else if op & 0x0c00_0000 == 0x0000_0000 {
+        6
+    } else if op & 0x0e00_0010 == 0x0600_0010 {
+        7
+    } else if op & 0x0c00_0000 == 0x0400_0000 {
+        8
+    } else if op & 0x0e00_0000 == 0x0800_0000 {
+        9
+    } else if op & 0x0e00_0000 == 0x0a00_0000 {
+        10
+    } else if op & 0x0f00_0000 == 0x0f00_0000 {
+        11
+    } else {
+        12
+    }
+}
+
+/// The index into `THUMB_FORMATS` of `op`'s format
+pub fn thumb_format(op: u16) -> usize {
+    match op >> 8 {
+        0x00..=0x17 => 0,
+        0x18..=0x1f => 1,
+        0x20..=0x3f => 2,
+        0x40..=0x43 => 3,
+        0x44..=0x47 => 4,
+        0x48..=0x4f => 5,
+        0x50..=0x5f if op >> 9 & 1 == 0 => 6,
+        0x50..=0x5f => 7,
+        0x60..=0x7f => 8,
+        0x80..=0x8f => 9,
+        0x90..=0x9f => 10,
+        0xa0..=0xaf => 11,
+        0xb0 => 12,
+        0xb4 | 0xb5 | 0xbc | 0xbd => 13,
+        0xc0..=0xcf => 14,
+        0xd0..=0xdd => 15,
+        0xdf => 16,
+        0xe0..=0xe7 => 17,
+        0xf0..=0xff => 18,
+        _ => 19,
+    }
+}
+
+/// `op`'s mnemonic without its condition, which would split every
+/// instruction into up to 15
+fn mnemonic(op: u32, thumb: bool) -> String {
+    let text = if thumb {
+        if thumb_format(op as u16) == 15 {
+            return "b<cond>".to_string();
+        }
+        disasm::disasm_thumb(op as u16, 0, 0)
+    } else {
+        disasm::disasm_arm(op & 0x0fff_ffff | 0xe000_0000, 0)
+    };
+    text.split_whitespace().next().unwrap_or("").to_string()
+}
+
+#[derive(Default)]
+pub struct Coverage {
+    arm: HashMap<u32, u64>,
+    thumb: HashMap<u16, u64>,
+}
+
+impl Coverage {
+    /// Counts `op` running
+    #[inline]
+    pub fn count(&mut self, op: u32, thumb: bool) {
+        if thumb {
+            *self.thumb.entry(op as u16).or_insert(0) += 1;
+        } else {
+            *self.arm.entry(op).or_insert(0) += 1;
+        }
+    }
+
+    /// How many times each format and each mnemonic ran, most first, for
+    /// ARM then Thumb
+    pub fn report(&self) -> Vec<String> {
+        let arm = self.arm.iter().map(|(&op, &count)| (op, count));
+        let thumb = self.thumb.iter().map(|(&op, &count)| (op as u32, count));
+        let mut lines = section("ARM", &ARM_FORMATS, arm, false);
+        lines.extend(section("Thumb", &THUMB_FORMATS, thumb, true));
+        lines
+    }
+}
+
+fn section<I>(name: &str, formats: &[&str], counts: I, thumb: bool) -> Vec<String>
+where
+    I: Iterator<Item = (u32, u64)>,
+{
+    let mut by_format = vec![0u64; formats.len()];
+    let mut by_mnemonic: HashMap<String, u64> = HashMap::new();
+    for (op, count) in counts {
+        let format = if thumb {
+            thumb_format(op as u16)
+        } else {
+            arm_format(op)
+        };
+        by_format[format] += count;
+        *by_mnemonic.entry(mnemonic(op, thumb)).or_insert(0) += count;
+    }
+    let total: u64 = by_format.iter().sum();
+    let line = |name: &str, count: u64| {
+        if count == 0 {
+            format!("  {:<28} never run", name)
+        } else {
+            let percent = count as f64 * 100.0 / total as f64;
+            format!("  {:<28} {:>12} {:>6.2}%", name, count, percent)
+        }
+    };
+
+    let mut formats: Vec<(&str, u64)> = formats.iter().cloned().zip(by_format).collect();
+    formats.sort_by(|a, b| b.1.cmp(&a.1));
+    let mut mnemonics: Vec<(String, u64)> = by_mnemonic.into_iter().collect();
+    mnemonics.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let mut lines = vec![format!("{} instructions run: {}", name, total)];
+    lines.push(format!("{} formats:", name));
+    lines.extend(formats.iter().map(|&(format, count)| line(format, count)));
+    lines.push(format!("{} mnemonics:", name));
+    lines.extend(
+        mnemonics
+            .iter()
+            .map(|&(ref mnemonic, count)| line(mnemonic, count)),
+    );
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        // bx lr, mul, umull, swp, ldrh, mrs, mov, ldr, stmfd, bl, swi
+        let ops = [
+            (0xe12f_ff1e, 0),
+            (0xe000_0291, 1),
+            (0xe081_0392, 2),
+            (0xe102_1093, 3),
+            (0xe1d0_00b2, 4),
+            (0xe10f_0000, 5),
+            (0x03a0_0001, 6),
+            (0xe591_0004, 8),
+            (0xe92d_4010, 9),
+            (0xeb00_0010, 10),
+            (0xef00_0005, 11),
+        ];
+        for &(op, format) in ops.iter() {
+            assert_eq!(
+                ARM_FORMATS[format],
+                ARM_FORMATS[arm_format(op)],
+                "{:08x}",
+                op
+            );
+        }
+
+        // lsl, add, mov, and, bx, ldr pc, str, ldsh, ldrb, strh, ldr sp, add
+        // pc, add sp, push, ldmia, beq, swi, b, bl
+        let ops = [
+            0x0088, 0x1c08, 0x2001, 0x4008, 0x4770, 0x4801, 0x5088, 0x5e88, 0x7808, 0x8008, 0x9801,
+            0xa001, 0xb082, 0xb510, 0xc803, 0xd0fe, 0xdf05, 0xe7fe, 0xf000,
+        ];
+        for (format, &op) in ops.iter().enumerate() {
+            assert_eq!(
+                THUMB_FORMATS[format],
+                THUMB_FORMATS[thumb_format(op)],
+                "{:04x}",
+                op
+            );
+        }
+        assert_eq!(19, thumb_format(0xde00));
+    }
+
+    #[test]
+    fn test_report() {
+        let mut coverage = Coverage::default();
+        for _ in 0..3 {
+            coverage.count(0x03a0_0001, false);
+        }
+        coverage.count(0xe3a0_0002, false);
+        coverage.count(0xe12f_ff1e, false);
+        coverage.count(0xd0fe, true);
+        let report = coverage.report();
+        assert_eq!("ARM instructions run: 5", report[0]);
+        assert_eq!(
+            format!("  {:<28} {:>12} {:>6.2}%", "data processing", 4, 80.0),
+            report[2]
+        );
+        assert!(report.contains(&format!("  {:<28} never run", "swap")));
+        let mov = report.iter().position(|line| line.contains("mov")).unwrap();
+        assert!(report[mov].contains(" 4 "));
+        assert!(report.contains(&format!("  {:<28} {:>12} {:>6.2}%", "b<cond>", 1, 100.0)));
+    }
+}
@@ -0,0 +1,589 @@
+//! ARMv4T disassembler, used for diagnostics only so it favours readability
+//! over matching any particular assembler's syntax exactly.
+
+use bit_util::{bit, extract, sign_extend};
+
+const CONDS: [&str; 16] = [
+    "eq", "ne", "cs", "cc", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "", "nv",
+];
+
+const SHIFTS: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+const DP_OPS: [&str; 16] = [
+    "and", "eor", "sub", "rsb", "add", "adc", "sbc", "rsc", "tst", "teq", "cmp", "cmn", "orr",
+    "mov", "bic", "mvn",
+];
+
+const THUMB_ALU_OPS: [&str; 16] = [
+    "and", "eor", "lsl", "lsr", "asr", "adc", "sbc", "ror", "tst", "neg", "cmp", "cmn", "orr",
+    "mul", "bic", "mvn",
+];
+
+pub fn reg_name(reg: u32) -> &'static str {
+    const NAMES: [&str; 16] = [
+        "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp",
+        "lr", "pc",
+    ];
+    NAMES[(reg & 0xf) as usize]
+}
+
+fn reg_list(list: u32) -> String {
+    let regs: Vec<&str> = (0..16)
+        .filter(|r| bit(list, *r as u8) == 1)
+        .map(reg_name)
+        .collect();
+    format!("{{{}}}", regs.join(", "))
+}
+
+fn signed_imm(up: bool, val: u32) -> String {
+    format!("#{}{:#x}", if up { "" } else { "-" }, val)
+}
+
+/// Disassembles an ARM instruction located at `pc`
+pub fn disasm_arm(op: u32, pc: u32) -> String {
+    let cond = CONDS[extract(op, 28, 4) as usize];
+    let rn = extract(op, 16, 4);
+    let rd = extract(op, 12, 4);
+    let rs = extract(op, 8, 4);
+    let rm = extract(op, 0, 4);
+
+    if op & 0x0fff_fff0 == 0x012f_ff10 {
+        format!("bx{} {}", cond, reg_name(rm))
+    } else if op & 0x0fc0_00f0 == 0x0000_0090 {
+        let s = if bit(op, 20) == 1 { "s" } else { "" };
+        if bit(op, 21) == 1 {
+            format!(
+                "mla{}{} {}, {}, {}, {}",
+                cond,
+                s,
+                reg_name(rn),
+                reg_name(rm),
+                reg_name(rs),
+                reg_name(rd)
+            )
+        } else {
+            format!(
+                "mul{}{} {}, {}, {}",
+                cond,
+                s,
+                reg_name(rn),
+                reg_name(rm),
+                reg_name(rs)
+            )
+        }
+    } else if op & 0x0f80_00f0 == 0x0080_0090 {
+        let sign = if bit(op, 22) == 1 { "s" } else { "u" };
+        let acc = if bit(op, 21) == 1 { "mlal" } else { "mull" };
+        let s = if bit(op, 20) == 1 { "s" } else { "" };
+        format!(
+            "{}{}{}{} {}, {}, {}, {}",
+            sign,
+            acc,
+            cond,
+            s,
+            reg_name(rd),
+            reg_name(rn),
+            reg_name(rm),
+            reg_name(rs)
+        )
+    } else if op & 0x0fb0_0ff0 == 0x0100_0090 {
+        let b = if bit(op, 22) == 1 { "b" } else { "" };
+        format!(
+            "swp{}{} {}, {}, [{}]",
+            cond,
+            b,
+            reg_name(rd),
+            reg_name(rm),
+            reg_name(rn)
+        )
+    } else if op & 0x0e00_0090 == 0x0000_0090 && extract(op, 5, 2) != 0 {
+        disasm_arm_halfword(op, cond)
+    } else if op & 0x0fbf_0fff == 0x010f_0000 {
+        let psr = if bit(op, 22) == 1 { "spsr" } else { "cpsr" };
+        format!("mrs{} {}, {}", cond, reg_name(rd), psr)
+    } else if op & 0x0db0_f000 == 0x0120_f000 {
+        let psr = if bit(op, 22) == 1 { "spsr" } else { "cpsr" };
+        let fields: String = ['c', 'x', 's', 'f']
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| bit(op, 16 + i as u8) == 1)
+            .map(|(_, c)| *c)
+            .collect();
+        let src = if bit(op, 25) == 1 {
+            format!(
+                "#{:#x}",
+                extract(op, 0, 8).rotate_right(extract(op, 8, 4) * 2)
+            )
+        } else {
+            reg_name(rm).to_string()
+        };
+        format!("msr{} {}_{}, {}", cond, psr, fields, src)
+    } else if op & 0x0c00_0000 == 0x0000_0000 {
+        disasm_arm_dataproc(op, cond)
+    } else if op & 0x0e00_0010 == 0x0600_0010 {
+        format!("undefined{} {:#010x}", cond, op)
+    } else if op & 0x0c00_0000 == 0x0400_0000 {
+        disasm_arm_transfer(op, cond)
+    } else if op & 0x0e00_0000 == 0x0800_0000 {
+        let kind = match (bit(op, 24), bit(op, 23)) {
+            (0, 0) => "da",
+            (0, 1) => "ia",
+            (1, 0) => "db",
+            _ => "ib",
+        };
+        let name = if bit(op, 20) == 1 { "ldm" } else { "stm" };
+        let wb = if bit(op, 21) == 1 { "!" } else { "" };
+        let user = if bit(op, 22) == 1 { "^" } else { "" };
+        format!(
+            "{}{}{} {}{}, {}{}",
+            name,
+            cond,
+            kind,
+            reg_name(rn),
+            wb,
+            reg_list(extract(op, 0, 16)),
+            user
+        )
+    } else if op & 0x0e00_0000 == 0x0a00_0000 {
+        let l = if bit(op, 24) == 1 { "l" } else { "" };
+        let target = pc
+            .wrapping_add(8)
+            .wrapping_add(sign_extend(extract(op, 0, 24), 24) << 2);
+        format!("b{}{} {:#010x}", l, cond, target)
+    } else if op & 0x0f00_0000 == 0x0f00_0000 {
+        format!("swi{} #{:#x}", cond, extract(op, 0, 24))
+    } else {
+        format!("coproc{} {:#010x}", cond, op)
+    }
+}
+
+fn arm_shift(op: u32) -> String {
+    let rm = reg_name(extract(op, 0, 4));
+    let kind = extract(op, 5, 2);
+    if bit(op, 4) == 1 {
+        return format!(
+            "{}, {} {}",
+            rm,
+            SHIFTS[kind as usize],
+            reg_name(extract(op, 8, 4))
+        );
+    }
+    let amount = extract(op, 7, 5);
+    match (kind, amount) {
+        (0, 0) => rm.to_string(),
+        (3, 0) => format!("{}, rrx", rm),
+        (1, 0) | (2, 0) => format!("{}, {} #32", rm, SHIFTS[kind as usize]),
+        _ => format!("{}, {} #{}", rm, SHIFTS[kind as usize], amount),
+    }
+}
+
+fn disasm_arm_dataproc(op: u32, cond: &str) -> String {
+    let opcode = extract(op, 21, 4);
+    let name = DP_OPS[opcode as usize];
+    let rn = reg_name(extract(op, 16, 4));
+    let rd = reg_name(extract(op, 12, 4));
+    let operand = if bit(op, 25) == 1 {
+        format!(
+            "#{:#x}",
+            extract(op, 0, 8).rotate_right(extract(op, 8, 4) * 2)
+        )
+    } else {
+        arm_shift(op)
+    };
+    match opcode {
+        // tst, teq, cmp, cmn always set flags
+        8..=11 => format!("{}{} {}, {}", name, cond, rn, operand),
+        _ => {
+            let s = if bit(op, 20) == 1 { "s" } else { "" };
+            if opcode == 13 || opcode == 15 {
+                format!("{}{}{} {}, {}", name, cond, s, rd, operand)
+            } else {
+                format!("{}{}{} {}, {}, {}", name, cond, s, rd, rn, operand)
+            }
+        }
+    }
+}
+
+fn arm_address(op: u32, offset: String) -> String {
+    let rn = reg_name(extract(op, 16, 4));
+    if bit(op, 24) == 1 {
+        let wb = if bit(op, 21) == 1 { "!" } else { "" };
+        format!("[{}, {}]{}", rn, offset, wb)
+    } else {
+        format!("[{}], {}", rn, offset)
+    }
+}
+
+fn disasm_arm_transfer(op: u32, cond: &str) -> String {
+    let name = if bit(op, 20) == 1 { "ldr" } else { "str" };
+    let b = if bit(op, 22) == 1 { "b" } else { "" };
+    // post-indexed with writeback forces a user mode access
+    let t = if bit(op, 24) == 0 && bit(op, 21) == 1 {
+        "t"
+    } else {
+        ""
+    };
+    let up = bit(op, 23) == 1;
+    let offset = if bit(op, 25) == 0 {
+        signed_imm(up, extract(op, 0, 12))
+    } else {
+        format!("{}{}", if up { "" } else { "-" }, arm_shift(op))
+    };
+    format!(
+        "{}{}{}{} {}, {}",
+        name,
+        cond,
+        b,
+        t,
+        reg_name(extract(op, 12, 4)),
+        arm_address(op, offset)
+    )
+}
+
+fn disasm_arm_halfword(op: u32, cond: &str) -> String {
+    let name = match (bit(op, 20), extract(op, 5, 2)) {
+        (0, 1) => "strh",
+        (1, 1) => "ldrh",
+        (1, 2) => "ldrsb",
+        (1, 3) => "ldrsh",
+        _ => return format!("undefined{} {:#010x}", cond, op),
+    };
+    let up = bit(op, 23) == 1;
+    let offset = if bit(op, 22) == 1 {
+        signed_imm(up, (extract(op, 8, 4) << 4) | extract(op, 0, 4))
+    } else {
+        format!(
+            "{}{}",
+            if up { "" } else { "-" },
+            reg_name(extract(op, 0, 4))
+        )
+    };
+    format!(
+        "{}{} {}, {}",
+        name,
+        cond,
+        reg_name(extract(op, 12, 4)),
+        arm_address(op, offset)
+    )
+}
+
+/// Disassembles a Thumb instruction located at `pc`.  `next` is the following
+/// halfword, which is only used to combine the two halves of a long branch.
+pub fn disasm_thumb(op: u16, next: u16, pc: u32) -> String {
+    let op = op as u32;
+    let rd = reg_name(extract(op, 0, 3));
+    let rs = reg_name(extract(op, 3, 3));
+    let rb = rs;
+
+    match extract(op, 11, 5) {
+        0b00000..=0b00010 => format!(
+            "{} {}, {}, #{}",
+            SHIFTS[extract(op, 11, 2) as usize],
+            rd,
+            rs,
+            extract(op, 6, 5)
+        ),
+        0b00011 => {
+            let name = if bit(op, 9) == 0 { "add" } else { "sub" };
+            let val = extract(op, 6, 3);
+            let operand = if bit(op, 10) == 1 {
+                format!("#{}", val)
+            } else {
+                reg_name(val).to_string()
+            };
+            format!("{} {}, {}, {}", name, rd, rs, operand)
+        }
+        0b00100..=0b00111 => {
+            const NAMES: [&str; 4] = ["mov", "cmp", "add", "sub"];
+            format!(
+                "{} {}, #{:#x}",
+                NAMES[extract(op, 11, 2) as usize],
+                reg_name(extract(op, 8, 3)),
+                extract(op, 0, 8)
+            )
+        }
+        0b01000 => {
+            if bit(op, 10) == 0 {
+                format!(
+                    "{} {}, {}",
+                    THUMB_ALU_OPS[extract(op, 6, 4) as usize],
+                    rd,
+                    rs
+                )
+            } else {
+                let hd = reg_name(extract(op, 0, 3) | (bit(op, 7) << 3));
+                let hs = reg_name(extract(op, 3, 4));
+                match extract(op, 8, 2) {
+                    0 => format!("add {}, {}", hd, hs),
+                    1 => format!("cmp {}, {}", hd, hs),
+                    2 => format!("mov {}, {}", hd, hs),
+                    _ => format!("bx {}", hs),
+                }
+            }
+        }
+        0b01001 => {
+            let addr = (pc.wrapping_add(4) & !2).wrapping_add(extract(op, 0, 8) * 4);
+            format!(
+                "ldr {}, [pc, #{:#x}] ; {:#010x}",
+                reg_name(extract(op, 8, 3)),
+                extract(op, 0, 8) * 4,
+                addr
+            )
+        }
+        0b01010 | 0b01011 => {
+            let ro = reg_name(extract(op, 6, 3));
+            let name = if bit(op, 9) == 0 {
+                ["str", "strb", "ldr", "ldrb"][extract(op, 10, 2) as usize]
+            } else {
+                ["strh", "ldsb", "ldrh", "ldsh"][extract(op, 10, 2) as usize]
+            };
+            format!("{} {}, [{}, {}]", name, rd, rb, ro)
+        }
+        0b01100..=0b01111 => {
+            let byte = bit(op, 12) == 1;
+            let name = match (bit(op, 11), byte) {
+                (0, false) => "str",
+                (0, true) => "strb",
+                (_, false) => "ldr",
+                (_, true) => "ldrb",
+            };
+            let scale = if byte { 1 } else { 4 };
+            format!(
+                "{} {}, [{}, #{:#x}]",
+                name,
+                rd,
+                rb,
+                extract(op, 6, 5) * scale
+            )
+        }
+        0b10000 | 0b10001 => {
+            let name = if bit(op, 11) == 0 { "strh" } else { "ldrh" };
+            format!("{} {}, [{}, #{:#x}]", name, rd, rb, extract(op, 6, 5) * 2)
+        }
+        0b10010 | 0b10011 => {
+            let name = if bit(op, 11) == 0 { "str" } else { "ldr" };
+            format!(
+                "{} {}, [sp, #{:#x}]",
+                name,
+                reg_name(extract(op, 8, 3)),
+                extract(op, 0, 8) * 4
+            )
+        }
+        0b10100 | 0b10101 => format!(
+            "add {}, {}, #{:#x}",
+            reg_name(extract(op, 8, 3)),
+            if bit(op, 11) == 0 { "pc" } else { "sp" },
+            extract(op, 0, 8) * 4
+        ),
+        0b10110 | 0b10111 => {
+            if extract(op, 8, 4) == 0b0000 {
+                let sign = if bit(op, 7) == 1 { "-" } else { "" };
+                format!("add sp, #{}{:#x}", sign, extract(op, 0, 7) * 4)
+            } else if extract(op, 9, 2) == 0b10 {
+                let mut list = extract(op, 0, 8);
+                let pop = bit(op, 11) == 1;
+                if bit(op, 8) == 1 {
+                    list |= if pop { 1 << 15 } else { 1 << 14 };
+                }
+                let name = if pop { "pop" } else { "push" };
+                format!("{} {}", name, reg_list(list))
+            } else {
+                format!("undefined {:#06x}", op)
+            }
+        }
+        0b11000 | 0b11001 => {
+            let name = if bit(op, 11) == 0 { "stmia" } else { "ldmia" };
+            format!(
+                "{} {}!, {}",
+                name,
+                reg_name(extract(op, 8, 3)),
+                reg_list(extract(op, 0, 8))
+            )
+        }
+        0b11010 | 0b11011 => match extract(op, 8, 4) {
+            0xe => format!("undefined {:#06x}", op),
+            0xf => format!("swi #{:#x}", extract(op, 0, 8)),
+            cond => {
+                let target = pc
+                    .wrapping_add(4)
+                    .wrapping_add(sign_extend(extract(op, 0, 8), 8) << 1);
+                format!("b{} {:#010x}", CONDS[cond as usize], target)
+            }
+        },
+        0b11100 => {
+            let target = pc
+                .wrapping_add(4)
+                .wrapping_add(sign_extend(extract(op, 0, 11), 11) << 1);
+            format!("b {:#010x}", target)
+        }
+        0b11110 => {
+            let next = next as u32;
+            if extract(next, 11, 5) == 0b11111 {
+                let target = pc
+                    .wrapping_add(4)
+                    .wrapping_add(sign_extend(extract(op, 0, 11), 11) << 12)
+                    .wrapping_add(extract(next, 0, 11) << 1);
+                format!("bl {:#010x}", target)
+            } else {
+                format!("bl.hi #{:#x}", extract(op, 0, 11))
+            }
+        }
+        0b11111 => format!("bl.lo #{:#x}", extract(op, 0, 11)),
+        _ => format!("undefined {:#06x}", op),
+    }
+}
+
+/// An instruction from `disasm_range`
+pub struct Line {
+    pub addr: u32,
+    /// The encoding, with a Thumb long branch's first half in the top 16 bits
+    pub opcode: u32,
+    /// 4 for ARM instructions and Thumb long branches, otherwise 2
+    pub size: u32,
+    pub text: String,
+    /// Where a direct branch goes
+    pub target: Option<u32>,
+    /// The address a pc-relative load reads its value from
+    pub literal: Option<u32>,
+}
+
+fn arm_target(op: u32, pc: u32) -> Option<u32> {
+    if op & 0x0e00_0000 == 0x0a00_0000 && op >> 28 != 0xf {
+        Some(
+            pc.wrapping_add(8)
+                .wrapping_add(sign_extend(extract(op, 0, 24), 24) << 2),
+        )
+    } else {
+        None
+    }
+}
+
+/// The address an ARM `ldr rd, [pc, #imm]` reads
+fn arm_literal(op: u32, pc: u32) -> Option<u32> {
+    // Single data transfer, immediate offset, pre-indexed load from pc
+    if op & 0x0f3f_0000 != 0x051f_0000 {
+        return None;
+    }
+    let base = pc.wrapping_add(8);
+    let off = extract(op, 0, 12);
+    Some(if bit(op, 23) == 1 {
+        base.wrapping_add(off)
+    } else {
+        base.wrapping_sub(off)
+    })
+}
+
+fn thumb_target(op: u32, next: u32, pc: u32) -> Option<u32> {
+    let base = pc.wrapping_add(4);
+    match extract(op, 11, 5) {
+        0b11010 | 0b11011 if extract(op, 8, 4) < 0xe => {
+            Some(base.wrapping_add(sign_extend(extract(op, 0, 8), 8) << 1))
+        }
+        0b11100 => Some(base.wrapping_add(sign_extend(extract(op, 0, 11), 11) << 1)),
+        0b11110 if extract(next, 11, 5) == 0b11111 => Some(
+            base.wrapping_add(sign_extend(extract(op, 0, 11), 11) << 12)
+                .wrapping_add(extract(next, 0, 11) << 1),
+        ),
+        _ => None,
+    }
+}
+
+fn thumb_literal(op: u32, pc: u32) -> Option<u32> {
+    if extract(op, 11, 5) == 0b01001 {
+        Some((pc.wrapping_add(4) & !2).wrapping_add(extract(op, 0, 8) * 4))
+    } else {
+        None
+    }
+}
+
+/// Disassembles `code`, which starts at `base`, as ARM or Thumb.  A trailing
+/// partial instruction is left out.
+pub fn disasm_range(code: &[u8], base: u32, thumb: bool) -> Vec<Line> {
+    let half = |off: usize| code[off] as u32 | (code[off + 1] as u32) << 8;
+    let mut lines = Vec::new();
+    let mut off = 0;
+    if thumb {
+        while off + 2 <= code.len() {
+            let pc = base.wrapping_add(off as u32);
+            let op = half(off);
+            let next = if off + 4 <= code.len() {
+                half(off + 2)
+            } else {
+                0
+            };
+            let long = extract(op, 11, 5) == 0b11110 && extract(next, 11, 5) == 0b11111;
+            let (opcode, size) = if long { (op << 16 | next, 4) } else { (op, 2) };
+            lines.push(Line {
+                addr: pc,
+                opcode: opcode,
+                size: size,
+                text: disasm_thumb(op as u16, next as u16, pc),
+                target: thumb_target(op, next, pc),
+                literal: thumb_literal(op, pc),
+            });
+            off += size as usize;
+        }
+    } else {
+        while off + 4 <= code.len() {
+            let pc = base.wrapping_add(off as u32);
+            let op = half(off) | half(off + 2) << 16;
+            lines.push(Line {
+                addr: pc,
+                opcode: op,
+                size: 4,
+                text: disasm_arm(op, pc),
+                target: arm_target(op, pc),
+                literal: arm_literal(op, pc),
+            });
+            off += 4;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arm() {
+        assert_eq!("mov r0, #0x4000000", disasm_arm(0xe3a0_0301, 0));
+        assert_eq!("addnes r1, r2, r3, lsl #2", disasm_arm(0x1092_1103, 0));
+        assert_eq!("bx lr", disasm_arm(0xe12f_ff1e, 0));
+        assert_eq!("bl 0x08000100", disasm_arm(0xeb00_003e, 0x0800_0000));
+        assert_eq!("ldr r0, [r1, #-0x4]!", disasm_arm(0xe531_0004, 0));
+        assert_eq!("stmdb sp!, {r4, r5, lr}", disasm_arm(0xe92d_4030, 0));
+        assert_eq!("ldrsh r0, [r1, #0x2]", disasm_arm(0xe1d1_00f2, 0));
+        assert_eq!("msr cpsr_c, r0", disasm_arm(0xe121_f000, 0));
+    }
+
+    #[test]
+    fn test_thumb() {
+        assert_eq!("mov r0, #0x10", disasm_thumb(0x2010, 0, 0));
+        assert_eq!("add r0, r1, #1", disasm_thumb(0x1c48, 0, 0));
+        assert_eq!("bx lr", disasm_thumb(0x4770, 0, 0));
+        assert_eq!("push {r4, lr}", disasm_thumb(0xb510, 0, 0));
+        assert_eq!("bne 0x08000000", disasm_thumb(0xd1fe, 0, 0x0800_0000));
+        assert_eq!("bl 0x08000104", disasm_thumb(0xf000, 0xf880, 0x0800_0000));
+    }
+
+    #[test]
+    fn test_range() {
+        // ldr r0, [pc, #0x4]; bl; b .
+        let code = [0x01, 0x48, 0x00, 0xf0, 0x80, 0xf8, 0xfe, 0xe7, 0xff];
+        let lines = disasm_range(&code, 0x0800_0000, true);
+        assert_eq!(3, lines.len());
+        assert_eq!(Some(0x0800_0008), lines[0].literal);
+        assert_eq!((0x0800_0002, 4), (lines[1].addr, lines[1].size));
+        assert_eq!(0xf000_f880, lines[1].opcode);
+        assert_eq!(Some(0x0800_0106), lines[1].target);
+        assert_eq!(Some(0x0800_0006), lines[2].target);
+
+        // ldr r0, [pc, #-0x8]; b .
+        let code = [0x08, 0x00, 0x1f, 0xe5, 0xfe, 0xff, 0xff, 0xea];
+        let lines = disasm_range(&code, 0x0800_0000, false);
+        assert_eq!(Some(0x0800_0000), lines[0].literal);
+        assert_eq!("b 0x08000004", lines[1].text);
+        assert_eq!(Some(0x0800_0004), lines[1].target);
+    }
+}
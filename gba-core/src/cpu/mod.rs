@@ -0,0 +1,377 @@
+use std::mem;
+
+use arm7tdmi_rs::{exception::Exception, reg::Reg, Cpu as Arm7TDMICpu, Memory};
+use log::Level;
+use shared::*;
+
+use mmu::MemoryUnit;
+use symbols::Symbols;
+
+use self::breakpoint::{Breakpoint, Operand};
+use self::calls::CallStack;
+use self::coverage::Coverage;
+use self::trace::{Regs, Tracer};
+
+pub use arm7tdmi_rs::{exception, reg};
+
+pub mod breakpoint;
+pub mod calls;
+pub mod coverage;
+pub mod disasm;
+pub mod trace;
+
+#[derive(Serialize, Deserialize)]
+pub struct Cpu<T: MemoryUnit> {
+    cpu: Arm7TDMICpu,
+    #[serde(skip, default = "Default::default")]
+    mmu: Option<MemWrapper<Shared<T>>>,
+    /// Names for addresses in traces, not saved in states
+    #[serde(skip, default = "Default::default")]
+    symbols: Symbols,
+    /// Not saved in states either
+    #[serde(skip, default = "Default::default")]
+    breaks: Vec<Breakpoint>,
+    /// The breakpoint the CPU stopped at, if it did, and whether to run on
+    /// from it
+    #[serde(skip, default = "Default::default")]
+    stopped: Option<u32>,
+    #[serde(skip, default = "Default::default")]
+    resume: bool,
+    /// Only kept when asked for, as it costs a little every instruction
+    #[serde(skip, default = "Default::default")]
+    calls: Option<CallStack>,
+    #[serde(skip, default = "Default::default")]
+    tracer: Option<Tracer>,
+    #[serde(skip, default = "Default::default")]
+    coverage: Option<Coverage>,
+}
+
+struct MemWrapper<T>(T);
+
+impl<T: MemoryUnit> Memory for MemWrapper<Shared<T>> {
+    fn r8(&mut self, addr: u32) -> u8 {
+        self.0.wait(addr, 1);
+        self.0.log_read(addr, 1);
+        self.0.load8(addr)
+    }
+    fn r16(&mut self, addr: u32) -> u16 {
+        self.0.wait(addr, 2);
+        self.0.log_read(addr, 2);
+        self.0.load16(addr)
+    }
+    fn r32(&mut self, addr: u32) -> u32 {
+        self.0.wait(addr, 4);
+        self.0.log_read(addr, 4);
+        self.0.load32(addr)
+    }
+    fn w8(&mut self, addr: u32, val: u8) {
+        self.0.wait(addr, 1);
+        self.0.set8(addr, val)
+    }
+    fn w16(&mut self, addr: u32, val: u16) {
+        self.0.wait(addr, 2);
+        self.0.set16(addr, val)
+    }
+    fn w32(&mut self, addr: u32, val: u32) {
+        self.0.wait(addr, 4);
+        self.0.set32(addr, val)
+    }
+}
+
+impl<T: MemoryUnit> Cpu<T> {
+    pub fn new<'a, I>(mmu: Shared<T>, regs: I) -> Self
+    where
+        I: IntoIterator<Item = &'a (usize, Reg, u32)>,
+    {
+        Cpu {
+            cpu: (Arm7TDMICpu::new(regs)),
+            mmu: Some(MemWrapper(mmu)),
+            symbols: Default::default(),
+            breaks: Vec::new(),
+            stopped: None,
+            resume: false,
+            calls: None,
+            tracer: None,
+            coverage: None,
+        }
+    }
+
+    pub fn set_mmu(&mut self, mmu: Shared<T>) {
+        self.mmu = Some(MemWrapper(mmu));
+    }
+
+    /// Initializes registers according to the ARM documentation
+    pub fn init_arm(&mut self) {
+        // http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.faqs/ka3761.html
+        self.init(&[(0, reg::PC, 0), (0, reg::CPSR, 0xd3)]);
+    }
+
+    /// Initializes the registers to emulate booting through BIOS, to directly
+    /// start a ROM
+    pub fn init_direct(&mut self) {
+        self.init_entry(0x8000000);
+    }
+
+    /// Like `init_direct`, but for a multiboot image that has already been
+    /// transferred into EWRAM
+    pub fn init_multiboot(&mut self) {
+        self.init_entry(0x20000c0);
+    }
+
+    /// Like `init_direct`, but starting at `entry`, in Thumb state if its
+    /// low bit is set
+    pub fn init_entry(&mut self, entry: u32) {
+        let cpsr = if entry & 1 == 1 { 0x3f } else { 0x1f };
+        self.init(&[
+            (0, reg::PC, entry & !1),
+            (0, reg::CPSR, cpsr),
+            (0, reg::SP, 0x3007f00),
+            (2, reg::SP, 0x3007fa0),
+            (3, reg::SP, 0x3007fe0),
+        ]);
+    }
+
+    fn init<'a, I>(&mut self, regs: I)
+    where
+        I: IntoIterator<Item = &'a (usize, Reg, u32)>,
+    {
+        self.cpu = Arm7TDMICpu::new(regs)
+    }
+
+    pub fn set_breaks(&mut self, breaks: Vec<Breakpoint>) {
+        self.breaks = breaks;
+    }
+
+    pub fn breaks(&self) -> &[Breakpoint] {
+        &self.breaks
+    }
+
+    pub fn take_breaks(&mut self) -> Vec<Breakpoint> {
+        mem::replace(&mut self.breaks, Vec::new())
+    }
+
+    /// Lets the CPU run on from the breakpoint it stopped at, if it did
+    pub fn resume(&mut self) {
+        self.resume = self.stopped.is_some();
+    }
+
+    /// Forgets the breakpoint the CPU stopped at, as `break_hit` does once
+    /// there are no breakpoints
+    pub fn clear_stop(&mut self) {
+        self.stopped = None;
+        self.resume = false;
+    }
+
+    /// Whether the CPU should stop before the instruction at the PC, for a
+    /// breakpoint there whose conditions hold.  Counts the breakpoint's hits,
+    /// once each time the CPU arrives at it rather than while it waits there.
+    pub fn break_hit(&mut self) -> bool {
+        let pc = self.get_prefetch_addr();
+        if mem::replace(&mut self.resume, false) {
+            self.stopped = None;
+            return false;
+        }
+        if self.stopped == Some(pc) {
+            return true;
+        }
+        self.stopped = if self.check_breaks() { Some(pc) } else { None };
+        self.stopped.is_some()
+    }
+
+    fn check_breaks(&mut self) -> bool {
+        if self.breaks.is_empty() {
+            return false;
+        }
+        let pc = self.get_prefetch_addr();
+        let idx = match self.breaks.iter().position(|brk| brk.addr == pc) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        self.breaks[idx].hits += 1;
+        let brk = &self.breaks[idx];
+        brk.conds
+            .iter()
+            .all(|cond| cond.holds(|op| self.operand(op, brk.hits)))
+    }
+
+    fn operand(&self, op: Operand, hits: u32) -> u32 {
+        let mmu = &self.mmu.as_ref().unwrap().0;
+        match op {
+            Operand::Reg(reg) => self.reg(reg),
+            Operand::Mem(addr, 1) => mmu.load8(addr) as u32,
+            Operand::Mem(addr, 2) => mmu.load16(addr) as u32,
+            Operand::Mem(addr, _) => mmu.load32(addr),
+            Operand::Hits => hits,
+            Operand::Value(val) => val,
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn take_symbols(&mut self) -> Symbols {
+        mem::replace(&mut self.symbols, Default::default())
+    }
+
+    /// `addr` named by the symbol it's in, if there is one
+    pub fn location(&self, addr: u32) -> String {
+        self.symbols.name(addr)
+    }
+
+    /// Starts or stops keeping track of calls, for `calls`
+    pub fn track_calls(&mut self, track: bool) {
+        self.calls = if track {
+            Some(Default::default())
+        } else {
+            None
+        };
+    }
+
+    /// The calls the CPU is in, if they're being tracked
+    pub fn calls(&self) -> Option<&CallStack> {
+        self.calls.as_ref()
+    }
+
+    pub fn cycle(&mut self) -> bool {
+        if log_enabled!(Level::Trace) {
+            self.trace();
+        }
+        if self.calls.is_some() {
+            let pc = self.get_prefetch_addr();
+            let size = if self.thumb_mode() { 2 } else { 4 };
+            let lr = self.reg(reg::LR);
+            self.calls.as_mut().unwrap().enter(pc, size, lr);
+        }
+        let pc = self.get_prefetch_addr();
+        self.mmu.as_ref().unwrap().0.log_exec(pc, self.thumb_mode());
+        if self.coverage.is_some() {
+            let thumb = self.thumb_mode();
+            let mmu = &self.mmu.as_ref().unwrap().0;
+            let op = if thumb {
+                mmu.load16(pc) as u32
+            } else {
+                mmu.load32(pc)
+            };
+            self.coverage.as_mut().unwrap().count(op, thumb);
+        }
+        match self.tracer {
+            Some(ref tracer) if tracer.wants(pc) => (),
+            _ => return self.cpu.cycle(self.mmu.as_mut().unwrap()),
+        }
+        let thumb = self.thumb_mode();
+        let (op, text) = self.disasm(pc);
+        let before = self.regs();
+        let ok = self.cpu.cycle(self.mmu.as_mut().unwrap());
+        let after = self.regs();
+        match self
+            .tracer
+            .as_mut()
+            .unwrap()
+            .write(pc, op, thumb, &text, &before, &after)
+        {
+            Ok(true) => (),
+            Ok(false) => {
+                info!("Trace finished");
+                self.tracer = None;
+            }
+            Err(err) => {
+                error!("Trace failed to write, stopping it: {}", err);
+                self.tracer = None;
+            }
+        }
+        ok
+    }
+
+    /// Starts writing each instruction run to `tracer`, or stops
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Starts or stops counting the instructions run
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Logs the instruction about to run
+    fn trace(&self) {
+        let pc = self.get_prefetch_addr();
+        let (_, text) = self.disasm(pc);
+        trace!("{}: {}", self.location(pc), text);
+    }
+
+    /// The instruction at `pc` in the CPU's state, and its disassembly
+    fn disasm(&self, pc: u32) -> (u32, String) {
+        let mmu = &self.mmu.as_ref().unwrap().0;
+        if self.thumb_mode() {
+            let op = mmu.load16(pc);
+            let text = disasm::disasm_thumb(op, mmu.load16(pc.wrapping_add(2)), pc);
+            (op as u32, text)
+        } else {
+            let op = mmu.load32(pc);
+            (op, disasm::disasm_arm(op, pc))
+        }
+    }
+
+    /// r0-r15 and the CPSR, as the CPU sees them in its current mode
+    fn regs(&self) -> Regs {
+        let mut regs = [0; 17];
+        for (r, val) in regs.iter_mut().enumerate().take(16) {
+            *val = self.reg(r as Reg);
+        }
+        regs[16] = self.reg(reg::CPSR);
+        regs
+    }
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn exception(&mut self, exc: &Exception) {
+        self.cpu.exception(*exc)
+    }
+
+    pub fn irq_enable(&self) -> bool {
+        self.cpu.irq_enable()
+    }
+
+    pub fn thumb_mode(&self) -> bool {
+        self.cpu.thumb_mode()
+    }
+
+    pub fn get_prefetch_addr(&self) -> u32 {
+        self.cpu.get_prefetch_addr()
+    }
+
+    /// The register bank the CPU's mode uses, numbered as in `init`
+    fn bank(&self) -> usize {
+        match self.cpu.reg_get(0, reg::CPSR) & 0x1f {
+            0x11 => 1,
+            0x12 => 2,
+            0x13 => 3,
+            0x17 => 4,
+            0x1b => 5,
+            // User and system mode
+            _ => 0,
+        }
+    }
+
+    /// `reg` as the CPU sees it in its current mode
+    pub fn reg(&self, reg: Reg) -> u32 {
+        self.cpu.reg_get(self.bank(), reg)
+    }
+
+    pub fn set_reg(&mut self, reg: Reg, val: u32) {
+        let bank = self.bank();
+        self.cpu.reg_set(bank, reg, val)
+    }
+}
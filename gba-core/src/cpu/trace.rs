@@ -0,0 +1,190 @@
+//! Execution traces, a line for each instruction the CPU runs written to a
+//! file, with the registers it changed:
+//!
+//! ```text
+//! 08000124 e3a00001 mov r0, #0x1               r0=00000001 nzCv
+//! 08000128     4708 bx r1                      cpsr=6000003f nZCv
+//! ```
+//!
+//! The flags are upper case when set.  Only instructions in the ranges the
+//! filter gives are traced, written `<start>-<end>` in hex or as a symbol
+//! name, and tracing stops after the filter's limit of lines.
+
+use std::io::{self, Write};
+
+use symbols::Symbols;
+
+use super::disasm;
+
+/// Register values before or after an instruction, r0-r15 then CPSR
+pub type Regs = [u32; 17];
+
+const CPSR: usize = 16;
+
+/// Which instructions to trace
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceFilter {
+    /// Start and end addresses, the end included.  Everything when empty.
+    pub ranges: Vec<(u32, u32)>,
+    /// How many instructions to trace before stopping
+    pub limit: Option<u64>,
+}
+
+impl TraceFilter {
+    /// Reads a range, `<start>-<end>` or a symbol covering its size
+    pub fn parse_range(s: &str, symbols: &Symbols) -> Result<(u32, u32), String> {
+        let hex = |s: &str| {
+            let digits = if s.starts_with("0x") { &s[2..] } else { s };
+            u32::from_str_radix(digits, 16).ok()
+        };
+        if let Some(idx) = s.find('-') {
+            return match (hex(&s[..idx]), hex(&s[idx + 1..])) {
+                (Some(start), Some(end)) if start <= end => Ok((start, end)),
+                _ => Err(format!("'{}' is not a range of hex addresses", s)),
+            };
+        }
+        match symbols.lookup(s) {
+            Some(sym) => Ok((sym.addr, sym.addr + sym.size.max(1) - 1)),
+            None => Err(format!("'{}' is not a range or symbol", s)),
+        }
+    }
+
+    fn contains(&self, pc: u32) -> bool {
+        self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| start <= pc && pc <= end)
+    }
+}
+
+pub struct Tracer {
+    out: Box<Write>,
+    filter: TraceFilter,
+    /// Lines written so far
+    count: u64,
+}
+
+impl Tracer {
+    pub fn new(out: Box<Write>, filter: TraceFilter) -> Self {
+        Tracer {
+            out: out,
+            filter: filter,
+            count: 0,
+        }
+    }
+
+    /// Whether the instruction at `pc` should be traced
+    pub fn wants(&self, pc: u32) -> bool {
+        self.filter.contains(pc)
+    }
+
+    /// Writes the line for an instruction, returns false once the limit is
+    /// reached and the trace is finished
+    pub fn write(
+        &mut self,
+        pc: u32,
+        op: u32,
+        thumb: bool,
+        text: &str,
+        before: &Regs,
+        after: &Regs,
+    ) -> io::Result<bool> {
+        writeln!(self.out, "{}", line(pc, op, thumb, text, before, after))?;
+        self.count += 1;
+        if self.filter.limit.map_or(false, |limit| self.count >= limit) {
+            self.out.flush()?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+fn flags(cpsr: u32) -> String {
+    [(31, 'n'), (30, 'z'), (29, 'c'), (28, 'v')]
+        .iter()
+        .map(|&(bit, flag)| {
+            if cpsr >> bit & 1 == 1 {
+                flag.to_ascii_uppercase()
+            } else {
+                flag
+            }
+        })
+        .collect()
+}
+
+fn line(pc: u32, op: u32, thumb: bool, text: &str, before: &Regs, after: &Regs) -> String {
+    let op = if thumb {
+        format!("{:>8x}", op & 0xffff)
+    } else {
+        format!("{:08x}", op)
+    };
+    // The PC changes every time, and the next line shows where it went
+    let mut changed: Vec<String> = (0..15)
+        .chain(Some(CPSR))
+        .filter(|&r| before[r] != after[r])
+        .map(|r| {
+            let name = if r == CPSR {
+                "cpsr"
+            } else {
+                disasm::reg_name(r as u32)
+            };
+            format!("{}={:08x}", name, after[r])
+        })
+        .collect();
+    changed.push(flags(after[CPSR]));
+    format!("{:08x} {} {:<26} {}", pc, op, text, changed.join(" "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use symbols::Symbol;
+
+    #[test]
+    fn test_line() {
+        let before = [0; 17];
+        let mut after = before;
+        after[0] = 1;
+        after[CPSR] = 0x2000_001f;
+        assert_eq!(
+            "08000124 e3a00001 mov r0, #0x1               r0=00000001 cpsr=2000001f nzCv",
+            line(
+                0x0800_0124,
+                0xe3a0_0001,
+                false,
+                "mov r0, #0x1",
+                &before,
+                &after
+            )
+        );
+        assert_eq!(
+            "08000128     4708 bx r1                      nzcv",
+            line(0x0800_0128, 0x4708, true, "bx r1", &before, &before)
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let symbols = Symbols::new(vec![Symbol {
+            name: "main".to_string(),
+            addr: 0x0800_01f0,
+            size: 0x10,
+            thumb: true,
+        }]);
+        let parse = |s: &str| TraceFilter::parse_range(s, &symbols);
+        assert_eq!(Ok((0x0800_0000, 0x0800_00ff)), parse("8000000-0x80000ff"));
+        assert_eq!(Ok((0x0800_01f0, 0x0800_01ff)), parse("main"));
+        assert!(parse("8000100-8000000").is_err());
+        assert!(parse("nowhere").is_err());
+
+        let filter = TraceFilter {
+            ranges: vec![(0x0800_0000, 0x0800_00ff)],
+            limit: None,
+        };
+        assert!(filter.contains(0x0800_00ff));
+        assert!(!filter.contains(0x0800_0100));
+        assert!(TraceFilter::default().contains(0));
+    }
+}
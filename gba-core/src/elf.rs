@@ -0,0 +1,261 @@
+//! ELF executables, as devkitARM and other gcc toolchains link them before
+//! `objcopy` turns them into a ROM.
+//!
+//! Running the ELF directly keeps its symbol table, so breakpoints and traces
+//! can name functions rather than addresses that move every build.  The
+//! loadable segments are laid out by their load address into a cartridge
+//! image, or an EWRAM image for multiboot programs, which the start-up code
+//! copies its IWRAM sections out of as it would on hardware.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use symbols::{Symbol, Symbols};
+
+const MAGIC: &[u8] = b"\x7fELF";
+const EM_ARM: u16 = 40;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// Where programs are loaded, with the most they can hold
+const ROM_BASE: u32 = 0x0800_0000;
+const ROM_MAX: u32 = 32 * 1024 * 1024;
+const EWRAM_BASE: u32 = 0x0200_0000;
+const EWRAM_MAX: u32 = 256 * 1024;
+
+/// Bytes to place at an address
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: Symbols,
+}
+
+fn slice(data: &[u8], off: u32, len: u32) -> Result<&[u8], String> {
+    let (off, len) = (off as usize, len as usize);
+    match off.checked_add(len) {
+        Some(end) if end <= data.len() => Ok(&data[off..end]),
+        _ => Err("file ends unexpectedly".to_string()),
+    }
+}
+
+/// The NUL terminated string at `off` in a string table
+fn string(table: &[u8], off: u32) -> String {
+    table
+        .get(off as usize..)
+        .unwrap_or(&[])
+        .iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| b as char)
+        .collect()
+}
+
+impl Elf {
+    pub fn parse(data: &[u8]) -> Result<Elf, String> {
+        let header = slice(data, 0, 52)?;
+        if &header[..4] != MAGIC {
+            return Err("not an ELF file".to_string());
+        }
+        // 32 bit, little endian
+        if header[4] != 1 || header[5] != 1 || LittleEndian::read_u16(&header[18..]) != EM_ARM {
+            return Err("not a 32 bit little endian ARM executable".to_string());
+        }
+        let entry = LittleEndian::read_u32(&header[24..]);
+        let (phoff, shoff) = (
+            LittleEndian::read_u32(&header[28..]),
+            LittleEndian::read_u32(&header[32..]),
+        );
+        let (phentsize, phnum) = (
+            LittleEndian::read_u16(&header[42..]) as u32,
+            LittleEndian::read_u16(&header[44..]) as u32,
+        );
+        let (shentsize, shnum) = (
+            LittleEndian::read_u16(&header[46..]) as u32,
+            LittleEndian::read_u16(&header[48..]) as u32,
+        );
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let ph = slice(data, phoff.wrapping_add(i * phentsize), 32)?;
+            let field = |idx: usize| LittleEndian::read_u32(&ph[idx * 4..]);
+            // Segments only in memory, like .bss, are cleared by the
+            // start-up code
+            if field(0) != PT_LOAD || field(4) == 0 {
+                continue;
+            }
+            // The physical address is where the segment is loaded, which
+            // for IWRAM code is in the ROM it's copied from
+            segments.push(Segment {
+                addr: field(3),
+                data: slice(data, field(1), field(4))?.to_vec(),
+            });
+        }
+
+        let mut symbols = Vec::new();
+        for i in 0..shnum {
+            let sh = slice(data, shoff.wrapping_add(i * shentsize), 40)?;
+            let field = |idx: usize| LittleEndian::read_u32(&sh[idx * 4..]);
+            if field(1) != SHT_SYMTAB {
+                continue;
+            }
+            let table = slice(data, field(4), field(5))?;
+            let strtab = slice(
+                data,
+                shoff.wrapping_add(field(6).wrapping_mul(shentsize)),
+                40,
+            )?;
+            let strings = slice(
+                data,
+                LittleEndian::read_u32(&strtab[16..]),
+                LittleEndian::read_u32(&strtab[20..]),
+            )?;
+            for sym in table.chunks(16).filter(|sym| sym.len() == 16) {
+                let name = string(strings, LittleEndian::read_u32(sym));
+                let (value, size) = (
+                    LittleEndian::read_u32(&sym[4..]),
+                    LittleEndian::read_u32(&sym[8..]),
+                );
+                let kind = sym[12] & 0xf;
+                let defined = LittleEndian::read_u16(&sym[14..]) != 0;
+                // ARM's mapping symbols, `$a` and friends, only mark where
+                // code and data start
+                if name.is_empty() || name.starts_with('$') || !defined {
+                    continue;
+                }
+                if kind != STT_NOTYPE && kind != STT_OBJECT && kind != STT_FUNC {
+                    continue;
+                }
+                // Thumb function addresses have the low bit set, as they
+                // would to `bx` to them
+                let thumb = kind == STT_FUNC && value & 1 == 1;
+                symbols.push(Symbol {
+                    name: name,
+                    addr: if thumb { value & !1 } else { value },
+                    size: size,
+                    thumb: thumb,
+                });
+            }
+        }
+
+        Ok(Elf {
+            entry: entry,
+            segments: segments,
+            symbols: Symbols::new(symbols),
+        })
+    }
+
+    /// Whether the program runs from EWRAM, as multiboot programs do,
+    /// rather than from a cartridge
+    pub fn multiboot(&self) -> bool {
+        self.entry >> 24 == EWRAM_BASE >> 24
+    }
+
+    /// The segments laid out as a ROM, or a multiboot image to load at the
+    /// start of EWRAM
+    pub fn image(&self) -> Result<Vec<u8>, String> {
+        let (base, max) = if self.multiboot() {
+            (EWRAM_BASE, EWRAM_MAX)
+        } else {
+            (ROM_BASE, ROM_MAX)
+        };
+        let mut image = Vec::new();
+        for segment in self.segments.iter() {
+            let off = segment.addr.wrapping_sub(base);
+            let end = off as u64 + segment.data.len() as u64;
+            if segment.addr < base || end > max as u64 {
+                return Err(format!(
+                    "segment at {:#010x} is outside the memory the program runs from",
+                    segment.addr
+                ));
+            }
+            let (off, end) = (off as usize, end as usize);
+            if image.len() < end {
+                image.resize(end, 0);
+            }
+            image[off..end].copy_from_slice(&segment.data);
+        }
+        if image.is_empty() {
+            return Err("no segments to load".to_string());
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push32(out: &mut Vec<u8>, vals: &[u32]) {
+        for &val in vals {
+            let mut buf = [0; 4];
+            LittleEndian::write_u32(&mut buf, val);
+            out.extend_from_slice(&buf);
+        }
+    }
+
+    /// An ELF with a code segment, an IWRAM segment loaded after it and
+    /// symbols for each
+    fn build_elf() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+        // An ARM executable, then the version, entry, where the program and
+        // section headers are and flags
+        data.extend_from_slice(&[2, 0, 40, 0]);
+        push32(&mut data, &[1, 0x0800_0000, 52, 116, 0]);
+        data.extend_from_slice(&[52, 0, 32, 0, 2, 0, 40, 0, 3, 0, 0, 0]);
+        // Program headers at 52, the second with .bss after its data
+        push32(
+            &mut data,
+            &[PT_LOAD, 236, 0x0800_0000, 0x0800_0000, 8, 8, 5, 4],
+        );
+        push32(
+            &mut data,
+            &[PT_LOAD, 244, 0x0300_0000, 0x0800_0008, 4, 16, 6, 4],
+        );
+        // Section headers at 116: null, the symbol table and its strings
+        push32(&mut data, &[0; 10]);
+        push32(&mut data, &[0, SHT_SYMTAB, 0, 0, 248, 64, 2, 0, 4, 16]);
+        push32(&mut data, &[0, 3, 0, 0, 312, 27, 0, 0, 1, 0]);
+        // Segment contents at 236
+        push32(&mut data, &[0xe3a0_0001, 0xeaff_fffe, 0x1234_5678]);
+        // Symbols at 248: null, a Thumb function, an object and `$a`
+        push32(&mut data, &[0; 4]);
+        push32(&mut data, &[1, 0x0800_0005, 4, 0x0001_0012]);
+        push32(&mut data, &[10, 0x0300_0000, 16, 0x0002_0011]);
+        push32(&mut data, &[24, 0x0800_0000, 0, 0x0001_0000]);
+        data.extend_from_slice(b"\0AgbMain\0\0counters\0\0\0\0\0\0$a\0");
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let elf = Elf::parse(&build_elf()).unwrap();
+        assert_eq!(0x0800_0000, elf.entry);
+        assert!(!elf.multiboot());
+        let image = elf.image().unwrap();
+        assert_eq!(12, image.len());
+        assert_eq!(0x1234_5678, LittleEndian::read_u32(&image[8..]));
+
+        let main = elf.symbols.lookup("AgbMain").unwrap();
+        assert_eq!((0x0800_0004, true), (main.addr, main.thumb));
+        assert!(elf.symbols.lookup("$a").is_none());
+        let (sym, off) = elf.symbols.containing(0x0300_0006).unwrap();
+        assert_eq!(("counters", 6), (sym.name.as_str(), off));
+        assert!(elf.symbols.containing(0x0300_0010).is_none());
+        assert!(elf.symbols.containing(0x0200_0000).is_none());
+
+        assert!(Elf::parse(b"PATCH").is_err());
+        let mut data = build_elf();
+        data.truncate(200);
+        assert!(Elf::parse(&data).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! `GbaCore`, a `Gba` wrapped up for embedding in something other than this
+//! crate's SDL frontend, e.g. a libretro core or a test harness.  The caller
+//! steps it as it likes, reads frames and sound out and feeds buttons in.
+//!
+//! ```
+//! use std::path::Path;
+//!
+//! use gba_core::rom::GameRom;
+//! use gba_core::{Buttons, GbaCore, Options};
+//!
+//! let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tiny.gba");
+//! let rom = GameRom::new(Path::new(path)).unwrap();
+//! let opts = Options {
+//!     direct_boot: true,
+//!     ..Default::default()
+//! };
+//! let mut core = GbaCore::new(rom, Default::default(), &opts);
+//!
+//! core.set_buttons(Buttons {
+//!     a: true,
+//!     ..Default::default()
+//! });
+//! let state = core.save_state();
+//! for _ in 0..2 {
+//!     assert!(core.step_frame());
+//! }
+//! // tiny.s draws a red pixel
+//! let pixel = core.framebuffer()[0];
+//! assert!(pixel >> 16 & 0xff > 0 && pixel & 0xffff == 0);
+//!
+//! let mut samples = Vec::new();
+//! core.audio_drain(&mut samples);
+//! assert!(!samples.is_empty());
+//!
+//! core.load_state(&state).unwrap();
+//! ```
+
+use bincode;
+use byteorder::{ByteOrder, LittleEndian};
+
+use gba::{Gba, Options, CYCLES_PER_FRAME};
+use io::key::{Buttons, InputProvider};
+use io::ppu::{COLS, ROWS};
+use io::spu::SoundBuf;
+use rom::GameRom;
+
+pub struct GbaCore {
+    gba: Box<Gba<'static>>,
+    audio: SoundBuf,
+    /// Samples drained from the SPU, before they're converted
+    samples: Vec<f32>,
+    framebuffer: Vec<u32>,
+    /// The frame `framebuffer` was copied at the end of
+    frame: u64,
+}
+
+impl GbaCore {
+    /// Starts `rom`, booting through `bios` unless `opts` says to skip it
+    pub fn new(rom: GameRom, bios: GameRom, opts: &Options) -> Self {
+        let mut gba = Gba::new(rom, bios, opts);
+        GbaCore {
+            audio: gba.spu.tap(),
+            gba: gba,
+            samples: Vec::new(),
+            framebuffer: vec![0; (COLS * ROWS) as usize],
+            frame: 0,
+        }
+    }
+
+    /// Runs one instruction, or while halted skips to the next event.
+    /// Returns false if the CPU stopped at a breakpoint or watchpoint.
+    pub fn step_instruction(&mut self) -> bool {
+        let ok = self.gba.cycle();
+        self.update_framebuffer();
+        ok
+    }
+
+    /// Runs to the end of the frame, returning false if the CPU stopped at a
+    /// breakpoint or watchpoint first
+    pub fn step_frame(&mut self) -> bool {
+        let ok = self.gba.run_frame();
+        self.update_framebuffer();
+        ok
+    }
+
+    /// The last complete frame, 240x160 pixels row by row as 0x00RRGGBB
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.framebuffer
+    }
+
+    fn update_framebuffer(&mut self) {
+        let frame = self.gba.now() / CYCLES_PER_FRAME;
+        if frame != self.frame {
+            LittleEndian::read_u32_into(self.gba.frame(), &mut self.framebuffer);
+            self.frame = frame;
+        }
+    }
+
+    /// Appends the sound produced since the last call to `out`, as
+    /// interleaved left and right samples at 32768Hz.  Only a few frames are
+    /// buffered, so this has to be called every frame or so.
+    pub fn audio_drain(&mut self, out: &mut Vec<i16>) {
+        self.samples.clear();
+        self.audio.drain(&mut self.samples);
+        out.extend(
+            self.samples
+                .iter()
+                .map(|&s| (s * i16::max_value() as f32) as i16),
+        );
+    }
+
+    /// Holds down `buttons` and releases the rest, until the next call
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.gba.io.set_keyreg(&buttons);
+    }
+
+    /// Holds down the buttons `input` gives, e.g. once before each frame
+    pub fn poll_input(&mut self, input: &mut InputProvider) {
+        self.gba.io.poll_input(input);
+    }
+
+    /// The whole emulated state, for `load_state`.  The ROM and BIOS aren't
+    /// included, so it can only be loaded into a core running the same ones.
+    pub fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(&*self.gba).unwrap()
+    }
+
+    /// Goes back to a state from `save_state`.  States don't hold the frame,
+    /// so `framebuffer` keeps the current one until the next is drawn.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let state = bincode::deserialize(state).map_err(|err| err.to_string())?;
+        self.gba.restore(state);
+        self.frame = self.gba.now() / CYCLES_PER_FRAME;
+        Ok(())
+    }
+
+    /// The hardware, for anything not covered here, e.g. reading memory or
+    /// setting breakpoints
+    pub fn gba(&self) -> &Gba<'static> {
+        &self.gba
+    }
+
+    pub fn gba_mut(&mut self) -> &mut Gba<'static> {
+        &mut self.gba
+    }
+}
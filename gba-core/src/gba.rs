@@ -0,0 +1,583 @@
+use std::boxed::Box;
+use std::default::Default;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::result::Result;
+
+use serde::de;
+use serde::de::{SeqAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use shared::Shared;
+
+use counts::CycleCounts;
+use cpu::breakpoint::{BreakEvent, Breakpoint};
+use cpu::exception::Exception;
+use cpu::Cpu;
+use io::ppu::Ppu;
+use io::sio::Link;
+use io::spu::Spu;
+use io::IoReg;
+use mmu::gba::Gba as GbaMmu;
+use mmu::watch::{WatchHit, Watchpoint};
+use mmu::MemoryUnit;
+use rom::{Backup, GameRom, RomPatch};
+use scheduler::Event;
+use symbols::Symbols;
+
+pub const CYCLES_PER_SEC: u64 = 16 * 1024 * 1024;
+pub const CYCLES_PER_FRAME: u64 = 280896;
+
+/// Settings that change how the hardware is set up
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub breaks: Vec<Breakpoint>,
+    pub watches: Vec<Watchpoint>,
+    pub break_events: Vec<BreakEvent>,
+    pub direct_boot: bool,
+    pub multiboot: bool,
+    pub sio_loopback: bool,
+    pub rom_patches: Vec<RomPatch>,
+    /// Development options, a larger EWRAM and extra RAM at 0x01000000, in
+    /// bytes.  Hardware has 256K of EWRAM and no debug RAM.
+    pub ewram_size: Option<usize>,
+    pub debug_ram_size: Option<usize>,
+    /// Make the cartridge look like a flashcart, for games that check
+    pub flashcart: bool,
+    /// The byte work RAM starts filled with.  Hardware's is left however it
+    /// powered up, so games shouldn't depend on it.
+    pub ram_fill: u8,
+    /// Overrides the backup type found in the ROM
+    pub backup: Option<Backup>,
+    /// The address of a loop the game waits in for an interrupt or VCOUNT,
+    /// which is skipped through to the next event
+    pub idle_loop: Option<u32>,
+    /// Where to start running, skipping the BIOS, e.g. an ELF's entry point
+    pub entry: Option<u32>,
+    /// Names for addresses in traces and breakpoint hits
+    pub symbols: Symbols,
+}
+
+/// Parent container for all components of the system
+pub struct Gba<'a> {
+    pub cpu: Cpu<GbaMmu<'a>>,
+    pub mmu: GbaMmu<'a>,
+    pub io: IoReg<'a>,
+    pub ppu: Ppu<'a>,
+    pub spu: Spu<'a>,
+    idle_loop: Option<u32>,
+    /// Accesses watchpoints caught, not saved in states
+    watch_hits: Vec<WatchHit>,
+    /// Only kept when asked for, by benchmarks
+    counts: Option<CycleCounts>,
+}
+
+impl<'a> Gba<'a> {
+    pub fn new(rom: GameRom, bios: GameRom, opts: &Options) -> Box<Self> {
+        // With multiboot there is no cartridge, the ROM is an image to run
+        // from EWRAM
+        let (cart, image) = if opts.multiboot {
+            (Default::default(), Some(rom))
+        } else {
+            (rom, None)
+        };
+        let mut mmu = GbaMmu::new(cart, bios);
+        mmu.extend_memory(opts.ewram_size, opts.debug_ram_size);
+        mmu.fill_work_ram(opts.ram_fill);
+        if let Some(image) = image {
+            if let Err(err) = mmu.load_multiboot(&image) {
+                error!("Failed to load multiboot image: {}", err);
+            }
+        }
+
+        let mut gba = Box::new(Gba {
+            cpu: Cpu::new(Shared::empty(), &[]),
+            mmu: mmu,
+            io: IoReg::new(),
+            ppu: Ppu::new(),
+            spu: Spu::new(),
+            idle_loop: opts.idle_loop,
+            watch_hits: Vec::new(),
+            counts: None,
+        });
+        gba.connect();
+
+        gba.mmu.rom.set_flashcart(opts.flashcart);
+        gba.mmu.rom.set_backup(opts.backup);
+        for &patch in opts.rom_patches.iter() {
+            gba.mmu.rom.apply_patch(patch);
+        }
+        gba.mmu.map_pages();
+
+        if let Some(entry) = opts.entry {
+            gba.cpu.init_entry(entry);
+            gba.io.init_direct();
+        } else if opts.multiboot {
+            // The BIOS can't boot without a cartridge, so skip the serial
+            // handshake and start the image directly
+            gba.cpu.init_multiboot();
+            gba.io.init_direct();
+        } else if opts.direct_boot {
+            gba.cpu.init_direct();
+            gba.io.init_direct();
+        } else {
+            gba.cpu.init_arm();
+        }
+        gba.cpu.set_breaks(opts.breaks.clone());
+        gba.mmu.set_watches(opts.watches.clone());
+        gba.io.set_break_events(opts.break_events.clone());
+        gba.cpu.set_symbols(opts.symbols.clone());
+
+        gba.io.set_link(if opts.sio_loopback {
+            Link::Loopback
+        } else {
+            Link::Disconnected
+        });
+
+        gba.io.scheduler_mut().schedule(Event::Ppu, 0);
+        gba.io.scheduler_mut().schedule(Event::Spu, 0);
+
+        gba
+    }
+
+    /// Points the components at each other.  The links are raw pointers, so
+    /// this has to be redone whenever the `Gba` is moved, e.g. after being
+    /// deserialized and boxed.
+    pub fn connect(&mut self) {
+        let cpu = Shared::new(&mut self.cpu);
+        let mmu = Shared::new(&mut self.mmu);
+        let io = Shared::new(&mut self.io);
+        let ppu = Shared::new(&mut self.ppu);
+        self.cpu.set_mmu(mmu);
+        self.mmu.init(cpu, io);
+        self.io.init(cpu, mmu, ppu);
+        self.ppu.init(io, mmu);
+        self.spu.init(io);
+    }
+
+    /// Assembles a core from separately deserialized components, which
+    /// still has to be boxed and connected, or passed to `restore`
+    pub fn from_parts(
+        cpu: Cpu<GbaMmu<'a>>,
+        mmu: GbaMmu<'a>,
+        io: IoReg<'a>,
+        ppu: Ppu<'a>,
+        spu: Spu<'a>,
+    ) -> Gba<'a> {
+        Gba {
+            cpu: cpu,
+            mmu: mmu,
+            io: io,
+            ppu: ppu,
+            spu: spu,
+            idle_loop: None,
+            watch_hits: Vec::new(),
+            counts: None,
+        }
+    }
+
+    /// Replaces the emulated state with `state`, e.g. one read from a save
+    /// state.  The ROM, BIOS, serial link, audio output, video sinks,
+    /// breakpoints, watchpoints, events to break on, symbols, trace,
+    /// code/data log, instruction and cycle counts and debug output registers
+    /// aren't saved, so they carry over from this one.
+    ///
+    /// ```
+    /// # extern crate bincode;
+    /// # extern crate gba_core;
+    /// # use std::path::Path;
+    /// # use gba_core::mmu::MemoryUnit;
+    /// # use gba_core::rom::GameRom;
+    /// # use gba_core::{Gba, Options};
+    /// # fn main() {
+    /// # let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tiny.gba");
+    /// # let rom = GameRom::new(Path::new(path)).unwrap();
+    /// # let opts = Options { direct_boot: true, ..Default::default() };
+    /// let mut gba = Gba::new(rom, Default::default(), &opts);
+    /// gba.run_frame();
+    ///
+    /// let state = bincode::serialize(&*gba).unwrap();
+    /// let count = gba.mmu.load32(0x0300_0000);
+    /// gba.run_frame();
+    /// assert_ne!(count, gba.mmu.load32(0x0300_0000));
+    ///
+    /// gba.restore(bincode::deserialize(&state).unwrap());
+    /// assert_eq!(count, gba.mmu.load32(0x0300_0000));
+    /// # }
+    /// ```
+    pub fn restore(&mut self, mut state: Gba<'a>) {
+        mem::swap(&mut state.mmu.rom, &mut self.mmu.rom);
+        mem::swap(&mut state.mmu.bios, &mut self.mmu.bios);
+        state.spu.swap_output(&mut self.spu);
+        state.ppu.swap_sinks(&mut self.ppu);
+        state.cpu.set_breaks(self.cpu.take_breaks());
+        state.mmu.set_watches(self.mmu.take_watches());
+        state.io.set_break_events(self.io.take_break_events());
+        state.watch_hits = mem::replace(&mut self.watch_hits, Vec::new());
+        state.cpu.set_symbols(self.cpu.take_symbols());
+        // Calls made before the state was saved can't be known
+        state.cpu.track_calls(self.cpu.calls().is_some());
+        state.cpu.set_tracer(self.cpu.take_tracer());
+        state.cpu.set_coverage(self.cpu.take_coverage());
+        state.mmu.set_cdl(self.mmu.take_cdl());
+        state.counts = self.counts.take();
+        state.idle_loop = self.idle_loop;
+        state.io.set_link(self.io.link());
+        state.io.take_debug(&mut self.io);
+        *self = state;
+        self.connect();
+    }
+
+    pub fn breaks(&self) -> &[Breakpoint] {
+        self.cpu.breaks()
+    }
+
+    /// Stops the CPU when it reaches any of `breaks` with their conditions
+    /// holding
+    pub fn set_breaks(&mut self, breaks: &[Breakpoint]) {
+        self.cpu.set_breaks(breaks.to_vec());
+    }
+
+    pub fn watches(&self) -> &[Watchpoint] {
+        self.mmu.watches()
+    }
+
+    /// Notes the CPU's and DMA's accesses to any of `watches`
+    pub fn set_watches(&mut self, watches: &[Watchpoint]) {
+        self.mmu.set_watches(watches.to_vec());
+    }
+
+    pub fn break_events(&self) -> &[BreakEvent] {
+        self.io.break_events()
+    }
+
+    /// Stops the CPU after any of `events` happen
+    pub fn set_break_events(&mut self, events: &[BreakEvent]) {
+        self.io.set_break_events(events.to_vec());
+    }
+
+    /// The number of the SWI the CPU is about to execute, if it is
+    fn swi_number(&self) -> Option<u8> {
+        let pc = self.cpu.get_prefetch_addr();
+        if self.cpu.thumb_mode() {
+            let op = self.mmu.load16(pc);
+            if op & 0xff00 == 0xdf00 {
+                return Some(op as u8);
+            }
+        } else {
+            let op = self.mmu.load32(pc);
+            if op & 0x0f00_0000 == 0x0f00_0000 {
+                return Some((op >> 16) as u8);
+            }
+        }
+        None
+    }
+
+    /// The accesses watchpoints caught since the last call, oldest first
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        mem::replace(&mut self.watch_hits, Vec::new())
+    }
+
+    /// Starts or stops counting where cycles go, for `cycle_counts`
+    pub fn count_cycles(&mut self, count: bool) {
+        self.counts = if count {
+            Some(Default::default())
+        } else {
+            None
+        };
+    }
+
+    pub fn cycle_counts(&self) -> Option<&CycleCounts> {
+        self.counts.as_ref()
+    }
+
+    /// The last frame drawn by the PPU, as RGB888 pixels in little endian u32s
+    pub fn frame(&self) -> &[u8] {
+        self.ppu.frame()
+    }
+
+    /// The number of cycles emulated
+    #[inline]
+    pub fn now(&self) -> u64 {
+        self.io.scheduler().now()
+    }
+
+    /// Steps the CPU by one instruction and runs any events that are due,
+    /// returns false if the CPU stopped at a breakpoint, or after an event or
+    /// an access to a watchpoint it stops for.  Undefined instructions take
+    /// the undefined instruction exception, as some games expect the BIOS's
+    /// handler to run.  While the CPU is halted this skips straight to the
+    /// next event, as only an event can raise the interrupt that wakes it,
+    /// and each time round the idle loop does the same.
+    #[inline]
+    pub fn cycle(&mut self) -> bool {
+        // Time stands still while stopped, so no event or interrupt can move
+        // the CPU off the breakpoint
+        if !self.io.halted() && self.cpu.break_hit() {
+            let pc = self.cpu.get_prefetch_addr();
+            info!("Breakpoint hit at {}", self.cpu.location(pc));
+            return false;
+        }
+        let mut ok = true;
+        // Read before watchpoints start counting accesses
+        let swi = if self.io.break_events().is_empty() || self.io.halted() {
+            None
+        } else {
+            self.swi_number()
+        };
+        let start = self.now();
+        let halted = self.io.halted();
+        let mut ran = false;
+        let mut idle = 0;
+        let watching = self.mmu.watching();
+        let pc = if watching {
+            // Leaving out accesses made from outside, e.g. by a debugger
+            self.mmu.take_watch_hits();
+            self.cpu.get_prefetch_addr()
+        } else {
+            0
+        };
+        if self.io.halted() {
+            let next = self.io.scheduler().next();
+            self.io.scheduler_mut().skip_to(next);
+        } else {
+            if self.idle_loop.is_some() && self.idle_loop == Some(self.cpu.get_prefetch_addr()) {
+                let next = self.io.scheduler().next();
+                self.io.scheduler_mut().skip_to(next);
+                idle = self.now() - start;
+            }
+            if let Some(num) = swi {
+                self.io.note_event(BreakEvent::Swi(Some(num)));
+            }
+            ran = true;
+            self.execute();
+        }
+        self.settle();
+        if let Some(ref mut counts) = self.counts {
+            let taken = self.io.scheduler().now() - start - idle;
+            counts.idle += idle;
+            if halted {
+                counts.halted += taken;
+            } else {
+                counts.cpu += taken;
+                counts.instructions += ran as u64;
+            }
+        }
+        if watching {
+            for mut hit in self.mmu.take_watch_hits() {
+                hit.pc = pc;
+                ok &= !hit.stop;
+                self.watch_hits.push(hit);
+            }
+        }
+        if let Some(event) = self.io.take_event_hit() {
+            let pc = self.cpu.get_prefetch_addr();
+            info!("Stopped on {} at {}", event, self.cpu.location(pc));
+            ok = false;
+        }
+        ok
+    }
+
+    /// Runs the instruction at the PC, taking the undefined instruction
+    /// exception if it isn't one
+    #[inline]
+    fn execute(&mut self) {
+        if !self.cpu.cycle() {
+            let pc = self.cpu.get_prefetch_addr();
+            warn!("Undefined instruction at {}", self.cpu.location(pc));
+            self.cpu.exception(&Exception::Undefined);
+        }
+    }
+
+    /// Runs the events that are due and checks for interrupts, then moves
+    /// time on by the cycles the last step took
+    #[inline]
+    fn settle(&mut self) {
+        if self.io.scheduler().next() <= self.io.scheduler().now() {
+            self.run_events();
+        }
+        self.io.cycle();
+        // Anything that didn't access memory, e.g. while halted, takes a
+        // cycle
+        let cycles = self.mmu.take_cycles().max(1);
+        self.io.scheduler_mut().advance(cycles as u64);
+    }
+
+    /// Whether nothing is watching the CPU between instructions, so it can
+    /// run in batches
+    fn unwatched(&self) -> bool {
+        self.cpu.breaks().is_empty()
+            && !self.mmu.watching()
+            && self.io.break_events().is_empty()
+            && self.counts.is_none()
+    }
+
+    /// Runs instructions up to the next event or `end`, whichever is first,
+    /// the same as `cycle` would without its checks between them.  Halts and
+    /// the idle loop are left to `cycle`, as is the instruction the next
+    /// event comes due on.
+    fn run_batch(&mut self, end: u64) {
+        self.cpu.clear_stop();
+        // Read each time, as writing IO registers can bring the next event in
+        while self.now() < self.io.scheduler().next().min(end) {
+            if self.io.halted() || self.idle_loop == Some(self.cpu.get_prefetch_addr()) {
+                return;
+            }
+            self.execute();
+            self.settle();
+        }
+    }
+
+    /// The cycle the current frame ends on
+    pub fn frame_end(&self) -> u64 {
+        (self.now() / CYCLES_PER_FRAME + 1) * CYCLES_PER_FRAME
+    }
+
+    /// Runs to the end of the frame, stopping early at a breakpoint.  With
+    /// nothing to stop for, the CPU runs in batches between events.
+    pub fn run_frame(&mut self) -> bool {
+        let end = self.frame_end();
+        while self.now() < end {
+            if self.unwatched() {
+                self.run_batch(end);
+                if self.now() >= end {
+                    break;
+                }
+            }
+            if !self.cycle() {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn run_events(&mut self) {
+        while let Some((at, event)) = self.io.scheduler_mut().pop_due() {
+            if let Some(ref mut counts) = self.counts {
+                counts.event(event);
+            }
+            let delay = match event {
+                Event::Ppu => self.ppu.event(),
+                Event::Spu => self.spu.event(),
+                _ => {
+                    self.io.event(at, event);
+                    continue;
+                }
+            };
+            self.io.scheduler_mut().schedule(event, at + delay);
+        }
+    }
+}
+
+impl<'a> Serialize for Gba<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("gba_rs::Gba", 5)?;
+        s.serialize_field("cpu", &self.cpu)?;
+        s.serialize_field("mmu", &self.mmu)?;
+        s.serialize_field("io", &self.io)?;
+        s.serialize_field("ppu", &self.ppu)?;
+        s.serialize_field("spu", &self.spu)?;
+        s.end()
+    }
+}
+
+impl<'de, 'a> Deserialize<'de> for Gba<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct GbaVisitor<'a>(PhantomData<Gba<'a>>);
+        impl<'de, 'a> Visitor<'de> for GbaVisitor<'a> {
+            type Value = Gba<'a>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("struct Gba")
+            }
+
+            fn visit_seq<V: SeqAccess<'de>>(self, mut seq: V) -> Result<Gba<'a>, V::Error> {
+                let cpu = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let mmu = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let io = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                let ppu = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+                let spu = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(4, &self))?;
+
+                // Not connected yet, the caller has to box it and call connect
+                Ok(Gba::from_parts(cpu, mmu, io, ppu, spu))
+            }
+        }
+
+        const FIELDS: &'static [&'static str] = &["cpu", "mmu", "io", "ppu", "spu"];
+        deserializer.deserialize_struct("gba_rs::Gba", FIELDS, GbaVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::Path;
+
+    use bincode;
+
+    fn tiny<'a>() -> Box<Gba<'a>> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tiny.gba");
+        let opts = Options {
+            direct_boot: true,
+            ..Default::default()
+        };
+        Gba::new(
+            GameRom::new(Path::new(path)).unwrap(),
+            Default::default(),
+            &opts,
+        )
+    }
+
+    #[test]
+    fn test_stopped_at_breakpoint() {
+        let mut gba = tiny();
+        // tiny.s's loop
+        let brk = Breakpoint::parse("80000e0", &Default::default()).unwrap();
+        gba.set_breaks(&[brk]);
+        assert!((0..100).any(|_| !gba.cycle()));
+
+        // Nothing moves on, and it's one hit however long it waits
+        let now = gba.now();
+        for _ in 0..10 {
+            assert!(!gba.cycle());
+        }
+        assert_eq!(now, gba.now());
+        assert_eq!(1, gba.breaks()[0].hits);
+
+        gba.cpu.resume();
+        assert!(gba.cycle());
+        assert!((0..100).any(|_| !gba.cycle()));
+        assert_eq!(2, gba.breaks()[0].hits);
+    }
+
+    #[test]
+    fn test_batches_match_cycles() {
+        let mut batched = tiny();
+        let mut stepped = tiny();
+        for _ in 0..3 {
+            assert!(batched.run_frame());
+            let end = stepped.frame_end();
+            while stepped.now() < end {
+                assert!(stepped.cycle());
+            }
+            assert_eq!(stepped.now(), batched.now());
+            assert_eq!(
+                bincode::serialize(&*stepped).unwrap(),
+                bincode::serialize(&*batched).unwrap()
+            );
+        }
+    }
+}
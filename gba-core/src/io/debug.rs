@@ -0,0 +1,121 @@
+//! mGBA's debug output registers, the interface homebrew and test ROMs print
+//! through.  Nothing is there on hardware.
+//!
+//! A game writes 0xc0de to REG_DEBUG_ENABLE, which then reads back 0x1dea so
+//! it can tell it's running in an emulator with the registers.  It writes a
+//! string into the buffer at 0x4fff600, then a log level with bit 8 set to
+//! REG_DEBUG_FLAGS to print it.
+
+use mmu::MemoryRead;
+
+/// The string buffer, relative to the IO registers
+const STRING: u32 = 0xfff600;
+const STRING_LEN: usize = 0x100;
+const FLAGS: u32 = 0xfff700;
+const ENABLE: u32 = 0xfff780;
+
+const ENABLE_KEY: u16 = 0xc0de;
+const ENABLED: u16 = 0x1dea;
+/// Set in REG_DEBUG_FLAGS to print the buffer
+const FLAG_SEND: u16 = 0x100;
+
+pub struct DebugPrint {
+    enabled: bool,
+    buf: Vec<u8>,
+}
+
+impl Default for DebugPrint {
+    fn default() -> Self {
+        DebugPrint {
+            enabled: false,
+            buf: vec![0; STRING_LEN],
+        }
+    }
+}
+
+impl DebugPrint {
+    /// Whether `addr`, relative to the IO registers, is one of these
+    pub fn contains(addr: u32) -> bool {
+        addr >= STRING && addr < ENABLE + 2
+    }
+
+    pub fn load16(&self, addr: u32) -> MemoryRead<u16> {
+        let addr = addr & !1;
+        match addr {
+            ENABLE if self.enabled => MemoryRead::Value(ENABLED),
+            _ if self.enabled && addr < STRING + STRING_LEN as u32 => {
+                let off = (addr - STRING) as usize;
+                MemoryRead::Value(self.buf[off] as u16 | (self.buf[off + 1] as u16) << 8)
+            }
+            _ => MemoryRead::Open,
+        }
+    }
+
+    pub fn set8(&mut self, addr: u32, val: u8) {
+        if self.enabled && addr < STRING + STRING_LEN as u32 {
+            self.buf[(addr - STRING) as usize] = val;
+        } else {
+            self.set16(addr & !1, (val as u16) << ((addr & 1) * 8));
+        }
+    }
+
+    pub fn set16(&mut self, addr: u32, val: u16) {
+        let addr = addr & !1;
+        match addr {
+            ENABLE => self.enabled = val == ENABLE_KEY,
+            FLAGS if self.enabled && val & FLAG_SEND != 0 => self.send(val & 7),
+            _ if self.enabled && addr < STRING + STRING_LEN as u32 => {
+                self.set8(addr, val as u8);
+                self.set8(addr + 1, (val >> 8) as u8);
+            }
+            _ => (),
+        }
+    }
+
+    /// The string in the buffer, up to its NUL
+    fn text(&self) -> String {
+        let len = self.buf.iter().position(|&b| b == 0).unwrap_or(STRING_LEN);
+        String::from_utf8_lossy(&self.buf[..len]).into_owned()
+    }
+
+    /// Prints the buffer at mGBA's `level`, from 0 for fatal errors to 4
+    /// for debug messages, and clears it for the next
+    fn send(&mut self, level: u16) {
+        match level {
+            0 | 1 => error!("Game: {}", self.text()),
+            2 => warn!("Game: {}", self.text()),
+            3 => info!("Game: {}", self.text()),
+            _ => debug!("Game: {}", self.text()),
+        }
+        for b in self.buf.iter_mut() {
+            *b = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_print() {
+        let mut debug = DebugPrint::default();
+        // Ignored until enabled
+        debug.set8(STRING, b'x');
+        match debug.load16(ENABLE) {
+            MemoryRead::Open => (),
+            read => panic!("expected open bus, got {:?}", read),
+        }
+        debug.set16(ENABLE, ENABLE_KEY);
+        assert_eq!(ENABLED, debug.load16(ENABLE).get());
+        assert_eq!(0, debug.load16(STRING).get());
+
+        for (i, &b) in b"ok\0".iter().enumerate() {
+            debug.set8(STRING + i as u32, b);
+        }
+        debug.set16(STRING + 2, u16::from(b'!'));
+        assert_eq!("ok!", debug.text());
+        debug.set16(FLAGS, FLAG_SEND | 3);
+        assert_eq!("", debug.text());
+    }
+}
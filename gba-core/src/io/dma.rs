@@ -1,9 +1,11 @@
 use bit_util::{bit, extract};
 
+use cpu::breakpoint::BreakEvent;
 use mmu::gba::Gba as GbaMmu;
 use mmu::{MemoryUnit, Mmu};
 use shared::Shared;
 
+use super::timeline::Kind;
 use super::IoReg;
 
 const CHANNELS: usize = 4;
@@ -114,6 +116,15 @@ impl<'a> Dma<'a> {
         let regs = &mut self.chs[ch];
 
         self.active_len = regs.len;
+        let now = self.io.scheduler.now();
+        self.io.timeline.mark(
+            now,
+            Kind::Dma {
+                channel: ch as u8,
+                units: regs.len,
+            },
+        );
+        self.io.note_event(BreakEvent::Dma(Some(ch as u8)));
         do_copy(regs, &mut self.io.mmu, ctrl);
         self.active_len = 0;
 
@@ -153,6 +164,7 @@ fn do_copy<'a>(regs: &mut DmaCh, mmu: &mut GbaMmu<'a>, ctrl: u16) {
     // FIXME: DMA copying from BIOS memory should write 0's when not executing
     // BIOS code
     for _ in 0..regs.len {
+        mmu.log_read(regs.sad, word);
         if halfword {
             let val = mmu.load16(regs.sad);
             mmu.set16(regs.dad, val);
@@ -0,0 +1,141 @@
+use bit_util::bit;
+
+use super::{IoReg, KEYCNT, KEYINPUT};
+
+/// Which buttons are held down, filled in by the frontend
+#[derive(Clone, Copy, Default, Debug)]
+pub struct KeyState {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub r: bool,
+    pub l: bool,
+    pub u: bool,
+    pub d: bool,
+    pub br: bool,
+    pub bl: bool,
+}
+
+/// What embedders and input sources call it
+pub type Buttons = KeyState;
+
+/// Somewhere button presses come from, e.g. the keyboard, a remote player or
+/// a script, polled once a frame
+pub trait InputProvider {
+    /// The buttons to hold for the next frame
+    fn poll(&mut self) -> Buttons;
+}
+
+impl<'a, T: InputProvider + ?Sized> InputProvider for &'a mut T {
+    fn poll(&mut self) -> Buttons {
+        (**self).poll()
+    }
+}
+
+/// A source that isn't there holds nothing
+impl<T: InputProvider> InputProvider for Option<T> {
+    fn poll(&mut self) -> Buttons {
+        match *self {
+            Some(ref mut input) => input.poll(),
+            None => Default::default(),
+        }
+    }
+}
+
+impl<'a> IoReg<'a> {
+    /// Holds the buttons `input` gives until the next poll
+    pub fn poll_input<I: InputProvider>(&mut self, mut input: I) {
+        let buttons = input.poll();
+        self.set_keyreg(&buttons);
+    }
+
+    pub fn set_keyreg(&mut self, state: &KeyState) {
+        let vals = ((state.a as u16) << 0)
+            | ((state.b as u16) << 1)
+            | ((state.select as u16) << 2)
+            | ((state.start as u16) << 3)
+            | ((state.r as u16) << 4)
+            | ((state.l as u16) << 5)
+            | ((state.u as u16) << 6)
+            | ((state.d as u16) << 7)
+            | ((state.br as u16) << 8)
+            | ((state.bl as u16) << 9);
+        let reg = !vals & 0x3ff;
+        self.set_priv(KEYINPUT, reg);
+
+        let keycnt = self.get_priv(KEYCNT);
+        self.check_key_intr(reg, keycnt);
+    }
+
+    /// Raises the keypad interrupt if KEYCNT asks for it with the current
+    /// keys.  Like the hardware this keeps requesting it while the keys stay
+    /// held, not just when they're first pressed.
+    pub(super) fn check_key_intr(&mut self, keyinput: u16, keycnt: u16) {
+        if key_intr(keyinput, keycnt) {
+            self.raise_interrupt(12);
+        }
+    }
+}
+
+/// Whether the keys in `keyinput` satisfy the condition in `keycnt`.  KEYINPUT
+/// is active low, a 0 bit is a held key.  In OR mode any of the selected keys
+/// raises the interrupt, in AND mode all of them have to be held.
+fn key_intr(keyinput: u16, keycnt: u16) -> bool {
+    if bit(keycnt as u32, 14) == 0 {
+        return false;
+    }
+    let held = !keyinput & 0x3ff;
+    let mask = keycnt & 0x3ff;
+    if bit(keycnt as u32, 15) == 0 {
+        held & mask != 0
+    } else {
+        mask != 0 && held & mask == mask
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_intr() {
+        let none = 0x3ff;
+        let a = 0x3fe;
+        let ab = 0x3fc;
+        // Disabled
+        assert!(!key_intr(a, 0x0003));
+        // OR mode, any of A or B
+        assert!(!key_intr(none, 0x4003));
+        assert!(key_intr(a, 0x4003));
+        assert!(key_intr(ab, 0x4003));
+        // AND mode, both A and B
+        assert!(!key_intr(a, 0xc003));
+        assert!(key_intr(ab, 0xc003));
+        // Other keys don't count
+        assert!(!key_intr(0x3f7, 0x4003));
+    }
+
+    struct Script(Vec<Buttons>);
+
+    impl InputProvider for Script {
+        fn poll(&mut self) -> Buttons {
+            self.0.pop().unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn test_providers() {
+        let start = Buttons {
+            start: true,
+            ..Default::default()
+        };
+        let mut script = Script(vec![start]);
+        assert!((&mut script).poll().start);
+        assert!(!script.poll().start);
+
+        let mut missing: Option<Script> = None;
+        assert!(!missing.poll().start);
+        assert!(Some(Script(vec![start])).poll().start);
+    }
+}
@@ -1,17 +1,27 @@
+mod debug;
 mod dma;
 pub mod key;
 pub mod ppu;
+pub mod sio;
 pub mod spu;
+pub mod timeline;
 mod timer;
 
+use std::mem;
+
+use self::debug::DebugPrint;
 use self::dma::Dma;
 use self::ppu::Ppu;
+use self::sio::{Link, Sio};
+use self::timeline::{Kind, Timeline};
 use self::timer::Timers;
 
+use cpu::breakpoint::BreakEvent;
 use cpu::{exception, Cpu};
-use mmu::gba::Gba as GbaMmu;
+use mmu::gba::{Gba as GbaMmu, MEMCNT_INITIAL};
 use mmu::ram::Ram;
 use mmu::{MemoryRead, Mmu};
+use scheduler::{Event, Scheduler};
 use shared::Shared;
 
 const IO_REG_SIZE: usize = 0x804;
@@ -24,6 +34,9 @@ const KEYCNT: u32 = 0x132;
 const IE: u32 = 0x200;
 const IF: u32 = 0x202;
 const IME: u32 = 0x208;
+const POSTFLG: u32 = 0x300;
+const HALTCNT: u32 = 0x301;
+const MEMCNT: u32 = 0x800;
 
 #[derive(Serialize, Deserialize)]
 pub struct IoReg<'a> {
@@ -36,8 +49,28 @@ pub struct IoReg<'a> {
     #[serde(skip)]
     ppu: Shared<Ppu<'a>>,
 
+    scheduler: Scheduler,
     timers: Timers<'a>,
     dma: Dma<'a>,
+    sio: Sio<'a>,
+
+    /// Set by a write to HALTCNT, the CPU stops until an enabled interrupt
+    /// is requested
+    halted: bool,
+
+    /// Records a frame's DMAs, interrupts and timer overflows on request
+    #[serde(skip)]
+    pub timeline: Timeline,
+
+    /// Not saved, so states stay compatible with ones made before it
+    #[serde(skip)]
+    debug: DebugPrint,
+
+    /// Events to stop the CPU for, and the last of them that happened
+    #[serde(skip)]
+    break_events: Vec<BreakEvent>,
+    #[serde(skip)]
+    event_hit: Option<BreakEvent>,
 }
 
 impl<'a> IoReg<'a> {
@@ -47,8 +80,15 @@ impl<'a> IoReg<'a> {
             cpu: Shared::empty(),
             mmu: Shared::empty(),
             ppu: Shared::empty(),
+            scheduler: Default::default(),
             timers: Default::default(),
             dma: Default::default(),
+            sio: Default::default(),
+            halted: false,
+            timeline: Default::default(),
+            debug: Default::default(),
+            break_events: Vec::new(),
+            event_hit: None,
         };
         io.set_initial();
         io
@@ -59,6 +99,16 @@ impl<'a> IoReg<'a> {
         self.reg.set16(0x26, 0x100);
         self.reg.set16(0x30, 0x100);
         self.reg.set16(0x36, 0x100);
+        // No keys held
+        self.reg.set16(KEYINPUT, 0x3ff);
+        self.reg.set16(MEMCNT, MEMCNT_INITIAL as u16);
+        self.reg.set16(MEMCNT + 2, (MEMCNT_INITIAL >> 16) as u16);
+    }
+
+    /// Sets POSTFLG as the BIOS does once it has booted, for when it's
+    /// skipped.  Games check it to tell a boot from a soft reset.
+    pub fn init_direct(&mut self) {
+        self.reg.set16(POSTFLG, 1);
     }
 
     pub fn init(
@@ -74,13 +124,92 @@ impl<'a> IoReg<'a> {
         let io = Shared::new(self);
         self.timers.init(io);
         self.dma.init(io);
+        self.sio.init(io);
+    }
+
+    pub fn link(&self) -> Link {
+        self.sio.link()
+    }
+
+    pub fn set_link(&mut self, link: Link) {
+        self.sio.set_link(link);
+    }
+
+    /// Takes over `other`'s debug output registers, for a state replacing it
+    pub fn take_debug(&mut self, other: &mut IoReg) {
+        mem::swap(&mut self.debug, &mut other.debug);
+    }
+
+    pub fn break_events(&self) -> &[BreakEvent] {
+        &self.break_events
+    }
+
+    pub fn set_break_events(&mut self, events: Vec<BreakEvent>) {
+        self.break_events = events;
+        self.event_hit = None;
+    }
+
+    pub fn take_break_events(&mut self) -> Vec<BreakEvent> {
+        mem::replace(&mut self.break_events, Vec::new())
+    }
+
+    /// The event the CPU should stop for, if one happened since the last
+    /// call
+    #[inline]
+    pub fn take_event_hit(&mut self) -> Option<BreakEvent> {
+        self.event_hit.take()
+    }
+
+    /// Called when `event` happens, to stop the CPU if it's watched for
+    #[inline]
+    pub fn note_event(&mut self, event: BreakEvent) {
+        if self.break_events.iter().any(|watch| watch.matches(event)) {
+            self.event_hit = Some(event);
+        }
+    }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    pub fn scheduler_mut(&mut self) -> &mut Scheduler {
+        &mut self.scheduler
+    }
+
+    /// Handles the events that belong to IO registers
+    pub fn event(&mut self, at: u64, event: Event) {
+        match event {
+            Event::Timers => self.timers.event(at),
+            Event::Sio => self.sio.finish(),
+            _ => unreachable!("{:?} is not an IO event", event),
+        }
     }
 
     pub fn cycle(&mut self) {
-        self.timers.cycle();
+        if self.halted && self.get_priv(IE) & self.get_priv(IF) != 0 {
+            self.halted = false;
+        }
         self.check_interrupt();
     }
 
+    /// Whether the CPU is halted waiting for an interrupt
+    #[inline]
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    fn set_haltcnt(&mut self, val: u8) {
+        if val & 0x80 == 0 {
+            self.halted = true;
+        } else {
+            // Stop mode also turns off the video and sound, which isn't
+            // emulated, so treat it as a halt.  Games use it with KEYCNT to
+            // sleep until a button combination is pressed.
+            warn!("Stop mode is treated as halt");
+            self.halted = true;
+        }
+    }
+
     pub fn dma_length(&self) -> u32 {
         self.dma.length()
     }
@@ -91,6 +220,8 @@ impl<'a> IoReg<'a> {
             let ie = self.get_priv(IE);
             if ir & ie != 0 {
                 self.cpu.exception(&exception::Exception::Interrupt);
+                let bit = (ir & ie).trailing_zeros() as u8;
+                self.note_event(BreakEvent::Irq(Some(bit)));
             }
         }
     }
@@ -98,6 +229,8 @@ impl<'a> IoReg<'a> {
     fn raise_interrupt(&mut self, itr: u8) {
         let pif = self.get_priv(IF);
         self.set_priv(IF, pif | (1 << (itr as u16)));
+        let now = self.scheduler.now();
+        self.timeline.mark(now, Kind::Irq(itr));
 
         info!("Interrupt {} raised", itr);
     }
@@ -135,6 +268,10 @@ impl<'a> IoReg<'a> {
             // If not writable, no point in doing anything
             return;
         }
+        if addr >= 0x100 && addr < 0x110 {
+            // Timers advance lazily and need the old settings to catch up
+            self.timers.catch_up();
+        }
         let ro = ro_mask(addr);
         let old = self.get_priv(addr);
         let nval = (ro & old) | (!ro & val);
@@ -142,6 +279,9 @@ impl<'a> IoReg<'a> {
 
         // Potential callback
         self.updated(addr, old, nval);
+        if addr >= 0x100 && addr < 0x110 {
+            self.timers.reschedule();
+        }
     }
 
     fn updated(&mut self, addr: u32, old: u16, new: u16) {
@@ -150,15 +290,18 @@ impl<'a> IoReg<'a> {
             0x38 | 0x3a | 0x3c | 0x3e => self.ppu.update_bg3ref(),
             0xBA | 0xC6 | 0xD2 | 0xDE => self.dma.updated(addr - 0xB0, old, new),
             0x102 | 0x106 | 0x10a | 0x10e => self.timers.updated((addr - 0x102) / 4, old, new),
-            0x130 => {
-                let keycnt = self.get_priv(KEYCNT);
-                self.check_key_intr(keycnt, new);
-            }
+            0x128 => self.sio.updated(old, new),
             0x132 => {
                 let keyinput = self.get_priv(KEYINPUT);
                 self.check_key_intr(keyinput, new);
             }
             0x202 => self.disable_intrreq(new),
+            0x204 => self.mmu.set_waitcnt(new),
+            0x300 => self.set_haltcnt((new >> 8) as u8),
+            0x800 | 0x802 => {
+                let val = self.get_priv(MEMCNT) as u32 | (self.get_priv(MEMCNT + 2) as u32) << 16;
+                self.mmu.set_memcnt(val);
+            }
             _ => (),
         }
     }
@@ -174,13 +317,29 @@ impl<'a> Mmu for IoReg<'a> {
     fn load8(&self, addr: u32) -> MemoryRead<u8> {
         use self::MemoryRead::*;
 
-        match self.get(addr & !1) {
+        let val = if DebugPrint::contains(addr) {
+            self.debug.load16(addr)
+        } else {
+            self.get(addr & !1)
+        };
+        match val {
             Value(x) => Value((x >> ((addr & 1) * 8)) as u8),
             Open => Open,
         }
     }
 
     fn set8(&mut self, addr: u32, val: u8) {
+        // HALTCNT takes effect on every write, so it can't be written along
+        // with the current POSTFLG like other byte writes
+        match addr {
+            POSTFLG => {
+                let pv = self.get_priv(POSTFLG);
+                return self.set_priv(POSTFLG, (pv & 0xff00) | (val & 1) as u16);
+            }
+            HALTCNT => return self.set_haltcnt(val),
+            _ if DebugPrint::contains(addr) => return self.debug.set8(addr, val),
+            _ => (),
+        }
         let pv = if (addr as usize) < self.reg.len() {
             self.get_priv(addr & !1)
         } else {
@@ -193,16 +352,29 @@ impl<'a> Mmu for IoReg<'a> {
     }
 
     fn load16(&self, addr: u32) -> MemoryRead<u16> {
+        if DebugPrint::contains(addr) {
+            return self.debug.load16(addr);
+        }
         self.get(addr)
     }
 
     fn set16(&mut self, addr: u32, val: u16) {
+        if DebugPrint::contains(addr) {
+            return self.debug.set16(addr, val);
+        }
         self.set(addr, val);
     }
 
     fn load32(&self, addr: u32) -> MemoryRead<u32> {
         use self::MemoryRead::*;
 
+        if DebugPrint::contains(addr) {
+            return match (self.debug.load16(addr), self.debug.load16(addr + 2)) {
+                (Value(lo), Value(hi)) => Value(lo as u32 | (hi as u32) << 16),
+                _ => Open,
+            };
+        }
+
         // If one register is non-open, the other one will be as well
         match self.get(addr) {
             Value(x) => Value((x as u32) | ((self.get(addr + 2).get() as u32) << 16)),
@@ -211,6 +383,10 @@ impl<'a> Mmu for IoReg<'a> {
     }
 
     fn set32(&mut self, addr: u32, val: u32) {
+        if DebugPrint::contains(addr) {
+            self.debug.set16(addr, val as u16);
+            return self.debug.set16(addr + 2, (val >> 16) as u16);
+        }
         // This setting order is correct for timers, but there may be
         // simultaneous setting issues elsewhere, unaware of any so far.
         self.set(addr, val as u16);
@@ -272,6 +448,8 @@ fn ro_mask(addr: u32) -> u16 {
     match addr {
         0x004 => 0x0047,
         0x084 => 0x000f,
+        // Only bit 0 of POSTFLG exists
+        0x300 => 0x00fe,
         _ => 0,
     }
 }
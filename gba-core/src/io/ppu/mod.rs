@@ -0,0 +1,230 @@
+use std::default::Default;
+use std::io;
+use std::mem;
+
+use mmu::gba::Gba as GbaMmu;
+use shared::Shared;
+
+use super::dma::Trigger;
+use super::*;
+
+mod render;
+
+pub use self::render::{Blend, Effect, Layers};
+
+pub const COLS: u32 = 240;
+pub const ROWS: u32 = 160;
+
+const PIX_BYTES: usize = 4;
+pub const ROW_BYTES: usize = PIX_BYTES * (COLS as usize);
+pub const FRAME_BYTES: usize = ROW_BYTES * (ROWS as usize);
+
+// Cycles from the start of a scanline to its hblank, and to its end
+const HBLANK_CYCLES: u64 = 956;
+const LINE_END_CYCLES: u64 = 1228;
+pub(super) const LINE_CYCLES: u64 = 1232;
+
+/// Takes each frame the PPU draws, as RGB888 pixels in little endian u32s,
+/// e.g. to show it in a window or write it to a file
+pub trait VideoSink {
+    fn frame(&mut self, frame: &[u8]) -> io::Result<()>;
+}
+
+/// Where the PPU is within the current scanline
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+enum Stage {
+    LineStart,
+    HBlank,
+    LineEnd,
+}
+
+/// Handle scanline drawing here
+// We skip almost everything because at the moment, save states can only be taken at frame
+// boundaries
+#[derive(Serialize, Deserialize)]
+pub struct Ppu<'a> {
+    #[serde(skip, default = "empty_frame")]
+    pixels: [u8; FRAME_BYTES],
+    /// The last completed frame, as RGB888 pixels stored in little endian u32s
+    #[serde(skip, default = "empty_frame")]
+    frame: [u8; FRAME_BYTES],
+
+    #[serde(skip)]
+    io: Shared<IoReg<'a>>,
+    #[serde(skip)]
+    mmu: Shared<GbaMmu<'a>>,
+    row: u32,
+    stage: Stage,
+
+    #[serde(skip)]
+    state: render::RenderState,
+    #[serde(skip)]
+    capture_next: bool,
+    #[serde(skip)]
+    captured: Option<Box<render::Layers>>,
+    #[serde(skip)]
+    sinks: Vec<Box<VideoSink>>,
+}
+
+fn empty_frame() -> [u8; FRAME_BYTES] {
+    [0u8; FRAME_BYTES]
+}
+
+impl<'a> Ppu<'a> {
+    pub fn new() -> Self {
+        Ppu {
+            pixels: [0u8; FRAME_BYTES],
+            frame: [0u8; FRAME_BYTES],
+            io: Shared::empty(),
+            mmu: Shared::empty(),
+            row: 0,
+            stage: Stage::LineStart,
+            state: Default::default(),
+            capture_next: false,
+            captured: None,
+            sinks: Vec::new(),
+        }
+    }
+
+    pub fn init(&mut self, io: Shared<IoReg<'a>>, mmu: Shared<GbaMmu<'a>>) {
+        self.io = io;
+        self.mmu = mmu;
+    }
+
+    /// Runs the current stage of the scanline, returns the cycles until the
+    /// next one
+    pub fn event(&mut self) -> u64 {
+        match self.stage {
+            Stage::LineStart => {
+                if self.row == 0 {
+                    self.frame_start();
+                }
+                self.line_start();
+                self.stage = Stage::HBlank;
+                HBLANK_CYCLES
+            }
+            Stage::HBlank => {
+                self.hblank();
+                self.stage = Stage::LineEnd;
+                LINE_END_CYCLES - HBLANK_CYCLES
+            }
+            Stage::LineEnd => {
+                self.row += 1;
+                if self.row == 160 {
+                    self.vblank();
+                } else if self.row == 228 {
+                    self.row = 0;
+                    self.vblank_end();
+                }
+                self.stage = Stage::LineStart;
+                LINE_CYCLES - LINE_END_CYCLES
+            }
+        }
+    }
+
+    fn frame_start(&mut self) {
+        self.start_capture();
+        let now = self.io.scheduler.now();
+        self.io.timeline.frame_start(now);
+        self.update_bg2ref();
+        self.update_bg3ref();
+
+        let mut ds = self.io.get_priv(DISPSTAT);
+        ds &= !1; // unset vblank flag
+        self.io.set_priv(DISPSTAT, ds);
+    }
+
+    fn line_start(&mut self) {
+        self.io.set_priv(VCOUNT, self.row as u16);
+        let mut ds = self.io.get_priv(DISPSTAT);
+        if (ds >> 8) == self.row as u16 {
+            ds |= 4; // vcounter
+            if ds & 0x20 != 0 {
+                self.io.raise_interrupt(2);
+            }
+        } else {
+            // unset vcounter flag
+            ds &= !4;
+        }
+        ds &= !2; // unset hblank flag
+        self.io.set_priv(DISPSTAT, ds);
+
+        if self.row < 160 {
+            // The borrow checker is really strict... self.row.clone() didn't work
+            let row = self.row;
+            self.render_line(row);
+        }
+    }
+
+    fn hblank(&mut self) {
+        let mut ds = self.io.get_priv(DISPSTAT);
+        ds |= 2;
+        if ds & 0x10 != 0 {
+            self.io.raise_interrupt(1);
+        }
+        self.io.set_priv(DISPSTAT, ds);
+        if self.row < 160 {
+            self.io.dma.trigger(Trigger::HBlank);
+        }
+    }
+
+    fn vblank(&mut self) {
+        self.finish_capture();
+        let mut ds = self.io.get_priv(DISPSTAT);
+        ds |= 1;
+        if ds & 0x8 != 0 {
+            self.io.raise_interrupt(0);
+        }
+        self.io.set_priv(DISPSTAT, ds);
+        self.io.note_event(BreakEvent::VBlank);
+        self.io.dma.trigger(Trigger::VBlank);
+    }
+
+    fn vblank_end(&mut self) {
+        // wrap around, publish our image as the completed frame
+        self.frame.clone_from_slice(&self.pixels);
+        let mut i = 0;
+        while i < self.sinks.len() {
+            if let Err(err) = self.sinks[i].frame(&self.frame) {
+                error!("Stopped writing video: {}", err);
+                self.sinks.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Sends every frame from now on to `sink`, until it fails
+    pub fn add_video_sink(&mut self, sink: Box<VideoSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Swaps the sinks frames are sent to, so they can stay with the running
+    /// core when a state is restored
+    pub fn swap_sinks(&mut self, other: &mut Ppu<'a>) {
+        mem::swap(&mut self.sinks, &mut other.sinks);
+    }
+
+    /// The last completed frame, `ROW_BYTES` per row
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    pub fn update_bg2ref(&mut self) {
+        let xl = self.io.get_priv(0x28);
+        let xh = self.io.get_priv(0x2a);
+        let yl = self.io.get_priv(0x2c);
+        let yh = self.io.get_priv(0x2e);
+
+        self.state.bg2ref = render::BgRef::new(xl, xh, yl, yh);
+    }
+
+    pub fn update_bg3ref(&mut self) {
+        let xl = self.io.get_priv(0x38);
+        let xh = self.io.get_priv(0x3a);
+        let yl = self.io.get_priv(0x3c);
+        let yh = self.io.get_priv(0x3e);
+
+        self.state.bg3ref = render::BgRef::new(xl, xh, yl, yh);
+    }
+}
@@ -254,7 +254,7 @@ pub(super) fn render_textmode_line(line: &mut LineBuf, row: u32, mmu: &GbaMmu, b
         let palette_colour = if c256 {
             mmu.vram.load8(tile_base + px_addr).get()
         } else {
-            (mmu.vram.load8(tile_base + px_addr / 2).get() >> ((px_addr & 1) * 4)) & 0xf
+            mmu.tiles.pixel(tile_base, px_addr)
         };
         line[x as usize] = if palette_colour == 0 {
             TRANSPARENT
@@ -130,6 +130,41 @@ fn blend_semitrans(
     }
 }
 
+/// Which of `blend` or `blend_semitrans` changes the pixel, and how
+fn applied_effect(
+    semitrans: bool,
+    win_effects: bool,
+    effect: u32,
+    bldcnt: u16,
+    first: u8,
+    second: u8,
+) -> Effect {
+    let second_target = bit(bldcnt as u32, 8 + second) == 1;
+    let effect = if semitrans {
+        if second_target {
+            1
+        } else if effect >= 2 {
+            effect
+        } else {
+            0
+        }
+    } else if win_effects && bit(bldcnt as u32, first) == 1 {
+        if effect == 1 && !second_target {
+            0
+        } else {
+            effect
+        }
+    } else {
+        0
+    };
+    match effect {
+        1 => Effect::Alpha,
+        2 => Effect::Brighten,
+        3 => Effect::Darken,
+        _ => Effect::None,
+    }
+}
+
 impl<'a> Ppu<'a> {
     fn bg0_drawline(&mut self, mode: u32, row: u32, dspcnt: u16) -> bool {
         let bg0en = mode <= 1 && bit(dspcnt as u32, 8) == 1;
@@ -246,6 +281,27 @@ impl<'a> Ppu<'a> {
 
         let backdrop = (self.mmu.pram.load16(0).get() as u32) | (0xe << 28);
 
+        if let Some(ref mut capture) = self.state.capture {
+            let lines = [
+                (bg0en, &self.state.line0),
+                (bg1en, &self.state.line1),
+                (bg2en, &self.state.line2),
+                (bg3en, &self.state.line3),
+                (objen, &self.state.lineo),
+            ];
+            let start = (row * COLS) as usize;
+            for (layer, &(enabled, line)) in capture.layers.iter_mut().zip(lines.iter()) {
+                let dest = &mut layer[start..start + COLS as usize];
+                if enabled {
+                    dest.copy_from_slice(line);
+                } else {
+                    for px in dest.iter_mut() {
+                        *px = TRANSPARENT;
+                    }
+                }
+            }
+        }
+
         for x in 0..COLS {
             let ux = x as usize;
             let en_mask = if win_enable {
@@ -339,6 +395,24 @@ impl<'a> Ppu<'a> {
             } else {
                 fc
             };
+
+            if let Some(ref mut capture) = self.state.capture {
+                let idx = (row * COLS + x) as usize;
+                capture.windows[idx] = en_mask as u8;
+                capture.blends[idx] = Blend {
+                    first: first,
+                    second: second,
+                    effect: applied_effect(
+                        fc & SEMITRANS != 0,
+                        bit(en_mask, 5) == 1,
+                        effect,
+                        bldcnt,
+                        first,
+                        second,
+                    ),
+                };
+                capture.output[idx] = self.state.line[ux];
+            }
         }
     }
 }
@@ -370,4 +444,26 @@ mod test {
 
         assert_eq!((29, 29, 29), cr);
     }
+
+    #[test]
+    fn test_applied_effect() {
+        // BG0 first target, BG1 second target
+        let bldcnt = (1 << 0) | (1 << 9);
+        assert_eq!(Effect::Alpha, applied_effect(false, true, 1, bldcnt, 0, 1));
+        assert_eq!(Effect::None, applied_effect(false, true, 1, bldcnt, 0, 2));
+        assert_eq!(Effect::None, applied_effect(false, false, 1, bldcnt, 0, 1));
+        assert_eq!(
+            Effect::Darken,
+            applied_effect(false, true, 3, bldcnt, 0, 16)
+        );
+        assert_eq!(Effect::None, applied_effect(false, true, 2, bldcnt, 1, 0));
+        // Semi-transparent objects blend whatever the first targets are
+        assert_eq!(Effect::Alpha, applied_effect(true, false, 0, bldcnt, 4, 1));
+        assert_eq!(
+            Effect::Brighten,
+            applied_effect(true, false, 2, bldcnt, 4, 2)
+        );
+        assert_eq!(Effect::Darken, applied_effect(true, false, 3, bldcnt, 4, 2));
+        assert_eq!(Effect::None, applied_effect(true, false, 1, bldcnt, 4, 2));
+    }
 }
@@ -12,7 +12,7 @@ use serde::{Serialize, Serializer};
 
 use bit_util::{bit, extract, sign_extend};
 
-use super::{Ppu, COLS, DSPCNT, PIX_BYTES};
+use super::{Ppu, COLS, DSPCNT, PIX_BYTES, ROWS};
 
 mod background;
 mod combine;
@@ -31,8 +31,7 @@ impl<'a> Ppu<'a> {
         for x in 0..COLS {
             let idx = row * COLS + x;
             let off = idx as usize * PIX_BYTES;
-            let colour = colour16_rgb(self.state.line[x as usize] as u16);
-            let rgb = colour_pack(colour);
+            let rgb = self.state.colours.get(self.state.line[x as usize]);
             LittleEndian::write_u32(&mut self.pixels[off..off + PIX_BYTES], rgb);
         }
     }
@@ -59,6 +58,119 @@ impl DerefMut for LineBuf {
     }
 }
 
+/// Every BGR555 colour converted to the output format, so converting a pixel
+/// is a single lookup.  The whole space is covered rather than just the
+/// palette, as blending makes colours that aren't in it.  It's built once and
+/// holds the raw colours; correcting them for the GBA's LCD is left to the
+/// frontend, after frame blending.
+struct ColourTable(Vec<u32>);
+
+impl ColourTable {
+    #[inline]
+    fn get(&self, colour: u32) -> u32 {
+        self.0[(colour & 0x7fff) as usize]
+    }
+}
+
+impl Default for ColourTable {
+    fn default() -> Self {
+        ColourTable(
+            (0..0x8000)
+                .map(|c| colour_pack(colour16_rgb(c as u16)))
+                .collect(),
+        )
+    }
+}
+
+/// How the top two layers of a pixel were combined
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Effect {
+    None,
+    Alpha,
+    Brighten,
+    Darken,
+}
+
+/// The layers that made up a pixel, 0-3 for the backgrounds, 4 for objects,
+/// 5 for the backdrop and 16 if there was no second layer
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Blend {
+    pub first: u8,
+    pub second: u8,
+    pub effect: Effect,
+}
+
+/// Every layer of one frame as it went into composition, along with what
+/// composition decided for each pixel, for diagnosing rendering bugs.
+/// Colours are BGR555 in the low bits, `TRANSPARENT` where a layer is off or
+/// drew nothing, and pixels are stored row by row.
+pub struct Layers {
+    /// BG0-3 then OBJ
+    pub layers: Vec<Vec<u32>>,
+    /// The layers enabled by the windows, bit 5 is the colour effects
+    pub windows: Vec<u8>,
+    pub blends: Vec<Blend>,
+    pub output: Vec<u32>,
+}
+
+impl Layers {
+    fn new() -> Self {
+        let pixels = (COLS * ROWS) as usize;
+        let blend = Blend {
+            first: 5,
+            second: 16,
+            effect: Effect::None,
+        };
+        Layers {
+            layers: vec![vec![TRANSPARENT; pixels]; 5],
+            windows: vec![0; pixels],
+            blends: vec![blend; pixels],
+            output: vec![0; pixels],
+        }
+    }
+
+    /// Converts one of the captured colour planes to a frame like
+    /// `Ppu::frame`, with `transparent` where nothing was drawn
+    pub fn image(plane: &[u32], transparent: u32) -> Vec<u8> {
+        let mut frame = vec![0; plane.len() * PIX_BYTES];
+        for (px, &colour) in frame.chunks_mut(PIX_BYTES).zip(plane.iter()) {
+            let rgb = if colour == TRANSPARENT {
+                transparent
+            } else {
+                colour_pack(colour16_rgb(colour as u16))
+            };
+            LittleEndian::write_u32(px, rgb);
+        }
+        frame
+    }
+}
+
+impl<'a> Ppu<'a> {
+    /// Captures the layers of the next frame, to be collected with
+    /// `take_layers` once it has been drawn
+    pub fn capture_layers(&mut self) {
+        self.capture_next = true;
+    }
+
+    /// The layers captured since the last call, if a capture has finished
+    pub fn take_layers(&mut self) -> Option<Box<Layers>> {
+        self.captured.take()
+    }
+
+    pub(super) fn start_capture(&mut self) {
+        if self.capture_next {
+            self.capture_next = false;
+            self.state.capture = Some(Box::new(Layers::new()));
+        }
+    }
+
+    pub(super) fn finish_capture(&mut self) {
+        if let Some(layers) = self.state.capture.take() {
+            self.captured = Some(layers);
+        }
+    }
+}
+
 #[derive(Default)]
 pub(super) struct RenderState {
     // Stealing a trick from VBA: upper bits are priority
@@ -72,6 +184,9 @@ pub(super) struct RenderState {
     line_objwindow: LineBuf,
 
     line: LineBuf,
+    colours: ColourTable,
+    /// Where this frame's layers are being captured to
+    capture: Option<Box<Layers>>,
 
     pub(super) bg2ref: BgRef,
     pub(super) bg3ref: BgRef,
@@ -168,4 +283,13 @@ mod test {
         assert_eq!((0, 0xf8, 0), colour16_rgb(0x3e0));
         assert_eq!((0, 0, 0xf8), colour16_rgb(0x7c00));
     }
+
+    #[test]
+    fn test_colour_table() {
+        let table = ColourTable::default();
+        assert_eq!(0xf8_00_00, table.get(0x1f));
+        assert_eq!(0x00_f8_f8, table.get(0x7fe0));
+        // Priority bits above the colour are ignored
+        assert_eq!(0xf8_00_00, table.get(0x3000_001f));
+    }
 }
@@ -168,8 +168,7 @@ pub(super) fn render_obj_line(
 
             let tile_addr = 0x10000 + t * 32;
             let palette_colour = if palette_mode == 0 {
-                let v = mmu.vram.load8(tile_addr + idx / 2).get();
-                (v >> ((idx & 1) * 4)) & 0xf
+                mmu.tiles.pixel(tile_addr, idx)
             } else {
                 mmu.vram.load8(tile_addr + idx).get()
             };
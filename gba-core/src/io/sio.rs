@@ -0,0 +1,194 @@
+use bit_util::{bit, extract};
+
+use scheduler::Event;
+use shared::Shared;
+
+use super::IoReg;
+
+const SIODATA32_L: u32 = 0x120;
+const SIODATA32_H: u32 = 0x122;
+const SIOCNT: u32 = 0x128;
+const SIODATA8: u32 = 0x12a;
+const RCNT: u32 = 0x134;
+
+/// What is on the other end of the link cable
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Link {
+    /// No cable attached, received bits are all 1
+    Disconnected,
+    /// The cable is looped back, received data is what was sent
+    Loopback,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Link::Disconnected
+    }
+}
+
+/// Serial port, only normal (8/32-bit) mode is emulated
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
+pub struct Sio<'a> {
+    #[serde(skip)]
+    link: Link,
+    #[serde(skip)]
+    io: Shared<IoReg<'a>>,
+}
+
+impl<'a> Sio<'a> {
+    pub fn init(&mut self, io: Shared<IoReg<'a>>) {
+        self.io = io;
+    }
+
+    pub fn link(&self) -> Link {
+        self.link
+    }
+
+    pub fn set_link(&mut self, link: Link) {
+        self.link = link;
+    }
+
+    pub fn updated(&mut self, old: u16, new: u16) {
+        if bit(old as u32, 7) == 1 || bit(new as u32, 7) == 0 {
+            return;
+        }
+        let rcnt = self.io.get_priv(RCNT) as u32;
+        let mode = extract(new as u32, 12, 2);
+        if bit(rcnt, 15) == 1 || mode > 1 {
+            warn!(
+                "Unsupported SIO mode: SIOCNT {:#06x} RCNT {:#06x}",
+                new, rcnt
+            );
+            return;
+        }
+
+        let internal = bit(new as u32, 0) == 1;
+        if !internal && self.link == Link::Disconnected {
+            // Nobody is there to drive the clock, the transfer never ends
+            return;
+        }
+        // 256KHz or 2MHz shift clock
+        let per_bit = if bit(new as u32, 1) == 0 { 64 } else { 8 };
+        let bits = if mode == 0 { 8 } else { 32 };
+        self.io.scheduler.schedule_in(Event::Sio, per_bit * bits);
+    }
+
+    /// Completes the transfer started by `updated`
+    pub fn finish(&mut self) {
+        let ctrl = self.io.get_priv(SIOCNT);
+        let word = extract(ctrl as u32, 12, 2) == 1;
+        match self.link {
+            Link::Disconnected => {
+                if word {
+                    self.io.set_priv(SIODATA32_L, 0xffff);
+                    self.io.set_priv(SIODATA32_H, 0xffff);
+                } else {
+                    let d = self.io.get_priv(SIODATA8);
+                    self.io.set_priv(SIODATA8, d | 0xff);
+                }
+            }
+            // Loopback leaves the sent data in place
+            Link::Loopback => (),
+        }
+
+        self.io.set_priv(SIOCNT, ctrl & !(1 << 7));
+        if bit(ctrl as u32, 14) == 1 {
+            self.io.raise_interrupt(7);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::Path;
+
+    use gba::{Gba, Options};
+    use io::IF;
+    use rom::GameRom;
+
+    fn tiny<'a>(loopback: bool) -> Box<Gba<'a>> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tiny.gba");
+        let opts = Options {
+            direct_boot: true,
+            sio_loopback: loopback,
+            ..Default::default()
+        };
+        Gba::new(
+            GameRom::new(Path::new(path)).unwrap(),
+            Default::default(),
+            &opts,
+        )
+    }
+
+    /// Starts a transfer with `ctrl` and returns how long it takes
+    fn start(gba: &mut Gba, ctrl: u16) -> Option<u64> {
+        let now = gba.io.scheduler().now();
+        gba.io.set(SIOCNT, ctrl);
+        gba.io.scheduler().pending(Event::Sio).map(|at| at - now)
+    }
+
+    /// Runs the transfer's completion event
+    fn finish(gba: &mut Gba) {
+        let at = gba.io.scheduler().pending(Event::Sio).unwrap();
+        gba.io.scheduler_mut().cancel(Event::Sio);
+        gba.io.event(at, Event::Sio);
+    }
+
+    #[test]
+    fn test_timing() {
+        let mut gba = tiny(false);
+        // 8 bits at 256KHz and 32 at 2MHz, on the internal clock
+        assert_eq!(Some(8 * 64), start(&mut gba, 0x0081));
+        finish(&mut gba);
+        assert_eq!(0, bit(gba.io.get_priv(SIOCNT) as u32, 7));
+        assert_eq!(Some(32 * 8), start(&mut gba, 0x1083));
+        finish(&mut gba);
+        assert_eq!(0, bit(gba.io.get_priv(SIOCNT) as u32, 7));
+
+        // Nothing drives an external clock with the cable unplugged
+        assert_eq!(None, start(&mut gba, 0x0080));
+        assert_eq!(1, bit(gba.io.get_priv(SIOCNT) as u32, 7));
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut gba = tiny(false);
+        start(&mut gba, 0x0081);
+        finish(&mut gba);
+        assert_eq!(0, bit(gba.io.get_priv(IF) as u32, 7));
+
+        start(&mut gba, 0x4081);
+        finish(&mut gba);
+        assert_eq!(1, bit(gba.io.get_priv(IF) as u32, 7));
+    }
+
+    #[test]
+    fn test_received() {
+        let mut gba = tiny(false);
+        gba.io.set(SIODATA8, 0x42);
+        start(&mut gba, 0x0081);
+        finish(&mut gba);
+        assert_eq!(0xff, gba.io.get_priv(SIODATA8) & 0xff);
+        gba.io.set(SIODATA32_L, 0x1234);
+        gba.io.set(SIODATA32_H, 0x5678);
+        start(&mut gba, 0x1081);
+        finish(&mut gba);
+        assert_eq!(0xffff, gba.io.get_priv(SIODATA32_L));
+        assert_eq!(0xffff, gba.io.get_priv(SIODATA32_H));
+
+        // Looped back, what was sent comes back
+        let mut gba = tiny(true);
+        gba.io.set(SIODATA8, 0x42);
+        start(&mut gba, 0x0081);
+        finish(&mut gba);
+        assert_eq!(0x42, gba.io.get_priv(SIODATA8) & 0xff);
+        gba.io.set(SIODATA32_L, 0x1234);
+        gba.io.set(SIODATA32_H, 0x5678);
+        start(&mut gba, 0x1080);
+        finish(&mut gba);
+        assert_eq!(0x1234, gba.io.get_priv(SIODATA32_L));
+        assert_eq!(0x5678, gba.io.get_priv(SIODATA32_H));
+    }
+}
@@ -0,0 +1,138 @@
+use std::default::Default;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use arraydeque::{ArrayDeque, Wrapping};
+
+use mmu::gba::Gba as GbaMmu;
+use shared::Shared;
+
+use super::IoReg;
+
+// Sound runs at 32768 Hz
+// 256 samples at a time leads to audio latency of ~8ms, which is
+// probably ok.
+
+pub const SAMPLES: usize = 256;
+pub const FREQ: i32 = 32768;
+
+// do SAMPLES * 4 to give extra buffer room
+type SoundDeque = ArrayDeque<[(f32, f32); SAMPLES * 16], Wrapping>;
+/// Samples are scaled down on the way out, to leave headroom
+const OUTPUT_LEVEL: f32 = 0.5;
+
+/// Takes each stereo sample the SPU produces, at `FREQ`, e.g. to play it or
+/// write it to a file.  Each is pushed as it's emulated, every 512 cycles, so
+/// this has to be quick.
+pub trait AudioSink {
+    fn push(&mut self, left: f32, right: f32);
+}
+
+/// Samples kept for another thread or a later frame to read, the oldest
+/// dropped once it's full.  Clones share the same buffer.
+#[derive(Clone)]
+pub struct SoundBuf(Arc<Mutex<SoundDeque>>);
+
+#[derive(Serialize, Deserialize)]
+pub struct Spu<'a> {
+    #[serde(skip)]
+    io: Shared<IoReg<'a>>,
+
+    #[serde(skip)]
+    sinks: Vec<Box<AudioSink>>,
+
+    idx: i32,
+}
+
+impl<'a> Spu<'a> {
+    pub fn new() -> Self {
+        Self {
+            io: Shared::empty(),
+            sinks: Vec::new(),
+            idx: 0,
+        }
+    }
+
+    pub fn init(&mut self, io: Shared<IoReg<'a>>) {
+        self.io = io;
+    }
+
+    /// Produces the next sample, returns the cycles until the one after
+    pub fn event(&mut self) -> u64 {
+        let (l, r) = if self.idx == 0 {
+            (1.0, 1.0)
+        } else {
+            (-1.0, -1.0)
+        };
+        for sink in self.sinks.iter_mut() {
+            sink.push(l * OUTPUT_LEVEL, r * OUTPUT_LEVEL);
+        }
+        self.idx = (self.idx + 512) % 1024;
+        512
+    }
+
+    /// Sends every sample from now on to `sink`
+    pub fn add_audio_sink(&mut self, sink: Box<AudioSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// A buffer of its own that gets every sample from now on, which must be
+    /// drained regularly or it drops the oldest
+    pub fn tap(&mut self) -> SoundBuf {
+        let buf = SoundBuf::default();
+        self.add_audio_sink(Box::new(buf.clone()));
+        buf
+    }
+
+    /// Swaps the sinks samples are sent to, so the audio device and any
+    /// recording can stay with the running core when a state is restored
+    pub fn swap_output(&mut self, other: &mut Spu<'a>) {
+        mem::swap(&mut self.sinks, &mut other.sinks);
+    }
+}
+
+impl AudioSink for SoundBuf {
+    fn push(&mut self, left: f32, right: f32) {
+        self.0.lock().unwrap().push_back((left, right));
+    }
+}
+
+impl SoundBuf {
+    /// Drains interleaved stereo samples into `out`, padding with silence if
+    /// the SPU has fallen behind
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let mut buf = self.0.lock().unwrap();
+        let mut missed = 0;
+        trace!("Sound buffer length: {}", buf.len());
+        for i in 0..(out.len() / 2) {
+            let (l, r) = match buf.pop_front() {
+                Some((l, r)) => (l, r),
+                None => {
+                    //warn!("Sound sample not available when queried");
+                    missed += 1;
+                    (0.0, 0.0)
+                }
+            };
+            out[i * 2 + 0] = l;
+            out[i * 2 + 1] = r;
+        }
+        if missed != 0 {
+            warn!("Missed {} samples", missed);
+        }
+    }
+
+    /// Appends every sample buffered to `out`, interleaved like `fill`
+    pub fn drain(&mut self, out: &mut Vec<f32>) {
+        let mut buf = self.0.lock().unwrap();
+        while let Some((l, r)) = buf.pop_front() {
+            out.push(l);
+            out.push(r);
+        }
+    }
+}
+
+impl Default for SoundBuf {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Default::default())))
+    }
+}
@@ -0,0 +1,97 @@
+//! A record of when DMA transfers, interrupts and timer overflows happen
+//! within a frame, to see timing problems rather than piece them together
+//! from logs.
+
+use std::mem;
+
+use super::ppu::LINE_CYCLES;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+pub enum Kind {
+    /// A DMA channel copied this many halfwords or words
+    Dma { channel: u8, units: u32 },
+    /// An interrupt was requested, by its bit in IF
+    Irq(u8),
+    /// A timer overflowed, whether or not it requests an interrupt
+    TimerOverflow(u8),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct Mark {
+    /// Cycles since the start of the frame
+    pub cycle: u64,
+    pub line: u32,
+    /// The pixel position within the line, 4 cycles each
+    pub dot: u32,
+    pub kind: Kind,
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    /// Start recording at the next frame
+    armed: bool,
+    /// The cycle the frame being recorded started at
+    start: Option<u64>,
+    marks: Vec<Mark>,
+    finished: Option<Vec<Mark>>,
+}
+
+impl Timeline {
+    /// Records the next whole frame, collected with `take` once it ends
+    pub fn record_next(&mut self) {
+        self.armed = true;
+    }
+
+    pub fn take(&mut self) -> Option<Vec<Mark>> {
+        self.finished.take()
+    }
+
+    /// Called when the PPU starts a frame at `now`
+    pub fn frame_start(&mut self, now: u64) {
+        if self.start.take().is_some() {
+            self.finished = Some(mem::replace(&mut self.marks, Vec::new()));
+        }
+        if self.armed {
+            self.armed = false;
+            self.start = Some(now);
+        }
+    }
+
+    #[inline]
+    pub fn mark(&mut self, now: u64, kind: Kind) {
+        if let Some(start) = self.start {
+            let cycle = now - start;
+            self.marks.push(Mark {
+                cycle: cycle,
+                line: (cycle / LINE_CYCLES) as u32,
+                dot: (cycle % LINE_CYCLES / 4) as u32,
+                kind: kind,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_one_frame() {
+        let mut timeline = Timeline::default();
+        timeline.mark(10, Kind::Irq(0));
+        timeline.record_next();
+        timeline.frame_start(1000);
+        timeline.mark(1000 + LINE_CYCLES * 3 + 40, Kind::TimerOverflow(1));
+        assert!(timeline.take().is_none());
+
+        timeline.frame_start(2000);
+        timeline.mark(2001, Kind::Irq(0));
+        let marks = timeline.take().unwrap();
+        assert_eq!(1, marks.len());
+        assert_eq!((3, 10), (marks[0].line, marks[0].dot));
+        assert_eq!(Kind::TimerOverflow(1), marks[0].kind);
+
+        timeline.frame_start(3000);
+        assert!(timeline.take().is_none());
+    }
+}
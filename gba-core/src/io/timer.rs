@@ -0,0 +1,191 @@
+use bit_util::{bit, extract};
+
+use mmu::Mmu;
+use scheduler::Event;
+use shared::Shared;
+
+use super::timeline::Kind;
+use super::IoReg;
+
+const TIMERS: usize = 4;
+
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
+pub struct Timers<'a> {
+    timers: [u16; TIMERS],
+    /// The first cycle the counters haven't been advanced through yet
+    last: u64,
+    /// When the pending overflow event is for, if there is one
+    next_event: Option<u64>,
+    #[serde(skip)]
+    io: Shared<IoReg<'a>>,
+}
+
+impl<'a> Timers<'a> {
+    pub fn init(&mut self, io: Shared<IoReg<'a>>) {
+        self.io = io;
+    }
+
+    /// Advances the counters through the cycles before `end`, returning
+    /// masks of the timers that overflowed and that requested an interrupt
+    fn advance(&mut self, end: u64) -> (u8, u8) {
+        let mut overflowed = 0;
+        let mut irqs = 0;
+        let mut prev_overflows = 0;
+        for i in 0..TIMERS {
+            let ctrl = self.io.reg.load32(0x100 + 4 * i as u32).get();
+            if bit(ctrl, 23) == 0 {
+                prev_overflows = 0;
+                continue;
+            }
+
+            let ticks = if bit(ctrl, 18) == 0 {
+                let period = prescaler(ctrl);
+                // count the multiples of the period in [last, end)
+                div_ceil(end, period) - div_ceil(self.last, period)
+            } else {
+                prev_overflows
+            };
+
+            let overflows = self.timers[i].advance(ctrl as u16, ticks);
+            if overflows != 0 {
+                overflowed |= 1 << i;
+                if bit(ctrl, 22) == 1 {
+                    irqs |= 1 << i;
+                }
+            }
+            prev_overflows = overflows;
+        }
+        self.last = end;
+        (overflowed, irqs)
+    }
+
+    /// Brings the counters up to date, has to be done before their control
+    /// registers change
+    pub fn catch_up(&mut self) {
+        let now = self.io.scheduler.now();
+        let (overflowed, irqs) = self.advance(now);
+        self.raise(now, overflowed, irqs);
+    }
+
+    /// Handles an overflow event scheduled by `reschedule`
+    pub fn event(&mut self, at: u64) {
+        if Some(at) != self.next_event {
+            return;
+        }
+        // The overflow happens during this cycle, so include it
+        let (overflowed, irqs) = self.advance(at + 1);
+        self.raise(at, overflowed, irqs);
+        self.reschedule();
+    }
+
+    fn raise(&mut self, at: u64, overflowed: u8, irqs: u8) {
+        for i in 0..TIMERS {
+            if overflowed & (1 << i) != 0 {
+                self.io.timeline.mark(at, Kind::TimerOverflow(i as u8));
+            }
+            if irqs & (1 << i) != 0 {
+                self.io.raise_interrupt(3 + i as u8);
+            }
+        }
+    }
+
+    /// Schedules an event for the next time a timer overflows.  Cascaded
+    /// timers can only overflow along with the timer before them, so they are
+    /// covered by its event.
+    pub fn reschedule(&mut self) {
+        let mut next = u64::max_value();
+        for i in 0..TIMERS {
+            let ctrl = self.io.reg.load32(0x100 + 4 * i as u32).get();
+            if bit(ctrl, 23) == 0 || bit(ctrl, 18) == 1 {
+                continue;
+            }
+            let period = prescaler(ctrl);
+            let ticks = 0x10000 - self.timers[i] as u64;
+            let first = div_ceil(self.last, period) * period;
+            next = next.min(first + (ticks - 1) * period);
+        }
+        if next == u64::max_value() {
+            self.next_event = None;
+            self.io.scheduler.cancel(Event::Timers);
+        } else if Some(next) != self.next_event {
+            self.next_event = Some(next);
+            self.io.scheduler.schedule(Event::Timers, next);
+        }
+    }
+
+    pub fn updated(&mut self, idx: u32, old: u16, new: u16) {
+        debug_assert!(idx <= TIMERS as u32);
+
+        if bit(old as u32, 7) == 0 && bit(new as u32, 7) == 1 {
+            self.timers[idx as usize] = self.io.get_priv(0x100 + 4 * idx);
+        }
+    }
+
+    pub fn get(&self, idx: u32) -> u16 {
+        debug_assert!(idx <= TIMERS as u32);
+        // Catch up on a copy, reads can't raise interrupts
+        let mut timers = *self;
+        timers.advance(self.io.scheduler.now());
+        timers.timers[idx as usize]
+    }
+}
+
+/// Cycles between increments of a timer that isn't cascaded
+fn prescaler(ctrl: u32) -> u64 {
+    match extract(ctrl, 16, 2) {
+        0 => 1,
+        1 => 64,
+        2 => 256,
+        3 => 1024,
+        _ => unreachable!(),
+    }
+}
+
+fn div_ceil(x: u64, y: u64) -> u64 {
+    (x + y - 1) / y
+}
+
+trait Timer {
+    fn advance(&mut self, reload: u16, ticks: u64) -> u64;
+}
+
+impl Timer for u16 {
+    /// Increments the timer `ticks` times, reloading it whenever it overflows
+    /// Returns the number of overflows
+    fn advance(&mut self, reload: u16, ticks: u64) -> u64 {
+        let to_overflow = 0x10000 - *self as u64;
+        if ticks < to_overflow {
+            *self += ticks as u16;
+            return 0;
+        }
+        let period = 0x10000 - reload as u64;
+        let rest = ticks - to_overflow;
+        *self = reload + (rest % period) as u16;
+        1 + rest / period
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let mut t = 0xfffeu16;
+        assert_eq!(0, t.advance(0xff00, 1));
+        assert_eq!(0xffff, t);
+        assert_eq!(1, t.advance(0xff00, 1));
+        assert_eq!(0xff00, t);
+        // 0x100 ticks per overflow after reloading
+        assert_eq!(3, t.advance(0xff00, 0x100 * 3 + 5));
+        assert_eq!(0xff05, t);
+    }
+
+    #[test]
+    fn test_div_ceil() {
+        assert_eq!(0, div_ceil(0, 64));
+        assert_eq!(1, div_ceil(1, 64));
+        assert_eq!(1, div_ceil(64, 64));
+        assert_eq!(2, div_ceil(65, 64));
+    }
+}
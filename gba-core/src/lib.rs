@@ -0,0 +1,73 @@
+//! The emulated GBA hardware, independent of any windowing, audio or input
+//! library.  Frontends construct a `Gba`, step it and present `Gba::frame`.
+//!
+//! ```
+//! use std::path::Path;
+//!
+//! use gba_core::mmu::MemoryUnit;
+//! use gba_core::rom::GameRom;
+//! use gba_core::{Gba, Options};
+//!
+//! // testdata/tiny.s draws a red pixel and counts up a word in IWRAM
+//! let rom = GameRom::new(Path::new(concat!(
+//!     env!("CARGO_MANIFEST_DIR"),
+//!     "/testdata/tiny.gba"
+//! )))
+//! .unwrap();
+//! // Without a BIOS image the BIOS has to be skipped
+//! let opts = Options {
+//!     direct_boot: true,
+//!     ..Default::default()
+//! };
+//! let mut gba = Gba::new(rom, Default::default(), &opts);
+//!
+//! for _ in 0..2 {
+//!     assert!(gba.run_frame());
+//! }
+//!
+//! // Pixels are B, G, R, X bytes
+//! let frame = gba.frame();
+//! assert!(frame[2] > 0 && frame[1] == 0 && frame[0] == 0);
+//!
+//! let count = gba.mmu.load32(0x0300_0000);
+//! assert!(count > 0);
+//! gba.mmu.set32(0x0300_0000, 0);
+//! gba.run_frame();
+//! assert!(gba.mmu.load32(0x0300_0000) < count);
+//! ```
+
+extern crate arm7tdmi_rs;
+extern crate arraydeque;
+extern crate bincode;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+extern crate memmap;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate zip;
+
+pub mod bit_util;
+pub mod shared;
+
+pub mod cheats;
+pub mod counts;
+pub mod cpu;
+pub mod elf;
+pub mod io;
+pub mod mmu;
+pub mod patch;
+pub mod rom;
+pub mod rules;
+pub mod scheduler;
+pub mod symbols;
+
+mod embed;
+mod gba;
+
+pub use embed::GbaCore;
+pub use gba::{Gba, Options, CYCLES_PER_FRAME, CYCLES_PER_SEC};
+pub use io::key::{Buttons, InputProvider};
+pub use io::ppu::VideoSink;
+pub use io::spu::AudioSink;
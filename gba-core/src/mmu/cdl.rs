@@ -0,0 +1,114 @@
+//! A code/data log of the cartridge ROM, noting which bytes ran as ARM or
+//! Thumb code and which were read as data, for ROM hackers' disassemblers.
+//!
+//! Logs are written a byte per ROM byte, with bit 0 set for ARM code, bit 1
+//! for Thumb code and bit 2 for data.  Only reads the CPU and DMA make are
+//! logged, not those of a debugger.
+
+pub const ARM: u8 = 1;
+pub const THUMB: u8 = 2;
+pub const DATA: u8 = 4;
+
+/// Where the cartridge ROM and its mirrors are mapped
+const ROM_START: u32 = 0x0800_0000;
+const ROM_END: u32 = 0x0e00_0000;
+
+pub struct CodeDataLog {
+    flags: Vec<u8>,
+    /// The instruction running and the one prefetched after it, whose reads
+    /// aren't data
+    fetch: (u32, u32),
+}
+
+impl CodeDataLog {
+    /// An empty log of a `len` byte ROM
+    pub fn new(len: usize) -> Self {
+        CodeDataLog {
+            flags: vec![0; len],
+            fetch: (0, 0),
+        }
+    }
+
+    /// Carries on from an earlier log of the same ROM
+    pub fn merge(&mut self, log: &[u8]) -> Result<(), String> {
+        if log.len() != self.flags.len() {
+            return Err(format!(
+                "log is of a {} byte ROM, not {} bytes",
+                log.len(),
+                self.flags.len()
+            ));
+        }
+        for (flags, &old) in self.flags.iter_mut().zip(log.iter()) {
+            *flags |= old;
+        }
+        Ok(())
+    }
+
+    pub fn flags(&self) -> &[u8] {
+        &self.flags
+    }
+
+    fn mark(&mut self, addr: u32, size: u32, flag: u8) {
+        if addr < ROM_START || addr >= ROM_END {
+            return;
+        }
+        let off = (addr & 0x1ff_ffff) as usize;
+        let end = (off + size as usize).min(self.flags.len());
+        for flags in self.flags[off.min(end)..end].iter_mut() {
+            *flags |= flag;
+        }
+    }
+
+    /// Notes the instruction at `addr` running
+    pub fn exec(&mut self, addr: u32, thumb: bool) {
+        let (size, flag) = if thumb { (2, THUMB) } else { (4, ARM) };
+        self.mark(addr, size, flag);
+        self.fetch = (addr, size);
+    }
+
+    /// Notes a `size` byte read of `addr`, aligned as the bus aligns it
+    pub fn read(&mut self, addr: u32, size: u32) {
+        let addr = addr & !(size - 1);
+        let (pc, insn) = self.fetch;
+        if size == insn && (addr == pc || addr == pc.wrapping_add(insn)) {
+            return;
+        }
+        self.mark(addr, size, DATA);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log() {
+        let mut cdl = CodeDataLog::new(0x100);
+        cdl.exec(0x0800_0000, false);
+        cdl.read(0x0800_0000, 4);
+        cdl.read(0x0800_0004, 4);
+        assert_eq!(&[ARM; 4], &cdl.flags()[..4]);
+        assert_eq!(&[0; 4], &cdl.flags()[4..8]);
+
+        cdl.exec(0x0a00_0010, true);
+        cdl.read(0x0a00_0012, 2);
+        cdl.read(0x0800_0080, 4);
+        cdl.read(0x0800_0093, 2);
+        assert_eq!(&[THUMB, THUMB, 0, 0], &cdl.flags()[0x10..0x14]);
+        assert_eq!(&[DATA; 4], &cdl.flags()[0x80..0x84]);
+        assert_eq!(&[DATA; 2], &cdl.flags()[0x92..0x94]);
+
+        // Past the end of the ROM, and outside it
+        cdl.read(0x0800_00fe, 4);
+        cdl.read(0x0900_0000, 4);
+        cdl.read(0x0300_0000, 4);
+        assert_eq!(DATA, cdl.flags()[0xff]);
+
+        let mut old = vec![0; 0x100];
+        old[0x20] = THUMB;
+        cdl.merge(&old).unwrap();
+        assert_eq!(THUMB, cdl.flags()[0x20]);
+        assert_eq!(ARM, cdl.flags()[0]);
+        assert!(cdl.merge(&[0; 4]).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::cell::Cell;
+
+use cpu::Cpu;
+use rom::GameRom;
+use shared::Shared;
+
+use super::{Gba, MemoryRead, Mmu};
+
+const BIOS_SIZE: u32 = 0x4000;
+
+#[derive(Default)]
+pub struct Bios<'a> {
+    bios: GameRom,
+    cpu: Shared<Cpu<Gba<'a>>>,
+    /// The last word on the BIOS bus while executing from it, which is what
+    /// reads see once it's protected.  Copy protection checks look for the
+    /// opcode left here by the BIOS's return from a SWI.
+    last: Cell<u32>,
+}
+
+impl<'a> Bios<'a> {
+    pub fn new(bios: GameRom) -> Self {
+        Self {
+            bios: bios,
+            cpu: Default::default(),
+            last: Cell::new(0),
+        }
+    }
+
+    /// Whether the BIOS can be read, latching the word at `addr` if it can.
+    /// The BIOS bus is 32 bits wide, so narrower reads latch the whole word.
+    #[inline]
+    fn latch(&self, addr: u32) -> bool {
+        if self.cpu.get_prefetch_addr() >= BIOS_SIZE {
+            return false;
+        }
+        if let MemoryRead::Value(v) = self.bios.load32(addr & !3) {
+            self.last.set(v);
+        }
+        true
+    }
+
+    pub fn init(&mut self, cpu: Shared<Cpu<Gba<'a>>>) {
+        self.cpu = cpu;
+    }
+}
+
+impl<'a> Mmu for Bios<'a> {
+    fn load8(&self, addr: u32) -> MemoryRead<u8> {
+        if addr >= BIOS_SIZE {
+            return MemoryRead::Open;
+        }
+        if self.latch(addr) {
+            self.bios.load8(addr)
+        } else {
+            // Not allowed to read from BIOS memory
+            MemoryRead::Value((self.last.get() >> ((addr & 3) * 8)) as u8)
+        }
+    }
+
+    fn set8(&mut self, addr: u32, val: u8) {
+        self.bios.set8(addr, val)
+    }
+
+    fn load16(&self, addr: u32) -> MemoryRead<u16> {
+        if addr >= BIOS_SIZE {
+            return MemoryRead::Open;
+        }
+        if self.latch(addr) {
+            self.bios.load16(addr)
+        } else {
+            // Not allowed to read from BIOS memory
+            MemoryRead::Value((self.last.get() >> ((addr & 3) * 8)) as u16)
+        }
+    }
+
+    fn set16(&mut self, addr: u32, val: u16) {
+        self.bios.set16(addr, val)
+    }
+
+    fn load32(&self, addr: u32) -> MemoryRead<u32> {
+        if addr >= BIOS_SIZE {
+            return MemoryRead::Open;
+        }
+        if self.latch(addr) {
+            self.bios.load32(addr)
+        } else {
+            // Not allowed to read from BIOS memory
+            MemoryRead::Value(self.last.get())
+        }
+    }
+
+    fn set32(&mut self, addr: u32, val: u32) {
+        self.bios.set32(addr, val)
+    }
+}
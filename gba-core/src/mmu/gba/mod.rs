@@ -0,0 +1,896 @@
+// FIXME: move unaligned access logic here from CPU
+use std::cell::RefCell;
+use std::cmp;
+use std::mem;
+use std::ptr;
+use std::slice;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use shared::Shared;
+
+use cpu::Cpu;
+
+use rom::{GameRom, RomPatch};
+
+use io::IoReg;
+
+use super::cdl::CodeDataLog;
+use super::ram::Ram;
+use super::watch::{WatchHit, Watchpoint};
+use super::{MemoryRead, MemoryUnit, Mmu};
+
+mod bios;
+mod save;
+mod tiles;
+mod timing;
+
+use self::bios::Bios;
+pub use self::tiles::Tiles;
+use self::timing::Timing;
+
+use self::save::Eeprom;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum MemoryRange {
+    Bios,
+    BoardWram,
+    ChipWram,
+    IoRegister,
+    Palette,
+    VideoRam,
+    ObjectAttr,
+    GamePakRom,
+    GamePakEe,
+    GamePakSram,
+    DebugRam,
+    Unused,
+}
+
+impl MemoryRange {
+    #[inline]
+    fn bounds(&self) -> (u32, u32) {
+        use self::MemoryRange::*;
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match *self {
+            Bios        => (0x00000000, 0x00004000),
+            BoardWram   => (0x02000000, 0x02040000),
+            ChipWram    => (0x03000000, 0x03008000),
+            IoRegister  => (0x04000000, 0x04008004),
+            Palette     => (0x05000000, 0x05000400),
+            VideoRam    => (0x06000000, 0x06018000),
+            ObjectAttr  => (0x07000000, 0x07000400),
+            GamePakRom  => (0x08000000, 0x0E000000),
+            GamePakEe   => (0x0D000000, 0x0E000000),
+            GamePakSram => (0x0E000000, 0x0E010000),
+            DebugRam    => (0x01000000, 0x02000000),
+            Unused      => (0x00000000, 0xFFFFFFFF),
+        }
+    }
+
+    pub fn convert_addr(&self, addr: u32) -> u32 {
+        use self::MemoryRange::*;
+        match *self {
+            Bios => addr,
+            BoardWram => addr & 0x3ffff, // mirroring
+            ChipWram => addr & 0x7fff,
+            IoRegister => {
+                if addr & 0xfffc == 0x800 {
+                    addr & 0xffff // only mirrored register
+                } else {
+                    addr & 0xffffff
+                }
+            }
+            Palette => addr & 0x3ff,
+            VideoRam => {
+                // Weird mirroring here
+                let chunk = addr & 0x1ffff;
+                // The upper 64k is two 32k mirrors
+                if chunk < 0x18000 {
+                    chunk
+                } else {
+                    chunk - 0x8000
+                }
+            }
+            ObjectAttr => addr & 0x3ff,
+            GamePakRom => addr & 0x1ffffff,
+            GamePakEe => addr,
+            GamePakSram => addr & 0xffff,
+            _ => addr - self.bounds().0,
+        }
+    }
+
+    pub fn match_addr(addr: u32) -> MemoryRange {
+        use self::MemoryRange::*;
+        use bit_util::extract;
+        // Nothing is mapped above the low 28 bits, those reads are open bus
+        if addr >> 28 != 0 {
+            return Unused;
+        }
+        match extract(addr, 24, 4) {
+            0x0 => Bios,
+            0x1 => DebugRam,
+            0x2 => BoardWram,
+            0x3 => ChipWram,
+            0x4 => IoRegister,
+            0x5 => Palette,
+            0x6 => VideoRam,
+            0x7 => ObjectAttr,
+            0x8 | 0x9 | 0xA | 0xB | 0xC => GamePakRom,
+            0xD => GamePakEe,
+            0xE | 0xF => GamePakSram,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// The gamepak wait state control, relative to the IO registers
+const WAITCNT: u32 = 0x204;
+
+/// The internal memory control register's value at reset
+pub const MEMCNT_INITIAL: u32 = 0x0d00_0020;
+
+const PAGE_BITS: u32 = 16;
+const PAGE_SIZE: u32 = 1 << PAGE_BITS;
+// Only the low 28 bits of an address select memory
+const PAGE_COUNT: usize = 1 << (28 - PAGE_BITS);
+const REGION_PAGES: u32 = 1 << (24 - PAGE_BITS);
+
+/// A 64KB page of the address space that is plain memory, so accesses can
+/// skip the region matching.  `mask` handles memories that are smaller than
+/// a page and mirrored within it.
+#[derive(Copy, Clone)]
+struct Page {
+    ptr: *mut u8,
+    mask: u32,
+    writable: bool,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page {
+            ptr: ptr::null_mut(),
+            mask: 0,
+            writable: false,
+        }
+    }
+}
+
+/// Implements the memory mapping for a GBA system
+#[derive(Serialize, Deserialize)]
+pub struct Gba<'a> {
+    #[serde(skip)]
+    pub bios: Bios<'a>,
+    /// Normally 256K, but can be enlarged for homebrew development
+    pub bram: Ram,
+    pub cram: Ram,
+    pub pram: Ram,
+    pub vram: Ram,
+    /// VRAM unpacked for the renderer, rebuilt from `vram` when connected
+    #[serde(skip)]
+    pub tiles: Tiles,
+    pub oam: Ram,
+    #[serde(skip)]
+    pub rom: GameRom,
+    pub gram: Ram,
+
+    #[serde(skip)]
+    pub io: Shared<IoReg<'a>>,
+    pub ee: Eeprom<'a>,
+    /// Extra RAM at 0x01000000 for homebrew development, empty on hardware
+    pub dram: Ram,
+    /// The internal memory control register at 0x4000800, which can turn
+    /// off the work RAMs
+    memcnt: u32,
+    /// How long the CPU's accesses take, rebuilt from WAITCNT and MEMCNT
+    /// when connected
+    #[serde(skip)]
+    timing: Timing,
+
+    #[serde(skip)]
+    pub cpu: Shared<Cpu<Gba<'a>>>,
+
+    /// Fast path for RAM and ROM, everything else goes through `get_range`
+    #[serde(skip)]
+    pages: Vec<Page>,
+
+    #[serde(skip)]
+    watches: Vec<Watchpoint>,
+    /// Where each watchpoint starts, resolved past mirroring
+    #[serde(skip)]
+    watch_ranges: Vec<(MemoryRange, u32)>,
+    /// Accesses watchpoints caught since the last `take_watch_hits`
+    #[serde(skip)]
+    watch_hits: RefCell<Vec<WatchHit>>,
+    /// Only kept when asked for
+    #[serde(skip)]
+    cdl: Option<RefCell<CodeDataLog>>,
+}
+
+impl<'a> Gba<'a> {
+    pub fn new(rom: GameRom, bios: GameRom) -> Gba<'a> {
+        let vram = Ram::new(128 * 1024);
+        let tiles = Tiles::new(vram.as_slice());
+        Gba {
+            bios: Bios::new(bios),
+            bram: Ram::new(256 * 1024),
+            cram: Ram::new(32 * 1024),
+            pram: Ram::new(1024),
+            vram: vram,
+            tiles: tiles,
+            oam: Ram::new(1024),
+            rom: rom,
+            ee: Default::default(),
+            gram: Ram::new(64 * 1024),
+            dram: Ram::new(0),
+            memcnt: MEMCNT_INITIAL,
+            timing: Timing::new(0, MEMCNT_INITIAL),
+            io: Shared::empty(),
+            cpu: Default::default(),
+            pages: Vec::new(),
+            watches: Vec::new(),
+            watch_ranges: Vec::new(),
+            watch_hits: Default::default(),
+            cdl: None,
+        }
+    }
+
+    /// Resizes EWRAM and the debug RAM at 0x01000000, which hardware doesn't
+    /// have.  Sizes must be powers of two up to 16M, the memories are
+    /// mirrored across their regions like the others.
+    pub fn extend_memory(&mut self, ewram_size: Option<usize>, debug_size: Option<usize>) {
+        for &size in ewram_size.iter().chain(debug_size.iter()) {
+            assert!(size.is_power_of_two() && size <= 0x100_0000);
+        }
+        if let Some(size) = ewram_size {
+            self.bram = Ram::new(size);
+        }
+        if let Some(size) = debug_size {
+            self.dram = Ram::new(size);
+        }
+        self.map_pages();
+    }
+
+    /// Fills both work RAMs and the debug RAM with `byte`, for games that
+    /// read them before writing
+    pub fn fill_work_ram(&mut self, byte: u8) {
+        self.bram.fill(byte);
+        self.cram.fill(byte);
+        self.dram.fill(byte);
+    }
+
+    /// Called when the internal memory control register is written.  Bit 0
+    /// disables both work RAMs, and clearing bit 5 replaces EWRAM with
+    /// mirrors of IWRAM.  Bits 24-27 set EWRAM's wait states, 15 locks up
+    /// hardware.
+    pub fn set_memcnt(&mut self, val: u32) {
+        if val >> 24 & 0xf == 0xf {
+            warn!("EWRAM wait control of 15 locks up hardware");
+        }
+        let changed = (self.memcnt ^ val) & 0x21 != 0;
+        self.memcnt = val;
+        let waitcnt = self.timing.waitcnt();
+        self.timing.update(waitcnt, val);
+        if changed {
+            self.map_pages();
+        }
+    }
+
+    /// Called when WAITCNT, the gamepak's wait state control, is written
+    pub fn set_waitcnt(&mut self, val: u16) {
+        self.timing.update(val, self.memcnt);
+    }
+
+    /// The cycles the CPU's accesses have taken since the last call
+    #[inline]
+    pub fn take_cycles(&self) -> u32 {
+        self.timing.take()
+    }
+
+    #[inline]
+    fn wram_enabled(&self) -> bool {
+        self.memcnt & 1 == 0
+    }
+
+    #[inline]
+    fn ewram_enabled(&self) -> bool {
+        self.memcnt & 0x20 != 0
+    }
+
+    /// Notes accesses to any of `watches`, or their mirrors
+    pub fn set_watches(&mut self, watches: Vec<Watchpoint>) {
+        self.watch_ranges = watches
+            .iter()
+            .map(|watch| {
+                let range = MemoryRange::match_addr(watch.addr);
+                (range, range.convert_addr(watch.addr))
+            })
+            .collect();
+        self.watches = watches;
+        self.watch_hits.borrow_mut().clear();
+    }
+
+    pub fn watches(&self) -> &[Watchpoint] {
+        &self.watches
+    }
+
+    pub fn take_watches(&mut self) -> Vec<Watchpoint> {
+        self.watch_ranges.clear();
+        mem::replace(&mut self.watches, Vec::new())
+    }
+
+    #[inline]
+    pub fn watching(&self) -> bool {
+        !self.watches.is_empty()
+    }
+
+    /// The accesses caught since the last call, without their PCs
+    pub fn take_watch_hits(&self) -> Vec<WatchHit> {
+        mem::replace(&mut *self.watch_hits.borrow_mut(), Vec::new())
+    }
+
+    #[inline]
+    fn check_watch(&self, addr: u32, size: u32, value: u32, write: bool) {
+        if self.watches.is_empty() {
+            return;
+        }
+        // Unaligned accesses are made aligned
+        let addr = addr & !(size - 1);
+        let range = MemoryRange::match_addr(addr);
+        let naddr = range.convert_addr(addr);
+        for (watch, &(start_range, start)) in self.watches.iter().zip(self.watch_ranges.iter()) {
+            let overlaps = start_range == range
+                && naddr < start.wrapping_add(watch.len)
+                && start < naddr + size;
+            if overlaps && if write { watch.write } else { watch.read } {
+                self.watch_hits.borrow_mut().push(WatchHit {
+                    pc: 0,
+                    addr: addr,
+                    size: size,
+                    value: value,
+                    write: write,
+                    stop: watch.stop,
+                });
+            }
+        }
+    }
+
+    /// Starts logging which ROM bytes are run as code and read as data to
+    /// `cdl`, or stops
+    pub fn set_cdl(&mut self, cdl: Option<CodeDataLog>) {
+        self.cdl = cdl.map(RefCell::new);
+    }
+
+    pub fn take_cdl(&mut self) -> Option<CodeDataLog> {
+        self.cdl.take().map(RefCell::into_inner)
+    }
+
+    pub fn init(&mut self, cpu: Shared<Cpu<Gba<'a>>>, io: Shared<IoReg<'a>>) {
+        self.cpu = cpu;
+        self.io = io;
+        self.bios.init(cpu);
+        self.ee.init(io);
+        self.tiles = Tiles::new(self.vram.as_slice());
+        let waitcnt = self.io.load16(WAITCNT).get();
+        self.set_waitcnt(waitcnt);
+        self.map_pages();
+    }
+
+    /// Rebuilds the page table, which points straight into the memories.
+    /// This must be redone whenever one of them is replaced or the ROM
+    /// patches change, as patched ROM is only readable through `get_range`.
+    pub fn map_pages(&mut self) {
+        use self::MemoryRange::*;
+
+        let mut pages = vec![Page::default(); PAGE_COUNT];
+        if self.wram_enabled() {
+            if self.ewram_enabled() {
+                map_ram(&mut pages, BoardWram, &mut self.bram);
+            } else {
+                map_ram(&mut pages, BoardWram, &mut self.cram);
+            }
+            map_ram(&mut pages, ChipWram, &mut self.cram);
+        }
+        if self.dram.len() > 0 {
+            map_ram(&mut pages, DebugRam, &mut self.dram);
+        }
+        map_ram(&mut pages, Palette, &mut self.pram);
+        map_ram(&mut pages, ObjectAttr, &mut self.oam);
+
+        // VRAM repeats every 128KB, with the last 32KB mirroring the 32KB
+        // before it.  Writes go through `set_range` to keep `tiles` current.
+        let vram = self.vram.as_mut_ptr();
+        let first = VideoRam.bounds().0 >> PAGE_BITS;
+        for i in 0..REGION_PAGES {
+            pages[(first + i) as usize] = Page {
+                ptr: unsafe { vram.offset(((i & 1) << PAGE_BITS) as isize) },
+                mask: if i & 1 == 0 { 0xffff } else { 0x7fff },
+                writable: false,
+            };
+        }
+
+        if !self.rom.has_patches() {
+            let rom = self.rom.as_ptr() as *mut u8;
+            let len = self.rom.len() as u32;
+            let (start, end) = GamePakRom.bounds();
+            // The EEPROM lives at the top of the ROM space
+            let end = cmp::min(end, GamePakEe.bounds().0);
+            for page in (start >> PAGE_BITS)..(end >> PAGE_BITS) {
+                let off = (page << PAGE_BITS) & 0x1ffffff;
+                // Past the end of the ROM reads return the address instead
+                if off + PAGE_SIZE <= len {
+                    pages[page as usize] = Page {
+                        ptr: unsafe { rom.offset(off as isize) },
+                        mask: PAGE_SIZE - 1,
+                        writable: false,
+                    };
+                }
+            }
+        }
+
+        self.pages = pages;
+    }
+
+    /// The memory backing `addr` if its page is mapped and a `size` byte
+    /// access there is aligned
+    #[inline]
+    fn page_ptr(&self, addr: u32, size: u32, write: bool) -> Option<*mut u8> {
+        if addr & (size - 1) != 0 {
+            return None;
+        }
+        match self.pages.get((addr >> PAGE_BITS) as usize) {
+            Some(page) if !page.ptr.is_null() && (page.writable || !write) => {
+                Some(unsafe { page.ptr.offset((addr & page.mask) as isize) })
+            }
+            _ => None,
+        }
+    }
+
+    /// Replaces the ROM patches in `old` with those in `new`, e.g. as cheats
+    /// are turned on and off, and remaps the ROM to match
+    pub fn repatch_rom(&mut self, old: &[RomPatch], new: &[RomPatch]) {
+        for &patch in old.iter() {
+            self.rom.remove_patch(patch);
+        }
+        for &patch in new.iter() {
+            self.rom.apply_patch(patch);
+        }
+        self.map_pages();
+    }
+
+    /// Places a multiboot image at the start of EWRAM, as the BIOS would after
+    /// receiving it over the serial port.  The rest of EWRAM keeps what it
+    /// was filled with.
+    pub fn load_multiboot(&mut self, image: &[u8]) -> Result<(), String> {
+        if image.len() > self.bram.len() {
+            return Err(format!(
+                "multiboot image is {}K, larger than the {}K of EWRAM",
+                image.len() / 1024,
+                self.bram.len() / 1024
+            ));
+        }
+        self.bram.copy_from(image);
+        Ok(())
+    }
+
+    pub fn get_range(&self, addr: u32) -> Option<(u32, &Mmu)> {
+        use self::MemoryRange::*;
+        let range = MemoryRange::match_addr(addr);
+        let naddr = range.convert_addr(addr);
+        match range {
+            Bios => Some((naddr, &self.bios)),
+            BoardWram | ChipWram if !self.wram_enabled() => None,
+            BoardWram if !self.ewram_enabled() => Some((addr & 0x7fff, &self.cram)),
+            BoardWram => Some((addr & (self.bram.len() as u32 - 1), &self.bram)),
+            ChipWram => Some((naddr, &self.cram)),
+            IoRegister => Some((naddr, &*self.io)),
+            Palette => Some((naddr, &self.pram)),
+            VideoRam => Some((naddr, &self.vram)),
+            ObjectAttr => Some((naddr, &self.oam)),
+            GamePakRom => Some((naddr, &self.rom)),
+            GamePakEe => Some((naddr, &self.ee)),
+            GamePakSram => Some((naddr, &self.gram)),
+            DebugRam if self.dram.len() > 0 => {
+                Some((naddr & (self.dram.len() as u32 - 1), &self.dram))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_range_mut(&mut self, addr: u32) -> Option<(u32, &mut Mmu)> {
+        use self::MemoryRange::*;
+        let range = MemoryRange::match_addr(addr);
+        let naddr = range.convert_addr(addr);
+        match range {
+            Bios => Some((naddr, &mut self.bios)),
+            BoardWram | ChipWram if !self.wram_enabled() => None,
+            BoardWram if !self.ewram_enabled() => Some((addr & 0x7fff, &mut self.cram)),
+            BoardWram => Some((addr & (self.bram.len() as u32 - 1), &mut self.bram)),
+            ChipWram => Some((naddr, &mut self.cram)),
+            IoRegister => Some((naddr, &mut *self.io)),
+            Palette => Some((naddr, &mut self.pram)),
+            VideoRam => Some((naddr, &mut self.vram)),
+            ObjectAttr => Some((naddr, &mut self.oam)),
+            GamePakRom => Some((naddr, &mut self.rom)),
+            GamePakEe => Some((naddr, &mut self.ee)),
+            GamePakSram => Some((naddr, &mut self.gram)),
+            DebugRam if self.dram.len() > 0 => {
+                Some((naddr & (self.dram.len() as u32 - 1), &mut self.dram))
+            }
+            _ => None,
+        }
+    }
+
+    /// Writes through `get_range_mut`.  Writes to a flashcart's ROM are kept
+    /// as patches, which the page table can't see, so the first one unmaps
+    /// the ROM pages.  VRAM writes also update the unpacked tiles.
+    #[inline]
+    fn set_range<F: FnOnce(&mut Mmu, u32)>(&mut self, addr: u32, set: F) {
+        let unpatched = !self.rom.has_patches();
+        match self.get_range_mut(addr) {
+            Some((naddr, mmu)) => set(mmu, naddr),
+            None => warning(addr),
+        }
+        if unpatched && self.rom.has_patches() {
+            self.map_pages();
+        }
+        if MemoryRange::match_addr(addr) == MemoryRange::VideoRam {
+            // Writes are at most a word, and aligned within it
+            let naddr = MemoryRange::VideoRam.convert_addr(addr) & !3;
+            for addr in naddr..naddr + 4 {
+                self.tiles.update(addr, self.vram.as_slice()[addr as usize]);
+            }
+        }
+    }
+
+    /// The value left on the bus by the last prefetch, which is what reads
+    /// of unmapped memory return
+    fn get_open_val(&self) -> u32 {
+        use self::MemoryRead::*;
+
+        let addr = self.cpu.get_prefetch_addr();
+        // Read the prefetch directly, so executing from unmapped memory can't
+        // recurse back into here
+        if self.cpu.thumb_mode() {
+            let addr = addr & !1;
+            match self.get_range(addr).map(|(naddr, mmu)| mmu.load16(naddr)) {
+                Some(Value(r)) => (r as u32) | ((r as u32) << 16),
+                _ => 0,
+            }
+        } else {
+            match self
+                .get_range(addr & !3)
+                .map(|(naddr, mmu)| mmu.load32(naddr))
+            {
+                Some(Value(r)) => r,
+                _ => 0,
+            }
+        }
+    }
+}
+
+impl<'a> MemoryUnit for Gba<'a> {
+    #[inline]
+    fn wait(&self, addr: u32, size: u32) {
+        self.timing.access(addr, size);
+    }
+
+    #[inline]
+    fn log_exec(&self, addr: u32, thumb: bool) {
+        if let Some(ref cdl) = self.cdl {
+            cdl.borrow_mut().exec(addr, thumb);
+        }
+    }
+
+    #[inline]
+    fn log_read(&self, addr: u32, size: u32) {
+        if let Some(ref cdl) = self.cdl {
+            cdl.borrow_mut().read(addr, size);
+        }
+    }
+
+    fn load8(&self, addr: u32) -> u8 {
+        use self::MemoryRead::*;
+
+        if let Some(ptr) = self.page_ptr(addr, 1, false) {
+            let res = unsafe { *ptr };
+            debug!("load08\t@ {:#010x}: {:#04x}", addr, res);
+            self.check_watch(addr, 1, res as u32, false);
+            return res;
+        }
+
+        let val = match self.get_range(addr) {
+            Some((naddr, mmu)) => mmu.load8(naddr),
+            None => {
+                warning(addr);
+                Open
+            }
+        };
+        let res = match val {
+            Value(v) => v,
+            Open => (self.get_open_val() >> ((addr & 3) * 8)) as u8,
+        };
+        debug!("load08\t@ {:#010x}: {:#04x}", addr, res);
+        self.check_watch(addr, 1, res as u32, false);
+        res
+    }
+
+    fn set8(&mut self, addr: u32, val: u8) {
+        debug!("set08\t@ {:#010x}: {:#04x}", addr, val);
+        self.check_watch(addr, 1, val as u32, true);
+        if let Some(ptr) = self.page_ptr(addr, 1, true) {
+            unsafe { *ptr = val };
+            return;
+        }
+        self.set_range(addr, |mmu, naddr| mmu.set8(naddr, val));
+    }
+
+    fn load16(&self, addr: u32) -> u16 {
+        use self::MemoryRead::*;
+
+        if let Some(ptr) = self.page_ptr(addr, 2, false) {
+            let res = LittleEndian::read_u16(unsafe { slice::from_raw_parts(ptr, 2) });
+            debug!("load16\t@ {:#010x}: {:#06x}", addr, res);
+            self.check_watch(addr, 2, res as u32, false);
+            return res;
+        }
+
+        let val = match self.get_range(addr) {
+            Some((naddr, mmu)) => mmu.load16(naddr),
+            None => {
+                warning(addr);
+                Open
+            }
+        };
+        let res = match val {
+            Value(v) => v,
+            Open => (self.get_open_val() >> ((addr & 2) * 8)) as u16,
+        };
+        debug!("load16\t@ {:#010x}: {:#06x}", addr, res);
+        self.check_watch(addr, 2, res as u32, false);
+        res
+    }
+
+    fn set16(&mut self, addr: u32, val: u16) {
+        debug!("set16\t@ {:#010x}: {:#06x}", addr, val);
+        self.check_watch(addr, 2, val as u32, true);
+        if let Some(ptr) = self.page_ptr(addr, 2, true) {
+            LittleEndian::write_u16(unsafe { slice::from_raw_parts_mut(ptr, 2) }, val);
+            return;
+        }
+        self.set_range(addr, |mmu, naddr| mmu.set16(naddr, val));
+    }
+
+    fn load32(&self, addr: u32) -> u32 {
+        use self::MemoryRead::*;
+
+        if let Some(ptr) = self.page_ptr(addr, 4, false) {
+            let res = LittleEndian::read_u32(unsafe { slice::from_raw_parts(ptr, 4) });
+            debug!("load32\t@ {:#010x}: {:#010x}", addr, res);
+            self.check_watch(addr, 4, res as u32, false);
+            return res;
+        }
+
+        let val = match self.get_range(addr) {
+            Some((naddr, mmu)) => mmu.load32(naddr),
+            None => {
+                warning(addr);
+                Open
+            }
+        };
+        let res = match val {
+            Value(v) => v,
+            Open => self.get_open_val(),
+        };
+        debug!("load32\t@ {:#010x}: {:#010x}", addr, res);
+        self.check_watch(addr, 4, res as u32, false);
+        res
+    }
+
+    fn set32(&mut self, addr: u32, val: u32) {
+        debug!("set32\t@ {:#010x}: {:#010x}", addr, val);
+        self.check_watch(addr, 4, val, true);
+        if let Some(ptr) = self.page_ptr(addr, 4, true) {
+            LittleEndian::write_u32(unsafe { slice::from_raw_parts_mut(ptr, 4) }, val);
+            return;
+        }
+        self.set_range(addr, |mmu, naddr| mmu.set32(naddr, val));
+    }
+}
+
+/// Maps `ram` into every page of `range`, mirroring it to fill the region
+fn map_ram(pages: &mut [Page], range: MemoryRange, ram: &mut Ram) {
+    let size = ram.len() as u32;
+    let base = ram.as_mut_ptr();
+    let first = range.bounds().0 >> PAGE_BITS;
+    for i in 0..REGION_PAGES {
+        let off = (i << PAGE_BITS) & (size - 1);
+        pages[(first + i) as usize] = Page {
+            ptr: unsafe { base.offset(off as isize) },
+            mask: cmp::min(size, PAGE_SIZE) - 1,
+            writable: true,
+        };
+    }
+}
+
+fn warning(addr: u32) {
+    warn!("Access to unmapped memory: {:#010x}", addr);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pages_match_ranges() {
+        let mut mmu = Gba::new(Default::default(), Default::default());
+        mmu.map_pages();
+
+        let addrs = [
+            0x0200_0000,
+            0x0203_fffc,
+            0x0204_0010,
+            0x0300_7ffc,
+            0x0301_8000,
+            0x0500_03fc,
+            0x0500_0400,
+            0x0601_0004,
+            0x0601_8004,
+            0x0603_7ffc,
+            0x0700_0400,
+        ];
+        for (i, &addr) in addrs.iter().enumerate() {
+            mmu.set32(addr, i as u32 * 0x0101_0101 + 0x1020_3040);
+            for &other in addrs.iter() {
+                let (naddr, range) = mmu.get_range(other).unwrap();
+                assert_eq!(range.load32(naddr).get(), mmu.load32(other));
+                assert_eq!(range.load16(naddr + 2).get(), mmu.load16(other + 2));
+                assert_eq!(range.load8(naddr + 1).get(), mmu.load8(other + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_mirrors() {
+        let mut mmu = Gba::new(Default::default(), Default::default());
+        mmu.map_pages();
+
+        let mirrors = [
+            (0x0200_0010, 0x0204_0010),
+            (0x0200_0010, 0x02fc_0010),
+            (0x0300_7ff0, 0x03ff_fff0),
+            (0x0500_0020, 0x0500_0420),
+            (0x0500_0020, 0x05ff_fc20),
+            (0x0600_0000, 0x0602_0000),
+            (0x0601_0004, 0x0601_8004),
+            (0x0601_7ffc, 0x0601_fffc),
+            (0x0601_7ffc, 0x06ff_fffc),
+            (0x0700_0100, 0x0700_0500),
+            (0x0e00_0010, 0x0f01_0010),
+        ];
+        for (i, &(addr, mirror)) in mirrors.iter().enumerate() {
+            let val = i as u32 * 0x0101_0101 + 0x1020_3040;
+            mmu.set32(mirror, val);
+            assert_eq!(val, mmu.load32(addr), "{:#010x}", mirror);
+            mmu.set32(addr, !val);
+            assert_eq!(!val, mmu.load32(mirror), "{:#010x}", mirror);
+        }
+
+        // Only the low 28 bits select memory
+        assert!(mmu.get_range(0x1200_0000).is_none());
+        assert!(mmu.page_ptr(0x1200_0000, 4, false).is_none());
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut mmu = Gba::new(Default::default(), Default::default());
+        mmu.map_pages();
+        mmu.set_watches(vec![Watchpoint::writes(0x0200_0012, 1)]);
+
+        mmu.set32(0x0200_000c, 0);
+        assert!(mmu.take_watch_hits().is_empty());
+        mmu.set32(0x0200_0010, 0x1234_5678);
+        let hits = mmu.take_watch_hits();
+        assert_eq!(1, hits.len());
+        assert_eq!(
+            (0x0200_0010, 4, 0x1234_5678),
+            (hits[0].addr, hits[0].size, hits[0].value)
+        );
+        assert!(mmu.take_watch_hits().is_empty());
+        mmu.set8(0x0204_0012, 0);
+        assert_eq!(1, mmu.take_watch_hits().len());
+        mmu.set16(0x0300_0012, 0);
+        assert!(mmu.take_watch_hits().is_empty());
+        // Only writes are watched
+        mmu.load8(0x0200_0012);
+        assert!(mmu.take_watch_hits().is_empty());
+
+        let mut reads = Watchpoint::writes(0x0200_0012, 2);
+        reads.read = true;
+        mmu.set_watches(vec![reads]);
+        assert_eq!(0x5678, mmu.load16(0x0200_0010));
+        assert!(mmu.take_watch_hits().is_empty());
+        mmu.load8(0x0200_0013);
+        assert!(!mmu.take_watch_hits()[0].write);
+
+        mmu.set_watches(vec![]);
+        mmu.set8(0x0200_0012, 0);
+        assert!(mmu.take_watch_hits().is_empty());
+    }
+
+    #[test]
+    fn test_extend_memory() {
+        let mut mmu = Gba::new(Default::default(), Default::default());
+        mmu.map_pages();
+        assert!(mmu.get_range(0x0100_0000).is_none());
+
+        mmu.extend_memory(Some(0x10_0000), Some(0x1_0000));
+        mmu.set32(0x020f_fffc, 0x1234_5678);
+        assert_eq!(0x1234_5678, mmu.load32(0x020f_fffc));
+        assert_eq!(0, mmu.load32(0x0203_fffc));
+        assert_eq!(0x1234_5678, mmu.load32(0x021f_fffc));
+
+        mmu.set16(0x0100_0010, 0xbeef);
+        assert_eq!(0xbeef, mmu.load16(0x0101_0010));
+        let (naddr, range) = mmu.get_range(0x01ff_0011).unwrap();
+        assert_eq!(0xbe, range.load8(naddr).get());
+    }
+
+    #[test]
+    fn test_fill_work_ram() {
+        let mut mmu = Gba::new(Default::default(), Default::default());
+        mmu.map_pages();
+        mmu.fill_work_ram(0xa5);
+        assert_eq!(0xa5a5_a5a5, mmu.load32(0x0200_0010));
+        assert_eq!(0xa5a5_a5a5, mmu.load32(0x0300_7ffc));
+        assert_eq!(0, mmu.load32(0x0600_0000));
+    }
+
+    #[test]
+    fn test_load_multiboot() {
+        let mut mmu = Gba::new(Default::default(), Default::default());
+        mmu.map_pages();
+        mmu.fill_work_ram(0xff);
+        mmu.load_multiboot(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(0x0403_0201, mmu.load32(0x0200_0000));
+        assert_eq!(0xffff_ffff, mmu.load32(0x0200_0004));
+        assert!(mmu.load_multiboot(&vec![0; 257 * 1024]).is_err());
+    }
+
+    #[test]
+    fn test_memcnt() {
+        let mut mmu = Gba::new(Default::default(), Default::default());
+        mmu.map_pages();
+        mmu.set32(0x0200_0010, 0x1234_5678);
+        mmu.set32(0x0300_0010, 0x9abc_def0);
+
+        // EWRAM replaced by IWRAM
+        mmu.set_memcnt(MEMCNT_INITIAL & !0x20);
+        assert_eq!(0x9abc_def0, mmu.load32(0x0200_0010));
+        assert_eq!(0x9abc_def0, mmu.load32(0x0200_8010));
+
+        // Both off
+        mmu.set_memcnt(MEMCNT_INITIAL | 1);
+        assert!(mmu.get_range(0x0200_0010).is_none());
+        assert!(mmu.get_range(0x0300_0010).is_none());
+        assert!(mmu.page_ptr(0x0300_0010, 4, false).is_none());
+
+        mmu.set_memcnt(MEMCNT_INITIAL);
+        assert_eq!(0x1234_5678, mmu.load32(0x0200_0010));
+    }
+
+    #[test]
+    fn test_tiles() {
+        let mut mmu = Gba::new(Default::default(), Default::default());
+        mmu.map_pages();
+
+        mmu.set32(0x0600_0020, 0x8765_4321);
+        assert_eq!(1, mmu.tiles.pixel(0x20, 0));
+        assert_eq!(8, mmu.tiles.pixel(0x20, 7));
+        mmu.set8(0x0602_0021, 0xa9);
+        assert_eq!(9, mmu.tiles.pixel(0x20, 2));
+        mmu.set16(0x0601_8022, 0xcbdc);
+        assert_eq!(0xc, mmu.tiles.pixel(0x1_0020, 4));
+        assert_eq!(0xb, mmu.tiles.pixel(0x1_0020, 6));
+    }
+}
@@ -2,6 +2,7 @@ use std::cell::RefCell;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
 
+use byteorder::{BigEndian, ByteOrder};
 use serde::de::{Error, SeqAccess, Visitor};
 use serde::ser::SerializeTuple;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -13,6 +14,8 @@ use io::IoReg;
 use mmu::{MemoryRead, Mmu};
 
 const MEM_SIZE: usize = 1024;
+/// Blocks addressable by a 4Kbit EEPROM's 6 bit addresses
+const SMALL_SIZE: usize = 64;
 
 #[derive(Serialize, Deserialize)]
 pub struct Eeprom<'a> {
@@ -57,6 +60,34 @@ impl<'a> Eeprom<'a> {
     pub fn init(&mut self, io: Shared<IoReg<'a>>) {
         self.ee.borrow_mut().init(io);
     }
+
+    /// The contents as other emulators save them, each 64 bit block big
+    /// endian so the bits are in the order they're transferred.  It's 512
+    /// bytes unless anything past the first 64 blocks is set, as the size
+    /// the game expects isn't known.
+    pub fn data(&self) -> Vec<u8> {
+        let ee = self.ee.borrow();
+        let blocks = if ee.mem[SMALL_SIZE..].iter().all(|&block| block == 0) {
+            SMALL_SIZE
+        } else {
+            MEM_SIZE
+        };
+        let mut data = vec![0u8; blocks * 8];
+        for (chunk, &block) in data.chunks_mut(8).zip(ee.mem.iter()) {
+            BigEndian::write_u64(chunk, block);
+        }
+        data
+    }
+
+    /// Loads contents in the format of `data`
+    pub fn load(&mut self, data: &[u8]) {
+        let mut ee = self.ee.borrow_mut();
+        for (block, chunk) in ee.mem.iter_mut().zip(data.chunks(8)) {
+            let mut bytes = [0u8; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *block = BigEndian::read_u64(&bytes);
+        }
+    }
 }
 
 impl<'a> Default for EepromInner<'a> {
@@ -255,3 +286,21 @@ impl<'a> Mmu for Eeprom<'a> {
         self.ee.borrow_mut().write(val as u16)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_data() {
+        let mut ee: Eeprom = Default::default();
+        ee.load(&[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(0x0102_0304_0506_0708, ee.ee.borrow().mem[0]);
+        assert_eq!(0x0900_0000_0000_0000, ee.ee.borrow().mem[1]);
+        assert_eq!(512, ee.data().len());
+        assert_eq!(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 0], &ee.data()[..10]);
+
+        ee.ee.borrow_mut().mem[SMALL_SIZE] = 1;
+        assert_eq!(8192, ee.data().len());
+    }
+}
@@ -0,0 +1,49 @@
+mod eeprom;
+pub use self::eeprom::Eeprom;
+
+use mmu::ram::Ram;
+use rom::Backup;
+
+use super::Gba;
+
+/// SRAM is 32K but mirrored through the 64K region
+const SRAM_SIZE: usize = 32 * 1024;
+
+impl<'a> Gba<'a> {
+    /// The cartridge's battery backed memory in the raw `.sav` format other
+    /// emulators and flashcarts use, or None if it has none
+    pub fn battery(&self) -> Option<Vec<u8>> {
+        match self.rom.backup() {
+            Backup::None => None,
+            Backup::Eeprom => Some(self.ee.data()),
+            Backup::Sram => Some(self.gram.as_slice()[..SRAM_SIZE].to_vec()),
+            Backup::Flash => Some(self.gram.as_slice().to_vec()),
+            Backup::Flash1M => {
+                warn!("128K flash saves aren't supported, only the first bank is saved");
+                Some(self.gram.as_slice().to_vec())
+            }
+        }
+    }
+
+    /// Loads battery backed memory saved by `battery` or another emulator
+    pub fn load_battery(&mut self, data: &[u8]) {
+        match self.rom.backup() {
+            Backup::None => warn!("Ignoring a battery save for a game without one"),
+            Backup::Eeprom => self.ee.load(data),
+            Backup::Sram => {
+                // Fill the mirror too
+                let len = data.len().min(SRAM_SIZE);
+                let mut gram = data[..len].to_vec();
+                gram.resize(SRAM_SIZE, 0);
+                let mirror = gram.clone();
+                gram.extend(mirror);
+                self.gram = Ram::new_with_data(gram.len(), &gram);
+            }
+            Backup::Flash | Backup::Flash1M => {
+                let len = data.len().min(self.gram.len());
+                self.gram = Ram::new_with_data(self.gram.len(), &data[..len]);
+            }
+        }
+        self.map_pages();
+    }
+}
@@ -0,0 +1,57 @@
+/// VRAM with each byte split into its two 4 bit halves, so 16 colour tiles
+/// can be read a pixel per byte like 256 colour ones instead of unpacking
+/// nibbles for every pixel of every line.  It's kept up to date on each
+/// VRAM write, which happen far less often than the renderer reads.
+pub struct Tiles {
+    pixels: Vec<u8>,
+}
+
+impl Tiles {
+    pub fn new(vram: &[u8]) -> Self {
+        let mut tiles = Tiles {
+            pixels: vec![0; vram.len() * 2],
+        };
+        for (addr, &val) in vram.iter().enumerate() {
+            tiles.update(addr as u32, val);
+        }
+        tiles
+    }
+
+    /// Records that the VRAM byte at `addr` is now `val`
+    #[inline]
+    pub fn update(&mut self, addr: u32, val: u8) {
+        let idx = addr as usize * 2;
+        self.pixels[idx] = val & 0xf;
+        self.pixels[idx + 1] = val >> 4;
+    }
+
+    /// The palette index of the 16 colour pixel `idx` of the tile at `addr`
+    #[inline]
+    pub fn pixel(&self, addr: u32, idx: u32) -> u8 {
+        self.pixels[(addr * 2 + idx) as usize]
+    }
+}
+
+impl Default for Tiles {
+    fn default() -> Self {
+        Tiles { pixels: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tiles() {
+        let mut tiles = Tiles::new(&[0x21, 0x43]);
+        assert_eq!(1, tiles.pixel(0, 0));
+        assert_eq!(2, tiles.pixel(0, 1));
+        assert_eq!(4, tiles.pixel(0, 3));
+        assert_eq!(3, tiles.pixel(1, 0));
+
+        tiles.update(1, 0xf5);
+        assert_eq!(5, tiles.pixel(0, 2));
+        assert_eq!(0xf, tiles.pixel(0, 3));
+    }
+}
@@ -0,0 +1,155 @@
+//! How long the CPU's memory accesses take, which is most of what an
+//! instruction costs.
+//!
+//! Each region has a bus width and wait states, the gamepak's set by WAITCNT
+//! and EWRAM's by MEMCNT.  A 32 bit access over a 16 bit bus is two accesses,
+//! the second sequential, so ARM code running from ROM pays for two fetches
+//! where Thumb code pays for one.  An access is sequential when it follows on
+//! from the previous one, as instruction fetches mostly do.
+//!
+//! With the gamepak prefetch buffer on, sequential ROM reads are served from
+//! the buffer in a cycle per halfword, which assumes the buffer keeps up.
+//! Instructions' internal cycles aren't counted.
+
+use std::cell::Cell;
+
+use bit_util::{bit, extract};
+
+/// Non-sequential wait states for SRAM and the gamepak's waitstate regions
+const N_WAITS: [u32; 4] = [4, 3, 2, 8];
+/// Sequential wait states for each of the gamepak's three waitstate regions,
+/// when their WAITCNT bit is clear
+const S_WAITS: [u32; 3] = [2, 4, 8];
+
+#[derive(Default)]
+pub struct Timing {
+    waitcnt: u16,
+    /// Cycles a non-sequential and a sequential 16 bit access take in each
+    /// region, by the top byte of the address
+    n16: [u32; 16],
+    s16: [u32; 16],
+    /// Regions with a 16 bit bus, indexed by bit
+    narrow: u16,
+    prefetch: bool,
+    /// Where an access would follow on from the last one
+    next: Cell<u32>,
+    cycles: Cell<u32>,
+}
+
+impl Timing {
+    pub fn new(waitcnt: u16, memcnt: u32) -> Timing {
+        let mut timing: Timing = Default::default();
+        timing.update(waitcnt, memcnt);
+        timing
+    }
+
+    /// Recalculates the access times from WAITCNT and MEMCNT
+    pub fn update(&mut self, waitcnt: u16, memcnt: u32) {
+        self.waitcnt = waitcnt;
+        let waitcnt = waitcnt as u32;
+        // BIOS, IWRAM, IO and OAM are 32 bit with no waits, as is anything
+        // unmapped
+        self.n16 = [1; 16];
+        self.s16 = [1; 16];
+        self.narrow = 0;
+
+        let ewram = 1 + 15 - extract(memcnt, 24, 4);
+        self.n16[2] = ewram;
+        self.s16[2] = ewram;
+        // Palette and VRAM
+        self.narrow |= (1 << 2) | (1 << 5) | (1 << 6);
+
+        for ws in 0..3 {
+            let n = 1 + N_WAITS[extract(waitcnt, 2 + ws * 3, 2) as usize];
+            let s = if bit(waitcnt, 4 + ws * 3) == 1 {
+                2
+            } else {
+                1 + S_WAITS[ws as usize]
+            };
+            for region in 8 + ws * 2..10 + ws * 2 {
+                self.n16[region as usize] = n;
+                self.s16[region as usize] = s;
+                self.narrow |= 1 << region;
+            }
+        }
+
+        // SRAM has an 8 bit bus, but is only meant to be accessed a byte at
+        // a time
+        let sram = 1 + N_WAITS[extract(waitcnt, 0, 2) as usize];
+        for region in 0xe..0x10 {
+            self.n16[region] = sram;
+            self.s16[region] = sram;
+        }
+
+        self.prefetch = bit(waitcnt, 14) == 1;
+    }
+
+    pub fn waitcnt(&self) -> u16 {
+        self.waitcnt
+    }
+
+    /// Counts a CPU access of `size` bytes at `addr`
+    #[inline]
+    pub fn access(&self, addr: u32, size: u32) {
+        let region = if addr >> 28 == 0 {
+            (addr >> 24) as usize
+        } else {
+            0
+        };
+        let seq = addr == self.next.get();
+        self.next.set(addr.wrapping_add(size));
+
+        let buffered = self.prefetch && region >= 8 && region < 0xe;
+        let s = if buffered { 1 } else { self.s16[region] };
+        let mut cycles = if seq { s } else { self.n16[region] };
+        if size == 4 && (self.narrow >> region) & 1 == 1 {
+            cycles += s;
+        }
+        self.cycles.set(self.cycles.get() + cycles);
+    }
+
+    /// The cycles taken by accesses since the last call
+    #[inline]
+    pub fn take(&self) -> u32 {
+        self.cycles.replace(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mmu::gba::MEMCNT_INITIAL;
+
+    /// The cycles to fetch `count` instructions of `size` bytes from `addr`
+    fn fetch(timing: &Timing, addr: u32, size: u32, count: u32) -> u32 {
+        for i in 0..count {
+            timing.access(addr + i * size, size);
+        }
+        timing.take()
+    }
+
+    #[test]
+    fn test_rom_fetch() {
+        // The power on default, 4 and 2 waits
+        let timing = Timing::new(0, MEMCNT_INITIAL);
+        assert_eq!(5 + 3 * 3, fetch(&timing, 0x0800_0000, 2, 4));
+        assert_eq!((5 + 3) + 3 * (3 + 3), fetch(&timing, 0x0800_1000, 4, 4));
+
+        // 3 and 1 waits with prefetch, which most games set
+        let timing = Timing::new(0x4317, MEMCNT_INITIAL);
+        assert_eq!(4 + 3, fetch(&timing, 0x0800_0000, 2, 4));
+        assert_eq!((4 + 1) + 3 * 2, fetch(&timing, 0x0800_1000, 4, 4));
+    }
+
+    #[test]
+    fn test_ram() {
+        let timing = Timing::new(0, MEMCNT_INITIAL);
+        assert_eq!(4, fetch(&timing, 0x0300_0000, 4, 4));
+        // 2 waits on a 16 bit bus
+        assert_eq!(4 * 6, fetch(&timing, 0x0200_0000, 4, 4));
+        assert_eq!(4 * 3, fetch(&timing, 0x0200_0000, 2, 4));
+        assert_eq!(2, fetch(&timing, 0x0600_0000, 4, 1));
+        assert_eq!(1, fetch(&timing, 0x1000_0000, 4, 1));
+    }
+}
@@ -1,6 +1,8 @@
 pub mod bytes;
+pub mod cdl;
 pub mod gba;
 pub mod ram;
+pub mod watch;
 
 // Add result type for memory accesses here
 #[derive(Debug)]
@@ -18,6 +20,15 @@ impl<T> MemoryRead<T> {
             Open => panic!("Attempted to force read of open address"),
         }
     }
+
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> MemoryRead<U> {
+        use self::MemoryRead::*;
+
+        match self {
+            Value(x) => Value(f(x)),
+            Open => Open,
+        }
+    }
 }
 
 /// A memory management unit for GBA, to handle memory accesses
@@ -28,6 +39,18 @@ pub trait MemoryUnit {
     fn set16(&mut self, addr: u32, val: u16);
     fn load32(&self, addr: u32) -> u32;
     fn set32(&mut self, addr: u32, val: u32);
+
+    /// Called for each access the CPU makes, before it is made, so the
+    /// access's time can be counted
+    #[inline]
+    fn wait(&self, _addr: u32, _size: u32) {}
+
+    /// Called before the CPU runs the instruction at `addr`, and for each
+    /// read the CPU or DMA makes, for a code/data log
+    #[inline]
+    fn log_exec(&self, _addr: u32, _thumb: bool) {}
+    #[inline]
+    fn log_read(&self, _addr: u32, _size: u32) {}
 }
 
 /// A subpiece of the MMU TODO: rename
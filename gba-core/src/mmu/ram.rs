@@ -21,9 +21,31 @@ impl Ram {
         ram
     }
 
+    /// Copies `data` to the start, leaving the rest and the backing memory
+    /// as they are
+    pub fn copy_from(&mut self, data: &[u8]) {
+        self.mem[..data.len()].copy_from_slice(data);
+    }
+
+    /// Sets every byte to `byte`, leaving the backing memory where it is
+    pub fn fill(&mut self, byte: u8) {
+        for b in self.mem.iter_mut() {
+            *b = byte;
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.mem.len()
     }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.mem.as_slice()
+    }
+
+    /// The backing memory stays put until the `Ram` is replaced or dropped
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.mem.as_mut_ptr()
+    }
 }
 
 impl Mmu for Ram {
@@ -0,0 +1,163 @@
+//! Watchpoints, which note the loads and stores to a range of memory, written
+//! `<addr>[+<len>] [r|w|rw] [break]`, e.g.
+//!
+//! ```text
+//! 3001234+4
+//! player_hp rw break
+//! ```
+//!
+//! The address is in hex or a symbol name, a symbol covering its size when
+//! no length is given.  Watchpoints catch writes by default, and stop the
+//! CPU after the access only with `break`.
+
+use std::fmt;
+
+use symbols::Symbols;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub addr: u32,
+    /// In bytes
+    pub len: u32,
+    pub read: bool,
+    pub write: bool,
+    /// Stop the CPU after an access, rather than only reporting it
+    pub stop: bool,
+}
+
+/// An access a watchpoint caught
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchHit {
+    /// The instruction that made the access, 0 until known
+    pub pc: u32,
+    pub addr: u32,
+    /// 1, 2 or 4 bytes
+    pub size: u32,
+    pub value: u32,
+    pub write: bool,
+    pub stop: bool,
+}
+
+impl Watchpoint {
+    /// Watches for writes to `len` bytes from `addr`
+    pub fn writes(addr: u32, len: u32) -> Self {
+        Watchpoint {
+            addr: addr,
+            len: len,
+            read: false,
+            write: true,
+            stop: false,
+        }
+    }
+
+    pub fn parse(s: &str, symbols: &Symbols) -> Result<Watchpoint, String> {
+        let mut words = s.split_whitespace();
+        let range = words.next().ok_or_else(|| "no address".to_string())?;
+        let (addr, len) = match range.find('+') {
+            Some(idx) => (&range[..idx], Some(&range[idx + 1..])),
+            None => (range, None),
+        };
+        let hex = |s: &str| {
+            let digits = if s.starts_with("0x") { &s[2..] } else { s };
+            u32::from_str_radix(digits, 16).ok()
+        };
+        let (addr, size) = match hex(addr) {
+            Some(addr) => (addr, 1),
+            None => match symbols.lookup(addr) {
+                Some(sym) => (sym.addr, sym.size.max(1)),
+                None => return Err(format!("'{}' is not an address or symbol", addr)),
+            },
+        };
+        let len = match len {
+            Some(len) => match hex(len) {
+                Some(len) if len > 0 => len,
+                _ => return Err(format!("'{}' is not a length in hex", len)),
+            },
+            None => size,
+        };
+        let mut watch = Watchpoint::writes(addr, len);
+        for word in words {
+            match word {
+                "r" => {
+                    watch.read = true;
+                    watch.write = false;
+                }
+                "w" => {
+                    watch.read = false;
+                    watch.write = true;
+                }
+                "rw" => {
+                    watch.read = true;
+                    watch.write = true;
+                }
+                "break" => watch.stop = true,
+                _ => return Err(format!("'{}' should be r, w, rw or break", word)),
+            }
+        }
+        Ok(watch)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match (self.read, self.write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, "{:08x}+{:x} {}", self.addr, self.len, access)?;
+        if self.stop {
+            write!(f, " break")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (access, dir) = if self.write {
+            ("Write", "to")
+        } else {
+            ("Read", "from")
+        };
+        write!(
+            f,
+            "{} of {:#0width$x} {} {:#010x}",
+            access,
+            self.value,
+            dir,
+            self.addr,
+            width = self.size as usize * 2 + 2
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use symbols::Symbol;
+
+    #[test]
+    fn test_parse() {
+        let symbols = Symbols::new(vec![Symbol {
+            name: "player_hp".to_string(),
+            addr: 0x0300_1234,
+            size: 2,
+            thumb: false,
+        }]);
+        let watch = Watchpoint::parse("3001234+4", &symbols).unwrap();
+        assert_eq!(Watchpoint::writes(0x0300_1234, 4), watch);
+        assert_eq!("03001234+4 w", watch.to_string());
+
+        let watch = Watchpoint::parse("player_hp rw break", &symbols).unwrap();
+        assert_eq!((0x0300_1234, 2), (watch.addr, watch.len));
+        assert!(watch.read && watch.write && watch.stop);
+        let watch = Watchpoint::parse("0x2000000 r", &symbols).unwrap();
+        assert!(watch.read && !watch.write);
+
+        assert!(Watchpoint::parse("nowhere", &symbols).is_err());
+        assert!(Watchpoint::parse("2000000+0", &symbols).is_err());
+        assert!(Watchpoint::parse("2000000 x", &symbols).is_err());
+    }
+}
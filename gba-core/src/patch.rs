@@ -0,0 +1,340 @@
+//! IPS, UPS and BPS patches, the formats ROM hacks and translations are
+//! distributed in, applied to a ROM as it's loaded.
+//!
+//! IPS is a list of bytes to write at offsets.  UPS holds the bytes that
+//! differ XORed with the original, and BPS copies runs from the original,
+//! the patch or the output so far.  Both end with the CRC-32s of the ROM
+//! they're for and the ROM they make, so a patch for another ROM or version
+//! is caught rather than making garbage.
+
+use rom::crc32;
+
+/// The offset ending an IPS patch, "EOF" in ASCII
+const IPS_EOF: u32 = 0x45_4f46;
+
+/// The largest ROM a patch can make, the size of the cartridge's address
+/// space
+const ROM_MAX: usize = 32 * 1024 * 1024;
+
+/// Reads through a patch, failing at its end
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.data.get(self.pos).ok_or("patch ends unexpectedly")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.pos < len {
+            return Err("patch ends unexpectedly".to_string());
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// A big endian number `len` bytes long
+    fn be(&mut self, len: usize) -> Result<u32, String> {
+        let bytes = self.bytes(len)?;
+        Ok(bytes.iter().fold(0, |val, &b| val << 8 | b as u32))
+    }
+
+    /// The variable length numbers UPS and BPS use
+    fn number(&mut self) -> Result<usize, String> {
+        let too_large = || "patch number too large".to_string();
+        let (mut val, mut shift) = (0usize, 1usize);
+        loop {
+            let byte = self.byte()?;
+            val = ((byte & 0x7f) as usize)
+                .checked_mul(shift)
+                .and_then(|n| val.checked_add(n))
+                .ok_or_else(too_large)?;
+            if byte & 0x80 != 0 {
+                return Ok(val);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(too_large)?;
+            val = val.checked_add(shift).ok_or_else(too_large)?;
+        }
+    }
+
+    /// The size of the ROM a UPS or BPS patch makes
+    fn target_len(&mut self) -> Result<usize, String> {
+        let len = self.number()?;
+        if len > ROM_MAX {
+            return Err(format!("patch makes a ROM of {} bytes, over 32M", len));
+        }
+        Ok(len)
+    }
+}
+
+fn le32(data: &[u8]) -> u32 {
+    data.iter().rev().fold(0, |val, &b| val << 8 | b as u32)
+}
+
+fn ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = rom.to_vec();
+    let mut reader = Reader {
+        data: patch,
+        pos: 5,
+    };
+    loop {
+        let off = reader.be(3)?;
+        if off == IPS_EOF {
+            break;
+        }
+        let off = off as usize;
+        let len = reader.be(2)? as usize;
+        let (len, fill) = match len {
+            // Run length encoded
+            0 => (reader.be(2)? as usize, Some(reader.byte()?)),
+            len => (len, None),
+        };
+        if out.len() < off + len {
+            out.resize(off + len, 0);
+        }
+        match fill {
+            Some(byte) => {
+                for b in out[off..off + len].iter_mut() {
+                    *b = byte;
+                }
+            }
+            None => out[off..off + len].copy_from_slice(reader.bytes(len)?),
+        }
+    }
+    // Some patches truncate the ROM after the end marker
+    if let Ok(len) = reader.be(3) {
+        out.truncate(len as usize);
+    }
+    Ok(out)
+}
+
+/// Checks the CRCs at the end of a UPS or BPS patch, returning the one for
+/// the output
+fn check_crcs(patch: &[u8], rom: &[u8]) -> Result<u32, String> {
+    if patch.len() < 16 {
+        return Err("patch ends unexpectedly".to_string());
+    }
+    let footer = &patch[patch.len() - 12..];
+    if crc32(&patch[..patch.len() - 4]) != le32(&footer[8..]) {
+        return Err("patch is corrupt".to_string());
+    }
+    if crc32(rom) != le32(&footer[..4]) {
+        return Err("patch is for a different ROM".to_string());
+    }
+    Ok(le32(&footer[4..8]))
+}
+
+fn ups(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    let target_crc = check_crcs(patch, rom)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader {
+        data: &patch[..end],
+        pos: 4,
+    };
+    let _source_len = reader.number()?;
+    let target_len = reader.target_len()?;
+    let mut out = rom.to_vec();
+    out.resize(target_len, 0);
+    let mut pos = 0usize;
+    while reader.pos < end {
+        // From here it only moves on by one per byte read, so can't overflow
+        pos = match pos.checked_add(reader.number()?) {
+            Some(pos) if pos <= target_len => pos,
+            _ => return Err("patch writes past the end of the ROM".to_string()),
+        };
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                break;
+            }
+            if let Some(b) = out.get_mut(pos) {
+                *b ^= byte;
+            }
+            pos += 1;
+        }
+        pos += 1;
+    }
+    if crc32(&out) != target_crc {
+        return Err("patched ROM doesn't match the patch's checksum".to_string());
+    }
+    Ok(out)
+}
+
+fn bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    let target_crc = check_crcs(patch, rom)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader {
+        data: &patch[..end],
+        pos: 4,
+    };
+    let _source_len = reader.number()?;
+    let target_len = reader.target_len()?;
+    let metadata_len = reader.number()?;
+    reader.bytes(metadata_len)?;
+
+    let mut out = Vec::with_capacity(target_len);
+    let (mut source_pos, mut target_pos) = (0usize, 0usize);
+    let out_of_range = || "patch copies from outside the ROM".to_string();
+    // Copy offsets are relative to the last, with the sign in the low bit
+    let relative = |pos: usize, data: usize| {
+        if data & 1 == 0 {
+            pos.checked_add(data >> 1)
+        } else {
+            pos.checked_sub(data >> 1)
+        }
+    };
+    while reader.pos < end {
+        let data = reader.number()?;
+        let len = (data >> 2) + 1;
+        if target_len - out.len() < len {
+            return Err("patch writes past the end of the ROM".to_string());
+        }
+        match data & 3 {
+            0 => {
+                // Can't overflow, `out` is no longer than the target
+                let start = out.len();
+                let bytes = rom.get(start..start + len).ok_or_else(out_of_range)?;
+                out.extend_from_slice(bytes);
+            }
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            2 => {
+                source_pos = relative(source_pos, reader.number()?).ok_or_else(out_of_range)?;
+                let source_end = source_pos.checked_add(len).ok_or_else(out_of_range)?;
+                let bytes = rom.get(source_pos..source_end).ok_or_else(out_of_range)?;
+                out.extend_from_slice(bytes);
+                source_pos += len;
+            }
+            _ => {
+                target_pos = relative(target_pos, reader.number()?).ok_or_else(out_of_range)?;
+                // The copy can overlap what it writes, to repeat a pattern
+                for _ in 0..len {
+                    let byte = *out.get(target_pos).ok_or_else(out_of_range)?;
+                    out.push(byte);
+                    target_pos += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_len || crc32(&out) != target_crc {
+        return Err("patched ROM doesn't match the patch's checksum".to_string());
+    }
+    Ok(out)
+}
+
+/// Applies `patch`, of any of the formats, to `rom`
+pub fn apply(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        ips(patch, rom)
+    } else if patch.starts_with(b"UPS1") {
+        ups(patch, rom)
+    } else if patch.starts_with(b"BPS1") {
+        bps(patch, rom)
+    } else {
+        Err("not an IPS, UPS or BPS patch".to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Appends the CRCs a UPS or BPS patch ends with
+    fn finish(mut patch: Vec<u8>, rom: &[u8], out: &[u8]) -> Vec<u8> {
+        for &crc in [crc32(rom), crc32(out)].iter() {
+            patch.extend_from_slice(&[
+                crc as u8,
+                (crc >> 8) as u8,
+                (crc >> 16) as u8,
+                (crc >> 24) as u8,
+            ]);
+        }
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&[
+            crc as u8,
+            (crc >> 8) as u8,
+            (crc >> 16) as u8,
+            (crc >> 24) as u8,
+        ]);
+        patch
+    }
+
+    #[test]
+    fn test_ips() {
+        let rom = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // Two bytes at 2, then 3 0xff bytes at 7, growing the ROM
+        patch.extend_from_slice(&[0, 0, 2, 0, 2, 0xaa, 0xbb]);
+        patch.extend_from_slice(&[0, 0, 7, 0, 0, 0, 3, 0xff]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(
+            vec![0, 0, 0xaa, 0xbb, 0, 0, 0, 0xff, 0xff, 0xff],
+            apply(&patch, &rom).unwrap()
+        );
+        patch.truncate(patch.len() - 4);
+        assert!(apply(&patch, &rom).is_err());
+    }
+
+    #[test]
+    fn test_ups() {
+        let rom = [1u8, 2, 3, 4];
+        let out = [1u8, 7, 3, 4, 5];
+        let mut patch = b"UPS1".to_vec();
+        // Sizes 4 and 5, then skip 1 and XOR 2 with 5, skip 1 more and XOR
+        // the new byte to 5
+        patch.extend_from_slice(&[0x84, 0x85, 0x81, 5, 0, 0x81, 5, 0]);
+        let patch = finish(patch, &rom, &out);
+        assert_eq!(out.to_vec(), apply(&patch, &rom).unwrap());
+        assert_eq!(
+            Err("patch is for a different ROM".to_string()),
+            apply(&patch, &out)
+        );
+    }
+
+    #[test]
+    fn test_bps() {
+        let rom = [1u8, 2, 3, 4];
+        let out = [1u8, 2, 9, 9, 9, 9, 3, 4];
+        let mut patch = b"BPS1".to_vec();
+        // Sizes 4 and 8, no metadata
+        patch.extend_from_slice(&[0x84, 0x88, 0x80]);
+        // Read 2 from the ROM, write a 9, copy it 3 times from the output,
+        // then copy 2 from the ROM at 2
+        patch.extend_from_slice(&[0x84 | 0x00, 0x80 | 0x01, 9]);
+        patch.extend_from_slice(&[0x80 | 0x0b, 0x80 | 4]);
+        patch.extend_from_slice(&[0x80 | 0x06, 0x80 | 4]);
+        let patch = finish(patch, &rom, &out);
+        assert_eq!(out.to_vec(), apply(&patch, &rom).unwrap());
+    }
+
+    #[test]
+    fn test_malformed() {
+        let rom = [1u8, 2, 3, 4];
+        let out = [1u8, 2, 3, 4];
+        // A number with more continuation bytes than fit in a usize
+        let mut patch = b"BPS1".to_vec();
+        patch.extend_from_slice(&[0x7f; 12]);
+        patch.push(0x80);
+        assert!(apply(&finish(patch, &rom, &out), &rom).is_err());
+
+        // A 4G ROM
+        let mut patch = b"UPS1".to_vec();
+        patch.extend_from_slice(&[0x84, 0x7f, 0x7f, 0x7f, 0x7f, 0x80]);
+        assert!(apply(&finish(patch, &rom, &out), &rom).is_err());
+
+        // Skipping far past the end
+        let mut patch = b"UPS1".to_vec();
+        patch.extend_from_slice(&[0x84, 0x84, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x80, 5, 0]);
+        assert!(apply(&finish(patch, &rom, &out), &rom).is_err());
+
+        // Copying more than the target's size from the output
+        let mut patch = b"BPS1".to_vec();
+        patch.extend_from_slice(&[0x84, 0x84, 0x80, 0x80 | 0x01, 9]);
+        patch.extend_from_slice(&[0x7f, 0x7f, 0x7f, 0x8f, 0x80]);
+        assert!(apply(&finish(patch, &rom, &out), &rom).is_err());
+    }
+}
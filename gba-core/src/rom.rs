@@ -0,0 +1,552 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap::{Mmap, MmapMut};
+use zip::ZipArchive;
+
+use mmu::{bytes, MemoryRead, Mmu};
+
+pub struct GameRom {
+    rom: Mmap,
+    /// Bytes that reads see instead of the ROM contents, for ROM patch cheats
+    /// and writes to a flashcart
+    patches: BTreeMap<u32, u8>,
+    /// Behave like a flashcart, which holds the ROM in writable memory and
+    /// has nothing driving the bus past its end
+    flashcart: bool,
+    /// Overrides the backup type found in the ROM, for games it's wrong for
+    backup: Option<Backup>,
+}
+
+impl GameRom {
+    /// Maps the ROM at `path`, or reads it out of a ZIP archive
+    pub fn new(path: &Path) -> io::Result<GameRom> {
+        let file = File::open(path)?;
+        let zipped = path
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("zip"));
+        if zipped {
+            return GameRom::from_zip(file);
+        }
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(GameRom {
+            rom: mmap,
+            patches: BTreeMap::new(),
+            flashcart: false,
+            backup: None,
+        })
+    }
+
+    /// A ROM holding a copy of `data`, e.g. a program built in memory
+    pub fn from_bytes(data: &[u8]) -> io::Result<GameRom> {
+        let mut mmap = MmapMut::map_anon(data.len())?;
+        mmap.copy_from_slice(data);
+        Ok(GameRom {
+            rom: mmap.make_read_only()?,
+            patches: BTreeMap::new(),
+            flashcart: false,
+            backup: None,
+        })
+    }
+
+    /// The first `.gba` file in the ZIP archive `archive`
+    pub fn from_zip<R: Read + Seek>(archive: R) -> io::Result<GameRom> {
+        let mut archive = ZipArchive::new(archive)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if file.name().to_lowercase().ends_with(".gba") {
+                let mut data = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut data)?;
+                return GameRom::from_bytes(&data);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no .gba file in the archive",
+        ))
+    }
+
+    /// A copy with `patch`, an IPS, UPS or BPS file, applied
+    pub fn with_patch(&self, patch: &[u8]) -> Result<GameRom, String> {
+        let data = ::patch::apply(patch, &self.rom)?;
+        let mut rom = GameRom::from_bytes(&data).map_err(|err| err.to_string())?;
+        rom.flashcart = self.flashcart;
+        rom.backup = self.backup;
+        Ok(rom)
+    }
+
+    /// A copy with the same contents and settings, for running a game twice
+    pub fn try_clone(&self) -> io::Result<GameRom> {
+        let mut rom = GameRom::from_bytes(&self.rom)?;
+        rom.patches = self.patches.clone();
+        rom.flashcart = self.flashcart;
+        rom.backup = self.backup;
+        Ok(rom)
+    }
+}
+
+impl GameRom {
+    /// The game title from the cartridge header, empty if there's no header
+    pub fn title(&self) -> String {
+        self.header()
+            .map_or_else(String::new, |header| header.title)
+    }
+
+    /// The 4 character game code from the cartridge header, e.g. `AXVE`
+    pub fn game_code(&self) -> String {
+        self.header()
+            .map_or_else(String::new, |header| header.game_code)
+    }
+
+    /// The cartridge header, None if the ROM is too short to have one
+    pub fn header(&self) -> Option<Header> {
+        Header::parse(&self.rom)
+    }
+
+    /// CRC-32 of the ROM contents, as used by ROM databases to identify dumps
+    pub fn crc32(&self) -> u32 {
+        crc32(&self.rom)
+    }
+
+    /// The kind of battery backed memory the cartridge has
+    pub fn backup(&self) -> Backup {
+        match self.backup {
+            Some(backup) => backup,
+            None => backup_type(&self.rom),
+        }
+    }
+
+    /// Uses `backup` instead of the type found in the ROM, or goes back to
+    /// finding it with None
+    pub fn set_backup(&mut self, backup: Option<Backup>) {
+        self.backup = backup;
+    }
+}
+
+/// What the cartridge header says about the game
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Header {
+    /// Up to 12 characters, in capitals
+    pub title: String,
+    /// The 4 character game code, e.g. `BPEE`, the last letter the region
+    pub game_code: String,
+    /// The 2 character code of the publisher, e.g. `01` for Nintendo
+    pub maker_code: String,
+    pub version: u8,
+    pub checksum: u8,
+    /// Whether `checksum` matches the header, which the BIOS refuses to boot
+    /// without
+    pub checksum_valid: bool,
+}
+
+impl Header {
+    /// Parses the header at the start of `data`, None if it's too short
+    pub fn parse(data: &[u8]) -> Option<Header> {
+        if data.len() < 0xc0 {
+            return None;
+        }
+        let text = |off: usize, len: usize| -> String {
+            data[off..off + len]
+                .iter()
+                .take_while(|&&b| b != 0)
+                .map(|&b| b as char)
+                .collect()
+        };
+        Some(Header {
+            title: text(0xa0, 12),
+            game_code: text(0xac, 4),
+            maker_code: text(0xb0, 2),
+            version: data[0xbc],
+            checksum: data[0xbd],
+            checksum_valid: header_valid(data),
+        })
+    }
+}
+
+/// Battery backed memory on the cartridge, as saved to `.sav` files
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Backup {
+    None,
+    /// 512 bytes or 8K, depending on the address width the game uses
+    Eeprom,
+    /// 32K
+    Sram,
+    /// 64K
+    Flash,
+    /// 128K in two banks
+    Flash1M,
+}
+
+/// Finds the backup type from the ID string Nintendo's save libraries leave
+/// in the ROM, which is also how other emulators and flashcarts decide.
+/// IDs are word aligned.
+pub fn backup_type(data: &[u8]) -> Backup {
+    const IDS: [(&[u8], Backup); 6] = [
+        (b"EEPROM_V", Backup::Eeprom),
+        (b"SRAM_V", Backup::Sram),
+        (b"SRAM_F_V", Backup::Sram),
+        (b"FLASH_V", Backup::Flash),
+        (b"FLASH512_V", Backup::Flash),
+        (b"FLASH1M_V", Backup::Flash1M),
+    ];
+    for off in (0..data.len()).step_by(4) {
+        for &(id, backup) in IDS.iter() {
+            if data[off..].starts_with(id) {
+                return backup;
+            }
+        }
+    }
+    Backup::None
+}
+
+/// Whether `data` starts with a cartridge header with a valid checksum
+pub fn header_valid(data: &[u8]) -> bool {
+    if data.len() < 0xc0 {
+        return false;
+    }
+    let sum = data[0xa0..0xbd]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b));
+    sum.wrapping_sub(0x19) == data[0xbd]
+}
+
+/// The standard (zlib/PNG) CRC-32
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    !data.iter().fold(!0u32, |crc, &b| {
+        table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+impl Default for GameRom {
+    fn default() -> Self {
+        return GameRom {
+            rom: MmapMut::map_anon(0).unwrap().make_read_only().unwrap(),
+            patches: BTreeMap::new(),
+            flashcart: false,
+            backup: None,
+        };
+    }
+}
+
+/// A cheat that replaces what the CPU reads from the gamepak
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RomPatch {
+    Byte(u32, u8),
+    Half(u32, u16),
+}
+
+impl RomPatch {
+    /// Parses `ADDR:VALUE` in hex, two value digits patch a byte and four a
+    /// halfword
+    pub fn parse(s: &str) -> ::std::result::Result<RomPatch, String> {
+        let mut parts = s.splitn(2, ':');
+        let addr = parts.next().unwrap();
+        let val = parts
+            .next()
+            .ok_or_else(|| format!("expected ADDR:VALUE, got '{}'", s))?;
+        let addr = u32::from_str_radix(addr, 16).map_err(|err| err.to_string())?;
+        match val.len() {
+            2 => Ok(RomPatch::Byte(
+                addr,
+                u8::from_str_radix(val, 16).map_err(|err| err.to_string())?,
+            )),
+            4 => Ok(RomPatch::Half(
+                addr,
+                u16::from_str_radix(val, 16).map_err(|err| err.to_string())?,
+            )),
+            _ => Err(format!("patch value '{}' must be 2 or 4 hex digits", val)),
+        }
+    }
+}
+
+impl GameRom {
+    pub fn apply_patch(&mut self, patch: RomPatch) {
+        match patch {
+            RomPatch::Byte(addr, val) => self.patch8(addr, val),
+            RomPatch::Half(addr, val) => self.patch16(addr, val),
+        }
+    }
+
+    /// Makes reads of the byte at `addr` return `val`, `addr` may be either a
+    /// bus address or an offset into the ROM
+    pub fn patch8(&mut self, addr: u32, val: u8) {
+        self.patches.insert(addr & 0x1ffffff, val);
+    }
+
+    pub fn patch16(&mut self, addr: u32, val: u16) {
+        let addr = addr & !1;
+        self.patch8(addr, val as u8);
+        self.patch8(addr + 1, (val >> 8) as u8);
+    }
+
+    /// Undoes `apply_patch`, so reads see the ROM's own data again
+    pub fn remove_patch(&mut self, patch: RomPatch) {
+        let (addr, len) = match patch {
+            RomPatch::Byte(addr, _) => (addr, 1),
+            RomPatch::Half(addr, _) => (addr & !1, 2),
+        };
+        for addr in addr..addr + len {
+            self.patches.remove(&(addr & 0x1ffffff));
+        }
+    }
+
+    pub fn has_patches(&self) -> bool {
+        !self.patches.is_empty()
+    }
+
+    /// Flashcarts let the ROM be written and read 0xff past its end, which
+    /// some games check for to detect them
+    pub fn set_flashcart(&mut self, flashcart: bool) {
+        self.flashcart = flashcart;
+    }
+
+    pub fn clear_patches(&mut self) {
+        self.patches.clear();
+    }
+
+    /// Overlays any patched bytes onto a `size` byte read of `val` at `addr`
+    #[inline]
+    fn patched(&self, addr: u32, size: u32, val: u32) -> u32 {
+        if self.patches.is_empty() {
+            return val;
+        }
+        self.patches
+            .range(addr..addr + size)
+            .fold(val, |val, (&paddr, &pval)| {
+                let shift = (paddr - addr) * 8;
+                (val & !(0xff << shift)) | ((pval as u32) << shift)
+            })
+    }
+}
+
+impl Deref for GameRom {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.rom.deref()
+    }
+}
+
+fn warning<T>(addr: u32, val: T)
+where
+    T: fmt::LowerHex,
+{
+    warn!(
+        "Attempted to store to game ROM: a: {:#010x}, v: {:#x}",
+        addr, val
+    );
+}
+
+impl Mmu for GameRom {
+    fn load8(&self, addr: u32) -> MemoryRead<u8> {
+        let val = if (addr as usize) < self.rom.len() {
+            bytes::load8(self.deref(), addr)
+        } else if self.flashcart {
+            MemoryRead::Value(0xff)
+        } else {
+            MemoryRead::Value((((addr >> 1) & 0xffff) << ((addr & 1) * 8)) as u8)
+        };
+        val.map(|v| self.patched(addr, 1, v as u32) as u8)
+    }
+
+    fn set8(&mut self, addr: u32, val: u8) {
+        if self.flashcart {
+            self.patch8(addr, val);
+        } else {
+            warning(addr, val);
+        }
+    }
+
+    fn load16(&self, addr: u32) -> MemoryRead<u16> {
+        let val = if (addr as usize) < self.rom.len() {
+            bytes::load16(self.deref(), addr)
+        } else if self.flashcart {
+            MemoryRead::Value(0xffff)
+        } else {
+            MemoryRead::Value((addr >> 1) as u16)
+        };
+        val.map(|v| self.patched(addr, 2, v as u32) as u16)
+    }
+
+    fn set16(&mut self, addr: u32, val: u16) {
+        if self.flashcart {
+            self.patch16(addr, val);
+        } else {
+            warning(addr, val);
+        }
+    }
+
+    fn load32(&self, addr: u32) -> MemoryRead<u32> {
+        let val = if (addr as usize) < self.rom.len() {
+            bytes::load32(self.deref(), addr)
+        } else if self.flashcart {
+            MemoryRead::Value(0xffff_ffff)
+        } else {
+            let r = (addr >> 1) & 0xffff;
+            MemoryRead::Value(r | ((r + 1) << 16))
+        };
+        val.map(|v| self.patched(addr, 4, v))
+    }
+
+    fn set32(&mut self, addr: u32, val: u32) {
+        if self.flashcart {
+            self.patch16(addr & !3, val as u16);
+            self.patch16((addr & !3) + 2, (val >> 16) as u16);
+        } else {
+            warning(addr, val);
+        }
+    }
+}
+
+impl fmt::Debug for GameRom {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("GameRom")
+            .field("len", &self.rom.len())
+            .field("ptr", &self.rom.as_ptr())
+            .field("val", &format!("{:#x}", self.rom[0xb2]))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let mut data = vec![0u8; 0xc0];
+        data[0xa0..0xa7].copy_from_slice(b"POKEMON");
+        data[0xac..0xb2].copy_from_slice(b"BPEE01");
+        data[0xbc] = 1;
+        let header = Header::parse(&data).unwrap();
+        assert_eq!(
+            ("POKEMON", "BPEE", "01", 1),
+            (
+                header.title.as_str(),
+                header.game_code.as_str(),
+                header.maker_code.as_str(),
+                header.version
+            )
+        );
+        assert!(!header.checksum_valid);
+        assert_eq!(None, Header::parse(&data[..0xbf]));
+
+        let rom = GameRom::from_bytes(&data).unwrap();
+        assert_eq!(("POKEMON", "BPEE"), (&*rom.title(), &*rom.game_code()));
+        assert_eq!("", GameRom::default().title());
+    }
+
+    #[test]
+    fn test_zip() {
+        use std::io::{Cursor, Write};
+        use zip::write::{FileOptions, ZipWriter};
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("readme.txt", FileOptions::default())
+            .unwrap();
+        zip.write_all(b"not the game").unwrap();
+        zip.start_file("Game (USA).GBA", FileOptions::default())
+            .unwrap();
+        zip.write_all(&[1, 2, 3, 4]).unwrap();
+        let data = zip.finish().unwrap().into_inner();
+
+        let rom = GameRom::from_zip(Cursor::new(&data[..])).unwrap();
+        assert_eq!([1, 2, 3, 4], rom[..]);
+        assert!(GameRom::from_zip(Cursor::new(&data[..4])).is_err());
+    }
+
+    #[test]
+    fn test_patches() {
+        let mut rom = GameRom::default();
+        let unpatched = rom.load32(0x10).get();
+        rom.patch16(0x0800_0010, 0xbeef);
+        rom.patch8(0x13, 0x12);
+        assert_eq!(0xbeef, rom.load16(0x10).get());
+        assert_eq!(0xbe, rom.load8(0x11).get());
+        assert_eq!(
+            0x1200_beef | (unpatched & 0x00ff_0000),
+            rom.load32(0x10).get()
+        );
+        rom.remove_patch(RomPatch::Half(0x0800_0011, 0));
+        assert_eq!(
+            0x1200_0000 | (unpatched & 0x00ff_ffff),
+            rom.load32(0x10).get()
+        );
+        rom.clear_patches();
+        assert_eq!(unpatched, rom.load32(0x10).get());
+    }
+
+    #[test]
+    fn test_flashcart() {
+        let mut rom = GameRom::default();
+        rom.set32(0x100, 0x1234_5678);
+        assert_eq!(0x0080, rom.load16(0x100).get());
+
+        rom.set_flashcart(true);
+        assert_eq!(0xffff, rom.load16(0x100).get());
+        rom.set32(0x100, 0x1234_5678);
+        assert_eq!(0x1234_5678, rom.load32(0x100).get());
+        rom.set8(0x103, 0xab);
+        assert_eq!(0xab34, rom.load16(0x102).get());
+    }
+
+    #[test]
+    fn test_backup_type() {
+        let mut data = vec![0u8; 0x40];
+        assert_eq!(Backup::None, backup_type(&data));
+        data[0x11..0x19].copy_from_slice(b"EEPROM_V");
+        assert_eq!(Backup::None, backup_type(&data));
+        data[0x20..0x29].copy_from_slice(b"FLASH1M_V");
+        assert_eq!(Backup::Flash1M, backup_type(&data));
+        data[0x10..0x16].copy_from_slice(b"SRAM_V");
+        assert_eq!(Backup::Sram, backup_type(&data));
+    }
+
+    #[test]
+    fn test_backup_override() {
+        let mut rom = GameRom::default();
+        assert_eq!(Backup::None, rom.backup());
+        rom.set_backup(Some(Backup::Flash1M));
+        assert_eq!(Backup::Flash1M, rom.backup());
+        rom.set_backup(None);
+        assert_eq!(Backup::None, rom.backup());
+
+        rom.set_backup(Some(Backup::Sram));
+        assert_eq!(Backup::Sram, rom.try_clone().unwrap().backup());
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_parse_patch() {
+        assert_eq!(
+            Ok(RomPatch::Half(0x0800_1234, 0x46c0)),
+            RomPatch::parse("08001234:46c0")
+        );
+        assert_eq!(Ok(RomPatch::Byte(0x1234, 0xff)), RomPatch::parse("1234:ff"));
+        assert!(RomPatch::parse("1234").is_err());
+        assert!(RomPatch::parse("1234:fff").is_err());
+        assert!(RomPatch::parse("xyz:ff").is_err());
+    }
+}
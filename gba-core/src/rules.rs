@@ -0,0 +1,276 @@
+//! Rules that watch memory and fire when a condition becomes true, for
+//! achievement style notifications ("badge count increased").
+//!
+//! Rules are read from a file with one rule per line, conditions joined with
+//! `&&` followed by `:` and the rule's name:
+//!
+//! ```text
+//! # the badge count went up since the last frame
+//! mem8 0x02024a6c > prev : New badge!
+//! mem16 0x02024a70 >= 9999 && mem8 0x02024a6c == 8 : Rich champion
+//! ```
+//!
+//! Conditions read with `mem8`, `mem16` or `mem32` and compare with `==`,
+//! `!=`, `<`, `>`, `<=` or `>=` against a number, or against `prev`, the
+//! value read the last time the rules were checked.
+
+use mmu::MemoryUnit;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+}
+
+impl Width {
+    pub fn parse(s: &str) -> Option<Width> {
+        match s {
+            "mem8" => Some(Width::Byte),
+            "mem16" => Some(Width::Half),
+            "mem32" => Some(Width::Word),
+            _ => None,
+        }
+    }
+
+    /// Reads a value of this width, aligning the address down as the bus does
+    pub fn read<M: MemoryUnit>(self, mmu: &M, addr: u32) -> u32 {
+        match self {
+            Width::Byte => mmu.load8(addr) as u32,
+            Width::Half => mmu.load16(addr & !1) as u32,
+            Width::Word => mmu.load32(addr & !3),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+impl Cmp {
+    pub fn parse(s: &str) -> Option<Cmp> {
+        use self::Cmp::*;
+        match s {
+            "==" => Some(Eq),
+            "!=" => Some(Ne),
+            "<" => Some(Lt),
+            ">" => Some(Gt),
+            "<=" => Some(Le),
+            ">=" => Some(Ge),
+            _ => None,
+        }
+    }
+
+    pub fn eval(self, lhs: u32, rhs: u32) -> bool {
+        use self::Cmp::*;
+        match self {
+            Eq => lhs == rhs,
+            Ne => lhs != rhs,
+            Lt => lhs < rhs,
+            Gt => lhs > rhs,
+            Le => lhs <= rhs,
+            Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hex number
+pub fn parse_num(s: &str) -> Result<u32, String> {
+    let res = if s.starts_with("0x") || s.starts_with("0X") {
+        u32::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    };
+    res.map_err(|_| format!("invalid number '{}'", s))
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Operand {
+    Value(u32),
+    /// The value read at the previous check
+    Prev,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemCond {
+    pub addr: u32,
+    pub width: Width,
+    pub cmp: Cmp,
+    pub rhs: Operand,
+}
+
+impl MemCond {
+    fn parse(text: &str) -> Result<MemCond, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.len() != 4 {
+            return Err(format!("could not parse condition '{}'", text));
+        }
+        let width = Width::parse(words[0]).ok_or_else(|| format!("unknown read '{}'", words[0]))?;
+        let cmp =
+            Cmp::parse(words[2]).ok_or_else(|| format!("unknown comparison '{}'", words[2]))?;
+        let rhs = match words[3] {
+            "prev" => Operand::Prev,
+            num => Operand::Value(parse_num(num)?),
+        };
+        Ok(MemCond {
+            addr: parse_num(words[1])?,
+            width: width,
+            cmp: cmp,
+            rhs: rhs,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub name: String,
+    pub conds: Vec<MemCond>,
+    /// How many times the rule has fired
+    pub hits: u32,
+    /// Values read at the last check, None before the first one
+    prev: Option<Vec<u32>>,
+    active: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Rule, String> {
+        let mut parts = line.splitn(2, ':');
+        let conds = parts.next().unwrap();
+        let name = match parts.next().map(str::trim) {
+            Some(name) if !name.is_empty() => name,
+            _ => return Err(format!("rule has no name: '{}'", line)),
+        };
+        let conds = conds
+            .split("&&")
+            .map(MemCond::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Rule {
+            name: name.to_string(),
+            conds: conds,
+            hits: 0,
+            prev: None,
+            active: false,
+        })
+    }
+
+    /// Evaluates the rule, returns true if it has just become true
+    fn check<M: MemoryUnit>(&mut self, mmu: &M) -> bool {
+        let cur: Vec<u32> = self
+            .conds
+            .iter()
+            .map(|cond| cond.width.read(mmu, cond.addr))
+            .collect();
+        let now = match self.prev {
+            // Nothing to compare `prev` against yet
+            None => false,
+            Some(ref prev) => self.conds.iter().enumerate().all(|(i, cond)| {
+                let rhs = match cond.rhs {
+                    Operand::Value(v) => v,
+                    Operand::Prev => prev[i],
+                };
+                cond.cmp.eval(cur[i], rhs)
+            }),
+        };
+        self.prev = Some(cur);
+
+        let fired = now && !self.active;
+        self.active = now;
+        if fired {
+            self.hits += 1;
+        }
+        fired
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    pub rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Parses a rule file, ignoring blank lines and `#` comments
+    pub fn parse(text: &str) -> Result<Rules, String> {
+        let rules = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.split('#').next().unwrap().trim()))
+            .filter(|&(_, line)| !line.is_empty())
+            .map(|(i, line)| Rule::parse(line).map_err(|err| format!("line {}: {}", i + 1, err)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Rules { rules: rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Checks every rule against memory, calling `fired` for each that has
+    /// become true since the last check
+    pub fn check<M: MemoryUnit, F: FnMut(&Rule)>(&mut self, mmu: &M, mut fired: F) {
+        for rule in self.rules.iter_mut() {
+            if rule.check(mmu) {
+                fired(rule);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mmu::ram::{Ram, RamUnit};
+
+    #[test]
+    fn test_parse() {
+        let rules = Rules::parse(
+            "# comment\n\
+             mem8 0x10 > prev : New badge!\n\
+             \n\
+             mem16 0x20 >= 100 && mem8 0x11 == 8: Rich: very\n",
+        )
+        .unwrap();
+        assert_eq!(2, rules.rules.len());
+        assert_eq!("New badge!", rules.rules[0].name);
+        assert_eq!(Operand::Prev, rules.rules[0].conds[0].rhs);
+        assert_eq!("Rich: very", rules.rules[1].name);
+        assert_eq!(
+            MemCond {
+                addr: 0x20,
+                width: Width::Half,
+                cmp: Cmp::Ge,
+                rhs: Operand::Value(100),
+            },
+            rules.rules[1].conds[0]
+        );
+
+        assert!(Rules::parse("mem8 0x10 > prev").is_err());
+        assert!(Rules::parse("mem8 0x10 ~ 1 : x").is_err());
+        assert!(Rules::parse("mem8 0x10 == 1 && : x").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut mem = RamUnit { ram: Ram::new(64) };
+        let mut rules = Rules::parse("mem8 0x10 > prev : up").unwrap();
+        let mut count = 0;
+
+        rules.check(&mem, |_| count += 1);
+        assert_eq!(0, count);
+        mem.set8(0x10, 1);
+        rules.check(&mem, |_| count += 1);
+        assert_eq!(1, count);
+        // No change, no longer greater than the previous value
+        rules.check(&mem, |_| count += 1);
+        mem.set8(0x10, 2);
+        rules.check(&mem, |_| count += 1);
+        assert_eq!(2, count);
+        assert_eq!(2, rules.rules[0].hits);
+    }
+}
@@ -0,0 +1,157 @@
+//! Timestamped events for the peripherals.
+//!
+//! Rather than stepping every component on every cycle, components schedule
+//! the cycle they next need to do something at, and the CPU runs on its own
+//! until then.  Anything that depends on elapsed time (e.g. timer counters)
+//! catches up lazily from `now` when it is read or written.  Each kind of
+//! event is pending at most once, scheduling it again moves it.
+
+/// Something that has to happen at a given cycle
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Event {
+    /// The PPU moves to the next stage of the scanline
+    Ppu,
+    /// The SPU produces a sample
+    Spu,
+    /// A timer overflows
+    Timers,
+    /// A serial transfer completes
+    Sio,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Scheduler {
+    now: u64,
+    /// Pending events, latest first so the next one can be popped cheaply
+    events: Vec<(u64, Event)>,
+}
+
+impl Scheduler {
+    /// The cycle currently being emulated
+    #[inline]
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    #[inline]
+    pub fn advance(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    /// Jumps ahead to `at` with nothing happening in between, e.g. while the
+    /// CPU is halted
+    #[inline]
+    pub fn skip_to(&mut self, at: u64) {
+        if at != u64::max_value() && at > self.now {
+            self.now = at;
+        }
+    }
+
+    /// The cycle of the next pending event
+    #[inline]
+    pub fn next(&self) -> u64 {
+        match self.events.last() {
+            Some(&(at, _)) => at,
+            None => u64::max_value(),
+        }
+    }
+
+    pub fn schedule(&mut self, event: Event, at: u64) {
+        self.cancel(event);
+        // Events at the same cycle run in the order they were scheduled
+        let idx = self
+            .events
+            .iter()
+            .position(|&(t, _)| t <= at)
+            .unwrap_or(self.events.len());
+        self.events.insert(idx, (at, event));
+    }
+
+    pub fn schedule_in(&mut self, event: Event, cycles: u64) {
+        let at = self.now + cycles;
+        self.schedule(event, at);
+    }
+
+    /// Drops `event` if it's pending
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|&(_, pending)| pending != event);
+    }
+
+    /// The cycle `event` is due at, if it's pending
+    pub fn pending(&self, event: Event) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|&&(_, pending)| pending == event)
+            .map(|&(at, _)| at)
+    }
+
+    /// Removes and returns the next event if it is due, along with the cycle
+    /// it was scheduled for
+    #[inline]
+    pub fn pop_due(&mut self) -> Option<(u64, Event)> {
+        if self.next() <= self.now {
+            self.events.pop()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let mut sched = Scheduler::default();
+        sched.schedule(Event::Spu, 10);
+        sched.schedule(Event::Ppu, 5);
+        sched.schedule(Event::Sio, 10);
+        assert_eq!(5, sched.next());
+        assert_eq!(None, sched.pop_due());
+
+        sched.advance(10);
+        assert_eq!(Some((5, Event::Ppu)), sched.pop_due());
+        assert_eq!(Some((10, Event::Spu)), sched.pop_due());
+        assert_eq!(Some((10, Event::Sio)), sched.pop_due());
+        assert_eq!(None, sched.pop_due());
+        assert_eq!(u64::max_value(), sched.next());
+    }
+
+    #[test]
+    fn test_reschedule() {
+        let mut sched = Scheduler::default();
+        sched.schedule(Event::Timers, 100);
+        sched.schedule(Event::Ppu, 50);
+        sched.schedule(Event::Timers, 20);
+        assert_eq!(20, sched.next());
+        sched.schedule(Event::Timers, 80);
+        assert_eq!(50, sched.next());
+
+        sched.advance(100);
+        assert_eq!(Some((50, Event::Ppu)), sched.pop_due());
+        assert_eq!(Some((80, Event::Timers)), sched.pop_due());
+        assert_eq!(None, sched.pop_due());
+
+        sched.schedule(Event::Sio, 200);
+        assert_eq!(Some(200), sched.pending(Event::Sio));
+        sched.cancel(Event::Sio);
+        assert_eq!(None, sched.pending(Event::Sio));
+        sched.cancel(Event::Spu);
+        assert_eq!(u64::max_value(), sched.next());
+    }
+
+    #[test]
+    fn test_skip_to() {
+        let mut sched = Scheduler::default();
+        sched.skip_to(sched.next());
+        assert_eq!(0, sched.now());
+
+        sched.schedule(Event::Timers, 1000);
+        sched.skip_to(sched.next());
+        assert_eq!(1000, sched.now());
+        sched.skip_to(10);
+        assert_eq!(1000, sched.now());
+        assert_eq!(Some((1000, Event::Timers)), sched.pop_due());
+    }
+}
@@ -0,0 +1,164 @@
+//! Names for addresses in the game, from an ELF file's symbol table or the
+//! symbol files linkers and other emulators write beside a ROM.
+//!
+//! `.sym` files are no$gba's format, an address in hex and a name per line.
+//! `.map` files are GNU ld's link maps, where each symbol the linker placed
+//! is a line of its address and name under the section it's in.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    /// In bytes, 0 for labels
+    pub size: u32,
+    /// A function made of Thumb instructions
+    pub thumb: bool,
+}
+
+impl Symbol {
+    fn label(name: &str, addr: u32) -> Self {
+        Symbol {
+            name: name.to_string(),
+            addr: addr,
+            size: 0,
+            thumb: false,
+        }
+    }
+}
+
+/// Symbols looked up by name or address
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    /// Sorted by address
+    symbols: Vec<Symbol>,
+}
+
+/// Whether `name` could be a C or assembler symbol, rather than part of a
+/// linker script in a map file
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    let s = if s.starts_with("0x") { &s[2..] } else { s };
+    u32::from_str_radix(s, 16).ok()
+}
+
+impl Symbols {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|sym| sym.addr);
+        Symbols { symbols: symbols }
+    }
+
+    /// Reads a no$gba `.sym` file
+    pub fn parse_sym(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let addr = parts.next().and_then(parse_hex);
+            match (addr, parts.next()) {
+                // `.arm`, `.thumb` and the like mark what follows
+                (Some(_), Some(name)) if name.starts_with('.') => (),
+                (Some(addr), Some(name)) => symbols.push(Symbol::label(name, addr)),
+                _ => return Err(format!("line {}: expected an address and a name", i + 1)),
+            }
+        }
+        Ok(Symbols::new(symbols))
+    }
+
+    /// Reads the symbols out of a GNU ld `.map` file
+    pub fn parse_map(text: &str) -> Symbols {
+        let mut symbols = Vec::new();
+        for line in text.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 2 || !parts[0].starts_with("0x") || !is_name(parts[1]) {
+                continue;
+            }
+            if let Some(addr) = parse_hex(parts[0]) {
+                symbols.push(Symbol::label(parts[1], addr));
+            }
+        }
+        Symbols::new(symbols)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|sym| sym.name == name)
+    }
+
+    /// The symbol `addr` is in, with how far into it `addr` is.  Labels
+    /// without a size cover up to the next symbol.
+    pub fn containing(&self, addr: u32) -> Option<(&Symbol, u32)> {
+        let idx = match self.symbols.binary_search_by_key(&addr, |sym| sym.addr) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        // Prefer a sized symbol among several at the same address
+        let start = self.symbols[idx].addr;
+        let sym = self.symbols[..idx + 1]
+            .iter()
+            .rev()
+            .take_while(|sym| sym.addr == start)
+            .max_by_key(|sym| sym.size)
+            .unwrap();
+        let off = addr - sym.addr;
+        if sym.size == 0 || off < sym.size {
+            Some((sym, off))
+        } else {
+            None
+        }
+    }
+
+    /// `addr` as `function+offset`, or in hex outside any symbol
+    pub fn name(&self, addr: u32) -> String {
+        match self.containing(addr) {
+            Some((sym, 0)) => sym.name.clone(),
+            Some((sym, off)) => format!("{}+{:#x}", sym.name, off),
+            None => format!("{:#010x}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sym() {
+        let text = "; no$gba symbols\n08000000 .arm\n08000000 _start\n080001f0 main\n\n\
+                    03000000 counter ; in IWRAM\n";
+        let symbols = Symbols::parse_sym(text).unwrap();
+        assert_eq!(0x0800_01f0, symbols.lookup("main").unwrap().addr);
+        assert_eq!("main+0x10", symbols.name(0x0800_0200));
+        assert_eq!("_start", symbols.name(0x0800_0000));
+        assert_eq!("0x02000000", symbols.name(0x0200_0000));
+        assert!(Symbols::parse_sym("main 08000000").is_err());
+    }
+
+    #[test]
+    fn test_map() {
+        let text = " .text          0x080001f0       0x40 main.o\n\
+                    \x20               0x080001f0                main\n\
+                    \x20               0x08000000                . = 0x8000000\n\
+                    \x20               0x03000000                PROVIDE (__iwram_start, .)\n\
+                    \x20               0x08000230                update_input\n";
+        let symbols = Symbols::parse_map(text);
+        assert_eq!(0x0800_01f0, symbols.lookup("main").unwrap().addr);
+        assert_eq!("update_input+0x4", symbols.name(0x0800_0234));
+        assert!(symbols.lookup("main.o").is_none());
+        assert!(symbols.lookup("PROVIDE").is_none());
+    }
+}
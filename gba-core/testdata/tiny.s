@@ -0,0 +1,37 @@
+@ A tiny ROM for the gba-core doctests, released into the public domain.
+@
+@ Shows a red pixel in the top left corner in mode 3, then counts up the word
+@ at 0x03000000 forever.  The header has a valid complement but no logo, so
+@ it only runs with direct boot.
+@
+@   arm-none-eabi-as tiny.s -o tiny.o
+@   arm-none-eabi-objcopy -O binary tiny.o tiny.gba
+
+    .arm
+    .global _start
+_start:
+    b       main
+    .fill   156, 1, 0           @ logo
+    .ascii  "TINY\0\0\0\0\0\0\0\0"  @ title
+    .ascii  "ZTNY"              @ game code
+    .ascii  "00"                @ maker code
+    .byte   0x96, 0, 0          @ fixed value, unit code, device type
+    .fill   7, 1, 0
+    .byte   0                   @ version
+    .byte   0x58                @ header complement
+    .hword  0
+
+main:
+    mov     r0, #0x04000000
+    mov     r1, #0x400
+    orr     r1, r1, #3
+    strh    r1, [r0]            @ DISPCNT: mode 3, BG2 on
+    mov     r2, #0x06000000
+    mov     r3, #0x1f
+    strh    r3, [r2]            @ red pixel at (0, 0)
+    mov     r4, #0x03000000
+loop:
+    ldr     r5, [r4]
+    add     r5, r5, #1
+    str     r5, [r4]
+    b       loop
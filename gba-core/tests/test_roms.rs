@@ -0,0 +1,137 @@
+//! Runs the public test ROM suites headlessly and checks the results they
+//! leave behind.  The ROMs aren't ours to ship, so these are ignored unless
+//! asked for with `--ignored`, and read them from the directory
+//! `GBA_TEST_ROMS` names:
+//!
+//! - `arm.gba` and `thumb.gba` from jsmolka's gba-suite, which leave the
+//!   number of the first test that failed in r12, or 0 if they all passed
+//! - `suite.gba`, mGBA's test suite, and `ags.gba`, the AGS aging cartridge,
+//!   which only show their results on screen.  The final frame is hashed and
+//!   compared with the hash in `suite.hash` or `ags.hash` beside the ROM,
+//!   taken from a run where every test passed.
+//!
+//! A missing ROM fails its test, so a run can't pass without covering
+//! anything; name one test to run just that one.  `GBA_TEST_BIOS` boots
+//! through a real BIOS, which AGS needs for its BIOS tests, rather than
+//! skipping straight to the ROM.
+//!
+//! ```text
+//! GBA_TEST_ROMS=~/gba-tests cargo test -p gba-core --test test_roms -- --ignored
+//! ```
+
+extern crate gba_core;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use gba_core::io::key::KeyState;
+use gba_core::rom::GameRom;
+use gba_core::{Gba, Options};
+
+/// Frames the BIOS's intro takes before it jumps to the ROM, rounded up
+const INTRO_FRAMES: u32 = 240;
+
+/// Boots `name` from the test ROM directory, running through the BIOS's
+/// intro if there's a BIOS so frame counts start from the ROM either way
+fn boot(name: &str) -> (Box<Gba<'static>>, PathBuf) {
+    let dir = env::var_os("GBA_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| panic!("set GBA_TEST_ROMS to the directory holding {}", name));
+    let path = dir.join(name);
+    if !path.exists() {
+        panic!("{} isn't in {}", name, dir.display());
+    }
+    let rom = GameRom::new(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    let bios = env::var_os("GBA_TEST_BIOS")
+        .map(|bios| GameRom::new(bios.as_ref()).expect("GBA_TEST_BIOS"));
+    let opts = Options {
+        direct_boot: bios.is_none(),
+        ..Default::default()
+    };
+    let mut gba = Gba::new(rom, bios.unwrap_or_default(), &opts);
+    if !opts.direct_boot {
+        run(&mut gba, INTRO_FRAMES, no_keys);
+    }
+    (gba, path)
+}
+
+/// Runs `frames` frames, holding the keys `keys` gives for each
+fn run(gba: &mut Gba, frames: u32, keys: fn(u32) -> KeyState) {
+    for frame in 0..frames {
+        gba.io.set_keyreg(&keys(frame));
+        gba.run_frame();
+    }
+}
+
+fn no_keys(_: u32) -> KeyState {
+    Default::default()
+}
+
+/// FNV-1a, to compare frames without keeping images of them
+fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Runs a gba-suite ROM to its end and checks none of its tests failed
+fn gba_suite(name: &str) {
+    let (mut gba, _) = boot(name);
+    run(&mut gba, 60, no_keys);
+    let failed = gba.cpu.reg(12);
+    assert!(failed == 0, "{} failed test {}", name, failed);
+}
+
+/// Runs a ROM that shows its results on screen and checks the last frame is
+/// the one where every test passed
+fn screen_suite(name: &str, frames: u32, keys: fn(u32) -> KeyState) {
+    let (mut gba, path) = boot(name);
+    run(&mut gba, frames, keys);
+    let got = format!("{:016x}", hash(gba.frame()));
+    let expected_path = path.with_extension("hash");
+    let expected = fs::read_to_string(&expected_path).unwrap_or_else(|_| {
+        panic!(
+            "no expected hash in {}; if the screen shows every test passed, save {} there",
+            expected_path.display(),
+            got
+        )
+    });
+    assert!(
+        expected.trim() == got,
+        "{}'s results screen changed: hash {}, expected {}",
+        name,
+        got,
+        expected.trim()
+    );
+}
+
+#[test]
+#[ignore]
+fn test_gba_suite_arm() {
+    gba_suite("arm.gba");
+}
+
+#[test]
+#[ignore]
+fn test_gba_suite_thumb() {
+    gba_suite("thumb.gba");
+}
+
+#[test]
+#[ignore]
+fn test_mgba_suite() {
+    // Runs the first entry in the menu, the memory tests, and shows their
+    // results
+    screen_suite("suite.gba", 300, |frame| KeyState {
+        a: (30..32).contains(&frame),
+        ..Default::default()
+    });
+}
+
+#[test]
+#[ignore]
+fn test_ags_aging() {
+    // The tests run from boot, and take about 20 seconds
+    screen_suite("ags.gba", 1800, no_keys);
+}
@@ -0,0 +1,38 @@
+//! `--bench`, which runs a game as fast as it will go with no window, sound
+//! or frame pacing, then prints how fast that was and where the emulated
+//! cycles went, to measure changes to the core.
+
+use std::time::Instant;
+
+use gba_core;
+use gba_core::rom::GameRom;
+use gba_core::{CYCLES_PER_FRAME, CYCLES_PER_SEC};
+
+/// Runs `rom` for `frames` frames with no input and prints the results
+pub fn run(
+    rom: GameRom,
+    bios: GameRom,
+    opts: &gba_core::Options,
+    frames: u64,
+) -> Result<(), String> {
+    let mut gba = gba_core::Gba::new(rom, bios, opts);
+    gba.count_cycles(true);
+
+    let start = Instant::now();
+    for frame in 0..frames {
+        if !gba.run_frame() {
+            return Err(format!("CPU stopped at a breakpoint on frame {}", frame));
+        }
+    }
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+    let emulated = (frames * CYCLES_PER_FRAME) as f64 / CYCLES_PER_SEC as f64;
+    println!("{} frames in {:.3}s", frames, secs);
+    println!("  {:.1} frames/sec", frames as f64 / secs);
+    println!("  {:.2} emulated seconds per second", emulated / secs);
+    for line in gba.cycle_counts().unwrap().report() {
+        println!("{}", line);
+    }
+    Ok(())
+}
@@ -0,0 +1,322 @@
+//! Portable save bundles, for moving a game's saves between machines.
+//!
+//! A bundle holds the battery save, cheats and per-game config along with
+//! the save states written under the save prefix (the numbered slots, the
+//! resume, crash and trigger states), and the CRC-32 of the ROM they belong
+//! to, so they aren't unpacked against the wrong
+//! game.  The file is a magic header followed by a zstd compressed bincode
+//! `Bundle`.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bincode;
+use zstd;
+
+use gba_core::rom::GameRom;
+
+const MAGIC: &'static [u8; 8] = b"GBABNDL1";
+
+/// A game's files kept outside its save prefix
+pub struct Extras {
+    pub battery: Option<PathBuf>,
+    pub cheats: Option<PathBuf>,
+    /// The game's settings in the config directory
+    pub game_config: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Bundle {
+    pub rom_crc32: u32,
+    pub title: String,
+    pub battery: Option<Vec<u8>>,
+    pub cheats: Option<Vec<u8>>,
+    pub game_config: Option<Vec<u8>>,
+    /// File contents, keyed by the part of their name after the save prefix
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    /// Checks the bundle was made for `rom` and only names files that stay
+    /// beside the save prefix
+    pub fn validate(&self, rom_crc32: u32) -> Result<(), String> {
+        if self.rom_crc32 != rom_crc32 {
+            return Err(format!(
+                "bundle is for '{}' (CRC {:08x}), but the ROM's CRC is {:08x}",
+                self.title, self.rom_crc32, rom_crc32
+            ));
+        }
+        for &(ref suffix, _) in self.files.iter() {
+            if suffix.is_empty() || suffix.contains(|c: char| c == '/' || c == '\\') {
+                return Err(format!("bundle contains an invalid file name '{}'", suffix));
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of files in the bundle
+    pub fn file_count(&self) -> usize {
+        let extras = [&self.battery, &self.cheats, &self.game_config];
+        self.files.len() + extras.iter().filter(|data| data.is_some()).count()
+    }
+}
+
+fn with_suffix(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(prefix);
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Whether the file named `suffix` after the save prefix is a save state,
+/// which keeps the bundle clear of other games whose names start with the
+/// prefix, like `mario-kart-0.state` under `mario-`
+fn save_state(suffix: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let trigger = |s: &str| match s.find('-') {
+        Some(dash) => digits(&s[..dash]) && digits(&s[dash + 1..]),
+        None => false,
+    };
+    if !suffix.ends_with(".state") {
+        return false;
+    }
+    let stem = &suffix[..suffix.len() - ".state".len()];
+    match stem {
+        "resume" | "crash" => true,
+        _ if stem.starts_with("trigger") => trigger(&stem["trigger".len()..]),
+        _ => digits(stem),
+    }
+}
+
+/// The contents of `path`, or None if there's no such file
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>, String> {
+    let mut data = Vec::new();
+    match File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+        Ok(_) => Ok(Some(data)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("could not read {}: {}", path.display(), err)),
+    }
+}
+
+/// Gathers `extras` and the files under `prefix` into a bundle for `rom`,
+/// skipping `exclude`
+pub fn collect(
+    rom: &GameRom,
+    prefix: &Path,
+    extras: &Extras,
+    exclude: &Path,
+) -> Result<Bundle, String> {
+    let dir = match prefix.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let base = match prefix.file_name().and_then(|name| name.to_str()) {
+        Some(base) => base,
+        None => return Err(format!("invalid save prefix {}", prefix.display())),
+    };
+    // Extras named after the prefix are only bundled once
+    let exclude: Vec<PathBuf> = Some(exclude)
+        .into_iter()
+        .chain(extras.battery.as_ref().map(|p| p.as_path()))
+        .chain(extras.cheats.as_ref().map(|p| p.as_path()))
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("could not read {}: {}", dir.display(), err))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|err| err.to_string())?;
+        let path = entry.path();
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if !name.starts_with(base) || !save_state(&name[base.len()..]) || !path.is_file() {
+            continue;
+        }
+        let suffix = &name[base.len()..];
+        if let Ok(canonical) = path.canonicalize() {
+            if exclude.contains(&canonical) {
+                continue;
+            }
+        }
+        if let Some(data) = read_file(&path)? {
+            files.push((suffix.to_string(), data));
+        }
+    }
+    files.sort();
+
+    let extra = |path: &Option<PathBuf>| match *path {
+        Some(ref path) => read_file(path),
+        None => Ok(None),
+    };
+    Ok(Bundle {
+        rom_crc32: rom.crc32(),
+        title: rom.title(),
+        battery: extra(&extras.battery)?,
+        cheats: extra(&extras.cheats)?,
+        game_config: extra(&extras.game_config)?,
+        files: files,
+    })
+}
+
+pub fn write<W: Write>(bundle: &Bundle, mut out: W) -> Result<(), String> {
+    out.write_all(MAGIC).map_err(|err| err.to_string())?;
+    let mut writer = zstd::Encoder::new(out, 3).map_err(|err| err.to_string())?;
+    bincode::serialize_into(&mut writer, bundle).map_err(|err| err.to_string())?;
+    writer.finish().map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+pub fn read<R: Read>(mut input: R) -> Result<Bundle, String> {
+    let mut magic = [0u8; 8];
+    input
+        .read_exact(&mut magic)
+        .map_err(|_| "not a save bundle".to_string())?;
+    if &magic != MAGIC {
+        return Err("not a save bundle".to_string());
+    }
+    let reader = zstd::Decoder::new(input).map_err(|err| err.to_string())?;
+    bincode::deserialize_from(reader).map_err(|err| format!("corrupt save bundle: {}", err))
+}
+
+/// Writes the bundle for `rom` to `path`
+pub fn export(rom: &GameRom, prefix: &Path, extras: &Extras, path: &Path) -> Result<(), String> {
+    let bundle = collect(rom, prefix, extras, path)?;
+    let file = File::create(path)
+        .map_err(|err| format!("could not create {}: {}", path.display(), err))?;
+    write(&bundle, file)?;
+    info!(
+        "Exported {} files for '{}' to {}",
+        bundle.file_count(),
+        bundle.title,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Unpacks the bundle at `path` under `prefix` and to `extras`, refusing to
+/// replace existing files unless `force` is set
+pub fn import(
+    rom: &GameRom,
+    prefix: &Path,
+    extras: &Extras,
+    path: &Path,
+    force: bool,
+) -> Result<(), String> {
+    let file =
+        File::open(path).map_err(|err| format!("could not open {}: {}", path.display(), err))?;
+    let bundle = read(file)?;
+    bundle.validate(rom.crc32())?;
+
+    let mut targets: Vec<(PathBuf, &[u8])> = bundle
+        .files
+        .iter()
+        .map(|&(ref suffix, ref data)| (with_suffix(prefix, suffix), data.as_slice()))
+        .collect();
+    let unpacked = [
+        ("battery save", &bundle.battery, &extras.battery),
+        ("cheats", &bundle.cheats, &extras.cheats),
+        ("game config", &bundle.game_config, &extras.game_config),
+    ];
+    for &(what, data, target) in unpacked.iter() {
+        match (data, target) {
+            (&Some(ref data), &Some(ref target)) => targets.push((target.clone(), data.as_slice())),
+            (&Some(_), &None) => warn!("Not unpacking the bundle's {}, it has nowhere to go", what),
+            _ => (),
+        }
+    }
+    if !force {
+        if let Some(&(ref existing, _)) = targets.iter().find(|&&(ref target, _)| target.exists()) {
+            return Err(format!(
+                "{} already exists, use --force to overwrite",
+                existing.display()
+            ));
+        }
+    }
+    for &(ref target, data) in targets.iter() {
+        if let Some(dir) = target.parent() {
+            if dir != Path::new("") {
+                fs::create_dir_all(dir)
+                    .map_err(|err| format!("could not create {}: {}", dir.display(), err))?;
+            }
+        }
+        File::create(target)
+            .and_then(|mut f| f.write_all(data))
+            .map_err(|err| format!("could not write {}: {}", target.display(), err))?;
+    }
+    info!(
+        "Imported {} files for '{}' from {}",
+        targets.len(),
+        bundle.title,
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bundle(files: &[&str]) -> Bundle {
+        Bundle {
+            rom_crc32: 0x1234_5678,
+            title: "TEST".to_string(),
+            battery: None,
+            cheats: None,
+            game_config: None,
+            files: files
+                .iter()
+                .map(|name| (name.to_string(), vec![]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(bundle(&["0.state", "1.state"])
+            .validate(0x1234_5678)
+            .is_ok());
+        assert!(bundle(&["0.state"]).validate(0x8765_4321).is_err());
+        assert!(bundle(&["/../../etc/passwd"])
+            .validate(0x1234_5678)
+            .is_err());
+        assert!(bundle(&["\\x"]).validate(0x1234_5678).is_err());
+        assert!(bundle(&[""]).validate(0x1234_5678).is_err());
+    }
+
+    #[test]
+    fn test_with_suffix() {
+        assert_eq!(
+            Path::new("games/zelda3.state"),
+            with_suffix(Path::new("games/zelda"), "3.state")
+        );
+    }
+
+    #[test]
+    fn test_save_state() {
+        assert!(save_state("0.state"));
+        assert!(save_state("12.state"));
+        assert!(save_state("resume.state"));
+        assert!(save_state("crash.state"));
+        assert!(save_state("trigger0-3.state"));
+        // Another game's states under a shorter prefix
+        assert!(!save_state("kart-0.state"));
+        assert!(!save_state("kart-resume.state"));
+        assert!(!save_state("trigger0.state"));
+        assert!(!save_state("triggerx-1.state"));
+        assert!(!save_state(".state"));
+        assert!(!save_state("0.sav"));
+        assert!(!save_state("layers-120-bg0.bmp"));
+        assert!(!save_state("crash-ewram.bin"));
+        assert!(!save_state("0.state.lock.1234"));
+    }
+
+    #[test]
+    fn test_read_bad_magic() {
+        assert!(read(&b"NOTABNDL"[..]).is_err());
+        assert!(read(&b"GBA"[..]).is_err());
+    }
+}
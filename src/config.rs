@@ -0,0 +1,26 @@
+//! Where gba-rs keeps its own files, as opposed to the saves for each game,
+//! which go wherever the save prefix says.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// `$XDG_CONFIG_HOME/gba-rs`, falling back to `~/.config/gba-rs`, or
+/// `%APPDATA%\gba-rs` on Windows
+pub fn dir() -> PathBuf {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("gba-rs")
+}
+
+/// Returns the path of `name` in the config directory, creating the
+/// directory if needed
+pub fn file(name: &str) -> Result<PathBuf, String> {
+    let dir = dir();
+    fs::create_dir_all(&dir)
+        .map_err(|err| format!("could not create {}: {}", dir.display(), err))?;
+    Ok(dir.join(name))
+}
@@ -0,0 +1,123 @@
+//! `--determinism-check`, which runs a game twice side by side with the same
+//! input and stops at the first frame their states differ.
+//!
+//! Replays, netplay and run-ahead all depend on the same input always giving
+//! the same frames, which anything reading the host's clock or state left
+//! uninitialised quietly breaks.  The input is a fixed pseudo-random
+//! sequence of buttons, changing every few frames, so games get past their
+//! title screens.
+//!
+//! The core itself has nothing from the host in it: work RAM starts filled
+//! with `--ram-fill`'s byte, zero by default, and the core never reads the
+//! host's clock.  The RTC some cartridges have isn't emulated, so there's no
+//! clock to seed, and games that look for one find none on every run.
+//! `--deterministic` takes care of the rest for a normal run, leaving out the
+//! battery save and the state resumed on launch, and refusing crowd play,
+//! whose votes arrive on the host's time, and Discord presence, which reads
+//! the host's clock.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use bincode;
+
+use gba_core;
+use gba_core::io::key::{InputProvider, KeyState};
+use gba_core::rom::GameRom;
+
+use state_diff;
+
+/// Frames each set of buttons is held for
+const HOLD_FRAMES: u64 = 8;
+
+/// The buttons held on `frame`, the same on every run
+fn input(frame: u64) -> KeyState {
+    // xorshift64*, seeded by which hold this frame is in
+    let mut x = (frame / HOLD_FRAMES + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    let bits = x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32;
+    let bit = |n: u64| (bits >> n) & 1 == 1;
+    KeyState {
+        a: bit(0),
+        b: bit(1),
+        // Leave select and start alone mostly, many games reset on both
+        select: bits & 0xf0 == 0,
+        start: bits & 0xf00 == 0,
+        r: bit(12),
+        l: bit(13) && !bit(12),
+        u: bit(14),
+        d: bit(15) && !bit(14),
+        br: bit(16),
+        bl: bit(17),
+    }
+}
+
+/// Plays `input` back from the first frame, one for each run
+#[derive(Default)]
+struct Script {
+    frame: u64,
+}
+
+impl InputProvider for Script {
+    fn poll(&mut self) -> KeyState {
+        let keys = input(self.frame);
+        self.frame += 1;
+        keys
+    }
+}
+
+/// A hash of the whole of `gba`'s state and the frame it drew
+fn hash_state(gba: &gba_core::Gba) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&bincode::serialize(gba).unwrap());
+    hasher.write(gba.frame());
+    hasher.finish()
+}
+
+/// Runs `rom` twice for `frames` frames, describing where they went apart
+/// if they did
+pub fn run(
+    rom: GameRom,
+    bios: GameRom,
+    opts: &gba_core::Options,
+    frames: u64,
+) -> Result<(), String> {
+    let copy = |rom: &GameRom| rom.try_clone().map_err(|err| err.to_string());
+    let mut a = gba_core::Gba::new(copy(&rom)?, copy(&bios)?, opts);
+    let mut b = gba_core::Gba::new(rom, bios, opts);
+
+    let mut scripts = [Script::default(), Script::default()];
+    for frame in 0..frames {
+        for (gba, script) in [&mut a, &mut b].iter_mut().zip(scripts.iter_mut()) {
+            gba.io.poll_input(script);
+            if !gba.run_frame() {
+                return Err(format!("CPU stopped at a breakpoint on frame {}", frame));
+            }
+        }
+        if hash_state(&a) != hash_state(&b) {
+            let mut lines = vec![format!("runs differ after frame {}", frame)];
+            lines.extend(state_diff::diff(&a, &b));
+            if a.frame() != b.frame() {
+                lines.push("picture: differs".to_string());
+            }
+            return Err(lines.join("\n"));
+        }
+    }
+    println!("{} frames matched", frames);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_input() {
+        assert_eq!(format!("{:?}", input(3)), format!("{:?}", input(5)));
+        let differs = (1..16)
+            .any(|hold| format!("{:?}", input(0)) != format!("{:?}", input(hold * HOLD_FRAMES)));
+        assert!(differs);
+    }
+}
@@ -0,0 +1,94 @@
+//! `disasm`, which prints a ROM, or a range of it, as annotated ARM or Thumb
+//! assembly without running it.
+//!
+//! Addresses inside the range that something branches to get a label, and
+//! pc-relative loads show the value they load when it's in the ROM.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use gba_core::cpu::disasm::{self, Line};
+
+use romfile::{self, Kind, LoadError};
+
+/// Where the ROM is mapped, and the end of the last of its three mirrors
+const ROM_BASE: u32 = 0x0800_0000;
+const ROM_END: u32 = 0x0e00_0000;
+const ROM_MIRROR_MASK: u32 = 0x01ff_ffff;
+
+/// The word at `addr` in `rom`, if it's in the ROM
+fn rom_word(rom: &[u8], addr: u32) -> Option<u32> {
+    if addr < ROM_BASE || addr >= ROM_END {
+        return None;
+    }
+    let off = (addr & ROM_MIRROR_MASK & !3) as usize;
+    rom.get(off..off + 4)
+        .map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+}
+
+fn format_line(line: &Line, rom: &[u8], thumb: bool) -> String {
+    let opcode = match (thumb, line.size) {
+        (false, _) => format!("{:08x}", line.opcode),
+        (true, 4) => format!("{:04x} {:04x}", line.opcode >> 16, line.opcode & 0xffff),
+        (true, _) => format!("{:04x}", line.opcode),
+    };
+    let mut text = format!("{:08x}:  {:<9}  {}", line.addr, opcode, line.text);
+    if let Some(val) = line.literal.and_then(|addr| rom_word(rom, addr)) {
+        text.push_str(&format!(" ; ={:#010x}", val));
+    }
+    text
+}
+
+/// The assembly for `len` bytes of the ROM at `path` from `start`, or to
+/// its end without `len`
+pub fn disassemble(
+    path: &Path,
+    start: u32,
+    len: Option<u32>,
+    thumb: bool,
+) -> Result<Vec<String>, LoadError> {
+    let rom = romfile::load(path, Kind::Rom)?;
+    let off = ((start & ROM_MIRROR_MASK) as usize).min(rom.len());
+    let end = len.map_or(rom.len(), |len| (off + len as usize).min(rom.len()));
+    let lines = disasm::disasm_range(&rom[off..end], start, thumb);
+
+    let first = lines.first().map_or(start, |line| line.addr);
+    let last = lines.last().map_or(start, |line| line.addr);
+    let targets: BTreeSet<u32> = lines
+        .iter()
+        .filter_map(|line| line.target)
+        .filter(|&target| target >= first && target <= last)
+        .collect();
+
+    let mut out = Vec::new();
+    for line in lines.iter() {
+        if targets.contains(&line.addr) {
+            out.push(String::new());
+            out.push(format!("loc_{:08x}:", line.addr));
+        }
+        out.push(format_line(line, &rom, thumb));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let rom = [
+            0x01, 0x48, 0x00, 0xf0, 0x80, 0xf8, 0xfe, 0xe7, 0x00, 0x00, 0x00, 0x04,
+        ];
+        let lines = disasm::disasm_range(&rom[..8], ROM_BASE, true);
+        assert_eq!(
+            "08000000:  4801       ldr r0, [pc, #0x4] ; 0x08000008 ; =0x04000000",
+            format_line(&lines[0], &rom, true)
+        );
+        assert_eq!(
+            "08000002:  f000 f880  bl 0x08000106",
+            format_line(&lines[1], &rom, true)
+        );
+        assert_eq!(None, rom_word(&rom, 0x0300_0000));
+    }
+}
@@ -0,0 +1,43 @@
+//! Shows the running game as a Discord Rich Presence, built with the
+//! `discord` feature.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use discord_rpc_client::Client;
+
+use gba::status::{Status, StatusCallback};
+
+// Discord drops activity updates sent more often than this
+const MIN_UPDATE: u64 = 15;
+
+/// Connects to the local Discord client and returns a status callback that
+/// forwards to it
+pub fn status_callback(app_id: u64) -> StatusCallback {
+    let mut client = Client::new(app_id);
+    client.start();
+
+    let mut last: Option<Instant> = None;
+    Box::new(move |status: &Status| {
+        if let Some(last) = last {
+            if Instant::now() - last < Duration::from_secs(MIN_UPDATE) {
+                return;
+            }
+        }
+        last = Some(Instant::now());
+
+        // Discord shows the time since this
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() - status.elapsed.as_secs())
+            .unwrap_or(0);
+        let state = format!("{:.0}% speed", status.speed * 100.0);
+        let res = client.set_activity(|act| {
+            act.details(status.title.clone())
+                .state(state)
+                .timestamps(|t| t.start(start))
+        });
+        if let Err(err) = res {
+            warn!("Failed to update Discord presence: {}", err);
+        }
+    })
+}
@@ -0,0 +1,183 @@
+//! Settings for particular games, so ones with quirks work without flags.
+//!
+//! Settings come from a small database built in, keyed by the game code in
+//! the cartridge header, and then from `games/<CODE>.toml` in the config
+//! directory, whose settings win:
+//!
+//! ```toml
+//! # 128K of flash, and skip the wait for VBlank in the main loop
+//! save = "flash1m"
+//! rtc = true
+//! idle-loop = "0x080008a4"
+//!
+//! [bindings]
+//! a = "J"
+//! fast-forward = "none"
+//! ```
+//!
+//! `save` is the backup type, one of `none`, `eeprom`, `sram`, `flash` or
+//! `flash1m`, for games the ROM's ID string is wrong or missing for.
+//! `idle-loop` is the address of a loop the game waits for an interrupt in,
+//! which is skipped through.  `bindings` presses `a`, `b`, `select`, `start`,
+//! `right`, `left`, `up`, `down`, `r` or `l` with the SDL key named, and can
+//! also move a hotkey such as `fast-forward` out of a game's way.
+//!
+//! The real-time clock some games have isn't emulated, `rtc` only warns that
+//! the game won't see the time.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use sdl2::keyboard::Scancode;
+use toml;
+
+use gba_core::rom::{Backup, GameRom};
+use gba_core::rules::parse_num;
+
+use config;
+use gba::bindings::{self, Bindings};
+use gba::Options;
+
+/// Games that need settings, by game code.  Only settings that change how a
+/// game runs belong here, so games that need the RTC or 128K flash banking,
+/// which aren't emulated, aren't listed.
+const GAMES: &'static [(&'static str, &'static str)] = &[];
+
+#[derive(Clone, Debug, Default)]
+pub struct GameConfig {
+    pub backup: Option<Backup>,
+    pub rtc: Option<bool>,
+    pub idle_loop: Option<u32>,
+    pub bindings: Vec<(String, Option<Scancode>)>,
+}
+
+/// A game's settings file, before the values are checked
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct GameFile {
+    save: Option<String>,
+    rtc: Option<bool>,
+    /// A string, so it can be written in hex
+    idle_loop: Option<String>,
+    bindings: BTreeMap<String, String>,
+}
+
+fn parse_backup(s: &str) -> Option<Backup> {
+    match s {
+        "none" => Some(Backup::None),
+        "eeprom" => Some(Backup::Eeprom),
+        "sram" => Some(Backup::Sram),
+        "flash" => Some(Backup::Flash),
+        "flash1m" => Some(Backup::Flash1M),
+        _ => None,
+    }
+}
+
+impl GameConfig {
+    /// Parses a settings file onto these settings
+    pub fn parse(&mut self, text: &str) -> Result<(), String> {
+        let file: GameFile = toml::from_str(text).map_err(|err| err.to_string())?;
+        if let Some(ref save) = file.save {
+            self.backup =
+                Some(parse_backup(save).ok_or_else(|| format!("unknown save type '{}'", save))?);
+        }
+        if file.rtc.is_some() {
+            self.rtc = file.rtc;
+        }
+        if let Some(ref addr) = file.idle_loop {
+            self.idle_loop = Some(parse_num(addr).map_err(|err| format!("idle-loop: {}", err))?);
+        }
+        for (name, key) in file.bindings.iter() {
+            let code = bindings::parse_key(key)?;
+            // Checks the name, and that buttons aren't left without a key
+            Bindings::default().set(name, code)?;
+            self.bindings.push((name.to_string(), code));
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, opts: &mut Options) {
+        if self.backup.is_some() {
+            opts.core.backup = self.backup;
+        }
+        if self.idle_loop.is_some() {
+            opts.core.idle_loop = self.idle_loop;
+        }
+        for &(ref name, code) in self.bindings.iter() {
+            opts.bindings.set(name, code).unwrap();
+        }
+    }
+}
+
+/// The config file for the game with `code`
+pub fn path(code: &str) -> PathBuf {
+    config::dir().join("games").join(format!("{}.toml", code))
+}
+
+/// The settings for `rom` from the database and its config file
+pub fn load(rom: &GameRom) -> Result<GameConfig, String> {
+    let code = rom.game_code();
+    let mut config = GameConfig::default();
+    if let Some(&(_, text)) = GAMES.iter().find(|&&(game, _)| game == code) {
+        config.parse(text).unwrap();
+    }
+
+    let path = path(&code);
+    let mut text = String::new();
+    match File::open(&path).and_then(|mut f| f.read_to_string(&mut text)) {
+        Ok(_) => {
+            info!("Using game settings from {}", path.display());
+            config
+                .parse(&text)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => return Err(format!("{}: {}", path.display(), err)),
+    }
+
+    if config.rtc == Some(true) {
+        warn!(
+            "{} has a real-time clock, which isn't emulated",
+            rom.title()
+        );
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut config = GameConfig::default();
+        config
+            .parse("# comment\nsave = \"flash1m\"\n\nrtc = true\nidle-loop = \"0x080008a4\"")
+            .unwrap();
+        assert_eq!(Some(Backup::Flash1M), config.backup);
+        assert_eq!(Some(true), config.rtc);
+        assert_eq!(Some(0x0800_08a4), config.idle_loop);
+
+        // Later settings override earlier ones
+        config.parse("rtc = false").unwrap();
+        assert_eq!(Some(false), config.rtc);
+        assert_eq!(Some(Backup::Flash1M), config.backup);
+
+        assert!(config.parse("save = \"floppy\"").is_err());
+        assert!(config.parse("idle-loop = \"loop\"").is_err());
+        assert!(config.parse("[bindings]\nturbo = \"J\"").is_err());
+        assert!(config.parse("[bindings]\na = \"none\"").is_err());
+        config.parse("[bindings]\nfast-forward = \"none\"").unwrap();
+        assert!(config.parse("rtc = true\nspeed = 2").is_err());
+        assert!(config.parse("save = flash").is_err());
+    }
+
+    #[test]
+    fn test_database() {
+        for &(_, text) in GAMES.iter() {
+            GameConfig::default().parse(text).unwrap();
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Frame advance while paused.  Each press of the frame step hotkey runs one
+//! frame, and holding it down runs a frame every frame after a short delay,
+//! as a key repeats.
+
+use std::time::{Duration, Instant};
+
+/// How long the frame step hotkey is held before frames repeat
+const REPEAT_DELAY_MS: u64 = 400;
+
+#[derive(Default)]
+pub struct FrameAdvance {
+    /// A press not yet stepped
+    pending: bool,
+    /// When the press being held started
+    held_since: Option<Instant>,
+}
+
+impl FrameAdvance {
+    pub fn press(&mut self) {
+        self.pending = true;
+    }
+
+    /// The frames to run at `now` while paused, with `held` whether the
+    /// frame step hotkey is down
+    pub fn frames(&mut self, held: bool, now: Instant) -> u32 {
+        if self.pending {
+            self.pending = false;
+            self.held_since = Some(now);
+            return 1;
+        }
+        if !held {
+            self.held_since = None;
+            return 0;
+        }
+        match self.held_since {
+            Some(since) if now - since >= Duration::from_millis(REPEAT_DELAY_MS) => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut advance = FrameAdvance::default();
+        assert_eq!(0, advance.frames(false, at(0)));
+
+        // A tap steps once
+        advance.press();
+        assert_eq!(1, advance.frames(true, at(0)));
+        assert_eq!(0, advance.frames(true, at(16)));
+        assert_eq!(0, advance.frames(false, at(33)));
+
+        // Holding repeats once the delay is up
+        advance.press();
+        assert_eq!(1, advance.frames(true, at(1000)));
+        assert_eq!(0, advance.frames(true, at(1200)));
+        assert_eq!(1, advance.frames(true, at(1400)));
+        assert_eq!(1, advance.frames(true, at(1416)));
+        assert_eq!(0, advance.frames(false, at(1433)));
+        assert_eq!(0, advance.frames(true, at(2000)));
+    }
+}
@@ -0,0 +1,230 @@
+//! Which keyboard keys press which GBA buttons, and which work the emulator.
+//!
+//! Both are named in the same namespace, so the config file's `[bindings]`
+//! table and a game's `bind.<name>` settings can set either.  A hotkey can be
+//! unbound with `none`, buttons always need a key.
+
+use sdl2::keyboard::{KeyboardState, Scancode};
+
+use gba_core::io::key::KeyState;
+
+/// Button names, in `KeyState` order
+pub(super) const BUTTONS: [&'static str; 10] = [
+    "a", "b", "select", "start", "right", "left", "up", "down", "r", "l",
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Hotkey {
+    Quit,
+    LogLevel,
+    FrameStep,
+    Pause,
+    FastForward,
+    SaveState,
+    LoadState,
+    SlotPicker,
+    CaptureLayers,
+    RecordTimeline,
+    Slower,
+    Faster,
+    NextSlot,
+    PreviousSlot,
+    ShowFps,
+    Fullscreen,
+    PostFilter,
+    Record,
+    Cheats,
+    Debugger,
+}
+
+/// Hotkey names, in `Hotkey` order
+const HOTKEYS: [(Hotkey, &'static str); 20] = [
+    (Hotkey::Quit, "quit"),
+    (Hotkey::LogLevel, "log-level"),
+    (Hotkey::FrameStep, "frame-step"),
+    (Hotkey::Pause, "pause"),
+    (Hotkey::FastForward, "fast-forward"),
+    (Hotkey::SaveState, "save-state"),
+    (Hotkey::LoadState, "load-state"),
+    (Hotkey::SlotPicker, "slot-picker"),
+    (Hotkey::CaptureLayers, "capture-layers"),
+    (Hotkey::RecordTimeline, "record-timeline"),
+    (Hotkey::Slower, "slower"),
+    (Hotkey::Faster, "faster"),
+    (Hotkey::NextSlot, "next-slot"),
+    (Hotkey::PreviousSlot, "previous-slot"),
+    (Hotkey::ShowFps, "show-fps"),
+    (Hotkey::Fullscreen, "fullscreen"),
+    (Hotkey::PostFilter, "post-filter"),
+    (Hotkey::Record, "record"),
+    (Hotkey::Cheats, "cheats"),
+    (Hotkey::Debugger, "debugger"),
+];
+
+#[derive(Copy, Clone, Debug)]
+pub struct Bindings {
+    keys: [Scancode; 10],
+    hotkeys: [Option<Scancode>; 20],
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use sdl2::keyboard::Scancode::*;
+        Bindings {
+            keys: [L, K, Z, X, D, A, W, S, P, I],
+            hotkeys: [
+                Some(Escape),
+                Some(B),
+                Some(F),
+                Some(Space),
+                Some(Tab),
+                Some(F5),
+                Some(F8),
+                Some(F7),
+                Some(F9),
+                Some(F10),
+                Some(Minus),
+                Some(Equals),
+                // The number keys pick slots directly
+                None,
+                None,
+                Some(F3),
+                // Alt+Enter works too
+                Some(F11),
+                Some(F4),
+                Some(F6),
+                Some(F2),
+                Some(F12),
+            ],
+        }
+    }
+}
+
+/// The hotkey called `name`
+pub fn hotkey_named(name: &str) -> Option<Hotkey> {
+    HOTKEYS
+        .iter()
+        .find(|&&(_, hotkey)| hotkey == name)
+        .map(|&(hotkey, _)| hotkey)
+}
+
+/// Parses an SDL key name, or `none`
+pub fn parse_key(name: &str) -> Result<Option<Scancode>, String> {
+    if name == "none" {
+        return Ok(None);
+    }
+    match Scancode::from_name(name) {
+        Some(code) => Ok(Some(code)),
+        None => Err(format!("unknown key '{}'", name)),
+    }
+}
+
+impl Bindings {
+    /// Binds the button or hotkey named `name` to `key`
+    pub fn set(&mut self, name: &str, key: Option<Scancode>) -> Result<(), String> {
+        if let Some(idx) = BUTTONS.iter().position(|&button| button == name) {
+            self.keys[idx] = key.ok_or_else(|| format!("button '{}' needs a key", name))?;
+            return Ok(());
+        }
+        match HOTKEYS.iter().position(|&(_, hotkey)| hotkey == name) {
+            Some(idx) => {
+                self.hotkeys[idx] = key;
+                Ok(())
+            }
+            None => Err(format!("unknown button or hotkey '{}'", name)),
+        }
+    }
+
+    pub fn read(&self, state: &KeyboardState) -> KeyState {
+        let pressed = |idx: usize| state.is_scancode_pressed(self.keys[idx]);
+        KeyState {
+            a: pressed(0),
+            b: pressed(1),
+            select: pressed(2),
+            start: pressed(3),
+            r: pressed(4),
+            l: pressed(5),
+            u: pressed(6),
+            d: pressed(7),
+            br: pressed(8),
+            bl: pressed(9),
+        }
+    }
+
+    /// The hotkey `key` is bound to, if any
+    pub fn hotkey(&self, key: Scancode) -> Option<Hotkey> {
+        self.hotkeys
+            .iter()
+            .position(|&bound| bound == Some(key))
+            .map(|idx| HOTKEYS[idx].0)
+    }
+
+    /// Whether `hotkey`'s key is held down
+    pub fn held(&self, state: &KeyboardState, hotkey: Hotkey) -> bool {
+        self.hotkeys[hotkey as usize].map_or(false, |key| state.is_scancode_pressed(key))
+    }
+
+    /// Pairs of names bound to the same key, where a press would do both
+    pub fn conflicts(&self) -> Vec<(&'static str, &'static str)> {
+        let mut bound: Vec<(&'static str, Scancode)> = BUTTONS
+            .iter()
+            .zip(self.keys.iter())
+            .map(|(&name, &key)| (name, key))
+            .collect();
+        for (&(_, name), &key) in HOTKEYS.iter().zip(self.hotkeys.iter()) {
+            if let Some(key) = key {
+                bound.push((name, key));
+            }
+        }
+        let mut conflicts = Vec::new();
+        for (i, &(first, key)) in bound.iter().enumerate() {
+            for &(second, other) in bound[i + 1..].iter() {
+                if key == other {
+                    conflicts.push((first, second));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+/// The keys held in either of `a` or `b`
+pub fn either(a: KeyState, b: KeyState) -> KeyState {
+    KeyState {
+        a: a.a || b.a,
+        b: a.b || b.b,
+        select: a.select || b.select,
+        start: a.start || b.start,
+        r: a.r || b.r,
+        l: a.l || b.l,
+        u: a.u || b.u,
+        d: a.d || b.d,
+        br: a.br || b.br,
+        bl: a.bl || b.bl,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hotkeys() {
+        let mut bindings = Bindings::default();
+        assert!(bindings.conflicts().is_empty());
+        assert_eq!(Some(Hotkey::Quit), bindings.hotkey(Scancode::Escape));
+        assert_eq!(None, bindings.hotkey(Scancode::L));
+
+        bindings.set("frame-step", Some(Scancode::N)).unwrap();
+        assert_eq!(Some(Hotkey::FrameStep), bindings.hotkey(Scancode::N));
+        assert_eq!(None, bindings.hotkey(Scancode::F));
+
+        bindings.set("log-level", None).unwrap();
+        assert_eq!(None, bindings.hotkey(Scancode::B));
+        assert!(bindings.set("a", None).is_err());
+        assert!(bindings.set("turbo", Some(Scancode::J)).is_err());
+
+        bindings.set("a", Some(Scancode::Tab)).unwrap();
+        assert_eq!(vec![("a", "fast-forward")], bindings.conflicts());
+    }
+}
@@ -0,0 +1,88 @@
+//! Frame blending, an enhancement that isn't accurate to the hardware and is
+//! off unless asked for.
+//!
+//! Many games draw a new frame only every other VBlank.  Once the frames
+//! shown have alternated between new and repeated for a while, each new frame
+//! is shown mixed half and half with the one before, and the repeat shows it
+//! unmixed, so motion takes two smaller steps instead of one large one.  Any
+//! other pattern shows frames as they are.
+
+use gba_core::io::ppu::FRAME_BYTES;
+
+/// Whether each of the last 8 frames was new, newest in the low bit, for a
+/// game updating every other frame
+const ALTERNATING: [u8; 2] = [0b1010_1010, 0b0101_0101];
+
+pub struct FrameBlend {
+    last: Vec<u8>,
+    mixed: Vec<u8>,
+    history: u8,
+    active: bool,
+}
+
+impl FrameBlend {
+    pub fn new() -> Self {
+        FrameBlend {
+            last: vec![0; FRAME_BYTES],
+            mixed: vec![0; FRAME_BYTES],
+            history: 0,
+            active: false,
+        }
+    }
+
+    /// The frame to show in place of `frame`
+    pub fn process<'f>(&'f mut self, frame: &'f [u8]) -> &'f [u8] {
+        let new = frame != &self.last[..];
+        self.history = self.history << 1 | new as u8;
+        let active = ALTERNATING.contains(&self.history);
+        if active != self.active {
+            info!(
+                "Frame blending {}",
+                if active { "started" } else { "stopped" }
+            );
+            self.active = active;
+        }
+
+        let mix = active && new;
+        if mix {
+            for (out, (&a, &b)) in self
+                .mixed
+                .iter_mut()
+                .zip(self.last.iter().zip(frame.iter()))
+            {
+                *out = ((a as u16 + b as u16) / 2) as u8;
+            }
+        }
+        self.last.copy_from_slice(frame);
+        if mix {
+            &self.mixed
+        } else {
+            frame
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blend() {
+        let mut blend = FrameBlend::new();
+        let frames: Vec<Vec<u8>> = (0..6).map(|i| vec![i * 20; FRAME_BYTES]).collect();
+
+        // Every frame new, shown as is
+        for frame in frames.iter() {
+            assert_eq!(frame[0], blend.process(frame)[0]);
+        }
+
+        // Then every other frame
+        let mut shown = Vec::new();
+        for i in 0..12 {
+            let frame = &frames[i / 2 % frames.len()];
+            shown.push(blend.process(frame)[0]);
+        }
+        assert_eq!(vec![0, 0, 20, 20, 40, 40, 60], shown[..7].to_vec());
+        assert_eq!(vec![70, 80, 90, 100], shown[8..].to_vec());
+    }
+}
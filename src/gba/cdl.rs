@@ -0,0 +1,54 @@
+//! Keeps a code/data log of the ROM in a file, see `gba_core::mmu::cdl`.
+//! An existing log is carried on from, so it builds up over sessions.
+
+use std::fs::File;
+use std::io::{Read, Write};
+
+use gba_core::mmu::cdl::CodeDataLog;
+
+use super::Gba;
+
+impl<'a> Gba<'a> {
+    /// Starts logging, from the log in the CDL file if there is one
+    pub(super) fn load_cdl(&mut self) {
+        let path = match self.opts.cdl_file {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+        let mut cdl = CodeDataLog::new(self.core.mmu.rom.len());
+        if path.exists() {
+            let mut data = Vec::new();
+            let res = File::open(&path)
+                .and_then(|mut f| f.read_to_end(&mut data))
+                .map_err(|err| err.to_string())
+                .and_then(|_| cdl.merge(&data));
+            match res {
+                Ok(()) => info!("Carrying on from code/data log {:?}", path),
+                Err(err) => {
+                    error!("Failed to read code/data log {:?}: {}", path, err);
+                    // Rather than write over it
+                    self.opts.cdl_file = None;
+                    return;
+                }
+            }
+        }
+        self.core.mmu.set_cdl(Some(cdl));
+    }
+
+    /// Writes the code/data log out to its file
+    pub fn write_cdl(&mut self) {
+        let path = match self.opts.cdl_file {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+        let cdl = match self.core.mmu.take_cdl() {
+            Some(cdl) => cdl,
+            None => return,
+        };
+        match File::create(&path).and_then(|mut f| f.write_all(cdl.flags())) {
+            Ok(()) => info!("Wrote code/data log {:?}", path),
+            Err(err) => error!("Failed to write code/data log {:?}: {}", path, err),
+        }
+        self.core.mmu.set_cdl(Some(cdl));
+    }
+}
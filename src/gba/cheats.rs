@@ -0,0 +1,135 @@
+//! Cheats from the file given with `--cheats`, written as `gba_core::cheats`
+//! describes.
+//!
+//! The file is checked every second of emulated time and read again once it
+//! changes, so cheats can be turned on and off by editing it while the game
+//! runs.  The cheats hotkey turns them all off and back on.  Codes that patch
+//! the ROM are applied to the cartridge while they're on, under the patches
+//! given with `--rom-patch`.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use gba_core::cheats::Cheats;
+use gba_core::rom::RomPatch;
+
+use super::*;
+
+/// How often the file is checked for changes
+const CHECK_FRAMES: u64 = 60;
+
+pub fn load(path: &Path) -> ::std::result::Result<Cheats, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|err| err.to_string())?;
+    Cheats::parse(&text)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+pub struct CheatFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    cheats: Cheats,
+    enabled: bool,
+    /// Frames since the file was last checked
+    unchecked: u64,
+}
+
+impl CheatFile {
+    pub fn new(path: PathBuf, cheats: Cheats) -> Self {
+        CheatFile {
+            modified: modified(&path),
+            path: path,
+            cheats: cheats,
+            enabled: true,
+            unchecked: 0,
+        }
+    }
+
+    /// Reads the file again if it's changed, returning whether it had.  The
+    /// cheats stay as they were if it doesn't parse.
+    fn reload(&mut self) -> ::std::result::Result<bool, String> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return Ok(false);
+        }
+        self.modified = modified;
+        self.cheats = load(&self.path)?;
+        Ok(true)
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Runs the cheats, at the start of each frame
+    pub(super) fn apply_cheats(&mut self) {
+        let check = match self.cheats {
+            Some(ref mut file) => {
+                file.unchecked += 1;
+                file.unchecked >= CHECK_FRAMES
+            }
+            None => return,
+        };
+        if check {
+            self.reload_cheats();
+        }
+        if let Some(ref file) = self.cheats {
+            if file.enabled {
+                file.cheats.apply(&mut self.core.mmu);
+            }
+        }
+    }
+
+    /// The ROM patches of the cheats that are on
+    fn cheat_patches(&self) -> Vec<RomPatch> {
+        match self.cheats {
+            Some(ref file) if file.enabled => file.cheats.rom_patches(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Replaces the cheats' ROM patches `old` with the current ones
+    pub(super) fn patch_rom(&mut self, old: &[RomPatch]) {
+        let new = self.cheat_patches();
+        if old == &new[..] {
+            return;
+        }
+        self.core.mmu.repatch_rom(old, &new);
+        // They may have overlapped the cheats' ones
+        self.core.mmu.repatch_rom(&[], &self.opts.core.rom_patches);
+    }
+
+    fn reload_cheats(&mut self) {
+        let old = self.cheat_patches();
+        let res = match self.cheats {
+            Some(ref mut file) => file
+                .reload()
+                .map(|changed| (changed, file.cheats.cheats.len())),
+            None => return,
+        };
+        self.patch_rom(&old);
+        match res {
+            Ok((true, count)) => self.show_message(format!("Reloaded {} cheats", count)),
+            Ok((false, _)) => (),
+            Err(err) => error!("Cheats failed to reload: {}", err),
+        }
+    }
+
+    pub(super) fn toggle_cheats(&mut self) {
+        let old = self.cheat_patches();
+        let enabled = match self.cheats {
+            Some(ref mut file) => {
+                file.enabled = !file.enabled;
+                file.enabled
+            }
+            None => return,
+        };
+        self.patch_rom(&old);
+        self.show_message(format!("Cheats {}", if enabled { "on" } else { "off" }));
+    }
+}
@@ -0,0 +1,91 @@
+//! A second save state held beside the running game for comparison, to see
+//! what a glitch changed or find where a value lives for a cheat.  The
+//! reference is never connected or run, only its memory is read.
+
+use state_diff::{self, memories};
+
+use super::*;
+
+/// Searches stop after this many matches
+const MAX_MATCHES: usize = 1000;
+
+/// Which machine a value has to be found in
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Side {
+    Live,
+    Reference,
+}
+
+/// The little endian `size` byte value at `off`
+pub(super) fn read(data: &[u8], off: usize, size: usize) -> u32 {
+    data[off..off + size]
+        .iter()
+        .rev()
+        .fold(0, |val, &byte| (val << 8) | byte as u32)
+}
+
+/// Offsets of `size` byte aligned values equal to `value` in `a` but not at
+/// the same offset in `b`
+pub fn search(a: &[u8], b: &[u8], value: u32, size: usize) -> Vec<usize> {
+    (0..a.len().min(b.len()) / size)
+        .map(|i| i * size)
+        .filter(|&off| read(a, off, size) == value && read(b, off, size) != value)
+        .take(MAX_MATCHES)
+        .collect()
+}
+
+impl<'a> Gba<'a> {
+    /// Loads the state at `path` to compare the running game against
+    pub fn load_reference(&mut self, path: &Path) -> ::std::result::Result<(), String> {
+        let (_, state) = read_state(path)?;
+        self.reference = Some(state);
+        info!("Loaded reference state {}", path.display());
+        Ok(())
+    }
+
+    /// Lists what differs between the running game and the reference state
+    pub(super) fn compare_reference(&self) -> ::std::result::Result<Vec<String>, String> {
+        let reference = self.reference.as_ref().ok_or("no reference state loaded")?;
+        Ok(state_diff::diff(&self.core, reference))
+    }
+
+    /// Finds the addresses where `value` is held in `side`'s memory but not
+    /// in the other's
+    pub(super) fn search_reference(
+        &self,
+        value: u32,
+        size: usize,
+        side: Side,
+    ) -> ::std::result::Result<Vec<(u32, u32)>, String> {
+        let reference = self.reference.as_ref().ok_or("no reference state loaded")?;
+        let (found, other) = match side {
+            Side::Live => (memories(&self.core), memories(reference)),
+            Side::Reference => (memories(reference), memories(&self.core)),
+        };
+
+        let mut matches = Vec::new();
+        for (&(_, base, ram), &(_, _, other_ram)) in found.iter().zip(other.iter()) {
+            let (data, other_data) = (ram.as_slice(), other_ram.as_slice());
+            for off in search(data, other_data, value, size) {
+                matches.push((base + off as u32, read(other_data, off, size)));
+            }
+        }
+        matches.truncate(MAX_MATCHES);
+        Ok(matches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search() {
+        let a = [0x34, 0x12, 0x00, 0x00, 0x34, 0x12, 0x34, 0x12];
+        let b = [0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x34, 0x13];
+        assert_eq!(vec![4, 6], search(&a, &b, 0x1234, 2));
+        assert_eq!(vec![4], search(&a, &b, 0x1234_1234, 4));
+        assert_eq!(Vec::<usize>::new(), search(&b, &a, 0x1234, 2));
+        assert_eq!(vec![5, 7], search(&a, &b, 0x12, 1));
+    }
+}
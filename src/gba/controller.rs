@@ -0,0 +1,207 @@
+//! Game controllers, through SDL's mapping of them onto an Xbox style pad.
+//!
+//! Controllers can be plugged in and out while the game runs, and every one
+//! connected presses buttons alongside the keyboard.  By default the buttons
+//! are where they are on a Nintendo pad, so the right face button is A and
+//! the bottom one is B, and the left stick works as well as the D-pad.
+//!
+//! Hotkeys are chords, buttons held together with the last pressed while the
+//! others are held, so a game can be played and saved without the keyboard.
+//! By default Back with the right or left shoulder picks the next or
+//! previous save state slot, and Back with Y or X saves to or loads from it.
+
+use sdl2::controller::{Axis, Button, GameController, GameControllerSubsystem};
+use sdl2::event::Event;
+
+use gba_core::io::key::KeyState;
+
+use super::bindings::{self, either, Hotkey, BUTTONS};
+
+/// How far the stick has to be pushed to press a direction, out of 32767
+const STICK_THRESHOLD: i16 = 16384;
+
+/// Buttons held together, the last pressed while the others are held
+type Chord = Vec<Button>;
+
+/// Which controller buttons press which GBA buttons, and which chords work
+/// the emulator
+#[derive(Clone, Debug)]
+pub struct ControllerBindings {
+    buttons: [Button; 10],
+    /// Whether the left stick presses the D-pad
+    pub stick: bool,
+    hotkeys: Vec<(Hotkey, Chord)>,
+}
+
+impl Default for ControllerBindings {
+    fn default() -> Self {
+        use sdl2::controller::Button::*;
+        ControllerBindings {
+            buttons: [
+                B,
+                A,
+                Back,
+                Start,
+                DPadRight,
+                DPadLeft,
+                DPadUp,
+                DPadDown,
+                RightShoulder,
+                LeftShoulder,
+            ],
+            stick: true,
+            hotkeys: vec![
+                (Hotkey::NextSlot, vec![Back, RightShoulder]),
+                (Hotkey::PreviousSlot, vec![Back, LeftShoulder]),
+                (Hotkey::SaveState, vec![Back, Y]),
+                (Hotkey::LoadState, vec![Back, X]),
+            ],
+        }
+    }
+}
+
+impl ControllerBindings {
+    /// Makes the controller button SDL calls `name` press `button`
+    pub fn set(&mut self, button: &str, name: &str) -> Result<(), String> {
+        let idx = BUTTONS
+            .iter()
+            .position(|&b| b == button)
+            .ok_or_else(|| format!("unknown button '{}'", button))?;
+        self.buttons[idx] = Button::from_string(name)
+            .ok_or_else(|| format!("unknown controller button '{}'", name))?;
+        Ok(())
+    }
+
+    /// Makes `chord`, button names joined by `+`, press the hotkey called
+    /// `name`, or unbinds it for `none`
+    pub fn set_hotkey(&mut self, name: &str, chord: &str) -> Result<(), String> {
+        let hotkey =
+            bindings::hotkey_named(name).ok_or_else(|| format!("unknown hotkey '{}'", name))?;
+        self.hotkeys.retain(|&(bound, _)| bound != hotkey);
+        if chord == "none" {
+            return Ok(());
+        }
+        let chord = chord
+            .split('+')
+            .map(|name| {
+                Button::from_string(name.trim())
+                    .ok_or_else(|| format!("unknown controller button '{}'", name))
+            })
+            .collect::<Result<Chord, String>>()?;
+        self.hotkeys.push((hotkey, chord));
+        Ok(())
+    }
+
+    /// The hotkey pressing `button` on `pad` finishes a chord for, the
+    /// longest if several match
+    fn chord(&self, pad: &GameController, button: Button) -> Option<Hotkey> {
+        self.hotkeys
+            .iter()
+            .filter(|&&(_, ref chord)| {
+                let (&last, held) = chord.split_last().unwrap();
+                last == button && held.iter().all(|&b| pad.button(b))
+            })
+            .max_by_key(|&&(_, ref chord)| chord.len())
+            .map(|&(hotkey, _)| hotkey)
+    }
+
+    fn read(&self, pad: &GameController) -> KeyState {
+        let pressed = |idx: usize| pad.button(self.buttons[idx]);
+        let mut state = KeyState {
+            a: pressed(0),
+            b: pressed(1),
+            select: pressed(2),
+            start: pressed(3),
+            r: pressed(4),
+            l: pressed(5),
+            u: pressed(6),
+            d: pressed(7),
+            br: pressed(8),
+            bl: pressed(9),
+        };
+        if self.stick {
+            let (x, y) = (pad.axis(Axis::LeftX), pad.axis(Axis::LeftY));
+            state.r |= x > STICK_THRESHOLD;
+            state.l |= x < -STICK_THRESHOLD;
+            state.d |= y > STICK_THRESHOLD;
+            state.u |= y < -STICK_THRESHOLD;
+        }
+        state
+    }
+}
+
+/// The controllers plugged in
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    open: Vec<GameController>,
+}
+
+impl Controllers {
+    /// SDL reports the controllers already plugged in as added, so they're
+    /// opened by the first events
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Controllers {
+            subsystem: subsystem,
+            open: Vec::new(),
+        }
+    }
+
+    /// Opens and closes controllers as they're plugged in and out, and
+    /// returns the hotkey a button press finishes a chord for
+    pub fn handle_event(&mut self, bindings: &ControllerBindings, event: &Event) -> Option<Hotkey> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(pad) => {
+                    let id = pad.instance_id();
+                    if self.open.iter().all(|open| open.instance_id() != id) {
+                        info!("Controller connected: {}", pad.name());
+                        self.open.push(pad);
+                    }
+                }
+                Err(err) => warn!("Controller {} failed to open: {}", which, err),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(idx) = self.open.iter().position(|pad| pad.instance_id() == which) {
+                    info!("Controller disconnected: {}", self.open[idx].name());
+                    self.open.remove(idx);
+                }
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                return self
+                    .open
+                    .iter()
+                    .find(|pad| pad.instance_id() == which)
+                    .and_then(|pad| bindings.chord(pad, button))
+            }
+            _ => (),
+        }
+        None
+    }
+
+    /// Adds the buttons held on any controller to `state`
+    pub fn read(&self, bindings: &ControllerBindings, state: KeyState) -> KeyState {
+        self.open
+            .iter()
+            .fold(state, |state, pad| either(state, bindings.read(pad)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let mut bindings = ControllerBindings::default();
+        assert!(bindings.set("turbo", "a").is_err());
+        assert!(bindings.set("a", "kazoo").is_err());
+
+        bindings.set_hotkey("next-slot", "none").unwrap();
+        assert!(bindings
+            .hotkeys
+            .iter()
+            .all(|&(hotkey, _)| hotkey != Hotkey::NextSlot));
+        assert!(bindings.set_hotkey("turbo", "none").is_err());
+        assert!(bindings.set_hotkey("quit", "back+kazoo").is_err());
+    }
+}
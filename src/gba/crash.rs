@@ -0,0 +1,243 @@
+use std::any::Any;
+use std::fs::File;
+use std::io::Write;
+
+use sdl2::event::Event;
+use sdl2::EventPump;
+
+use gba_core::cpu::disasm;
+use gba_core::mmu::MemoryUnit;
+
+use super::font;
+use super::*;
+
+const PITCH: usize = COLS as usize * 4;
+
+const BACKGROUND: u32 = 0x00_00_00_80;
+const TEXT: u32 = 0x00_ff_ff_ff;
+const HEADING: u32 = 0x00_ff_ff_00;
+
+/// Snapshot of where the core was when it stopped, shown on the crash screen
+pub struct Crash {
+    pub reason: String,
+    pub pc: u32,
+    pub thumb: bool,
+    pub opcode: u32,
+    pub disasm: String,
+    /// The function the PC is in and how far into it, from an ELF file
+    pub symbol: Option<(String, u32)>,
+    /// Whether there were symbols to look the PC up in
+    pub has_symbols: bool,
+}
+
+impl Crash {
+    pub fn rom_offset(&self) -> Option<u32> {
+        if self.pc >= 0x0800_0000 && self.pc < 0x0e00_0000 {
+            Some(self.pc & 0x1ff_ffff)
+        } else {
+            None
+        }
+    }
+
+    pub fn report(&self) -> Vec<String> {
+        let opcode = if self.thumb {
+            format!("{:04x}", self.opcode)
+        } else {
+            format!("{:08x}", self.opcode)
+        };
+        vec![
+            format!("Reason: {}", self.reason),
+            format!("PC:     {:#010x}", self.pc),
+            format!("Mode:   {}", if self.thumb { "Thumb" } else { "ARM" }),
+            format!("Op:     {}", opcode),
+            format!("        {}", self.disasm),
+            match self.rom_offset() {
+                Some(off) => format!("ROM:    {:#09x}", off),
+                None => "ROM:    (not in ROM)".to_string(),
+            },
+            match self.symbol {
+                Some((ref name, 0)) => format!("Symbol: {}", name),
+                Some((ref name, off)) => format!("Symbol: {}+{:#x}", name, off),
+                None if self.has_symbols => "Symbol: (no symbol covers this address)".to_string(),
+                None => "Symbol: (no symbols loaded)".to_string(),
+            },
+        ]
+    }
+}
+
+/// Extracts the message from a caught panic's payload
+pub fn panic_message(payload: Box<Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "panic".to_string()
+    }
+}
+
+/// Breaks `text` into lines that fit across the screen
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .chunks(width)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
+
+impl<'a> Gba<'a> {
+    /// The PC, its mode, and the instruction there
+    fn crash_site(&self) -> (u32, bool, u32, String) {
+        let pc = self.core.cpu.get_prefetch_addr();
+        let thumb = self.core.cpu.thumb_mode();
+        if thumb {
+            let op = self.core.mmu.load16(pc);
+            let next = self.core.mmu.load16(pc.wrapping_add(2));
+            (pc, thumb, op as u32, disasm::disasm_thumb(op, next, pc))
+        } else {
+            let op = self.core.mmu.load32(pc);
+            (pc, thumb, op, disasm::disasm_arm(op, pc))
+        }
+    }
+
+    pub(super) fn capture_crash(&self, reason: String) -> Crash {
+        // After a panic the core may be half way through an update, so
+        // reading it can panic again
+        let site = panic::catch_unwind(AssertUnwindSafe(|| self.crash_site()));
+        let (pc, thumb, opcode, disasm) = site.unwrap_or_else(|payload| {
+            let err = format!("(couldn't read the CPU: {})", panic_message(payload));
+            (0, false, 0, err)
+        });
+        Crash {
+            reason: reason,
+            pc: pc,
+            thumb: thumb,
+            opcode: opcode,
+            disasm: disasm,
+            symbol: self
+                .opts
+                .core
+                .symbols
+                .containing(pc)
+                .map(|(sym, off)| (sym.name.clone(), off)),
+            has_symbols: !self.opts.core.symbols.is_empty(),
+        }
+    }
+
+    /// Shows the crash screen until the user quits, letting them save a
+    /// state (S) or dump memory and a report (D) first.
+    pub(super) fn crash_screen(&mut self, crash: &Crash, event_pump: &mut EventPump) -> Result<()> {
+        error!("Emulation stopped: {}", crash.reason);
+        for line in crash.report() {
+            error!("{}", line);
+        }
+
+        let width = (COLS / font::ADVANCE_X) as usize;
+        let mut frame = vec![0u8; PITCH * ROWS as usize];
+        font::fill_rect(&mut frame, PITCH, 0, 0, COLS, ROWS, BACKGROUND);
+        font::draw_text(&mut frame, PITCH, 2, 2, "EMULATION STOPPED", HEADING);
+        let mut y = 2 + font::ADVANCE_Y * 2;
+        for line in crash.report() {
+            for part in wrap(&line, width) {
+                font::draw_text(&mut frame, PITCH, 2, y, &part, TEXT);
+                y += font::ADVANCE_Y;
+            }
+        }
+        let footer = ROWS - font::ADVANCE_Y * 2;
+        font::draw_text(
+            &mut frame,
+            PITCH,
+            2,
+            footer,
+            "S: save state  D: dump",
+            HEADING,
+        );
+        font::draw_text(
+            &mut frame,
+            PITCH,
+            2,
+            footer + font::ADVANCE_Y,
+            "Esc: quit",
+            HEADING,
+        );
+
+        if let Some(ref mut frontend) = self.frontend {
+            frontend.present(&frame, None);
+        }
+
+        loop {
+            match event_pump.wait_event() {
+                Event::Quit { .. } => break,
+                Event::KeyDown {
+                    scancode: Some(code),
+                    ..
+                } => match code {
+                    Scancode::Escape => break,
+                    Scancode::S => {
+                        let mut path = self.opts.save_file.to_os_string();
+                        path.push("crash.state");
+                        self.save_state(&path);
+                    }
+                    Scancode::D => self.dump_crash(crash),
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn dump_crash(&self, crash: &Crash) {
+        let prefix = self.opts.save_file.to_os_string();
+        let write = |suffix: &str, data: &[u8]| {
+            let mut path = prefix.clone();
+            path.push(suffix);
+            match File::create(Path::new(&path)).and_then(|mut f| f.write_all(data)) {
+                Ok(_) => info!("Wrote {:?}", path),
+                Err(err) => error!("Failed to write {:?}: {}", path, err),
+            }
+        };
+        write("crash.txt", (crash.report().join("\n") + "\n").as_bytes());
+        write("crash-ewram.bin", self.core.mmu.bram.as_slice());
+        write("crash-iwram.bin", self.core.mmu.cram.as_slice());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rom_offset() {
+        let mut crash = Crash {
+            reason: String::new(),
+            pc: 0x0900_1234,
+            thumb: true,
+            opcode: 0,
+            disasm: String::new(),
+            symbol: None,
+            has_symbols: false,
+        };
+        assert_eq!(Some(0x100_1234), crash.rom_offset());
+        crash.pc = 0x0300_0000;
+        assert_eq!(None, crash.rom_offset());
+    }
+
+    #[test]
+    fn test_report_symbol() {
+        let mut crash = Crash {
+            reason: String::new(),
+            pc: 0x0800_0100,
+            thumb: false,
+            opcode: 0,
+            disasm: String::new(),
+            symbol: None,
+            has_symbols: false,
+        };
+        assert_eq!("Symbol: (no symbols loaded)", crash.report()[6]);
+        crash.has_symbols = true;
+        assert_eq!("Symbol: (no symbol covers this address)", crash.report()[6]);
+        crash.symbol = Some(("main".to_string(), 0x10));
+        assert_eq!("Symbol: main+0x10", crash.report()[6]);
+    }
+}
@@ -0,0 +1,207 @@
+//! Crowd input, where the keys come from votes sent to the control server
+//! rather than from one player.
+//!
+//! In democracy mode votes are counted over a window of frames and the most
+//! popular key is pressed at the end of it.  In anarchy mode every vote is
+//! pressed in the order it arrived.
+
+use std::collections::VecDeque;
+
+use gba_core::io::key::KeyState;
+
+/// Key names accepted in votes, in `KeyState` order
+pub const KEYS: [&'static str; 10] = [
+    "a", "b", "select", "start", "right", "left", "up", "down", "r", "l",
+];
+
+/// How long a chosen key is held down, long enough for games that poll
+/// input every few frames to see it
+const HOLD_FRAMES: u32 = 6;
+/// Anarchy votes beyond this are dropped, so a flood of votes can't build
+/// up minutes of input lag
+const MAX_QUEUE: usize = 64;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Democracy,
+    Anarchy,
+}
+
+impl Mode {
+    pub fn parse(s: &str) -> Option<Mode> {
+        match s {
+            "democracy" => Some(Mode::Democracy),
+            "anarchy" => Some(Mode::Anarchy),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse_key(name: &str) -> Option<usize> {
+    KEYS.iter().position(|&key| key == name)
+}
+
+fn key_state(key: usize) -> KeyState {
+    let mut keys = KeyState::default();
+    match key {
+        0 => keys.a = true,
+        1 => keys.b = true,
+        2 => keys.select = true,
+        3 => keys.start = true,
+        4 => keys.r = true,
+        5 => keys.l = true,
+        6 => keys.u = true,
+        7 => keys.d = true,
+        8 => keys.br = true,
+        9 => keys.bl = true,
+        _ => unreachable!(),
+    }
+    keys
+}
+
+pub struct Crowd {
+    mode: Mode,
+    /// Length of a democracy voting window in frames
+    window: u32,
+    tally: [u32; 10],
+    queue: VecDeque<usize>,
+    /// Frames into the current voting window
+    frame: u32,
+    held: Option<usize>,
+    hold_left: u32,
+}
+
+impl Crowd {
+    pub fn new(mode: Mode, window: u32) -> Self {
+        Crowd {
+            mode: mode,
+            window: window.max(1),
+            tally: [0; 10],
+            queue: VecDeque::new(),
+            frame: 0,
+            held: None,
+            hold_left: 0,
+        }
+    }
+
+    pub fn vote(&mut self, key: usize) {
+        match self.mode {
+            Mode::Democracy => self.tally[key] += 1,
+            Mode::Anarchy => {
+                if self.queue.len() < MAX_QUEUE {
+                    self.queue.push_back(key);
+                }
+            }
+        }
+    }
+
+    /// The key with the most votes, ties going to the first in `KEYS`
+    fn winner(&self) -> Option<usize> {
+        let (key, &votes) = self
+            .tally
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|&(_, votes)| *votes)
+            .unwrap();
+        if votes > 0 {
+            Some(key)
+        } else {
+            None
+        }
+    }
+
+    /// Advances by a frame, returning the keys the crowd is holding down
+    pub fn step(&mut self) -> KeyState {
+        // Leave a frame between anarchy presses so repeats of the same key
+        // are seen as separate presses
+        let released = self.held.is_some() && self.hold_left == 0;
+        if self.hold_left > 0 {
+            self.hold_left -= 1;
+        } else {
+            self.held = None;
+        }
+
+        let next = match self.mode {
+            Mode::Democracy => {
+                self.frame += 1;
+                if self.frame < self.window {
+                    None
+                } else {
+                    self.frame = 0;
+                    let winner = self.winner();
+                    self.tally = [0; 10];
+                    winner
+                }
+            }
+            Mode::Anarchy if self.held.is_none() && !released => self.queue.pop_front(),
+            Mode::Anarchy => None,
+        };
+        if let Some(key) = next {
+            info!("Crowd pressed {}", KEYS[key]);
+            self.held = Some(key);
+            self.hold_left = HOLD_FRAMES - 1;
+        }
+
+        self.held.map(key_state).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn held(crowd: &mut Crowd) -> Option<&'static str> {
+        let keys = crowd.step();
+        let pressed = [
+            keys.a,
+            keys.b,
+            keys.select,
+            keys.start,
+            keys.r,
+            keys.l,
+            keys.u,
+            keys.d,
+            keys.br,
+            keys.bl,
+        ];
+        pressed.iter().position(|&p| p).map(|i| KEYS[i])
+    }
+
+    #[test]
+    fn test_democracy() {
+        let mut crowd = Crowd::new(Mode::Democracy, 3);
+        crowd.vote(parse_key("up").unwrap());
+        crowd.vote(parse_key("a").unwrap());
+        crowd.vote(parse_key("a").unwrap());
+        assert_eq!(None, held(&mut crowd));
+        assert_eq!(None, held(&mut crowd));
+        assert_eq!(Some("a"), held(&mut crowd));
+        for _ in 1..HOLD_FRAMES {
+            assert_eq!(Some("a"), held(&mut crowd));
+        }
+        assert_eq!(None, held(&mut crowd));
+
+        // Ties go to the earlier key
+        let mut crowd = Crowd::new(Mode::Democracy, 1);
+        crowd.vote(parse_key("l").unwrap());
+        crowd.vote(parse_key("b").unwrap());
+        assert_eq!(Some("b"), held(&mut crowd));
+    }
+
+    #[test]
+    fn test_anarchy() {
+        let mut crowd = Crowd::new(Mode::Anarchy, 60);
+        crowd.vote(parse_key("left").unwrap());
+        crowd.vote(parse_key("start").unwrap());
+        for _ in 0..HOLD_FRAMES {
+            assert_eq!(Some("left"), held(&mut crowd));
+        }
+        assert_eq!(None, held(&mut crowd));
+        for _ in 0..HOLD_FRAMES {
+            assert_eq!(Some("start"), held(&mut crowd));
+        }
+        assert_eq!(None, held(&mut crowd));
+        assert_eq!(None, parse_key("x"));
+    }
+}
@@ -0,0 +1,747 @@
+//! A debugger console on stdin.  With `--debugger` the CPU stops at the
+//! prompt when it hits a breakpoint or the debugger hotkey is pressed, rather
+//! than emulation stopping, and the window stays responsive while commands
+//! are typed.
+//!
+//! Addresses, values and lengths are in hex, and addresses can be symbol
+//! names when the game has symbols.  An empty line repeats the last command,
+//! as in gdb.
+//!
+//! Each time the CPU stops, the console shows the code around the PC with
+//! the next instruction highlighted, the registers with the ones that changed
+//! since the last stop highlighted, and the flags with the conditions they
+//! pass.  Highlighting uses colour on a terminal that has it, unless
+//! `NO_COLOR` is set, and a `*` after the value otherwise.
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use gba_core::cpu::breakpoint::{BreakEvent, Breakpoint};
+use gba_core::cpu::calls::FrameKind;
+use gba_core::cpu::disasm::{self, Line};
+use gba_core::cpu::reg::{self, Reg};
+use gba_core::mmu::watch::Watchpoint;
+use gba_core::mmu::MemoryUnit;
+use gba_core::symbols::Symbols;
+use gba_core::CYCLES_PER_SEC;
+
+use super::Gba;
+
+const HELP: [&'static str; 20] = [
+    "step|s [count]          run instructions, 1 by default",
+    "next|n                  step, running calls until they return",
+    "continue|c              leave the debugger",
+    "regs|r                  show the registers",
+    "backtrace|bt            show the calls the CPU is in",
+    "set <reg> <value>       change a register, e.g. set r0 1f",
+    "x <addr> [len]          show memory, 40 bytes by default",
+    "write|w <addr> <value> [bytes]",
+    "                        write 1, 2 or 4 bytes, 4 by default",
+    "disasm|d [addr] [count] disassemble, around the PC by default",
+    "break|b <addr> [if <condition>]",
+    "                        add a breakpoint, e.g. b main if r0 == 0x40",
+    "delete <addr>           remove a breakpoint",
+    "catch <event>           stop after irq, swi, vblank or dma, e.g. catch irq:timer0",
+    "uncatch <event>         stop catching an event",
+    "breaks                  list the breakpoints and events caught",
+    "watch <addr>[+len] [r|w|rw] [break]",
+    "                        report accesses, writes by default",
+    "unwatch <addr>          remove a watchpoint",
+    "watches                 list the watchpoints",
+];
+
+/// How long `next` waits for a call to return before stopping anyway
+const NEXT_LIMIT: u64 = CYCLES_PER_SEC;
+
+/// Instructions shown before and after the PC when the CPU stops
+const CONTEXT_BEFORE: u32 = 3;
+const CONTEXT_AFTER: u32 = 4;
+
+/// The most `x` shows and `disasm` disassembles at once
+const READ_LIMIT: u32 = 0x1000;
+const DISASM_LIMIT: u32 = 0x400;
+
+/// r0-r15 then the CPSR
+type Regs = [u32; 17];
+
+const CPSR: Reg = reg::CPSR;
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Step(u32),
+    Next,
+    Continue,
+    Regs,
+    Backtrace,
+    SetReg(Reg, u32),
+    Read(u32, u32),
+    Write(u32, u32, u32),
+    Disasm(Option<u32>, u32),
+    Break(Breakpoint),
+    Delete(u32),
+    Breaks,
+    Catch(BreakEvent),
+    Uncatch(BreakEvent),
+    Watch(Watchpoint),
+    Unwatch(u32),
+    Watches,
+    Help,
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    let digits = if s.starts_with("0x") { &s[2..] } else { s };
+    u32::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex number", s))
+}
+
+fn parse_addr(s: &str, symbols: &Symbols) -> Result<u32, String> {
+    parse_hex(s).or_else(|_| {
+        symbols
+            .lookup(s)
+            .map(|sym| sym.addr)
+            .ok_or_else(|| format!("'{}' is not an address or symbol", s))
+    })
+}
+
+fn parse_reg(name: &str) -> Option<Reg> {
+    match name {
+        "cpsr" => Some(CPSR),
+        _ => (0..16)
+            .find(|&r| disasm::reg_name(r) == name || format!("r{}", r) == name)
+            .map(|r| r as Reg),
+    }
+}
+
+fn parse(line: &str, symbols: &Symbols) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (*name, args),
+        None => return Err("no command".to_string()),
+    };
+    let count = |i: usize, default: u32| match args.get(i) {
+        Some(s) => s.parse().map_err(|_| format!("'{}' is not a count", s)),
+        None => Ok(default),
+    };
+    let addr = |i: usize| parse_addr(args[i], symbols);
+    match name {
+        "step" | "s" if args.len() <= 1 => Ok(Command::Step(count(0, 1)?)),
+        "next" | "n" if args.is_empty() => Ok(Command::Next),
+        "continue" | "c" if args.is_empty() => Ok(Command::Continue),
+        "regs" | "r" if args.is_empty() => Ok(Command::Regs),
+        "backtrace" | "bt" if args.is_empty() => Ok(Command::Backtrace),
+        "set" if args.len() == 2 => match parse_reg(args[0]) {
+            Some(reg) => Ok(Command::SetReg(reg, parse_hex(args[1])?)),
+            None => Err(format!("'{}' is not a register", args[0])),
+        },
+        "x" if args.len() == 1 || args.len() == 2 => {
+            let len = match args.get(1) {
+                Some(len) => parse_hex(len)?,
+                None => 0x40,
+            };
+            if len > READ_LIMIT {
+                return Err(format!("can't show more than {:#x} bytes", READ_LIMIT));
+            }
+            Ok(Command::Read(addr(0)?, len))
+        }
+        "write" | "w" if args.len() == 2 || args.len() == 3 => match count(2, 4)? {
+            size @ 1 | size @ 2 | size @ 4 => {
+                Ok(Command::Write(addr(0)?, parse_hex(args[1])?, size))
+            }
+            _ => Err("writes are 1, 2 or 4 bytes".to_string()),
+        },
+        "disasm" | "d" if args.len() <= 2 => {
+            let start = if args.is_empty() {
+                None
+            } else {
+                Some(addr(0)?)
+            };
+            match count(1, 10)? {
+                n if n > DISASM_LIMIT => Err(format!(
+                    "can't disassemble more than {} instructions",
+                    DISASM_LIMIT
+                )),
+                n => Ok(Command::Disasm(start, n)),
+            }
+        }
+        "break" | "b" if !args.is_empty() => {
+            let text = &line.trim_start()[name.len()..];
+            Ok(Command::Break(Breakpoint::parse(text, symbols)?))
+        }
+        "delete" if args.len() == 1 => Ok(Command::Delete(addr(0)?)),
+        "breaks" if args.is_empty() => Ok(Command::Breaks),
+        "catch" if args.len() == 1 => Ok(Command::Catch(BreakEvent::parse(args[0])?)),
+        "uncatch" if args.len() == 1 => Ok(Command::Uncatch(BreakEvent::parse(args[0])?)),
+        "watch" if !args.is_empty() => {
+            let text = &line.trim_start()[name.len()..];
+            Ok(Command::Watch(Watchpoint::parse(text, symbols)?))
+        }
+        "unwatch" if args.len() == 1 => Ok(Command::Unwatch(addr(0)?)),
+        "watches" if args.is_empty() => Ok(Command::Watches),
+        "help" | "h" => Ok(Command::Help),
+        _ => Err(format!("can't run '{}', try help", line.trim())),
+    }
+}
+
+/// `data` from `addr` as lines of 16 hex bytes and their text
+fn hex_dump(addr: u32, data: &[u8]) -> Vec<String> {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, row)| {
+            let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+            let text: String = row
+                .iter()
+                .map(|&b| {
+                    if b >= 0x20 && b < 0x7f {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08x}: {:<47}  {}",
+                addr.wrapping_add(i as u32 * 16),
+                hex.join(" "),
+                text
+            )
+        })
+        .collect()
+}
+
+fn mode_name(cpsr: u32) -> &'static str {
+    match cpsr & 0x1f {
+        0x10 => "usr",
+        0x11 => "fiq",
+        0x12 => "irq",
+        0x13 => "svc",
+        0x17 => "abt",
+        0x1b => "und",
+        0x1f => "sys",
+        _ => "???",
+    }
+}
+
+/// The conditions the flags in `cpsr` pass, leaving out `al`
+fn conditions(cpsr: u32) -> Vec<&'static str> {
+    let flag = |bit: u32| cpsr >> bit & 1 == 1;
+    let (n, z, c, v) = (flag(31), flag(30), flag(29), flag(28));
+    let pick = |cond: bool, yes, no| if cond { yes } else { no };
+    vec![
+        pick(z, "eq", "ne"),
+        pick(c, "cs", "cc"),
+        pick(n, "mi", "pl"),
+        pick(v, "vs", "vc"),
+        pick(c && !z, "hi", "ls"),
+        pick(n == v, "ge", "lt"),
+        pick(!z && n == v, "gt", "le"),
+    ]
+}
+
+/// Whether to highlight with ANSI colours
+fn colour() -> bool {
+    env::var_os("NO_COLOR").is_none() && env::var("TERM").map_or(false, |term| term != "dumb")
+}
+
+/// `text` in the SGR style `style`, e.g. "7" for reverse video
+fn styled(text: &str, style: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", style, text)
+}
+
+/// `text`, made to stand out if `changed`, and the same width either way
+fn mark_changed(text: String, changed: bool, colour: bool) -> String {
+    match (changed, colour) {
+        (false, _) => text + " ",
+        (true, true) => styled(&text, "1;33") + " ",
+        (true, false) => text + "*",
+    }
+}
+
+fn prompt() {
+    print!("(gba) ");
+    let _ = io::stdout().flush();
+}
+
+pub struct Debugger {
+    lines: Receiver<String>,
+    /// At the prompt, with the emulator not running
+    stopped: bool,
+    last: Option<String>,
+    /// The registers when the CPU last stopped, to show which changed
+    shown: Option<Regs>,
+}
+
+impl Debugger {
+    /// Starts reading commands from stdin
+    pub fn new() -> Self {
+        let (send, lines) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                let sent = line.map(|line| send.send(line).is_ok());
+                if sent.ok() != Some(true) {
+                    break;
+                }
+            }
+        });
+        Debugger {
+            lines: lines,
+            stopped: false,
+            last: None,
+            shown: None,
+        }
+    }
+}
+
+impl<'a> Gba<'a> {
+    pub(super) fn debugger_stopped(&self) -> bool {
+        self.debugger
+            .as_ref()
+            .map_or(false, |debugger| debugger.stopped)
+    }
+
+    /// Stops at the debugger's prompt, false if it isn't running
+    pub(super) fn break_into_debugger(&mut self, reason: &str) -> bool {
+        match self.debugger {
+            Some(ref mut debugger) => debugger.stopped = true,
+            None => return false,
+        }
+        println!("{}", reason);
+        self.show_stop();
+        prompt();
+        true
+    }
+
+    /// Runs the commands typed since the last call
+    pub(super) fn poll_debugger(&mut self) {
+        loop {
+            let line = match self.debugger {
+                Some(ref mut debugger) => match debugger.lines.try_recv() {
+                    Ok(ref line) if line.trim().is_empty() => debugger.last.clone(),
+                    Ok(line) => {
+                        debugger.last = Some(line.clone());
+                        Some(line)
+                    }
+                    Err(_) => return,
+                },
+                None => return,
+            };
+            if let Some(line) = line {
+                match parse(&line, &self.opts.core.symbols) {
+                    Ok(command) => self.run_debugger_command(command),
+                    Err(err) => println!("{}", err),
+                }
+            }
+            if self.debugger_stopped() {
+                prompt();
+            }
+        }
+    }
+
+    fn run_debugger_command(&mut self, command: Command) {
+        match command {
+            Command::Step(count) => {
+                for _ in 0..count {
+                    self.step_instruction();
+                }
+                self.stop_after_step();
+            }
+            Command::Next => {
+                self.step_over();
+                self.stop_after_step();
+            }
+            Command::Continue => {
+                // Off the breakpoint it's at, or it would stop straight away
+                self.core.cpu.resume();
+                if let Some(ref mut debugger) = self.debugger {
+                    debugger.stopped = false;
+                }
+            }
+            Command::Regs => {
+                let shown = self.debugger.as_ref().and_then(|debugger| debugger.shown);
+                self.print_regs(shown.as_ref());
+            }
+            Command::Backtrace => {
+                for line in self.backtrace() {
+                    println!("{}", line);
+                }
+            }
+            Command::SetReg(reg, val) => self.core.cpu.set_reg(reg, val),
+            Command::Read(addr, len) => {
+                let data: Vec<u8> = (0..len)
+                    .map(|i| self.core.mmu.load8(addr.wrapping_add(i)))
+                    .collect();
+                for line in hex_dump(addr, &data) {
+                    println!("{}", line);
+                }
+            }
+            Command::Write(addr, val, size) => match size {
+                1 => self.core.mmu.set8(addr, val as u8),
+                2 => self.core.mmu.set16(addr, val as u16),
+                _ => self.core.mmu.set32(addr, val),
+            },
+            Command::Disasm(addr, count) => {
+                let start = addr.unwrap_or_else(|| {
+                    let size = if self.core.cpu.thumb_mode() { 2 } else { 4 };
+                    let before = count / 2;
+                    self.core
+                        .cpu
+                        .get_prefetch_addr()
+                        .wrapping_sub(before * size)
+                });
+                for line in self.disasm_lines(start, count) {
+                    println!("{}", line);
+                }
+            }
+            Command::Break(brk) => {
+                println!("Breakpoint at {}", self.core.cpu.location(brk.addr));
+                // Replacing any already there, to change its conditions
+                let mut breaks = self.core.breaks().to_vec();
+                breaks.retain(|other| other.addr != brk.addr);
+                breaks.push(brk);
+                self.set_breaks(breaks);
+            }
+            Command::Delete(addr) => {
+                let mut breaks = self.core.breaks().to_vec();
+                breaks.retain(|brk| brk.addr != addr);
+                self.set_breaks(breaks);
+            }
+            Command::Breaks => {
+                for brk in self.core.breaks() {
+                    let location = self.core.cpu.location(brk.addr);
+                    println!("{} {}, hit {} times", brk, location, brk.hits);
+                }
+                for event in self.core.break_events() {
+                    println!("catch {}", event);
+                }
+            }
+            Command::Catch(event) => {
+                let mut events = self.core.break_events().to_vec();
+                if !events.contains(&event) {
+                    events.push(event);
+                }
+                self.set_break_events(events);
+            }
+            Command::Uncatch(event) => {
+                let mut events = self.core.break_events().to_vec();
+                events.retain(|&other| other != event);
+                self.set_break_events(events);
+            }
+            Command::Watch(watch) => {
+                let mut watches = self.core.watches().to_vec();
+                watches.retain(|other| other.addr != watch.addr);
+                watches.push(watch);
+                self.set_watches(watches);
+            }
+            Command::Unwatch(addr) => {
+                let mut watches = self.core.watches().to_vec();
+                watches.retain(|watch| watch.addr != addr);
+                self.set_watches(watches);
+            }
+            Command::Watches => {
+                for watch in self.core.watches() {
+                    println!("{} {}", watch, self.core.cpu.location(watch.addr));
+                }
+            }
+            Command::Help => {
+                for line in HELP.iter() {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+
+    /// Stays at the prompt after stepping, and shows where the CPU got to
+    fn stop_after_step(&mut self) {
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.stopped = true;
+        }
+        self.show_stop();
+    }
+
+    /// Shows the code around the PC and the registers, highlighting the next
+    /// instruction and what changed since the last stop
+    fn show_stop(&mut self) {
+        let colour = colour();
+        let size = if self.core.cpu.thumb_mode() { 2 } else { 4 };
+        let pc = self.core.cpu.get_prefetch_addr();
+        let start = pc.wrapping_sub(CONTEXT_BEFORE * size);
+        for line in self.disasm_lines(start, CONTEXT_BEFORE + 1 + CONTEXT_AFTER) {
+            if colour && line.starts_with('>') {
+                println!("{}", styled(&line, "7"));
+            } else {
+                println!("{}", line);
+            }
+        }
+        let shown = self.debugger.as_ref().and_then(|debugger| debugger.shown);
+        self.print_regs(shown.as_ref());
+        let regs = self.regs();
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.shown = Some(regs);
+        }
+    }
+
+    fn regs(&self) -> Regs {
+        let mut regs = [0; 17];
+        for (r, val) in regs.iter_mut().enumerate().take(16) {
+            *val = self.core.cpu.reg(r as Reg);
+        }
+        regs[16] = self.core.cpu.reg(CPSR);
+        regs
+    }
+
+    /// Keeps the options' breakpoints, which rewinding uses, the same as the
+    /// core's, which count their hits
+    fn set_breaks(&mut self, breaks: Vec<Breakpoint>) {
+        self.core.set_breaks(&breaks);
+        self.opts.core.breaks = breaks;
+    }
+
+    fn set_break_events(&mut self, events: Vec<BreakEvent>) {
+        self.core.set_break_events(&events);
+        self.opts.core.break_events = events;
+    }
+
+    fn set_watches(&mut self, watches: Vec<Watchpoint>) {
+        self.core.set_watches(&watches);
+        self.opts.core.watches = watches;
+    }
+
+    /// Runs one instruction, even one with a breakpoint on it
+    fn step_instruction(&mut self) {
+        self.core.cpu.resume();
+        self.core.cycle();
+        self.report_watch_hits();
+    }
+
+    /// Steps, but runs a call until it returns
+    fn step_over(&mut self) {
+        let pc = self.core.cpu.get_prefetch_addr();
+        let thumb = self.core.cpu.thumb_mode();
+        let (opcode, size) = match self.disasm_at(pc, 1).first() {
+            Some(line) => (line.opcode, line.size),
+            None => return,
+        };
+        // Thumb calls are the only instructions in two halves
+        let call = if thumb {
+            size == 4
+        } else {
+            opcode & 0x0f00_0000 == 0x0b00_0000
+        };
+        self.step_instruction();
+        if !call {
+            return;
+        }
+        let ret = pc.wrapping_add(size);
+        let start = self.core.now();
+        while self.core.cpu.get_prefetch_addr() != ret {
+            let ran = self.core.cycle();
+            self.report_watch_hits();
+            if !ran {
+                let pc = self.core.cpu.get_prefetch_addr();
+                println!("Stopped at {}", self.core.cpu.location(pc));
+                return;
+            }
+            if self.core.now() - start > NEXT_LIMIT {
+                println!("The call hasn't returned after a second");
+                return;
+            }
+        }
+    }
+
+    /// `count` instructions from `addr`, in the CPU's current state
+    fn disasm_at(&self, addr: u32, count: u32) -> Vec<Line> {
+        let thumb = self.core.cpu.thumb_mode();
+        // With room for the second half of a Thumb call
+        let len = count * if thumb { 2 } else { 4 } + 2;
+        let code: Vec<u8> = (0..len)
+            .map(|i| self.core.mmu.load8(addr.wrapping_add(i)))
+            .collect();
+        let mut lines = disasm::disasm_range(&code, addr, thumb);
+        lines.truncate(count as usize);
+        lines
+    }
+
+    fn disasm_lines(&self, addr: u32, count: u32) -> Vec<String> {
+        let pc = self.core.cpu.get_prefetch_addr();
+        let named = !self.opts.core.symbols.is_empty();
+        self.disasm_at(addr, count)
+            .iter()
+            .map(|line| {
+                let marker = if line.addr == pc { '>' } else { ' ' };
+                if named {
+                    format!(
+                        "{} {:08x} {:<24} {}",
+                        marker,
+                        line.addr,
+                        self.core.cpu.location(line.addr),
+                        line.text
+                    )
+                } else {
+                    format!("{} {:08x}  {}", marker, line.addr, line.text)
+                }
+            })
+            .collect()
+    }
+
+    /// Where the CPU is, then where each call it's in was made from,
+    /// innermost first
+    fn backtrace(&self) -> Vec<String> {
+        let cpu = &self.core.cpu;
+        let frames = match cpu.calls() {
+            Some(calls) => calls.frames(),
+            None => return vec!["Calls aren't being tracked".to_string()],
+        };
+        let pc = cpu.get_prefetch_addr();
+        let mut lines = vec![format!("#0 {:08x} {}", pc, cpu.location(pc))];
+        for (i, frame) in frames.iter().rev().enumerate() {
+            let kind = match frame.kind {
+                FrameKind::Call => "",
+                FrameKind::Swi => " (swi)",
+                FrameKind::Irq => " (interrupted)",
+            };
+            lines.push(format!(
+                "#{} {:08x} {}{}",
+                i + 1,
+                frame.call,
+                cpu.location(frame.call),
+                kind
+            ));
+        }
+        lines
+    }
+
+    /// Prints the registers, highlighting any that differ from `last`
+    fn print_regs(&self, last: Option<&Regs>) {
+        let colour = colour();
+        let regs = self.regs();
+        let changed = |r: usize| last.map_or(false, |last| last[r] != regs[r]);
+        for row in 0..4 {
+            let line: Vec<String> = (row * 4..row * 4 + 4)
+                .map(|r| {
+                    let text = format!("{:>3}={:08x}", disasm::reg_name(r), regs[r]);
+                    mark_changed(text, changed(r), colour)
+                })
+                .collect();
+            println!("{}", line.join(" "));
+        }
+        let cpsr = regs[16];
+        let flags: String = [
+            (31, 'N'),
+            (30, 'Z'),
+            (29, 'C'),
+            (28, 'V'),
+            (7, 'I'),
+            (6, 'F'),
+        ]
+        .iter()
+        .map(|&(bit, flag)| if cpsr >> bit & 1 == 1 { flag } else { '-' })
+        .collect();
+        let state = if cpsr >> 5 & 1 == 1 { "thumb" } else { "arm" };
+        let text = format!("cpsr={:08x}", cpsr);
+        println!(
+            "{}{} {} {}  {}",
+            mark_changed(text, changed(16), colour),
+            flags,
+            mode_name(cpsr),
+            state,
+            conditions(cpsr).join(" ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use gba_core::symbols::Symbol;
+
+    #[test]
+    fn test_parse() {
+        let symbols = Symbols::new(vec![Symbol {
+            name: "main".to_string(),
+            addr: 0x0800_01f0,
+            size: 0,
+            thumb: true,
+        }]);
+        let parse = |line: &str| parse(line, &symbols);
+        assert_eq!(Ok(Command::Step(1)), parse("s"));
+        assert_eq!(Ok(Command::Backtrace), parse("bt"));
+        assert_eq!(Ok(Command::Step(20)), parse("step 20"));
+        assert_eq!(
+            Ok(Command::Break(Breakpoint::new(0x0800_01f0))),
+            parse("b main")
+        );
+        assert_eq!(
+            Ok(Command::Break(Breakpoint::new(0x0300_0000))),
+            parse("break 0x3000000")
+        );
+        match parse("b main if hits == 3") {
+            Ok(Command::Break(ref brk)) => assert_eq!(1, brk.conds.len()),
+            other => panic!("expected a breakpoint, got {:?}", other),
+        }
+        assert_eq!(
+            Ok(Command::SetReg(13, 0x0300_7f00)),
+            parse("set sp 3007f00")
+        );
+        assert_eq!(Ok(Command::SetReg(CPSR, 0x1f)), parse("set cpsr 1f"));
+        assert_eq!(Ok(Command::Read(0x0200_0000, 0x40)), parse("x 2000000"));
+        assert_eq!(
+            Ok(Command::Write(0x0400_0000, 3, 2)),
+            parse("w 4000000 3 2")
+        );
+        assert_eq!(Ok(Command::Disasm(None, 10)), parse("d"));
+        assert_eq!(
+            Ok(Command::Catch(BreakEvent::Irq(Some(0)))),
+            parse("catch irq:vblank")
+        );
+        assert_eq!(
+            Ok(Command::Watch(Watchpoint::writes(0x0300_0000, 4))),
+            parse("watch 3000000+4")
+        );
+        assert!(parse("w 4000000 3 3").is_err());
+        assert!(parse("x 2000000 ffffffff").is_err());
+        assert!(parse("d 8000000 4294967295").is_err());
+        assert!(parse("b nowhere").is_err());
+        assert!(parse("set r16 0").is_err());
+        assert!(parse("launch").is_err());
+    }
+
+    #[test]
+    fn test_conditions() {
+        assert_eq!(
+            vec!["ne", "cc", "pl", "vc", "ls", "ge", "gt"],
+            conditions(0x0000_001f)
+        );
+        // Z and C
+        assert_eq!(
+            vec!["eq", "cs", "pl", "vc", "ls", "ge", "le"],
+            conditions(0x6000_001f)
+        );
+        // N without V
+        assert_eq!(
+            vec!["ne", "cc", "mi", "vc", "ls", "lt", "le"],
+            conditions(0x8000_001f)
+        );
+    }
+
+    #[test]
+    fn test_mark_changed() {
+        let text = || "r0=00000001".to_string();
+        assert_eq!("r0=00000001 ", mark_changed(text(), false, true));
+        assert_eq!("r0=00000001*", mark_changed(text(), true, false));
+        assert_eq!(
+            "\x1b[1;33mr0=00000001\x1b[0m ",
+            mark_changed(text(), true, true)
+        );
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let lines = hex_dump(0x0800_00a0, b"POKEMON EMER\0\0\0\0BPEE");
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with("080000a0: 50 4f 4b 45"));
+        assert!(lines[0].ends_with("  POKEMON EMER...."));
+        assert!(lines[1].ends_with("  BPEE"));
+    }
+}
@@ -0,0 +1,178 @@
+//! A tiny 5x7 bitmap font for drawing diagnostics over the GBA frame.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+/// Horizontal and vertical distance between characters, including spacing
+pub const ADVANCE_X: u32 = GLYPH_WIDTH + 1;
+pub const ADVANCE_Y: u32 = GLYPH_HEIGHT + 1;
+
+// Rows of each glyph from top to bottom, the low 5 bits are the pixels with
+// the leftmost pixel in bit 4.  Lowercase letters are drawn as uppercase.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const GLYPHS: [[u8; 7]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // '!'
+    [0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a], // '#'
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04], // '$'
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // '%'
+    [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d], // '&'
+    [0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // '('
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // ')'
+    [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00], // '*'
+    [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08], // ','
+    [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c], // '.'
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // '/'
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e], // '0'
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e], // '1'
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f], // '2'
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e], // '3'
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02], // '4'
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e], // '5'
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e], // '6'
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // '7'
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e], // '8'
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c], // '9'
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], // ':'
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08], // ';'
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // '<'
+    [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00], // '='
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // '>'
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // '?'
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e], // '@'
+    [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // 'A'
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], // 'B'
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e], // 'C'
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], // 'D'
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], // 'E'
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], // 'F'
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f], // 'G'
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // 'H'
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // 'I'
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // 'J'
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // 'K'
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], // 'L'
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], // 'M'
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // 'N'
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // 'O'
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], // 'P'
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], // 'Q'
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], // 'R'
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e], // 'S'
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // 'T'
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // 'U'
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], // 'V'
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a], // 'W'
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], // 'X'
+    [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], // 'Y'
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], // 'Z'
+    [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e], // '['
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // '\\'
+    [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e], // ']'
+    [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f], // '_'
+    [0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // 'a'
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], // 'b'
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e], // 'c'
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], // 'd'
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], // 'e'
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], // 'f'
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f], // 'g'
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // 'h'
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // 'i'
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // 'j'
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // 'k'
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], // 'l'
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], // 'm'
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // 'n'
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // 'o'
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], // 'p'
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], // 'q'
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], // 'r'
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e], // 's'
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // 't'
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // 'u'
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], // 'v'
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a], // 'w'
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], // 'x'
+    [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], // 'y'
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], // 'z'
+    [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02], // '{'
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // '|'
+    [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08], // '}'
+    [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00], // '~'
+];
+
+fn glyph(c: char) -> &'static [u8; 7] {
+    let idx = c as u32;
+    if idx >= 0x20 && idx < 0x7f {
+        &GLYPHS[(idx - 0x20) as usize]
+    } else {
+        &GLYPHS[('?' as u32 - 0x20) as usize]
+    }
+}
+
+/// Draws `text` into an RGB888 frame buffer with the top-left corner at
+/// (`x`, `y`), clipping anything that falls outside of it.
+pub fn draw_text(frame: &mut [u8], pitch: usize, x: u32, y: u32, text: &str, colour: u32) {
+    let rows = (frame.len() / pitch) as u32;
+    let cols = (pitch / 4) as u32;
+    for (i, c) in text.chars().enumerate() {
+        let gx = x + i as u32 * ADVANCE_X;
+        for (gy, line) in glyph(c).iter().enumerate() {
+            let py = y + gy as u32;
+            if py >= rows {
+                break;
+            }
+            for bx in 0..GLYPH_WIDTH {
+                let px = gx + bx;
+                if px >= cols || (line >> (GLYPH_WIDTH - 1 - bx)) & 1 == 0 {
+                    continue;
+                }
+                let off = py as usize * pitch + px as usize * 4;
+                LittleEndian::write_u32(&mut frame[off..off + 4], colour);
+            }
+        }
+    }
+}
+
+/// Fills a rectangle of an RGB888 frame buffer with a solid colour
+pub fn fill_rect(frame: &mut [u8], pitch: usize, x: u32, y: u32, w: u32, h: u32, colour: u32) {
+    let rows = (frame.len() / pitch) as u32;
+    let cols = (pitch / 4) as u32;
+    for py in y..(y + h).min(rows) {
+        for px in x..(x + w).min(cols) {
+            let off = py as usize * pitch + px as usize * 4;
+            LittleEndian::write_u32(&mut frame[off..off + 4], colour);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_draw_clips() {
+        let mut frame = vec![0u8; 8 * 4 * 8];
+        draw_text(&mut frame, 8 * 4, 4, 4, "HI", 0xffffff);
+        // Top-left pixel of the H
+        assert_eq!(
+            0xffffff,
+            LittleEndian::read_u32(&frame[4 * 8 * 4 + 4 * 4..])
+        );
+        assert_eq!(0, LittleEndian::read_u32(&frame[0..]));
+    }
+
+    #[test]
+    fn test_lowercase() {
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('\n'), glyph('?'));
+    }
+}
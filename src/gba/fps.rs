@@ -0,0 +1,65 @@
+//! The frame rate and emulation speed, measured over each second and shown
+//! in the corner of the screen while the FPS hotkey has it on.
+
+use std::time::{Duration, Instant};
+
+use gba_core::{CYCLES_PER_FRAME, CYCLES_PER_SEC};
+
+pub struct FpsCounter {
+    since: Instant,
+    shown: u32,
+    emulated: u32,
+    /// The last second's figures, e.g. `60 fps 100%`
+    text: String,
+}
+
+impl FpsCounter {
+    pub fn new(now: Instant) -> Self {
+        FpsCounter {
+            since: now,
+            shown: 0,
+            emulated: 0,
+            text: String::new(),
+        }
+    }
+
+    /// Counts a frame shown at `now`, after emulating `emulated` frames
+    pub fn frame(&mut self, emulated: u32, now: Instant) {
+        self.shown += 1;
+        self.emulated += emulated;
+        let elapsed = now - self.since;
+        if elapsed < Duration::from_secs(1) {
+            return;
+        }
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let gba_fps = CYCLES_PER_SEC as f64 / CYCLES_PER_FRAME as f64;
+        let fps = self.shown as f64 / secs;
+        let speed = self.emulated as f64 / secs / gba_fps * 100.0;
+        self.text = format!("{:.0} fps {:.0}%", fps, speed);
+        debug!("{}", self.text);
+
+        self.since = now;
+        self.shown = 0;
+        self.emulated = 0;
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counter() {
+        let start = Instant::now();
+        let mut counter = FpsCounter::new(start);
+        // A second of frames at double speed
+        for i in 1..61 {
+            counter.frame(2, start + Duration::from_millis(i * 1000 / 60));
+        }
+        assert_eq!("60 fps 201%", counter.text());
+    }
+}
@@ -0,0 +1,63 @@
+//! The frontend's sources of button presses, each an `InputProvider` the
+//! core polls once a frame.
+
+use sdl2::keyboard::KeyboardState;
+
+use gba_core::io::key::{Buttons, InputProvider};
+
+use super::bindings::{either, Bindings};
+use super::controller::{ControllerBindings, Controllers};
+
+/// The keyboard and any controllers plugged in
+pub struct SdlInput<'a> {
+    pub keyboard: KeyboardState<'a>,
+    pub bindings: &'a Bindings,
+    pub controllers: &'a Controllers,
+    pub pad_bindings: &'a ControllerBindings,
+}
+
+impl<'a> InputProvider for SdlInput<'a> {
+    fn poll(&mut self) -> Buttons {
+        let keys = self.bindings.read(&self.keyboard);
+        self.controllers.read(self.pad_bindings, keys)
+    }
+}
+
+/// The buttons held in either of two sources, e.g. for a remote player to
+/// play alongside the keyboard
+pub struct Both<A, B>(pub A, pub B);
+
+impl<A: InputProvider, B: InputProvider> InputProvider for Both<A, B> {
+    fn poll(&mut self) -> Buttons {
+        either(self.0.poll(), self.1.poll())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Hold(Buttons);
+
+    impl InputProvider for Hold {
+        fn poll(&mut self) -> Buttons {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_both() {
+        let a = Buttons {
+            a: true,
+            ..Default::default()
+        };
+        let b = Buttons {
+            b: true,
+            ..Default::default()
+        };
+        let held = Both(Hold(a), Hold(b)).poll();
+        assert!(held.a && held.b && !held.start);
+        let held = Both(Hold(a), None::<Hold>).poll();
+        assert!(held.a && !held.b);
+    }
+}
@@ -0,0 +1,121 @@
+//! Dumps of every layer that went into a frame, for PPU bug reports.
+//!
+//! A dump writes, next to the save file:
+//!
+//! - `layers-<frame>-bg0.bmp` to `bg3`, `obj` and `final`, with magenta where
+//!   a layer is disabled or drew nothing
+//! - `layers-<frame>-windows.bmp`, the layers each pixel's window enables,
+//!   BG0/BG1 in red, BG2/BG3 in green and OBJ/effects in blue
+//! - `layers-<frame>-blend.txt`, the layers and colour effect chosen for each
+//!   pixel
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gba_core::io::ppu::{Effect, Layers, COLS};
+
+use super::screenshot;
+use super::*;
+
+/// Drawn where a layer has no pixel
+const MAGENTA: u32 = 0x00ff_00ff;
+
+const LAYER_NAMES: [&str; 5] = ["bg0", "bg1", "bg2", "bg3", "obj"];
+
+fn layer_name(layer: u8) -> &'static str {
+    match layer {
+        0..=4 => LAYER_NAMES[layer as usize],
+        5 => "backdrop",
+        _ => "none",
+    }
+}
+
+/// Shows which layers a window enables, two bits in each colour channel
+fn windows_image(windows: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; windows.len() * 4];
+    for (px, &mask) in frame.chunks_mut(4).zip(windows.iter()) {
+        let channel = |shift: u8| ((mask >> shift) & 3) as u32 * 0x55;
+        let rgb = (channel(0) << 16) | (channel(2) << 8) | channel(4);
+        LittleEndian::write_u32(px, rgb);
+    }
+    frame
+}
+
+/// One line per pixel: position, top layer, second layer, effect and the
+/// window's enable mask
+pub fn write_blends<W: Write>(layers: &Layers, mut out: W) -> io::Result<()> {
+    writeln!(out, "# x y first second effect windows")?;
+    for (i, blend) in layers.blends.iter().enumerate() {
+        let effect = match blend.effect {
+            Effect::None => "none",
+            Effect::Alpha => "alpha",
+            Effect::Brighten => "brighten",
+            Effect::Darken => "darken",
+        };
+        writeln!(
+            out,
+            "{} {} {} {} {} {:06b}",
+            i as u32 % COLS,
+            i as u32 / COLS,
+            layer_name(blend.first),
+            layer_name(blend.second),
+            effect,
+            layers.windows[i]
+        )?;
+    }
+    Ok(())
+}
+
+fn write_dump(layers: &Layers, prefix: &OsStr) -> io::Result<()> {
+    let file = |suffix: &str| {
+        let mut path = prefix.to_os_string();
+        path.push(suffix);
+        File::create(Path::new(&path)).map(BufWriter::new)
+    };
+
+    for (name, plane) in LAYER_NAMES.iter().zip(layers.layers.iter()) {
+        let image = Layers::image(plane, MAGENTA);
+        screenshot::write_bmp(&image, file(&format!("{}.bmp", name))?)?;
+    }
+    let image = Layers::image(&layers.output, MAGENTA);
+    screenshot::write_bmp(&image, file("final.bmp")?)?;
+    screenshot::write_bmp(&windows_image(&layers.windows), file("windows.bmp")?)?;
+    write_blends(layers, file("blend.txt")?)
+}
+
+impl<'a> Gba<'a> {
+    /// Captures the layers of the next frame drawn
+    pub(super) fn capture_layers(&mut self) {
+        self.core.ppu.capture_layers();
+        self.show_message("Capturing layers".to_string());
+    }
+
+    /// Writes out a capture once its frame has finished
+    pub(super) fn check_layers(&mut self) {
+        let layers = match self.core.ppu.take_layers() {
+            Some(layers) => layers,
+            None => return,
+        };
+        let mut prefix = self.opts.save_file.to_os_string();
+        prefix.push(format!("layers-{}-", self.session.frames));
+        match write_dump(&layers, &prefix) {
+            Ok(()) => self.show_message(format!("Saved layers to {:?}*", prefix)),
+            Err(err) => error!("Failed to save layers {:?}: {}", prefix, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_windows_image() {
+        let frame = windows_image(&[0b11_01_10, 0]);
+        assert_eq!(0x00aa_55ff, LittleEndian::read_u32(&frame[0..4]));
+        assert_eq!(0, LittleEndian::read_u32(&frame[4..8]));
+    }
+}
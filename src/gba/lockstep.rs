@@ -0,0 +1,239 @@
+//! Runs the CPU in lockstep with a trace from a reference emulator, stopping
+//! at the first instruction the registers differ before, to track down CPU
+//! bugs.  The trace has a line for each instruction, with r0-r15 and the CPSR
+//! in hex as they are before it runs:
+//!
+//! ```text
+//! 00000000 00000000 ... 03007f00 00000000 08000000 0000001f
+//! r0: 00000000 r1: 00000000 ... r15: 08000008 cpsr: 0000001F
+//! ```
+//!
+//! Registers named on the line, as `r0: ` or `r0=`, are picked out by name,
+//! otherwise the first 17 hex numbers are taken in order.  r15 can be the
+//! instruction's address, or two instructions past it as mGBA shows the PC,
+//! which is worked out from the first line.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use gba_core::cpu::disasm;
+use gba_core::cpu::reg::{self, Reg};
+
+use super::crash::Crash;
+use super::Gba;
+
+const CPSR: usize = 16;
+
+fn name(r: usize) -> &'static str {
+    if r == CPSR {
+        "cpsr"
+    } else {
+        disasm::reg_name(r as u32)
+    }
+}
+
+/// The registers on a line of the trace
+fn parse_line(line: &str) -> Result<[u32; 17], String> {
+    let mut regs = [None; 17];
+    let labelled = line.contains(':') || line.contains('=');
+    let mut words = line.split(|c: char| c.is_whitespace() || c == ':' || c == '=');
+    let mut next = 0;
+    while let Some(word) = words.next() {
+        let word = word.to_lowercase();
+        if labelled {
+            let r = match (0..17).find(|&r| {
+                word == name(r) || word == format!("r{}", r) || (r == CPSR && word == "r16")
+            }) {
+                Some(r) => r,
+                None => continue,
+            };
+            let val = words.by_ref().find(|word| !word.is_empty());
+            regs[r] = val.and_then(|val| u32::from_str_radix(val, 16).ok());
+        } else if next < 17 {
+            if let Ok(val) = u32::from_str_radix(&word, 16) {
+                regs[next] = Some(val);
+                next += 1;
+            }
+        }
+    }
+    let mut vals = [0; 17];
+    for (r, (val, reg)) in vals.iter_mut().zip(regs.iter()).enumerate() {
+        *val = reg.ok_or_else(|| format!("no value for {}", name(r)))?;
+    }
+    Ok(vals)
+}
+
+pub struct Lockstep {
+    lines: io::Lines<Box<BufRead>>,
+    /// Of the last line read
+    line: u64,
+    /// Where the last instruction that matched was
+    last_pc: Option<u32>,
+    /// Whether the reference's r15 runs two instructions ahead
+    pipelined: Option<bool>,
+}
+
+impl Lockstep {
+    pub fn new(trace: Box<BufRead>) -> Self {
+        Lockstep {
+            lines: trace.lines(),
+            line: 0,
+            last_pc: None,
+            pipelined: None,
+        }
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(Lockstep::new(Box::new(BufReader::new(file))))
+    }
+
+    /// Compares `regs`, the registers before the next instruction, with the
+    /// trace.  Returns false at the end of the trace, or a report of the
+    /// registers that differ.
+    pub fn check(&mut self, regs: &[u32; 17]) -> Result<bool, Vec<String>> {
+        let text = loop {
+            self.line += 1;
+            match self.lines.next() {
+                Some(Ok(ref text)) if text.trim().is_empty() => continue,
+                Some(Ok(text)) => break text,
+                Some(Err(err)) => return Err(vec![format!("Reference trace failed: {}", err)]),
+                None => return Ok(false),
+            }
+        };
+        let mut expected = parse_line(&text)
+            .map_err(|err| vec![format!("Reference trace line {}: {}", self.line, err)])?;
+        let thumb = expected[CPSR] >> 5 & 1 == 1;
+        let ahead = if thumb { 4 } else { 8 };
+        let pipelined = *self
+            .pipelined
+            .get_or_insert(expected[15] == regs[15].wrapping_add(ahead));
+        if pipelined {
+            expected[15] = expected[15].wrapping_sub(ahead);
+        }
+        let diffs: Vec<String> = (0..17)
+            .filter(|&r| expected[r] != regs[r])
+            .map(|r| {
+                format!(
+                    "  {:<4} expected {:08x}, got {:08x}",
+                    name(r),
+                    expected[r],
+                    regs[r]
+                )
+            })
+            .collect();
+        if diffs.is_empty() {
+            self.last_pc = Some(regs[15]);
+            return Ok(true);
+        }
+        let after = match self.last_pc {
+            Some(pc) => format!(", after the instruction at {:08x}", pc),
+            None => String::new(),
+        };
+        let mut report = vec![
+            format!("Diverged from the reference at line {}{}", self.line, after),
+            format!("  {}", text.trim()),
+        ];
+        report.extend(diffs);
+        Err(report)
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Compares every instruction run from now on with `lockstep`'s trace
+    pub fn compare_trace(&mut self, lockstep: Lockstep) {
+        self.lockstep = Some(lockstep);
+    }
+
+    /// Checks the registers before the instruction about to run, stopping
+    /// emulation if they differ from the reference
+    pub(super) fn check_lockstep(&mut self) -> ::std::result::Result<(), Crash> {
+        // No instruction runs while halted
+        if self.lockstep.is_none() || self.core.io.halted() {
+            return Ok(());
+        }
+        let mut regs = [0; 17];
+        for (r, val) in regs.iter_mut().enumerate().take(16) {
+            *val = self.core.cpu.reg(r as Reg);
+        }
+        regs[CPSR] = self.core.cpu.reg(reg::CPSR);
+        match self.lockstep.as_mut().unwrap().check(&regs) {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                info!("Reached the end of the reference trace without diverging");
+                self.lockstep = None;
+                Ok(())
+            }
+            Err(report) => {
+                for line in report {
+                    error!("{}", line);
+                }
+                self.lockstep = None;
+                Err(self.capture_crash("Diverged from the reference trace".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let mut regs = [0; 17];
+        regs[13] = 0x0300_7f00;
+        regs[15] = 0x0800_0008;
+        regs[CPSR] = 0x1f;
+        let plain = "00000000 00000000 00000000 00000000 00000000 00000000 00000000 \
+                     00000000 00000000 00000000 00000000 00000000 00000000 03007f00 \
+                     00000000 08000008 0000001f";
+        assert_eq!(Ok(regs), parse_line(plain));
+
+        let labelled: Vec<String> = (0..17)
+            .map(|r| match r {
+                CPSR => format!("cpsr: {:08X}", regs[r]),
+                _ => format!("r{}: {:08X}", r, regs[r]),
+            })
+            .collect();
+        assert_eq!(Ok(regs), parse_line(&labelled.join("  ")));
+        let named = labelled.join(" ").replace("r13: ", "sp=");
+        assert_eq!(Ok(regs), parse_line(&named));
+
+        assert!(parse_line("r0: 00000000").is_err());
+        assert!(parse_line("00000000").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let trace = "00000000 00000000 00000000 00000000 00000000 00000000 00000000 \
+                     00000000 00000000 00000000 00000000 00000000 00000000 00000000 \
+                     00000000 08000008 0000001f\n\
+                     \n\
+                     00000001 00000000 00000000 00000000 00000000 00000000 00000000 \
+                     00000000 00000000 00000000 00000000 00000000 00000000 00000000 \
+                     00000000 0800000c 0000001f\n\
+                     00000001 00000000 00000000 00000000 00000000 00000000 00000000 \
+                     00000000 00000000 00000000 00000000 00000000 00000000 00000000 \
+                     00000000 08000010 6000001f\n";
+        let mut lockstep = Lockstep::new(Box::new(io::Cursor::new(trace.as_bytes().to_vec())));
+        let mut regs = [0; 17];
+        regs[15] = 0x0800_0000;
+        regs[CPSR] = 0x1f;
+        assert_eq!(Ok(true), lockstep.check(&regs));
+        regs[0] = 1;
+        regs[15] = 0x0800_0004;
+        assert_eq!(Ok(true), lockstep.check(&regs));
+
+        regs[15] = 0x0800_0008;
+        let report = lockstep.check(&regs).unwrap_err();
+        assert_eq!(
+            "Diverged from the reference at line 4, after the instruction at 08000004",
+            report[0]
+        );
+        assert_eq!("  cpsr expected 6000001f, got 0000001f", report[2]);
+        assert_eq!(3, report.len());
+        assert_eq!(Ok(false), lockstep.check(&regs));
+    }
+}
@@ -1,247 +1,813 @@
-use std::boxed::Box;
 use std::default::Default;
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
-use std::mem;
-use std::path::Path;
-use std::ptr;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use flame;
 
 use sdl2;
-use sdl2::audio::{AudioDevice, AudioSpecDesired};
-use sdl2::keyboard::Scancode;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::keyboard::{self, Scancode};
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::{Canvas, Texture, TextureCreator};
-use sdl2::video::{Window, WindowContext};
+use sdl2::video::{FullscreenType, Window, WindowContext};
 use sdl2::Sdl;
 
-use shared::Shared;
-
-use Result;
+use gba_core;
+use gba_core::cheats::Cheats;
+use gba_core::cpu::trace::Tracer;
+use gba_core::io::key::KeyState;
+use gba_core::io::ppu::{VideoSink, COLS, FRAME_BYTES, ROWS, ROW_BYTES};
+use gba_core::io::spu::{SoundBuf, Spu, FREQ, SAMPLES};
+use gba_core::rom::GameRom;
+use gba_core::rules::Rules;
+use gba_core::{CYCLES_PER_FRAME, CYCLES_PER_SEC};
+
+#[cfg(feature = "retroachievements")]
+use retro::Cheevos;
+use {GBAError, Result};
+
+mod advance;
+pub mod bindings;
+mod blend;
+mod cdl;
+pub mod cheats;
+#[cfg(feature = "http-server")]
+pub mod compare;
+pub mod controller;
+mod crash;
+#[cfg(feature = "http-server")]
+pub mod crowd;
+mod debugger;
+mod font;
+mod fps;
+mod input;
+mod layers;
+pub mod lockstep;
+mod osd;
+mod pacing;
+mod picker;
+pub mod pipe;
+pub mod post;
+mod record;
+#[cfg(feature = "http-server")]
+pub mod remote;
+#[cfg(feature = "http-server")]
+mod rewind;
+mod save_state;
+pub mod screenshot;
+#[cfg(feature = "http-server")]
+pub mod search;
+mod session;
+pub mod status;
+mod timeline;
+pub mod triggers;
+
+pub use self::pacing::validate as validate_speed;
+pub use self::save_state::read_state;
+
+use self::advance::FrameAdvance;
+use self::bindings::{Bindings, Hotkey};
+use self::blend::FrameBlend;
+use self::cheats::CheatFile;
+use self::controller::{ControllerBindings, Controllers};
+use self::crash::Crash;
+use self::debugger::Debugger;
+use self::fps::FpsCounter;
+#[cfg(feature = "http-server")]
+use self::input::Both;
+use self::input::SdlInput;
+use self::lockstep::Lockstep;
+use self::osd::Osd;
+use self::pacing::Pacing;
+use self::post::Pipeline;
+use self::record::Recording;
+use self::save_state::SLOTS;
+use self::session::Session;
+use self::status::StatusReporter;
+use self::triggers::Trigger;
+
+/// How the frame is scaled up to the window
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    /// Sharp pixels, unevenly sized unless the scale is whole
+    Nearest,
+    /// Smooth, and blurry
+    Linear,
+}
 
-use cpu::Cpu;
-use io::key::KeyState;
-use io::ppu::{Ppu, COLS, ROWS};
-use io::spu::{SoundBuf, Spu, FREQ, SAMPLES};
-use io::IoReg;
-use mmu::gba::Gba as GbaMmu;
-use rom::GameRom;
+pub const FILTERS: [&'static str; 2] = ["nearest", "linear"];
 
-mod save_state;
+impl FromStr for Filter {
+    type Err = String;
 
-const CYCLES_PER_SEC: u64 = 16 * 1024 * 1024;
-const CYCLES_PER_FRAME: u64 = 280896;
+    fn from_str(s: &str) -> ::std::result::Result<Self, String> {
+        match s {
+            "nearest" => Ok(Filter::Nearest),
+            "linear" => Ok(Filter::Linear),
+            _ => Err(format!("unknown filter '{}'", s)),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Options {
+    pub core: gba_core::Options,
     pub fps_limit: bool,
-    pub breaks: Vec<u32>,
+    /// Start paused, to advance a frame at a time
     pub step_frames: bool,
-    pub direct_boot: bool,
+    /// Emulated frames per frame shown, and the same while fast forwarding
+    pub speed: f64,
+    pub fast_forward: f64,
     pub save_file: OsString,
+    /// The zstd level save states are compressed with
+    pub state_level: i32,
+    /// Save the state on exit and restore it on the next launch
+    pub resume: bool,
+    /// Stop at the debugger console on breakpoints and watchpoints, see
+    /// `debugger`
+    pub debugger: bool,
+    /// Where the cartridge's battery backed memory is kept between runs
+    pub battery_file: Option<PathBuf>,
+    /// Set when another instance has the battery save, so it's only read
+    pub battery_read_only: bool,
+    /// Where to keep a log of the ROM's code and data, see `cdl`
+    pub cdl_file: Option<PathBuf>,
+    /// Count the instructions run, to print on exit
+    pub coverage: bool,
+    pub bindings: Bindings,
+    pub controller: ControllerBindings,
+    /// The window's size in multiples of the screen
+    pub scale: u32,
+    pub filter: Filter,
+    /// Only scale by whole numbers, leaving a border, so pixels stay square
+    pub integer_scale: bool,
+    pub fullscreen: bool,
+    /// Show the frame rate and speed in the corner
+    pub show_fps: bool,
+    /// Mix frames together for games that update every other frame
+    pub frame_blend: bool,
+    /// The post filters to start with, see `post`
+    pub post_filter: String,
+    /// Whether to open an audio device, and the volume from 0 to 1
+    pub audio: bool,
+    pub volume: f32,
+    pub triggers: Vec<Trigger>,
+    pub rules: Rules,
+    /// Where `cheats` were read from, to read again when it changes
+    pub cheat_file: Option<PathBuf>,
+    pub cheats: Cheats,
+    #[cfg(feature = "retroachievements")]
+    pub cheevos: Option<Cheevos>,
+    /// How many frames of checkpoints to keep for stepping backwards, 0 to
+    /// keep none
+    #[cfg(feature = "http-server")]
+    pub rewind_frames: usize,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Options {
+            core: Default::default(),
             fps_limit: true,
-            breaks: Default::default(),
             step_frames: false,
-            direct_boot: false,
+            speed: 1.0,
+            fast_forward: 4.0,
             save_file: OsStr::new("gba").to_os_string(),
+            state_level: 3,
+            resume: false,
+            debugger: false,
+            battery_file: None,
+            battery_read_only: false,
+            cdl_file: None,
+            coverage: false,
+            bindings: Default::default(),
+            controller: Default::default(),
+            scale: 3,
+            filter: Filter::Nearest,
+            integer_scale: false,
+            fullscreen: false,
+            show_fps: false,
+            frame_blend: false,
+            post_filter: "none".to_string(),
+            audio: true,
+            volume: 1.0,
+            triggers: Vec::new(),
+            rules: Default::default(),
+            cheat_file: None,
+            cheats: Default::default(),
+            #[cfg(feature = "retroachievements")]
+            cheevos: None,
+            #[cfg(feature = "http-server")]
+            rewind_frames: 0,
         }
     }
 }
 
-/// Parent container for all components of the system
-pub struct Gba<'a> {
-    opts: Options,
+/// The SDL audio backend, which plays what the SPU pushes into a `SoundBuf`
+/// from SDL's audio thread, at a volume
+struct AudioOut(SoundBuf, f32);
 
-    pub ctx: Sdl,
+impl AudioCallback for AudioOut {
+    type Channel = f32;
 
-    canvas: Canvas<Window>,
-    texture_creator: TextureCreator<WindowContext>,
-    texture: Texture<'a>,
-    audio: AudioDevice<SoundBuf>,
-
-    cpu: Cpu<GbaMmu<'a>>,
-    mmu: GbaMmu<'a>,
-    io: IoReg<'a>,
-    ppu: Ppu<'a>,
-    spu: Spu<'a>,
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+        for sample in out.iter_mut() {
+            *sample *= self.1;
+        }
+    }
 }
 
-impl<'a> Gba<'a> {
-    pub fn new(rom: GameRom, bios: GameRom, options: Options) -> Box<Self> {
-        unsafe {
-            let mut gba: Box<Gba> = Box::new(mem::uninitialized());
-            ptr::write(&mut gba.opts, options);
-
-            ptr::write(&mut gba.ctx, sdl2::init().unwrap());
-            let video = gba.ctx.video().unwrap();
-            let window = video
-                .window("GBA", 720, 480)
-                .position_centered()
-                .build()
-                .unwrap();
-
-            ptr::write(&mut gba.canvas, window.into_canvas().build().unwrap());
-            gba.canvas.set_logical_size(COLS, ROWS).unwrap();
-            ptr::write(&mut gba.texture_creator, gba.canvas.texture_creator());
-            info!(
-                "Default pixel format: {:?}",
-                gba.texture_creator.default_pixel_format()
-            );
-            ptr::write(
-                &mut gba.texture,
-                mem::transmute(
-                    gba.texture_creator
-                        .create_texture_streaming(PixelFormatEnum::RGB888, COLS, ROWS)
-                        .unwrap(),
-                ),
-            );
-
-            ptr::write(&mut gba.io, IoReg::new());
-            ptr::write(
-                &mut gba.mmu,
-                GbaMmu::new(rom, bios, Shared::new(&mut gba.io)),
-            );
-
-            ptr::write(&mut gba.cpu, Cpu::new(Shared::new(&mut gba.mmu), &[]));
-            if gba.opts.direct_boot {
-                gba.cpu.init_direct();
-            } else {
-                gba.cpu.init_arm();
-            }
-            let opts = Shared::new(&mut gba.opts);
-            gba.cpu.set_breaks(opts.breaks.iter());
+/// The SDL window, texture and audio device the emulator presents to
+struct Frontend {
+    // The texture creator is leaked so the texture can outlive this function,
+    // which also keeps the renderer alive until the texture is dropped
+    texture: Texture<'static>,
+    canvas: Canvas<Window>,
+    audio: Option<AudioDevice<AudioOut>>,
+    controllers: Controllers,
+    /// The frame with any messages drawn over it
+    overlay: Vec<u8>,
 
-            ptr::write(
-                &mut gba.ppu,
-                Ppu::new(
-                    Shared::new(&mut gba.texture),
-                    Shared::new(&mut gba.io),
-                    Shared::new(&mut gba.mmu),
-                ),
-            );
+    ctx: Sdl,
+}
 
-            ptr::write(&mut gba.spu, Spu::new(Shared::new(&mut gba.io)));
+impl Frontend {
+    fn new(spu: &mut Spu, opts: &Options) -> Self {
+        let ctx = sdl2::init().unwrap();
+        let video = ctx.video().unwrap();
+        // Read when the texture is created
+        let quality = match opts.filter {
+            Filter::Nearest => "0",
+            Filter::Linear => "1",
+        };
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", quality);
+        let mut builder = video.window("GBA", COLS * opts.scale, ROWS * opts.scale);
+        builder.position_centered().resizable();
+        if opts.fullscreen {
+            builder.fullscreen_desktop();
+        }
+        let window = builder.build().unwrap();
+
+        // SDL keeps the logical size's aspect ratio with black bars at any
+        // window size, including after the display's resolution changes
+        let mut canvas = window.into_canvas().build().unwrap();
+        canvas.set_logical_size(COLS, ROWS).unwrap();
+        canvas.set_integer_scale(opts.integer_scale).unwrap();
+        let texture_creator: &'static TextureCreator<WindowContext> =
+            Box::leak(Box::new(canvas.texture_creator()));
+        info!(
+            "Default pixel format: {:?}",
+            texture_creator.default_pixel_format()
+        );
+        let texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB888, COLS, ROWS)
+            .unwrap();
 
+        let audio = if opts.audio {
             let desired_spec = AudioSpecDesired {
                 freq: Some(FREQ),
                 channels: Some(2),
                 samples: Some((SAMPLES * 2) as u16),
             };
-            let audio = gba.ctx.audio().unwrap();
-            let device = audio
+            let audio = ctx
+                .audio()
+                .unwrap()
                 .open_playback(None, &desired_spec, |spec| {
                     warn!("Audio spec: {:?}", spec);
-                    gba.spu.get_callback()
+                    AudioOut(spu.tap(), opts.volume)
                 })
                 .unwrap();
-            ptr::write(&mut gba.audio, device);
-            gba.audio.resume();
+            audio.resume();
+            Some(audio)
+        } else {
+            None
+        };
+
+        let controllers = Controllers::new(ctx.game_controller().unwrap());
+
+        Frontend {
+            texture: texture,
+            canvas: canvas,
+            audio: audio,
+            controllers: controllers,
+            overlay: vec![0; FRAME_BYTES],
+            ctx: ctx,
+        }
+    }
 
-            let cpu = Shared::new(&mut gba.cpu);
-            let ppu = Shared::new(&mut gba.ppu);
-            gba.mmu.init(cpu);
-            gba.io.init(cpu, Shared::new(&mut gba.mmu), ppu);
+    /// Uploads a frame from the PPU and shows it in the window, with any
+    /// messages from `osd` over it
+    fn present(&mut self, frame: &[u8], osd: Option<&mut Osd>) {
+        let frame = match osd {
+            Some(osd) if !osd.is_empty() => {
+                self.overlay.copy_from_slice(frame);
+                osd.draw(&mut self.overlay, Instant::now());
+                &self.overlay[..]
+            }
+            _ => frame,
+        };
+        VideoSink::frame(self, frame).unwrap();
+    }
 
-            gba
+    /// Switches between a window and filling the desktop
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let state = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(err) = window.set_fullscreen(state) {
+            error!("Failed to change fullscreen: {}", err);
         }
     }
+}
+
+/// Shows frames in the window as they are, with nothing drawn over them
+impl VideoSink for Frontend {
+    fn frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let err = |err: String| io::Error::new(io::ErrorKind::Other, err);
+        self.texture
+            .update(None, frame, ROW_BYTES)
+            .map_err(|e| err(e.to_string()))?;
+        // The letterbox bars aren't drawn over otherwise
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, None).map_err(err)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+/// The SDL frontend, drives the core and presents its output
+pub struct Gba<'a> {
+    opts: Options,
+    triggers: Vec<Trigger>,
+    rules: Rules,
+    cheats: Option<CheatFile>,
+    #[cfg(feature = "retroachievements")]
+    cheevos: Option<Cheevos>,
+    paused: bool,
+    advance: FrameAdvance,
+    pacing: Pacing,
+    /// Whether the fast forward hotkey was held last frame
+    fast_forward: bool,
+    /// The save state slot the hotkeys use
+    slot: u32,
+    /// Messages shown over the frame
+    osd: Osd,
+    session: Session,
+    status: StatusReporter,
+    #[cfg(feature = "http-server")]
+    remote: Option<remote::Remote>,
+    /// A save state to compare the running game against
+    #[cfg(feature = "http-server")]
+    reference: Option<gba_core::Gba<'a>>,
+    #[cfg(feature = "http-server")]
+    rewind: Option<rewind::Rewind>,
+    #[cfg(feature = "http-server")]
+    search: Option<search::RamSearch>,
+    blend: Option<FrameBlend>,
+    post: Pipeline,
+    audio_pipe: Option<pipe::AudioPipe>,
+    recording: Option<Recording>,
+    debugger: Option<Debugger>,
+    /// A reference trace the CPU is compared with
+    lockstep: Option<Lockstep>,
+
+    /// None when running headless
+    frontend: Option<Frontend>,
+
+    core: Box<gba_core::Gba<'a>>,
+}
+
+impl<'a> Gba<'a> {
+    pub fn new(rom: GameRom, bios: GameRom, options: Options) -> Self {
+        let mut gba = Gba::new_headless(rom, bios, options);
+        gba.frontend = Some(Frontend::new(&mut gba.core.spu, &gba.opts));
+        if gba.opts.debugger {
+            gba.debugger = Some(Debugger::new());
+            gba.core.cpu.track_calls(true);
+        }
+        gba
+    }
+
+    /// Creates the core without a window or audio device, frames can be read
+    /// back with `frame`
+    pub fn new_headless(rom: GameRom, bios: GameRom, options: Options) -> Self {
+        let mut gba = Gba {
+            triggers: options.triggers.clone(),
+            rules: options.rules.clone(),
+            cheats: options
+                .cheat_file
+                .clone()
+                .map(|path| CheatFile::new(path, options.cheats.clone())),
+            #[cfg(feature = "retroachievements")]
+            cheevos: options.cheevos.clone(),
+            paused: options.step_frames,
+            advance: Default::default(),
+            pacing: Pacing::new(options.speed, options.fast_forward),
+            fast_forward: false,
+            slot: 0,
+            osd: Osd::new(),
+            session: Session::new(),
+            status: StatusReporter::new(),
+            #[cfg(feature = "http-server")]
+            remote: None,
+            #[cfg(feature = "http-server")]
+            reference: None,
+            #[cfg(feature = "http-server")]
+            rewind: match options.rewind_frames {
+                0 => None,
+                frames => Some(rewind::Rewind::new(frames)),
+            },
+            #[cfg(feature = "http-server")]
+            search: None,
+            blend: if options.frame_blend {
+                Some(FrameBlend::new())
+            } else {
+                None
+            },
+            post: Pipeline::new(&options.post_filter),
+            audio_pipe: None,
+            recording: None,
+            debugger: None,
+            lockstep: None,
+            frontend: None,
+            core: gba_core::Gba::new(rom, bios, &options.core),
+            opts: options,
+        };
+        gba.load_battery();
+        gba.patch_rom(&[]);
+        gba.load_cdl();
+        if gba.opts.coverage {
+            gba.core.cpu.set_coverage(Some(Default::default()));
+        }
+        gba
+    }
+
+    /// The last frame drawn by the PPU, as RGB888 pixels in little endian u32s
+    pub fn frame(&self) -> &[u8] {
+        self.core.frame()
+    }
+
+    /// Writes each instruction the CPU runs to `tracer` from now on
+    pub fn trace(&mut self, tracer: Tracer) {
+        self.core.cpu.set_tracer(Some(tracer));
+    }
+
+    /// Shows `message` over the frame for a couple of seconds
+    fn show_message(&mut self, message: String) {
+        info!("{}", message);
+        self.osd.push(message, Instant::now());
+    }
+
+    /// Emulates `frames` frames without presenting them or reading input
+    pub fn run_headless(&mut self, frames: u64) -> Result<()> {
+        for _ in 0..frames {
+            let emulated = panic::catch_unwind(AssertUnwindSafe(|| self.emulate_frame()));
+            let crash = match emulated {
+                Ok(Ok(())) => continue,
+                Ok(Err(crash)) => crash,
+                Err(payload) => self.capture_crash(crash::panic_message(payload)),
+            };
+            for line in crash.report() {
+                error!("{}", line);
+            }
+            return Err(GBAError::EmulationStopped(crash.reason));
+        }
+        Ok(())
+    }
 
     pub fn run(&mut self) -> Result<()> {
-        let mut frame = 0;
-        let mut event_pump = self.ctx.event_pump().unwrap();
+        let res = self.run_frontend();
+        self.stop_recording();
+        self.write_battery();
+        self.write_cdl();
+        self.end_session();
+        self.print_coverage();
+        res
+    }
+
+    /// Prints the counts of the instructions run, if they were counted
+    pub fn print_coverage(&self) {
+        if let Some(coverage) = self.core.cpu.coverage() {
+            for line in coverage.report() {
+                println!("{}", line);
+            }
+        }
+    }
+
+    fn run_frontend(&mut self) -> Result<()> {
+        if self.opts.resume {
+            self.resume();
+        }
+        let mut event_pump = self
+            .frontend
+            .as_ref()
+            .expect("Gba::run needs a frontend, use run_headless")
+            .ctx
+            .event_pump()
+            .unwrap();
 
         let frame_duration = Duration::new(
             0,
             ((1_000_000_000u64 * CYCLES_PER_FRAME) / CYCLES_PER_SEC) as u32,
         );
         let mut prev_time = Instant::now();
+        let mut fps = FpsCounter::new(prev_time);
         loop {
             let _guard = flame::start_guard("frame cycle");
             let start = Instant::now();
 
-            flame::span_of("frame emu", || self.emulate_frame());
-            flame::span_of("frame copy", || {
-                self.canvas.copy(&self.texture, None, None).unwrap()
+            let keys = event_pump.keyboard_state();
+            let fast = !self.paused && self.opts.bindings.held(&keys, Hotkey::FastForward);
+            let frames = if self.debugger_stopped() {
+                0
+            } else if self.paused {
+                let held = self.opts.bindings.held(&keys, Hotkey::FrameStep);
+                self.advance.frames(held, start)
+            } else {
+                self.pacing.frames(fast)
+            };
+            drop(keys);
+            if fast != self.fast_forward {
+                self.fast_forward = fast;
+                let message = if fast {
+                    "Fast forward on"
+                } else {
+                    "Fast forward off"
+                };
+                self.show_message(message.to_string());
+            }
+            if self.paused && frames > 0 {
+                info!("Frame: {}", self.session.frames);
+            }
+            for _ in 0..frames {
+                let emulated = flame::span_of("frame emu", || {
+                    panic::catch_unwind(AssertUnwindSafe(|| self.emulate_frame()))
+                });
+                let crash = match emulated {
+                    Ok(Ok(())) => None,
+                    Ok(Err(crash)) => Some(crash),
+                    Err(payload) => Some(self.capture_crash(crash::panic_message(payload))),
+                };
+                if let Some(crash) = crash {
+                    return self.crash_screen(&crash, &mut event_pump);
+                }
+                self.session.frames += 1;
+                if self.debugger_stopped() {
+                    break;
+                }
+            }
+            self.report_status();
+
+            fps.frame(frames, Instant::now());
+            self.osd.expire(Instant::now());
+            self.osd.set_status(if self.opts.show_fps {
+                Some(fps.text())
+            } else {
+                None
+            });
+            flame::span_of("frame present", || {
+                let mut frame = self.core.frame();
+                if let Some(ref mut blend) = self.blend {
+                    frame = blend.process(frame);
+                }
+                frame = self.post.process(frame);
+                self.frontend
+                    .as_mut()
+                    .unwrap()
+                    .present(frame, Some(&mut self.osd))
             });
-            flame::span_of("frame present", || self.canvas.present());
 
             {
                 event_pump.pump_events();
-                let keys = event_pump.keyboard_state();
-                self.io.set_keyreg(&KeyState::new_from_keystate(&keys));
-
-                if keys.is_scancode_pressed(Scancode::Escape) {
-                    break;
+                let input = SdlInput {
+                    keyboard: event_pump.keyboard_state(),
+                    bindings: &self.opts.bindings,
+                    controllers: &self.frontend.as_ref().unwrap().controllers,
+                    pad_bindings: &self.opts.controller,
+                };
+                #[cfg(feature = "http-server")]
+                let input = Both(input, self.remote.as_mut());
+                self.core.io.poll_input(input);
+            }
+            let mut quit = false;
+            while let Some(event) = event_pump.poll_event() {
+                if let sdl2::event::Event::KeyDown {
+                    scancode: Some(code),
+                    ..
+                } = event
+                {
+                    self.check_slot_keys(code);
                 }
-                if keys.is_scancode_pressed(Scancode::B) {
-                    log::set_max_level(match log::max_level() {
+                match self.event_hotkey(&event) {
+                    Some(Hotkey::Quit) => quit = true,
+                    Some(Hotkey::Pause) => self.toggle_pause(),
+                    // The first press pauses, the next ones advance
+                    Some(Hotkey::FrameStep) if !self.paused => self.toggle_pause(),
+                    Some(Hotkey::FrameStep) => self.advance.press(),
+                    Some(Hotkey::LogLevel) => log::set_max_level(match log::max_level() {
                         log::LevelFilter::Debug => log::LevelFilter::Error,
                         _ => log::LevelFilter::Debug,
-                    });
-                }
-            }
-            loop {
-                let ctrl = {
-                    let keys = event_pump.keyboard_state();
-                    keys.is_scancode_pressed(Scancode::LCtrl)
-                        || keys.is_scancode_pressed(Scancode::RCtrl)
-                };
-                if let Some(event) = event_pump.poll_event() {
-                    if let sdl2::event::Event::KeyDown { scancode, .. } = event {
-                        if let Some(code) = scancode {
-                            self.check_save(code, ctrl);
-                        }
+                    }),
+                    Some(Hotkey::SaveState) => self.save_slot(self.slot),
+                    Some(Hotkey::LoadState) => self.load_current_slot(),
+                    Some(Hotkey::NextSlot) => self.select_slot((self.slot + 1) % SLOTS),
+                    Some(Hotkey::PreviousSlot) => self.select_slot((self.slot + SLOTS - 1) % SLOTS),
+                    Some(Hotkey::Fullscreen) => self.frontend.as_mut().unwrap().toggle_fullscreen(),
+                    Some(Hotkey::PostFilter) => {
+                        self.post = self.post.next();
+                        let message = format!("Post filter: {}", self.post.name());
+                        self.show_message(message);
                     }
-                } else {
-                    break;
-                }
-            }
-            if self.opts.step_frames {
-                info!("Frame: {}", frame);
-                loop {
-                    let event = event_pump.wait_event();
-                    if let sdl2::event::Event::KeyDown { scancode, .. } = event {
-                        if scancode == Some(Scancode::F) {
-                            break;
+                    Some(Hotkey::ShowFps) => self.opts.show_fps = !self.opts.show_fps,
+                    Some(Hotkey::Slower) => self.change_speed(false),
+                    Some(Hotkey::Faster) => self.change_speed(true),
+                    Some(Hotkey::CaptureLayers) => self.capture_layers(),
+                    Some(Hotkey::RecordTimeline) => self.record_timeline(),
+                    Some(Hotkey::Record) => self.toggle_recording(),
+                    Some(Hotkey::Cheats) => self.toggle_cheats(),
+                    Some(Hotkey::SlotPicker) => self.pick_slot(&mut event_pump),
+                    Some(Hotkey::Debugger) => {
+                        if !self.break_into_debugger("Stopped by the debugger hotkey") {
+                            let message = "Start with --debugger to use the debugger";
+                            self.show_message(message.to_string());
                         }
                     }
+                    _ => (),
                 }
             }
-
+            if quit {
+                break;
+            }
+            #[cfg(feature = "http-server")]
+            self.poll_remote();
+            self.poll_debugger();
             let end = Instant::now();
-            if self.opts.fps_limit {
+            // Paused, there's nothing to gain from spinning
+            if self.opts.fps_limit || self.paused || self.debugger_stopped() {
                 if end < prev_time + frame_duration {
                     let sleep_time = (prev_time + frame_duration) - end;
                     thread::sleep(sleep_time);
                 }
             }
             prev_time = prev_time + frame_duration;
+        }
+        // Not after a crash, it would only resume into the crash
+        if self.opts.resume {
+            self.suspend();
+        }
+        Ok(())
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        let message = if self.paused { "Paused" } else { "Resumed" };
+        self.show_message(message.to_string());
+    }
 
-            let now = Instant::now();
-            info!("{} fps", 1_000_000_000u32 / ((now - start).subsec_nanos()));
-            frame += 1;
+    /// The hotkey `event` presses, from the keyboard or a controller chord
+    fn event_hotkey(&mut self, event: &sdl2::event::Event) -> Option<Hotkey> {
+        let controllers = &mut self.frontend.as_mut().unwrap().controllers;
+        if let Some(hotkey) = controllers.handle_event(&self.opts.controller, event) {
+            return Some(hotkey);
         }
+        match *event {
+            sdl2::event::Event::KeyDown {
+                scancode: Some(Scancode::Return),
+                keymod,
+                ..
+            } if keymod.intersects(keyboard::LALTMOD | keyboard::RALTMOD) => {
+                Some(Hotkey::Fullscreen)
+            }
+            sdl2::event::Event::KeyDown {
+                scancode: Some(code),
+                ..
+            } => self.opts.bindings.hotkey(code),
+            _ => None,
+        }
+    }
+
+    fn emulate_frame(&mut self) -> ::std::result::Result<(), Crash> {
+        #[cfg(feature = "http-server")]
+        self.checkpoint();
+        self.apply_cheats();
+        if self.triggers.is_empty() && self.lockstep.is_none() {
+            let ran = self.core.run_frame();
+            self.report_watch_hits();
+            if !ran {
+                return self.stop_at_breakpoint();
+            }
+        } else {
+            let end = self.core.frame_end();
+            while self.core.now() < end {
+                self.check_lockstep()?;
+                if !self.core.cycle() {
+                    self.report_watch_hits();
+                    return self.stop_at_breakpoint();
+                }
+                self.check_exec_triggers();
+            }
+            self.report_watch_hits();
+            self.run_triggers();
+        }
+        self.check_rules();
+        self.check_layers();
+        self.check_timeline();
+        self.write_pipes();
+        self.write_recording();
         Ok(())
     }
 
-    fn emulate_frame(&mut self) {
-        for _ in 0..CYCLES_PER_FRAME {
-            self.cycle();
+    /// Logs the accesses watchpoints caught, with the instructions that made
+    /// them
+    fn report_watch_hits(&mut self) {
+        for hit in self.core.take_watch_hits() {
+            info!("{} by {}", hit, self.core.cpu.location(hit.pc));
+        }
+    }
+
+    /// Stops at the debugger's prompt on a breakpoint or watchpoint, or
+    /// without the debugger stops emulation
+    fn stop_at_breakpoint(&mut self) -> ::std::result::Result<(), Crash> {
+        let pc = self.core.cpu.get_prefetch_addr();
+        let reason = format!("Stopped at {}", self.core.cpu.location(pc));
+        if self.break_into_debugger(&reason) {
+            Ok(())
+        } else {
+            Err(self.capture_crash("CPU stopped at a breakpoint".to_string()))
         }
     }
 
-    fn cycle(&mut self) {
-        self.cpu.cycle();
-        self.ppu.cycle();
-        self.spu.cycle();
-        self.io.cycle();
+    /// Announces any achievement rules that became true this frame
+    fn check_rules(&mut self) {
+        if !self.rules.is_empty() {
+            self.rules.check(&self.core.mmu, |rule| {
+                info!("Achievement: {} (x{})", rule.name, rule.hits);
+            });
+        }
+
+        #[cfg(feature = "retroachievements")]
+        {
+            if let Some(ref mut cheevos) = self.cheevos {
+                cheevos.check(&self.core.mmu, |ach| {
+                    info!(
+                        "Achievement {} unlocked: {} - {} ({} points)",
+                        ach.id, ach.title, ach.description, ach.points
+                    );
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Copies frames out like the window's texture upload
+    struct Upload(Vec<u8>);
+
+    impl VideoSink for Upload {
+        fn frame(&mut self, frame: &[u8]) -> io::Result<()> {
+            self.0.copy_from_slice(frame);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_frame_allocations() {
+        let mut opts: Options = Default::default();
+        opts.core.direct_boot = true;
+        #[cfg(feature = "http-server")]
+        {
+            opts.rewind_frames = 2;
+        }
+        let mut gba = Gba::new_headless(Default::default(), Default::default(), opts);
+        gba.core
+            .ppu
+            .add_video_sink(Box::new(Upload(vec![0; FRAME_BYTES])));
+        // Drained a callback's worth at a time, like SDL's audio thread
+        let mut audio = gba.core.spu.tap();
+        let mut samples = vec![0.0; SAMPLES * 2];
+
+        // The first frames may grow buffers to their working size, and fill
+        // the checkpoints so the oldest one's buffer is reused from then on
+        gba.run_headless(4).unwrap();
+        let allocations = ::test::allocations(|| {
+            for _ in 0..2 {
+                gba.run_headless(1).unwrap();
+                audio.fill(&mut samples);
+            }
+        });
+        assert_eq!(0, allocations);
     }
 }
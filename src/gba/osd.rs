@@ -0,0 +1,186 @@
+//! The on-screen display, short messages like "Slot 3 saved" drawn over the
+//! frame so feedback doesn't need the log.
+//!
+//! Messages stack up from the bottom left, newest lowest, and each fades out
+//! over its last half second.  Showing a message that's already up restarts
+//! it rather than adding another line.
+//!
+//! A status line, like the frame rate, can stay up in the top right corner.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gba_core::io::ppu::{COLS, FRAME_BYTES, ROWS, ROW_BYTES};
+
+use super::font;
+
+/// How long messages stay on screen, including the fade
+const MESSAGE_MS: u64 = 2000;
+const FADE_MS: u64 = 500;
+/// Older messages are dropped past this many
+const MAX_MESSAGES: usize = 4;
+
+const TEXT_COLOUR: u32 = 0x00_ff_ff_ff;
+
+struct Message {
+    text: String,
+    shown: Instant,
+}
+
+pub struct Osd {
+    messages: VecDeque<Message>,
+    status: Option<String>,
+    /// Messages drawn opaque, to mix into the frame while they fade
+    scratch: Vec<u8>,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd {
+            messages: VecDeque::new(),
+            status: None,
+            scratch: vec![0; FRAME_BYTES],
+        }
+    }
+
+    pub fn push(&mut self, text: String, now: Instant) {
+        self.messages.retain(|message| message.text != text);
+        self.messages.push_back(Message {
+            text: text,
+            shown: now,
+        });
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+
+    /// Drops messages that have been up long enough
+    pub fn expire(&mut self, now: Instant) {
+        let duration = Duration::from_millis(MESSAGE_MS);
+        self.messages
+            .retain(|message| now - message.shown < duration);
+    }
+
+    /// Shows `status` in the corner until it's changed
+    pub fn set_status(&mut self, status: Option<&str>) {
+        if self.status.as_ref().map(|text| text.as_str()) != status {
+            self.status = status.map(|text| text.to_string());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.status.is_none()
+    }
+
+    /// From 1 while `message` is fully shown down to 0 as it goes
+    fn opacity(message: &Message, now: Instant) -> f32 {
+        let elapsed = now - message.shown;
+        let elapsed_ms = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+        let left = MESSAGE_MS.saturating_sub(elapsed_ms);
+        (left as f32 / FADE_MS as f32).min(1.0)
+    }
+
+    /// Draws the messages over `frame`, an RGB888 frame from the PPU
+    pub fn draw(&mut self, frame: &mut [u8], now: Instant) {
+        let height = font::ADVANCE_Y + 1;
+        if let Some(ref status) = self.status {
+            let width = status.len() as u32 * font::ADVANCE_X + 2;
+            let x = COLS.saturating_sub(width);
+            font::fill_rect(frame, ROW_BYTES, x, 0, width, height, 0);
+            font::draw_text(frame, ROW_BYTES, x + 1, 1, status, TEXT_COLOUR);
+        }
+        for (i, message) in self.messages.iter().rev().enumerate() {
+            let y = ROWS - (i as u32 + 1) * height;
+            let width = message.text.len() as u32 * font::ADVANCE_X + 2;
+            let opacity = Osd::opacity(message, now);
+
+            self.scratch.copy_from_slice(frame);
+            font::fill_rect(&mut self.scratch, ROW_BYTES, 0, y, width, height, 0);
+            font::draw_text(
+                &mut self.scratch,
+                ROW_BYTES,
+                1,
+                y + 1,
+                &message.text,
+                TEXT_COLOUR,
+            );
+            mix_rect(frame, &self.scratch, y, width, height, opacity);
+        }
+    }
+}
+
+/// Mixes the rectangle at the left of `frame` starting at row `y` towards
+/// `over` by `opacity`
+fn mix_rect(frame: &mut [u8], over: &[u8], y: u32, width: u32, height: u32, opacity: f32) {
+    let width = width.min(ROW_BYTES as u32 / 4) as usize;
+    for row in y..(y + height).min(ROWS) {
+        let start = row as usize * ROW_BYTES;
+        let end = start + width * 4;
+        for (px, over) in frame[start..end]
+            .chunks_mut(4)
+            .zip(over[start..end].chunks(4))
+        {
+            if opacity >= 1.0 {
+                px.copy_from_slice(over);
+                continue;
+            }
+            let (a, b) = (LittleEndian::read_u32(px), LittleEndian::read_u32(over));
+            let mut mixed = 0;
+            for shift in [0, 8, 16].iter() {
+                let (a, b) = ((a >> shift) & 0xff, (b >> shift) & 0xff);
+                let c = a as f32 + (b as f32 - a as f32) * opacity;
+                mixed |= (c as u32) << shift;
+            }
+            LittleEndian::write_u32(px, mixed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_queue() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut osd = Osd::new();
+        for i in 0..6 {
+            osd.push(format!("Slot {}", i), at(i * 100));
+        }
+        assert_eq!(MAX_MESSAGES, osd.messages.len());
+        assert_eq!("Slot 2", osd.messages[0].text);
+
+        // A repeat moves to the bottom rather than stacking
+        osd.push("Slot 3".to_string(), at(1000));
+        assert_eq!(MAX_MESSAGES, osd.messages.len());
+        assert_eq!("Slot 3", osd.messages[3].text);
+
+        osd.expire(at(2600));
+        assert_eq!(
+            vec!["Slot 3"],
+            osd.messages
+                .iter()
+                .map(|m| m.text.as_str())
+                .collect::<Vec<_>>()
+        );
+        osd.expire(at(3000));
+        assert!(osd.is_empty());
+    }
+
+    #[test]
+    fn test_fade() {
+        let start = Instant::now();
+        let message = Message {
+            text: String::new(),
+            shown: start,
+        };
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        assert_eq!(1.0, Osd::opacity(&message, at(0)));
+        assert_eq!(1.0, Osd::opacity(&message, at(1500)));
+        assert_eq!(0.5, Osd::opacity(&message, at(1750)));
+        assert_eq!(0.0, Osd::opacity(&message, at(2500)));
+    }
+}
@@ -0,0 +1,97 @@
+//! How many emulated frames to run for each frame shown, so fast forward,
+//! slow motion and frame skipping all keep presenting at the GBA's rate.
+
+use super::*;
+
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 16.0;
+
+pub struct Pacing {
+    speed: f64,
+    fast_forward: f64,
+    /// Fractions of a frame owed from previous frames
+    credit: f64,
+}
+
+impl Pacing {
+    pub fn new(speed: f64, fast_forward: f64) -> Self {
+        Pacing {
+            speed: speed,
+            fast_forward: fast_forward,
+            credit: 0.0,
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Doubles the speed, up to `MAX_SPEED`
+    pub fn faster(&mut self) {
+        self.speed = (self.speed * 2.0).min(MAX_SPEED);
+    }
+
+    /// Halves the speed, down to `MIN_SPEED`
+    pub fn slower(&mut self) {
+        self.speed = (self.speed / 2.0).max(MIN_SPEED);
+    }
+
+    /// The number of frames to emulate before presenting the next one, at
+    /// the fast forward speed while `fast` is set
+    pub fn frames(&mut self, fast: bool) -> u32 {
+        let speed = if fast {
+            self.fast_forward.max(self.speed)
+        } else {
+            self.speed
+        };
+        self.credit += speed;
+        let frames = self.credit.floor();
+        self.credit -= frames;
+        frames as u32
+    }
+}
+
+/// Checks a speed from the command line is in the supported range
+pub fn validate(s: &str) -> ::std::result::Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed >= MIN_SPEED && speed <= MAX_SPEED => Ok(()),
+        _ => Err(format!("must be from {} to {}", MIN_SPEED, MAX_SPEED)),
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Doubles the speed, or halves it
+    pub(super) fn change_speed(&mut self, faster: bool) {
+        if faster {
+            self.pacing.faster();
+        } else {
+            self.pacing.slower();
+        }
+        let speed = self.pacing.speed();
+        self.show_message(format!("Speed {}x", speed));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let mut pacing = Pacing::new(1.0, 4.0);
+        assert_eq!(1, pacing.frames(false));
+        assert_eq!(4, pacing.frames(true));
+
+        pacing.slower();
+        pacing.slower();
+        let frames: Vec<u32> = (0..8).map(|_| pacing.frames(false)).collect();
+        assert_eq!(vec![0, 0, 0, 1, 0, 0, 0, 1], frames);
+
+        for _ in 0..10 {
+            pacing.faster();
+        }
+        assert_eq!(MAX_SPEED, pacing.speed());
+        // Fast forward never slows things down
+        assert_eq!(16, pacing.frames(true));
+    }
+}
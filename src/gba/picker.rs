@@ -0,0 +1,185 @@
+//! An overlay for choosing a save state slot to load, showing each slot's
+//! thumbnail and how long ago it was saved.
+
+use std::time::SystemTime;
+
+use sdl2::event::Event;
+use sdl2::EventPump;
+
+use super::font;
+use super::save_state::{read_preview, Preview, SLOTS};
+use super::*;
+
+/// Thumbnails are the frame shrunk by this much in each direction
+const THUMB_SCALE: u32 = 4;
+const THUMB_COLS: u32 = COLS / THUMB_SCALE;
+const THUMB_ROWS: u32 = ROWS / THUMB_SCALE;
+
+/// Thumbnails across the picker, each with its label underneath
+const GRID_COLS: u32 = COLS / THUMB_COLS;
+const CELL_ROWS: u32 = THUMB_ROWS + font::ADVANCE_Y;
+
+const BACKGROUND: u32 = 0x00_00_00_00;
+const TEXT: u32 = 0x00_ff_ff_ff;
+const SELECTED: u32 = 0x00_ff_ff_00;
+
+/// Shrinks a frame from the PPU to a thumbnail by averaging each block of
+/// pixels
+pub fn thumbnail(frame: &[u8]) -> Vec<u8> {
+    let mut thumb = vec![0u8; (THUMB_COLS * THUMB_ROWS * 4) as usize];
+    for ty in 0..THUMB_ROWS {
+        for tx in 0..THUMB_COLS {
+            let mut sums = [0u32; 3];
+            for y in ty * THUMB_SCALE..(ty + 1) * THUMB_SCALE {
+                for x in tx * THUMB_SCALE..(tx + 1) * THUMB_SCALE {
+                    let off = ((y * COLS + x) * 4) as usize;
+                    for (sum, &byte) in sums.iter_mut().zip(frame[off..off + 3].iter()) {
+                        *sum += byte as u32;
+                    }
+                }
+            }
+            let off = ((ty * THUMB_COLS + tx) * 4) as usize;
+            for (i, sum) in sums.iter().enumerate() {
+                thumb[off + i] = (sum / (THUMB_SCALE * THUMB_SCALE)) as u8;
+            }
+        }
+    }
+    thumb
+}
+
+/// A short description of how long ago `saved` was, e.g. "5m ago"
+fn age(saved: SystemTime, now: SystemTime) -> String {
+    let secs = match now.duration_since(saved) {
+        Ok(elapsed) => elapsed.as_secs(),
+        Err(_) => 0,
+    };
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn draw(previews: &[Option<Preview>], selected: u32, now: SystemTime) -> Vec<u8> {
+    let mut frame = vec![0u8; FRAME_BYTES];
+    font::fill_rect(&mut frame, ROW_BYTES, 0, 0, COLS, ROWS, BACKGROUND);
+    for (slot, preview) in previews.iter().enumerate() {
+        let slot = slot as u32;
+        let x = (slot % GRID_COLS) * THUMB_COLS;
+        let y = (slot / GRID_COLS) * CELL_ROWS;
+        let colour = if slot == selected { SELECTED } else { TEXT };
+
+        let label = match *preview {
+            Some(ref preview) => {
+                for row in 0..THUMB_ROWS {
+                    let src = (row * THUMB_COLS * 4) as usize;
+                    let dst = ((y + row) * COLS + x) as usize * 4;
+                    frame[dst..dst + (THUMB_COLS * 4) as usize]
+                        .copy_from_slice(&preview.thumbnail[src..src + (THUMB_COLS * 4) as usize]);
+                }
+                format!("{} {}", slot, age(preview.saved, now))
+            }
+            None => format!("{} empty", slot),
+        };
+        if slot == selected {
+            // An outline just inside the thumbnail, the cells have no gaps
+            for &(rx, ry, w, h) in &[
+                (x, y, THUMB_COLS, 1),
+                (x, y + THUMB_ROWS - 1, THUMB_COLS, 1),
+                (x, y, 1, THUMB_ROWS),
+                (x + THUMB_COLS - 1, y, 1, THUMB_ROWS),
+            ] {
+                font::fill_rect(&mut frame, ROW_BYTES, rx, ry, w, h, SELECTED);
+            }
+        }
+        font::draw_text(&mut frame, ROW_BYTES, x + 1, y + THUMB_ROWS, &label, colour);
+    }
+    font::draw_text(
+        &mut frame,
+        ROW_BYTES,
+        1,
+        ROWS - font::ADVANCE_Y,
+        "Enter: load  F7: close",
+        TEXT,
+    );
+    frame
+}
+
+impl<'a> Gba<'a> {
+    /// Shows the slot picker until a slot is loaded or it is closed, the
+    /// arrow keys move between slots
+    pub(super) fn pick_slot(&mut self, event_pump: &mut EventPump) {
+        let previews: Vec<Option<Preview>> = (0..SLOTS)
+            .map(|slot| read_preview(Path::new(&self.slot_path(slot))).ok())
+            .collect();
+        let mut selected = self.slot;
+        loop {
+            let frame = draw(&previews, selected, SystemTime::now());
+            if let Some(ref mut frontend) = self.frontend {
+                frontend.present(&frame, None);
+            }
+
+            let code = match event_pump.wait_event() {
+                Event::Quit { .. } => return,
+                Event::KeyDown {
+                    scancode: Some(code),
+                    ..
+                } => code,
+                _ => continue,
+            };
+            match code {
+                Scancode::Left => selected = (selected + SLOTS - 1) % SLOTS,
+                Scancode::Right => selected = (selected + 1) % SLOTS,
+                Scancode::Up if selected >= GRID_COLS => selected -= GRID_COLS,
+                Scancode::Down if selected + GRID_COLS < SLOTS => selected += GRID_COLS,
+                Scancode::Return => {
+                    self.slot = selected;
+                    if let Err(err) = self.load_slot(selected) {
+                        error!("Failed to load slot {}: {}", selected, err);
+                        self.show_message(format!("Slot {} failed to load", selected));
+                    }
+                    return;
+                }
+                Scancode::Backspace => return,
+                _ if self.opts.bindings.hotkey(code) == Some(Hotkey::SlotPicker) => return,
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn test_thumbnail() {
+        let mut frame = vec![0u8; FRAME_BYTES];
+        // Half of the top-left block is white
+        for y in 0..2 {
+            for x in 0..THUMB_SCALE {
+                let off = ((y * COLS + x) * 4) as usize;
+                LittleEndian::write_u32(&mut frame[off..off + 4], 0x00ff_ffff);
+            }
+        }
+        let thumb = thumbnail(&frame);
+        assert_eq!((THUMB_COLS * THUMB_ROWS * 4) as usize, thumb.len());
+        assert_eq!(0x007f_7f7f, LittleEndian::read_u32(&thumb[0..4]));
+        assert_eq!(0, LittleEndian::read_u32(&thumb[4..8]));
+    }
+
+    #[test]
+    fn test_age() {
+        let now = SystemTime::now();
+        let ago = |secs| age(now - Duration::from_secs(secs), now);
+        assert_eq!("5s ago", ago(5));
+        assert_eq!("2m ago", ago(150));
+        assert_eq!("3h ago", ago(3 * 3600 + 10));
+        assert_eq!("4d ago", ago(4 * 86400));
+    }
+}
@@ -0,0 +1,217 @@
+//! Raw video and audio written out as frames are emulated, to pipe into
+//! ffmpeg or OBS rather than bundling an encoder.
+//!
+//! Video is a YUV4MPEG2 stream, which ffmpeg reads with no other options, or
+//! bare RGB24 frames for tools that want those:
+//!
+//! ```text
+//! gba-rs bios.bin game.gba --video-pipe - | ffmpeg -i - game.mp4
+//! ffmpeg -f rawvideo -pixel_format rgb24 -video_size 240x160 \
+//!     -framerate 16777216/280896 -i video.rgb ...
+//! ```
+//!
+//! Audio is interleaved stereo, signed 16 bit little endian PCM at 32768 Hz,
+//! `ffmpeg -f s16le -ar 32768 -ac 2 -i audio.pcm`.  Either can be a named
+//! pipe, a file, or `-` for stdout.  Every emulated frame is written, so the
+//! streams run at the GBA's rate whatever speed the window runs at.
+
+use std::ffi::OsStr;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gba_core::io::ppu::{VideoSink, COLS, ROWS};
+use gba_core::io::spu::SoundBuf;
+use gba_core::{CYCLES_PER_FRAME, CYCLES_PER_SEC};
+
+use super::*;
+
+pub const FORMATS: [&'static str; 2] = ["y4m", "rgb"];
+
+/// Opens `path` for writing without truncating, so named pipes work, or
+/// stdout for `-`
+pub fn open(path: &OsStr) -> io::Result<Box<Write>> {
+    if path == OsStr::new("-") {
+        return Ok(Box::new(BufWriter::new(io::stdout())));
+    }
+    let file = OpenOptions::new().write(true).create(true).open(path)?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+/// A sink writing `format`, one of `FORMATS`
+pub fn video_sink(format: &str, out: Box<Write>) -> Box<VideoSink> {
+    match format {
+        "rgb" => Box::new(Rgb::new(out)),
+        _ => Box::new(Y4m::new(out)),
+    }
+}
+
+fn rgb(px: &[u8]) -> (i32, i32, i32) {
+    let px = LittleEndian::read_u32(px);
+    (
+        (px >> 16 & 0xff) as i32,
+        (px >> 8 & 0xff) as i32,
+        (px & 0xff) as i32,
+    )
+}
+
+/// Converts to BT.601 studio range, which players assume without being told
+fn yuv(px: &[u8]) -> (u8, u8, u8) {
+    let (r, g, b) = rgb(px);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    (y as u8, u as u8, v as u8)
+}
+
+/// YUV4MPEG2 with full resolution chroma, so pixel art stays sharp
+pub struct Y4m<W: Write> {
+    out: W,
+    started: bool,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4m<W> {
+    pub fn new(out: W) -> Self {
+        Y4m {
+            out: out,
+            started: false,
+            planes: vec![0; (COLS * ROWS * 3) as usize],
+        }
+    }
+}
+
+impl<W: Write> VideoSink for Y4m<W> {
+    fn frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        if !self.started {
+            writeln!(
+                self.out,
+                "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                COLS, ROWS, CYCLES_PER_SEC, CYCLES_PER_FRAME
+            )?;
+            self.started = true;
+        }
+        let plane = (COLS * ROWS) as usize;
+        for (i, px) in frame.chunks(4).enumerate() {
+            let (y, u, v) = yuv(px);
+            self.planes[i] = y;
+            self.planes[plane + i] = u;
+            self.planes[plane * 2 + i] = v;
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&self.planes)?;
+        self.out.flush()
+    }
+}
+
+/// Packed 8 bit RGB with no header
+pub struct Rgb<W: Write> {
+    out: W,
+    packed: Vec<u8>,
+}
+
+impl<W: Write> Rgb<W> {
+    pub fn new(out: W) -> Self {
+        Rgb {
+            out: out,
+            packed: vec![0; (COLS * ROWS * 3) as usize],
+        }
+    }
+}
+
+impl<W: Write> VideoSink for Rgb<W> {
+    fn frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        for (out, px) in self.packed.chunks_mut(3).zip(frame.chunks(4)) {
+            let (r, g, b) = rgb(px);
+            out.copy_from_slice(&[r as u8, g as u8, b as u8]);
+        }
+        self.out.write_all(&self.packed)?;
+        self.out.flush()
+    }
+}
+
+/// Writes the samples the SPU produced since the last frame
+pub struct AudioPipe {
+    out: Box<Write>,
+    buf: SoundBuf,
+    samples: Vec<f32>,
+    bytes: Vec<u8>,
+}
+
+impl AudioPipe {
+    pub(super) fn new(out: Box<Write>, buf: SoundBuf) -> Self {
+        AudioPipe {
+            out: out,
+            buf: buf,
+            samples: Vec::new(),
+            bytes: Vec::new(),
+        }
+    }
+
+    pub(super) fn write(&mut self) -> io::Result<()> {
+        self.samples.clear();
+        self.buf.drain(&mut self.samples);
+        self.bytes.clear();
+        let mut bytes = [0; 2];
+        for &sample in self.samples.iter() {
+            let sample = (sample.max(-1.0).min(1.0) * i16::max_value() as f32) as i16;
+            LittleEndian::write_i16(&mut bytes, sample);
+            self.bytes.extend_from_slice(&bytes);
+        }
+        self.out.write_all(&self.bytes)?;
+        self.out.flush()
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Writes every frame from now on to `sink`
+    pub fn pipe_video(&mut self, sink: Box<VideoSink>) {
+        self.core.ppu.add_video_sink(sink);
+    }
+
+    /// Writes the audio from now on to `out`
+    pub fn pipe_audio(&mut self, out: Box<Write>) {
+        self.audio_pipe = Some(AudioPipe::new(out, self.core.spu.tap()));
+    }
+
+    /// Stops writing audio once it fails, as when its reader goes away.  The
+    /// PPU writes the video itself.
+    pub(super) fn write_pipes(&mut self) {
+        let res = match self.audio_pipe {
+            Some(ref mut pipe) => pipe.write(),
+            None => Ok(()),
+        };
+        if let Err(err) = res {
+            error!("Stopped writing audio: {}", err);
+            self.audio_pipe = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_y4m() {
+        let mut out = Vec::new();
+        {
+            let mut sink = Y4m::new(&mut out);
+            let mut frame = vec![0; (COLS * ROWS * 4) as usize];
+            LittleEndian::write_u32(&mut frame[0..4], 0x00ff_ffff);
+            sink.frame(&frame).unwrap();
+            sink.frame(&frame).unwrap();
+        }
+        let header = "YUV4MPEG2 W240 H160 F16777216:280896 Ip A1:1 C444\n";
+        assert!(out.starts_with(header.as_bytes()));
+        let frame_len = 6 + (COLS * ROWS * 3) as usize;
+        assert_eq!(header.len() + frame_len * 2, out.len());
+
+        let planes = &out[header.len() + 6..];
+        // White and black in studio range, with no colour
+        assert_eq!([235, 16], planes[0..2]);
+        let plane = (COLS * ROWS) as usize;
+        assert_eq!([128, 128], planes[plane..plane + 2]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Post-processing of frames before they're shown, a chain of filters run on
+//! the CPU over the PPU's output.
+//!
+//! The PPU's colours are the raw RGB values games write, which look much
+//! more saturated on a monitor than on the GBA's own dim, washed out LCD.
+//! `lcd` corrects for that, with the curves from byuu's higan.
+//!
+//! The LCD is slow to change too, so a pixel still shows some of the last
+//! frame.  `ghosting` mixes each frame half and half with the one before,
+//! which games flickering sprites on and off every frame rely on for
+//! transparency.  Unlike `--frame-blend` it mixes every frame, whether or not
+//! the game is updating every other one.
+//!
+//! A chain is filter names joined by `+`, and the post filter hotkey steps
+//! through `CHAINS` while the game runs.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gba_core::io::ppu::FRAME_BYTES;
+
+/// The chains the hotkey steps through, in order
+pub const CHAINS: [&'static str; 4] = ["none", "lcd", "ghosting", "lcd+ghosting"];
+
+/// The filters chains are made of
+const FILTERS: [&'static str; 2] = ["lcd", "ghosting"];
+
+pub trait PostFilter {
+    /// Filters `frame` into `out`, both RGB888 frames
+    fn apply(&mut self, frame: &[u8], out: &mut [u8]);
+}
+
+/// Maps each of the GBA's 15 bit colours to how it looks on the LCD
+struct LcdColour {
+    table: Vec<u32>,
+}
+
+impl LcdColour {
+    fn new() -> Self {
+        const LCD_GAMMA: f64 = 4.0;
+        const OUT_GAMMA: f64 = 2.2;
+        let mut table = vec![0; 1 << 15];
+        for (idx, entry) in table.iter_mut().enumerate() {
+            let channel = |shift: usize| ((idx >> shift) & 0x1f) as f64 / 31.0;
+            let (r, g, b) = (
+                channel(10).powf(LCD_GAMMA),
+                channel(5).powf(LCD_GAMMA),
+                channel(0).powf(LCD_GAMMA),
+            );
+            let out = |mix: f64| {
+                let level = (mix / 255.0).powf(1.0 / OUT_GAMMA) * 255.0 * 255.0 / 280.0;
+                level.round().min(255.0) as u32
+            };
+            *entry = out(255.0 * r + 50.0 * g) << 16
+                | out(10.0 * r + 230.0 * g + 30.0 * b) << 8
+                | out(50.0 * r + 10.0 * g + 220.0 * b);
+        }
+        LcdColour { table: table }
+    }
+}
+
+impl PostFilter for LcdColour {
+    fn apply(&mut self, frame: &[u8], out: &mut [u8]) {
+        for (px, out) in frame.chunks(4).zip(out.chunks_mut(4)) {
+            let rgb = LittleEndian::read_u32(px);
+            let idx = (rgb >> 9 & 0x7c00) | (rgb >> 6 & 0x3e0) | (rgb >> 3 & 0x1f);
+            LittleEndian::write_u32(out, self.table[idx as usize]);
+        }
+    }
+}
+
+/// Mixes each frame with the one before it
+struct Ghosting {
+    last: Vec<u8>,
+}
+
+impl Ghosting {
+    fn new() -> Self {
+        Ghosting {
+            last: vec![0; FRAME_BYTES],
+        }
+    }
+}
+
+impl PostFilter for Ghosting {
+    fn apply(&mut self, frame: &[u8], out: &mut [u8]) {
+        for (out, (last, &new)) in out.iter_mut().zip(self.last.iter_mut().zip(frame.iter())) {
+            *out = ((*last as u16 + new as u16) / 2) as u8;
+            *last = new;
+        }
+    }
+}
+
+fn filter(name: &str) -> Option<Box<PostFilter>> {
+    match name {
+        "lcd" => Some(Box::new(LcdColour::new())),
+        "ghosting" => Some(Box::new(Ghosting::new())),
+        _ => None,
+    }
+}
+
+/// Checks a chain from the command line or config file
+pub fn validate(chain: &str) -> Result<(), String> {
+    if chain == "none" {
+        return Ok(());
+    }
+    for name in chain.split('+') {
+        if !FILTERS.contains(&name) {
+            return Err(format!("unknown post filter '{}'", name));
+        }
+    }
+    Ok(())
+}
+
+pub struct Pipeline {
+    name: String,
+    filters: Vec<Box<PostFilter>>,
+    bufs: [Vec<u8>; 2],
+}
+
+impl Pipeline {
+    /// The pipeline for `chain`, which must be valid
+    pub fn new(chain: &str) -> Self {
+        let filters = if chain == "none" {
+            Vec::new()
+        } else {
+            chain.split('+').map(|name| filter(name).unwrap()).collect()
+        };
+        Pipeline {
+            name: chain.to_string(),
+            filters: filters,
+            bufs: [vec![0; FRAME_BYTES], vec![0; FRAME_BYTES]],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The pipeline after this one in `CHAINS`
+    pub fn next(&self) -> Pipeline {
+        let idx = CHAINS.iter().position(|&chain| chain == self.name);
+        let next = idx.map_or(0, |idx| (idx + 1) % CHAINS.len());
+        Pipeline::new(CHAINS[next])
+    }
+
+    /// The frame to show in place of `frame`
+    pub fn process<'f>(&'f mut self, frame: &'f [u8]) -> &'f [u8] {
+        let (first, rest) = match self.filters.split_first_mut() {
+            Some(split) => split,
+            None => return frame,
+        };
+        first.apply(frame, &mut self.bufs[0]);
+        for filter in rest.iter_mut() {
+            let (a, b) = self.bufs.split_at_mut(1);
+            filter.apply(&a[0], &mut b[0]);
+            self.bufs.swap(0, 1);
+        }
+        &self.bufs[0]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lcd() {
+        let mut lcd = LcdColour::new();
+        let mut frame = vec![0; 12];
+        LittleEndian::write_u32(&mut frame[0..4], 0x00f8_f8f8);
+        LittleEndian::write_u32(&mut frame[4..8], 0x00f8_0000);
+        let mut out = vec![0; 12];
+        lcd.apply(&frame, &mut out);
+        // White takes on the LCD's tint, red loses saturation and black
+        // stays black
+        assert_eq!(0x00fc_eef2, LittleEndian::read_u32(&out[0..4]));
+        let red = LittleEndian::read_u32(&out[4..8]);
+        assert!(red >> 16 & 0xff > 0xd0 && red & 0xff > 0x30, "{:06x}", red);
+        assert_eq!(0, LittleEndian::read_u32(&out[8..12]));
+    }
+
+    #[test]
+    fn test_chains() {
+        for chain in CHAINS.iter() {
+            validate(chain).unwrap();
+        }
+        assert!(validate("lcd+crt").is_err());
+        let pipeline = Pipeline::new("none");
+        assert_eq!("lcd", pipeline.next().name());
+        assert_eq!("ghosting", pipeline.next().next().name());
+        assert_eq!("none", Pipeline::new("ghosting+lcd").next().name());
+    }
+
+    #[test]
+    fn test_ghosting() {
+        let mut pipeline = Pipeline::new("ghosting");
+        let (on, off) = (vec![200; FRAME_BYTES], vec![0; FRAME_BYTES]);
+        assert_eq!(100, pipeline.process(&on)[0]);
+        // A sprite flickering every frame shows steadily at half strength
+        for _ in 0..4 {
+            assert_eq!(100, pipeline.process(&off)[0]);
+            assert_eq!(100, pipeline.process(&on)[0]);
+        }
+        assert_eq!(200, pipeline.process(&on)[0]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Recording gameplay to a video file with sound, started with `--record` or
+//! the record hotkey and stopped with it again or on exit.
+//!
+//! ffmpeg does the encoding, so it has to be on the `PATH`.  Frames go to it
+//! through its stdin as they're emulated and are encoded straight away, while
+//! the audio collects beside the output as raw PCM.  Stopping runs ffmpeg
+//! again to mux the two into the file asked for, so it only appears then.
+//! The video is H.264, which mp4 and mkv files both hold.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+
+use gba_core::io::ppu::VideoSink;
+use gba_core::io::spu::FREQ;
+
+use super::pipe::{AudioPipe, Y4m};
+use super::*;
+
+pub struct Recording {
+    path: PathBuf,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    encoder: Child,
+    video: Y4m<BufWriter<ChildStdin>>,
+    audio: AudioPipe,
+}
+
+/// `path` with `suffix` on the end, for the files kept while recording
+fn beside(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn check(status: ExitStatus) -> io::Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("ffmpeg failed: {}", status),
+        ))
+    }
+}
+
+impl Recording {
+    fn start(path: &Path, sound: SoundBuf) -> io::Result<Recording> {
+        let video_path = beside(path, ".video.mkv");
+        let audio_path = beside(path, ".audio.pcm");
+        let audio = File::create(&audio_path)?;
+        let mut encoder = Command::new("ffmpeg")
+            .args(&["-loglevel", "error", "-y", "-f", "yuv4mpegpipe", "-i", "-"])
+            .args(&["-c:v", "libx264", "-preset", "veryfast", "-crf", "16"])
+            // Players mostly can't cope with full resolution chroma
+            .args(&["-pix_fmt", "yuv420p"])
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = encoder.stdin.take().unwrap();
+        Ok(Recording {
+            path: path.to_path_buf(),
+            video_path: video_path,
+            audio_path: audio_path,
+            encoder: encoder,
+            video: Y4m::new(BufWriter::new(stdin)),
+            audio: AudioPipe::new(Box::new(BufWriter::new(audio)), sound),
+        })
+    }
+
+    fn frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.video.frame(frame)?;
+        self.audio.write()
+    }
+
+    /// Waits for the video to be encoded and muxes in the audio
+    fn finish(self) -> io::Result<PathBuf> {
+        let Recording {
+            path,
+            video_path,
+            audio_path,
+            mut encoder,
+            video,
+            audio,
+        } = self;
+        // Closing stdin ends the stream
+        drop(video);
+        drop(audio);
+        let res = check(encoder.wait()?).and_then(|()| {
+            let status = Command::new("ffmpeg")
+                .args(&["-loglevel", "error", "-y"])
+                .arg("-i")
+                .arg(&video_path)
+                .args(&["-f", "s16le", "-ar", &FREQ.to_string(), "-ac", "2"])
+                .arg("-i")
+                .arg(&audio_path)
+                .args(&["-c:v", "copy", "-shortest"])
+                .arg(&path)
+                .status()?;
+            check(status)
+        });
+        for temp in [&video_path, &audio_path].iter() {
+            if let Err(err) = fs::remove_file(temp) {
+                warn!("Failed to remove {:?}: {}", temp, err);
+            }
+        }
+        res.map(|()| path)
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Records from the next frame on to `path`
+    pub fn start_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.stop_recording();
+        let sound = self.core.spu.tap();
+        self.recording = Some(Recording::start(path, sound)?);
+        self.show_message(format!("Recording to {:?}", path));
+        Ok(())
+    }
+
+    /// Finishes the recording, if there is one
+    pub fn stop_recording(&mut self) {
+        let recording = match self.recording.take() {
+            Some(recording) => recording,
+            None => return,
+        };
+        match recording.finish() {
+            Ok(path) => self.show_message(format!("Saved recording {:?}", path)),
+            Err(err) => error!("Failed to save recording: {}", err),
+        }
+    }
+
+    /// Starts recording beside the saves, or stops
+    pub(super) fn toggle_recording(&mut self) {
+        if self.recording.is_some() {
+            self.stop_recording();
+            return;
+        }
+        let mut path = self.opts.save_file.to_os_string();
+        path.push(format!("recording-{}.mp4", self.session.frames));
+        if let Err(err) = self.start_recording(Path::new(&path)) {
+            error!("Failed to start recording {:?}: {}", path, err);
+        }
+    }
+
+    /// Stops recording once writing fails, as when ffmpeg quits
+    pub(super) fn write_recording(&mut self) {
+        let res = match self.recording {
+            Some(ref mut recording) => recording.frame(self.core.frame()),
+            None => return,
+        };
+        if let Err(err) = res {
+            error!("Recording stopped: {}", err);
+            self.stop_recording();
+        }
+    }
+}
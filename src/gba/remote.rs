@@ -0,0 +1,304 @@
+//! Commands from outside the emulator, e.g. the HTTP control server, run
+//! between frames on the emulator's thread.
+
+use std::sync::mpsc::{Receiver, Sender};
+
+use serde_json;
+
+use gba_core::io::key::InputProvider;
+use gba_core::mmu::MemoryUnit;
+use gba_core::rules::Cmp;
+
+use super::bindings::either;
+use super::compare::Side;
+use super::crowd::Crowd;
+use super::screenshot;
+use super::search::Against;
+use super::*;
+
+pub enum Command {
+    Pause,
+    Resume,
+    SaveState(u32),
+    LoadState(u32),
+    Peek {
+        addr: u32,
+        len: u32,
+    },
+    Poke {
+        addr: u32,
+        data: Vec<u8>,
+    },
+    Screenshot,
+    /// Dump the layers of the next frame
+    CaptureLayers,
+    /// Export the DMA, interrupt and timer timeline of the next frame
+    RecordTimeline,
+    /// Keys to hold down on top of the keyboard, until replaced
+    Input(KeyState),
+    /// Run this many cycles forwards or backwards while paused
+    Step(u64),
+    ReverseStep(u64),
+    /// Run backwards to the last breakpoint hit
+    ReverseContinue,
+    /// Find the last write to an address
+    LastWrite(u32),
+    /// Load a save state slot to compare against
+    LoadReference(u32),
+    CompareReference,
+    SearchReference {
+        value: u32,
+        size: usize,
+        side: Side,
+    },
+    /// Start a RAM search over values of this many bytes
+    StartSearch(usize),
+    /// Keep the search's candidates that compare true
+    FilterSearch(Cmp, Against),
+    SearchResults,
+    /// A crowd vote for a key, an index into `crowd::KEYS`
+    Vote(usize),
+    Status,
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn text(status: u16, text: &str) -> Reply {
+        Reply {
+            status: status,
+            content_type: "text/plain",
+            body: text.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn ok() -> Reply {
+        Reply::text(200, "ok\n")
+    }
+}
+
+pub type Request = (Command, Sender<Reply>);
+
+#[derive(Serialize)]
+struct StatusReply {
+    title: String,
+    paused: bool,
+    frames: u64,
+}
+
+pub struct Remote {
+    requests: Receiver<Request>,
+    keys: KeyState,
+    crowd: Option<Crowd>,
+}
+
+/// The keys held through the control server, and those the crowd voted for
+impl InputProvider for Remote {
+    fn poll(&mut self) -> KeyState {
+        match self.crowd {
+            Some(ref mut crowd) => either(self.keys, crowd.step()),
+            None => self.keys,
+        }
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Takes commands from `requests` while the frontend is running
+    pub fn set_remote(&mut self, requests: Receiver<Request>) {
+        self.remote = Some(Remote {
+            requests: requests,
+            keys: Default::default(),
+            crowd: None,
+        });
+    }
+
+    /// Takes votes for keys from the control server, which must already be
+    /// set with `set_remote`
+    pub fn set_crowd(&mut self, crowd: Crowd) {
+        if let Some(ref mut remote) = self.remote {
+            remote.crowd = Some(crowd);
+        }
+    }
+
+    /// Runs any commands that have arrived since the last call
+    pub(super) fn poll_remote(&mut self) {
+        loop {
+            let request = match self.remote {
+                Some(ref remote) => remote.requests.try_recv(),
+                None => return,
+            };
+            match request {
+                Ok((command, reply)) => {
+                    let res = self.run_command(command);
+                    // The requester may have given up waiting
+                    let _ = reply.send(res);
+                }
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Where the CPU is after a step, or why it couldn't step
+    fn position_reply(&self, res: ::std::result::Result<(), String>) -> Reply {
+        match res {
+            Ok(()) => Reply::text(
+                200,
+                &format!(
+                    "pc {:#010x}\ncycle {}\n",
+                    self.core.cpu.get_prefetch_addr(),
+                    self.core.now()
+                ),
+            ),
+            Err(err) => Reply::text(409, &format!("{}\n", err)),
+        }
+    }
+
+    fn run_command(&mut self, command: Command) -> Reply {
+        match command {
+            Command::Pause => {
+                self.paused = true;
+                Reply::ok()
+            }
+            Command::Resume => {
+                self.paused = false;
+                Reply::ok()
+            }
+            Command::SaveState(slot) => {
+                self.save_slot(slot);
+                Reply::ok()
+            }
+            Command::LoadState(slot) => match self.load_slot(slot) {
+                Ok(()) => Reply::ok(),
+                Err(err) => Reply::text(500, &format!("{}\n", err)),
+            },
+            Command::Step(_)
+            | Command::ReverseStep(_)
+            | Command::ReverseContinue
+            | Command::LastWrite(_)
+                if !self.paused =>
+            {
+                Reply::text(409, "pause first\n")
+            }
+            Command::Step(n) => {
+                let res = self.step(n);
+                self.position_reply(res)
+            }
+            Command::ReverseStep(n) => {
+                let res = self.reverse_step(n);
+                self.position_reply(res)
+            }
+            Command::ReverseContinue => {
+                let res = self.reverse_continue().map(|_| ());
+                self.position_reply(res)
+            }
+            Command::LastWrite(addr) => match self.last_write(addr) {
+                Ok((pc, ago)) => {
+                    Reply::text(200, &format!("pc {:#010x}\ncycles ago {}\n", pc, ago))
+                }
+                Err(err) => Reply::text(409, &format!("{}\n", err)),
+            },
+            Command::LoadReference(slot) => {
+                let path = self.slot_path(slot);
+                match self.load_reference(Path::new(&path)) {
+                    Ok(()) => Reply::ok(),
+                    Err(err) => Reply::text(500, &format!("{}\n", err)),
+                }
+            }
+            Command::CompareReference => match self.compare_reference() {
+                Ok(lines) => Reply::text(
+                    200,
+                    &lines.iter().map(|l| l.clone() + "\n").collect::<String>(),
+                ),
+                Err(err) => Reply::text(409, &format!("{}\n", err)),
+            },
+            Command::SearchReference { value, size, side } => {
+                match self.search_reference(value, size, side) {
+                    Ok(matches) => {
+                        let text: String = matches
+                            .iter()
+                            .map(|&(addr, other)| format!("{:#010x} {:#x}\n", addr, other))
+                            .collect();
+                        Reply::text(200, &text)
+                    }
+                    Err(err) => Reply::text(409, &format!("{}\n", err)),
+                }
+            }
+            Command::StartSearch(size) => {
+                let count = self.start_search(size);
+                Reply::text(200, &format!("{} candidates\n", count))
+            }
+            Command::FilterSearch(cmp, against) => match self.filter_search(cmp, against) {
+                Ok(count) => Reply::text(200, &format!("{} candidates\n", count)),
+                Err(err) => Reply::text(409, &format!("{}\n", err)),
+            },
+            Command::SearchResults => match self.search_results() {
+                Ok(results) => {
+                    let text: String = results
+                        .iter()
+                        .map(|&(addr, value)| format!("{:#010x} {:#x}\n", addr, value))
+                        .collect();
+                    Reply::text(200, &text)
+                }
+                Err(err) => Reply::text(409, &format!("{}\n", err)),
+            },
+            Command::Peek { addr, len } => {
+                let hex: String = (0..len)
+                    .map(|i| format!("{:02x}", self.core.mmu.load8(addr.wrapping_add(i))))
+                    .collect();
+                Reply::text(200, &(hex + "\n"))
+            }
+            Command::Poke { addr, data } => {
+                for (i, &byte) in data.iter().enumerate() {
+                    self.core.mmu.set8(addr.wrapping_add(i as u32), byte);
+                }
+                Reply::ok()
+            }
+            Command::Screenshot => {
+                let mut bmp = Vec::new();
+                screenshot::write_bmp(self.core.frame(), &mut bmp).unwrap();
+                Reply {
+                    status: 200,
+                    content_type: "image/bmp",
+                    body: bmp,
+                }
+            }
+            Command::CaptureLayers => {
+                self.capture_layers();
+                Reply::ok()
+            }
+            Command::RecordTimeline => {
+                self.record_timeline();
+                Reply::ok()
+            }
+            Command::Input(keys) => {
+                if let Some(ref mut remote) = self.remote {
+                    remote.keys = keys;
+                }
+                Reply::ok()
+            }
+            Command::Vote(key) => match self.remote.as_mut().and_then(|r| r.crowd.as_mut()) {
+                Some(crowd) => {
+                    crowd.vote(key);
+                    Reply::ok()
+                }
+                None => Reply::text(409, "crowd input is not enabled\n"),
+            },
+            Command::Status => {
+                let status = StatusReply {
+                    title: self.title(),
+                    paused: self.paused,
+                    frames: self.session.frames,
+                };
+                Reply {
+                    status: 200,
+                    content_type: "application/json",
+                    body: serde_json::to_vec(&status).unwrap(),
+                }
+            }
+        }
+    }
+}
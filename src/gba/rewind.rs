@@ -0,0 +1,301 @@
+//! Checkpoints of recent frames, so execution can be stepped backwards by
+//! restoring the closest earlier checkpoint and running forward from it.
+//!
+//! A checkpoint is taken at the start of every frame, after that frame's keys
+//! are set, so running forward from one needs no recorded input.
+
+use std::collections::VecDeque;
+
+use bincode;
+
+use gba_core::mmu::watch::Watchpoint;
+
+use super::*;
+
+struct Checkpoint {
+    /// Cycles run before the checkpoint was taken
+    cycle: u64,
+    state: Vec<u8>,
+}
+
+pub struct Rewind {
+    capacity: usize,
+    checkpoints: VecDeque<Checkpoint>,
+}
+
+impl Rewind {
+    /// Keeps checkpoints for the last `frames` frames
+    pub fn new(frames: usize) -> Self {
+        let capacity = frames.max(1);
+        Rewind {
+            capacity: capacity,
+            checkpoints: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// A buffer to serialize the next checkpoint into.  Once full this is the
+    /// oldest checkpoint's, so recording every frame doesn't allocate.
+    fn take_buffer(&mut self) -> Vec<u8> {
+        if self.checkpoints.len() < self.capacity {
+            return Vec::new();
+        }
+        let mut state = self.checkpoints.pop_front().unwrap().state;
+        state.clear();
+        state
+    }
+
+    fn push(&mut self, cycle: u64, state: Vec<u8>) {
+        if self.checkpoints.len() == self.capacity {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(Checkpoint {
+            cycle: cycle,
+            state: state,
+        });
+    }
+
+    /// Index of the latest checkpoint at or before `cycle`
+    fn before(&self, cycle: u64) -> Option<usize> {
+        self.checkpoints.iter().rposition(|cp| cp.cycle <= cycle)
+    }
+
+    /// Drops checkpoints after `cycle`, which no longer happened once
+    /// execution is rewound past them
+    fn truncate(&mut self, cycle: u64) {
+        while self.checkpoints.back().map_or(false, |cp| cp.cycle > cycle) {
+            self.checkpoints.pop_back();
+        }
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Records a checkpoint if rewinding is enabled, called at the start of
+    /// each frame
+    pub(super) fn checkpoint(&mut self) {
+        let cycle = self.core.now();
+        if let Some(ref mut rewind) = self.rewind {
+            let mut state = rewind.take_buffer();
+            bincode::serialize_into(&mut state, &*self.core).unwrap();
+            rewind.push(cycle, state);
+        }
+    }
+
+    fn restore_checkpoint(&mut self, index: usize) -> ::std::result::Result<(), String> {
+        let state: gba_core::Gba<'a> = {
+            let cp = &self.rewind.as_ref().unwrap().checkpoints[index];
+            bincode::deserialize(&cp.state).map_err(|err| err.to_string())?
+        };
+        self.core.restore(state);
+        Ok(())
+    }
+
+    /// Steps the core `n` times, a step skips ahead to the next event while
+    /// the CPU is halted
+    pub(super) fn step(&mut self, n: u64) -> ::std::result::Result<(), String> {
+        for _ in 0..n {
+            if !self.core.cycle() {
+                return Err("CPU stopped at a breakpoint".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Steps the core once while replaying, running on past breakpoints as
+    /// the CPU already got past them the first time
+    fn replay_step(&mut self) {
+        let now = self.core.now();
+        // A breakpoint stops the CPU without any time passing
+        if !self.core.cycle() && self.core.now() == now {
+            self.core.cpu.resume();
+            self.core.cycle();
+        }
+    }
+
+    /// Runs `replay` with the breakpoints' hit counts put back afterwards, as
+    /// replaying reaches them again
+    fn keeping_hits<T, F>(&mut self, replay: F) -> T
+    where
+        F: FnOnce(&mut Self) -> T,
+    {
+        let breaks = self.core.breaks().to_vec();
+        let res = replay(self);
+        self.core.set_breaks(&breaks);
+        res
+    }
+
+    /// Puts the machine back to how it was after `target` cycles
+    fn run_to(&mut self, target: u64) -> ::std::result::Result<(), String> {
+        let index = match self.rewind {
+            Some(ref rewind) => rewind.before(target),
+            None => return Err("rewinding is not enabled".to_string()),
+        };
+        let index = index.ok_or("not enough checkpoints to go back that far")?;
+        self.restore_checkpoint(index)?;
+        self.rewind.as_mut().unwrap().truncate(target);
+        while self.core.now() < target {
+            self.replay_step();
+        }
+        Ok(())
+    }
+
+    /// Goes back `n` cycles
+    pub(super) fn reverse_step(&mut self, n: u64) -> ::std::result::Result<(), String> {
+        let now = self.core.now();
+        if n > now {
+            return Err("not enough checkpoints to go back that far".to_string());
+        }
+        self.keeping_hits(|gba| gba.run_to(now - n))
+    }
+
+    /// Runs backwards to the last time the CPU was about to execute a
+    /// breakpoint, returning how many cycles back that was.  Conditions
+    /// aren't checked, as hit counts can't be replayed.
+    pub(super) fn reverse_continue(&mut self) -> ::std::result::Result<u64, String> {
+        self.keeping_hits(|gba| gba.find_break())
+    }
+
+    fn find_break(&mut self) -> ::std::result::Result<u64, String> {
+        let now = self.core.now();
+        let count = match self.rewind {
+            Some(ref rewind) => rewind.checkpoints.len(),
+            None => return Err("rewinding is not enabled".to_string()),
+        };
+        // Replay each checkpoint's span, latest first, until one contains a
+        // breakpoint hit before `now`
+        let mut end = now;
+        for index in (0..count).rev() {
+            self.restore_checkpoint(index)?;
+            // Replayed to the end, as a breakpoint in a loop is hit again and
+            // again and only the last time counts
+            let mut hit = None;
+            while self.core.now() < end {
+                let pc = self.core.cpu.get_prefetch_addr();
+                let halted = self.core.io.halted();
+                if !halted && self.opts.core.breaks.iter().any(|brk| brk.addr == pc) {
+                    hit = Some(self.core.now());
+                }
+                self.replay_step();
+            }
+            if let Some(target) = hit {
+                self.run_to(target)?;
+                return Ok(now - target);
+            }
+            end = self.rewind.as_ref().unwrap().checkpoints[index].cycle;
+        }
+        // Nothing found, so go back to where we started
+        self.run_to(now)?;
+        Err("no breakpoint hit within the checkpoints".to_string())
+    }
+
+    /// Finds the last write to the byte at `addr` within the checkpoints,
+    /// returning the PC that made it and how many cycles ago it was
+    pub(super) fn last_write(&mut self, addr: u32) -> ::std::result::Result<(u32, u64), String> {
+        let now = self.core.now();
+        let count = match self.rewind {
+            Some(ref rewind) => rewind.checkpoints.len(),
+            None => return Err("rewinding is not enabled".to_string()),
+        };
+        // Only this watchpoint while searching, then the user's again
+        let watches = self.core.watches().to_vec();
+        self.core.set_watches(&[Watchpoint::writes(addr, 1)]);
+        let (found, ran) = self.keeping_hits(|gba| {
+            let found = gba.find_write(now, count);
+            gba.core.set_watches(&[]);
+            gba.core.take_watch_hits();
+            (found, gba.run_to(now))
+        });
+        self.core.set_watches(&watches);
+        ran?;
+        found?.ok_or_else(|| format!("{:#010x} wasn't written within the checkpoints", addr))
+    }
+
+    /// Replays the checkpoints before `now`, latest first, for the last
+    /// access the watchpoint catches
+    fn find_write(
+        &mut self,
+        now: u64,
+        count: usize,
+    ) -> ::std::result::Result<Option<(u32, u64)>, String> {
+        let mut end = now;
+        let mut found = None;
+        for index in (0..count).rev() {
+            self.restore_checkpoint(index)?;
+            self.core.take_watch_hits();
+            while self.core.now() < end {
+                let at = self.core.now();
+                self.replay_step();
+                if let Some(hit) = self.core.take_watch_hits().pop() {
+                    found = Some((hit.pc, now - at));
+                }
+            }
+            if found.is_some() {
+                break;
+            }
+            end = self.rewind.as_ref().unwrap().checkpoints[index].cycle;
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use gba_core::cpu::breakpoint::Breakpoint;
+
+    #[test]
+    fn test_checkpoints() {
+        let mut rewind = Rewind::new(3);
+        for &cycle in [0, 100, 200, 300].iter() {
+            rewind.push(cycle, vec![]);
+        }
+        assert_eq!(3, rewind.checkpoints.len());
+        assert_eq!(None, rewind.before(50));
+        assert_eq!(Some(0), rewind.before(150));
+        assert_eq!(Some(2), rewind.before(300));
+
+        rewind.truncate(250);
+        assert_eq!(2, rewind.checkpoints.len());
+        assert_eq!(Some(1), rewind.before(1000));
+    }
+
+    #[test]
+    fn test_replay_past_breakpoints() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/gba-core/testdata/tiny.gba");
+        let mut opts: Options = Default::default();
+        opts.core.direct_boot = true;
+        opts.rewind_frames = 4;
+        let rom = GameRom::new(Path::new(path)).unwrap();
+        let mut gba = Gba::new_headless(rom, Default::default(), opts);
+        gba.run_headless(2).unwrap();
+
+        // tiny.s's loop, which the CPU went round thousands of times
+        let brk = Breakpoint::parse("80000e0", &Default::default()).unwrap();
+        gba.opts.core.breaks = vec![brk.clone()];
+        gba.core.set_breaks(&[brk]);
+
+        let now = gba.core.now();
+        gba.reverse_step(1000).unwrap();
+        assert!(gba.core.now() >= now - 1000 && gba.core.now() < now - 900);
+
+        // The last time round, not the first in the checkpoint
+        let back = gba.reverse_continue().unwrap();
+        assert!(back < 100);
+        assert_eq!(0x0800_00e0, gba.core.cpu.get_prefetch_addr());
+        assert_eq!(0, gba.core.breaks()[0].hits);
+    }
+
+    #[test]
+    fn test_buffer_reuse() {
+        let mut rewind = Rewind::new(2);
+        let record = |rewind: &mut Rewind| {
+            let mut state = rewind.take_buffer();
+            state.extend_from_slice(&[0; 64]);
+            rewind.push(0, state);
+        };
+        record(&mut rewind);
+        record(&mut rewind);
+        assert_eq!(0, ::test::allocations(|| record(&mut rewind)));
+        assert_eq!(2, rewind.checkpoints.len());
+    }
+}
@@ -1,20 +1,173 @@
-use std::fmt;
+use std::io::{Read, Write};
 use std::result::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use bincode;
+use byteorder::{ByteOrder, LittleEndian};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use zstd;
 
-use serde::de;
-use serde::de::{SeqAccess, Visitor};
-use serde::ser::SerializeStruct;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use stats;
 
+use super::picker;
+use super::screenshot;
 use super::*;
 
+/// Save states start with this, then the format version
+const MAGIC: &'static [u8; 8] = b"GBASTATE";
+/// Bumped whenever a component's serialized layout changes, as bincode can't
+/// tell an old layout from a corrupt file
+const VERSION: u32 = 1;
+
+/// One component of a state, serialized on its own so a missing or damaged
+/// one can be named
+#[derive(Serialize, Deserialize)]
+struct Section {
+    name: String,
+    data: Vec<u8>,
+}
+
+fn section<T: Serialize>(name: &str, value: &T) -> Section {
+    Section {
+        name: name.to_string(),
+        data: bincode::serialize(value).unwrap(),
+    }
+}
+
+fn get_section<T: DeserializeOwned>(sections: &[Section], name: &str) -> Result<T, String> {
+    let section = sections
+        .iter()
+        .find(|section| section.name == name)
+        .ok_or_else(|| format!("save state has no {} section", name))?;
+    bincode::deserialize(&section.data).map_err(|err| format!("corrupt {} section: {}", name, err))
+}
+
+/// What the slot picker shows for a state, without restoring it
+pub struct Preview {
+    /// The frame shrunk by `picker::thumbnail`
+    pub thumbnail: Vec<u8>,
+    pub saved: SystemTime,
+    /// The CRC32 of the ROM it was saved from, if the state records it
+    pub rom: Option<u32>,
+}
+
+/// Writes `frame` and the core as a save state: the magic and version, then
+/// sections for the frame, its thumbnail, the time it was saved, the ROM's
+/// CRC32 and each component compressed with zstd at `level`
+pub fn write_state<W: Write>(
+    mut out: W,
+    frame: &[u8],
+    core: &gba_core::Gba,
+    level: i32,
+) -> Result<(), String> {
+    let mut version = [0u8; 4];
+    LittleEndian::write_u32(&mut version, VERSION);
+    out.write_all(MAGIC).map_err(|err| err.to_string())?;
+    out.write_all(&version).map_err(|err| err.to_string())?;
+
+    let saved = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let sections = vec![
+        section("frame", &frame),
+        section("thumbnail", &picker::thumbnail(frame)),
+        section("saved", &saved),
+        section("rom", &core.mmu.rom.crc32()),
+        section("cpu", &core.cpu),
+        section("mmu", &core.mmu),
+        section("io", &core.io),
+        section("ppu", &core.ppu),
+        section("spu", &core.spu),
+    ];
+    let mut writer = zstd::Encoder::new(out, level).map_err(|err| err.to_string())?;
+    bincode::serialize_into(&mut writer, &sections).map_err(|err| err.to_string())?;
+    writer.finish().map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+/// Checks the magic and version at the start of a state
+fn read_header<R: Read>(input: &mut R) -> Result<(), String> {
+    let mut header = [0u8; 12];
+    input
+        .read_exact(&mut header)
+        .map_err(|_| "not a save state".to_string())?;
+    if &header[..8] != MAGIC {
+        return Err("not a save state".to_string());
+    }
+    match LittleEndian::read_u32(&header[8..]) {
+        VERSION => Ok(()),
+        version if version > VERSION => Err(format!(
+            "save state version {} is from a newer version of the emulator, this one reads {}",
+            version, VERSION
+        )),
+        version => Err(format!(
+            "save state version {} is no longer supported, this one reads {}",
+            version, VERSION
+        )),
+    }
+}
+
+fn read_sections<R: Read>(mut input: R) -> Result<Vec<Section>, String> {
+    read_header(&mut input)?;
+    let reader = zstd::Decoder::new(input).map_err(|err| err.to_string())?;
+    bincode::deserialize_from(reader).map_err(|err| format!("corrupt save state: {}", err))
+}
+
+/// Reads a state written by `write_state`, returning its frame and the
+/// unconnected core
+pub fn read_state_from<'a, R: Read>(input: R) -> Result<(Vec<u8>, gba_core::Gba<'a>), String> {
+    let sections = read_sections(input)?;
+
+    let frame = get_section(&sections, "frame")?;
+    let core = gba_core::Gba::from_parts(
+        get_section(&sections, "cpu")?,
+        get_section(&sections, "mmu")?,
+        get_section(&sections, "io")?,
+        get_section(&sections, "ppu")?,
+        get_section(&sections, "spu")?,
+    );
+    Ok((frame, core))
+}
+
+/// Reads the state file at `path`
+pub fn read_state<'a>(path: &Path) -> Result<(Vec<u8>, gba_core::Gba<'a>), String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    read_state_from(file)
+}
+
+/// Reads the thumbnail and save time of the state at `path`.  States from
+/// before thumbnails were stored fall back to shrinking their frame and to
+/// the file's modification time.
+pub fn read_preview(path: &Path) -> Result<Preview, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let modified = file.metadata().and_then(|meta| meta.modified());
+    let sections = read_sections(file)?;
+
+    let thumbnail = match get_section(&sections, "thumbnail") {
+        Ok(thumbnail) => thumbnail,
+        Err(_) => picker::thumbnail(&get_section::<Vec<u8>>(&sections, "frame")?),
+    };
+    let saved = match get_section::<u64>(&sections, "saved") {
+        Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+        Err(_) => modified.map_err(|err| err.to_string())?,
+    };
+    Ok(Preview {
+        thumbnail: thumbnail,
+        saved: saved,
+        rom: get_section(&sections, "rom").ok(),
+    })
+}
+
+/// Save state slots the hotkeys can use, one for each number key
+pub(super) const SLOTS: u32 = 10;
+
 impl<'a> Gba<'a> {
-    pub(super) fn check_save(&mut self, key: Scancode, _ctrl: bool) {
+    /// Number keys pick the slot, which the save and load hotkeys use
+    pub(super) fn check_slot_keys(&mut self, key: Scancode) {
         use self::Scancode::*;
-        let index = match key {
+        let slot = match key {
             Num0 => 0,
             Num1 => 1,
             Num2 => 2,
@@ -25,71 +178,191 @@ impl<'a> Gba<'a> {
             Num7 => 7,
             Num8 => 8,
             Num9 => 9,
-            _ => 10,
+            _ => return,
         };
-        if index == 10 {
-            return;
+        self.select_slot(slot);
+    }
+
+    /// Makes `slot` the one the hotkeys use
+    pub(super) fn select_slot(&mut self, slot: u32) {
+        self.slot = slot;
+        self.show_message(format!("Slot {}", slot));
+    }
+
+    pub(super) fn load_current_slot(&mut self) {
+        let slot = self.slot;
+        if let Err(err) = self.load_slot(slot) {
+            error!("Failed to load slot {}: {}", slot, err);
+            self.show_message(format!("Slot {} failed to load", slot));
         }
+    }
+
+    /// The state file for `slot`, named after the save prefix
+    pub(super) fn slot_path(&self, slot: u32) -> OsString {
         let mut path = self.opts.save_file.to_os_string();
-        path.push(format!("{}.sav", index));
-        match File::create(Path::new(&path)) {
-            Ok(file) => {
-                let mut writer = zstd::Encoder::new(&file, 1).unwrap();
-                bincode::serialize_into(&mut writer, self).unwrap();
-                info!("Saved file {:?}", path);
+        path.push(format!("{}.state", slot));
+        path
+    }
+
+    pub(super) fn save_slot(&mut self, slot: u32) {
+        let path = self.slot_path(slot);
+        self.save_state(&path);
+        self.show_message(format!("Saved slot {}", slot));
+    }
+
+    pub(super) fn load_slot(&mut self, slot: u32) -> Result<(), String> {
+        let path = self.slot_path(slot);
+        self.load_state(&path)?;
+        self.show_message(format!("Loaded slot {}", slot));
+        Ok(())
+    }
+
+    /// Writes the state to `path`, along with a screenshot beside it
+    pub(super) fn save_state(&mut self, path: &OsStr) {
+        let path = Path::new(path);
+        let res = File::create(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| {
+                write_state(file, self.core.frame(), &self.core, self.opts.state_level)
+            });
+        match res {
+            Ok(()) => info!("Saved file {:?}", path),
+            Err(err) => {
+                error!("Failed to write save state {:?}: {}", path, err);
+                return;
             }
-            Err(err) => error!("Failed to create save state: {}", err),
         }
+
+        let image = path.with_extension("bmp");
+        screenshot::save(self.core.frame(), &image);
+        let label = format!("Saved {}", path.display());
+        self.session
+            .add_shot(label, image, Some(path.to_path_buf()));
     }
-}
 
-impl<'a> Serialize for Gba<'a> {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut s = serializer.serialize_struct("gba_rs::Gba", 4)?;
-        s.serialize_field("cpu", &self.cpu)?;
-        s.serialize_field("mmu", &self.mmu)?;
-        s.serialize_field("io", &self.io)?;
-        s.serialize_field("ppu", &self.ppu)?;
-        s.end()
+    /// Restores a state written by `save_state`
+    pub(super) fn load_state(&mut self, path: &OsStr) -> Result<(), String> {
+        let (_, state) = read_state(Path::new(path))?;
+        self.core.restore(state);
+        info!("Loaded state {:?}", path);
+        Ok(())
+    }
+
+    /// Loads the battery save, if the game has one and it has been saved
+    /// before
+    pub(super) fn load_battery(&mut self) {
+        let path = match self.opts.battery_file {
+            Some(ref path) if path.exists() => path.clone(),
+            _ => return,
+        };
+        let mut data = Vec::new();
+        match File::open(&path).and_then(|mut f| f.read_to_end(&mut data)) {
+            Ok(_) => {
+                self.core.mmu.load_battery(&data);
+                info!("Loaded battery save {:?}", path);
+            }
+            Err(err) => error!("Failed to read battery save {:?}: {}", path, err),
+        }
+    }
+
+    /// Writes the game's battery backed memory to the battery save
+    pub(super) fn write_battery(&mut self) {
+        let path = match self.opts.battery_file {
+            Some(ref path) if !self.opts.battery_read_only => path.clone(),
+            _ => return,
+        };
+        let data = match self.core.mmu.battery() {
+            Some(data) => data,
+            None => return,
+        };
+        match File::create(&path).and_then(|mut f| f.write_all(&data)) {
+            Ok(()) => info!("Wrote battery save {:?}", path),
+            Err(err) => error!("Failed to write battery save {:?}: {}", path, err),
+        }
     }
-}
 
-impl<'de> Deserialize<'de> for Gba<'static> {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct GbaVisitor;
-        impl<'de> Visitor<'de> for GbaVisitor {
-            type Value = Gba<'static>;
+    /// The state saved on exit and restored on the next launch, with `resume`
+    /// set
+    fn resume_path(&self) -> OsString {
+        let mut path = self.opts.save_file.to_os_string();
+        path.push("resume.state");
+        path
+    }
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("struct Gba")
+    /// Restores the state saved when the game last exited, if there is one
+    /// and it was saved from the same ROM
+    pub(super) fn resume(&mut self) {
+        let path = self.resume_path();
+        if !Path::new(&path).exists() {
+            return;
+        }
+        let preview = match read_preview(Path::new(&path)) {
+            Ok(preview) => preview,
+            Err(err) => {
+                warn!("Not resuming from {:?}: {}", path, err);
+                return;
             }
+        };
+        if preview
+            .rom
+            .map_or(false, |crc| crc != self.core.mmu.rom.crc32())
+        {
+            warn!(
+                "Not resuming from {:?}, it was saved from another ROM",
+                path
+            );
+            return;
+        }
+        match self.load_state(&path) {
+            Ok(()) => self.show_message("Resumed".to_string()),
+            Err(err) => warn!("Not resuming from {:?}: {}", path, err),
+        }
+    }
 
-            fn visit_seq<V: SeqAccess<'de>>(self, mut seq: V) -> Result<Gba<'static>, V::Error> {
-                let cpu = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
-                let mmu = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let io = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
-                let ppu = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(3, &self))?;
-
-                unsafe {
-                    let mut gba: Gba<'static> = mem::uninitialized();
-                    ptr::write(&mut gba.cpu, cpu);
-                    ptr::write(&mut gba.mmu, mmu);
-                    ptr::write(&mut gba.io, io);
-                    ptr::write(&mut gba.ppu, ppu);
-                    Ok(gba)
-                }
+    /// Saves the state to resume from on the next launch
+    pub(super) fn suspend(&mut self) {
+        let path = self.resume_path();
+        self.save_state(&path);
+    }
+
+    /// Takes a final screenshot and writes the session summary
+    pub(super) fn end_session(&mut self) {
+        let mut image = self.opts.save_file.to_os_string();
+        image.push("-exit.bmp");
+        let image = PathBuf::from(image);
+        screenshot::save(self.core.frame(), &image);
+        self.session.add_shot("Exit".to_string(), image, None);
+
+        let title = self.title();
+        let mut summary = self.opts.save_file.to_os_string();
+        summary.push("-session.html");
+        self.session.write_html(&title, Path::new(&summary));
+
+        // Multiboot images aren't in the ROM, so there's nothing to key on
+        if !self.opts.core.multiboot {
+            let crc = self.core.mmu.rom.crc32();
+            let played = self.session.elapsed();
+            if let Err(err) = stats::record(crc, &title, played, self.session.frames) {
+                error!("Failed to record play time: {}", err);
             }
         }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error(mut data: &[u8]) -> String {
+        read_header(&mut data).unwrap_err()
+    }
 
-        const FIELDS: &'static [&'static str] = &["cpu", "mmu", "io", "ppu"];
-        deserializer.deserialize_struct("gba_rs::Gba", FIELDS, GbaVisitor)
+    #[test]
+    fn test_header() {
+        assert!(read_header(&mut &b"GBASTATE\x01\0\0\0"[..]).is_ok());
+        assert_eq!("not a save state", error(b"GBA"));
+        assert_eq!("not a save state", error(b"GBABNDL1\x01\0\0\0"));
+        assert!(error(b"GBASTATE\x00\0\0\0").contains("no longer supported"));
+        assert!(error(b"GBASTATE\x02\0\0\0").contains("newer version"));
     }
 }
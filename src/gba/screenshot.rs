@@ -0,0 +1,131 @@
+//! Screenshots of the PPU's output, written as 24-bit BMPs so they open
+//! anywhere without pulling in an image library.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+use gba_core::io::ppu::{COLS, FRAME_BYTES, ROWS};
+
+const HEADER_BYTES: u32 = 14 + 40;
+
+/// Encodes a frame from the PPU (little endian RGB888 u32s) as a BMP
+pub fn write_bmp<W: Write>(frame: &[u8], mut out: W) -> io::Result<()> {
+    let image_bytes = COLS * ROWS * 3;
+
+    // File header
+    out.write_all(b"BM")?;
+    out.write_u32::<LittleEndian>(HEADER_BYTES + image_bytes)?;
+    out.write_u32::<LittleEndian>(0)?;
+    out.write_u32::<LittleEndian>(HEADER_BYTES)?;
+
+    // BITMAPINFOHEADER, a negative height stores the rows top down
+    out.write_u32::<LittleEndian>(40)?;
+    out.write_i32::<LittleEndian>(COLS as i32)?;
+    out.write_i32::<LittleEndian>(-(ROWS as i32))?;
+    out.write_u16::<LittleEndian>(1)?;
+    out.write_u16::<LittleEndian>(24)?;
+    out.write_u32::<LittleEndian>(0)?;
+    out.write_u32::<LittleEndian>(image_bytes)?;
+    out.write_i32::<LittleEndian>(2835)?;
+    out.write_i32::<LittleEndian>(2835)?;
+    out.write_u32::<LittleEndian>(0)?;
+    out.write_u32::<LittleEndian>(0)?;
+
+    // 240 * 3 bytes is already a multiple of 4, so the rows need no padding.
+    // The pixels are stored B, G, R, X which is BMP's order minus the X.
+    let pixels: Vec<u8> = frame
+        .chunks(4)
+        .flat_map(|px| px[..3].iter().cloned())
+        .collect();
+    out.write_all(&pixels)
+}
+
+/// Decodes a 24-bit BMP of a whole frame back into the PPU's format, e.g.
+/// one `write_bmp` wrote.  Rows may be stored either way up.
+pub fn read_bmp<R: Read>(mut input: R) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    input
+        .read_to_end(&mut data)
+        .map_err(|err| err.to_string())?;
+    if data.len() < HEADER_BYTES as usize || &data[0..2] != b"BM" {
+        return Err("not a BMP".to_string());
+    }
+    let start = LittleEndian::read_u32(&data[10..14]) as usize;
+    let width = LittleEndian::read_i32(&data[18..22]);
+    let height = LittleEndian::read_i32(&data[22..26]);
+    let bits = LittleEndian::read_u16(&data[28..30]);
+    if width != COLS as i32 || height.abs() != ROWS as i32 || bits != 24 {
+        return Err(format!(
+            "{}x{} {} bit image, not {}x{} 24 bit",
+            width,
+            height.abs(),
+            bits,
+            COLS,
+            ROWS
+        ));
+    }
+    let row_bytes = COLS as usize * 3;
+    if data.len() < start + row_bytes * ROWS as usize {
+        return Err("image is cut short".to_string());
+    }
+    let mut frame = vec![0u8; FRAME_BYTES];
+    for (y, row) in frame.chunks_mut(COLS as usize * 4).enumerate() {
+        let y = if height < 0 { y } else { ROWS as usize - 1 - y };
+        let src = &data[start + y * row_bytes..start + (y + 1) * row_bytes];
+        for (px, bgr) in row.chunks_mut(4).zip(src.chunks(3)) {
+            px[..3].copy_from_slice(bgr);
+        }
+    }
+    Ok(frame)
+}
+
+pub fn save(frame: &[u8], path: &Path) {
+    match File::create(path).and_then(|f| write_bmp(frame, io::BufWriter::new(f))) {
+        Ok(_) => info!("Saved screenshot {}", path.display()),
+        Err(err) => error!("Failed to save screenshot {}: {}", path.display(), err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_bmp() {
+        let mut frame = vec![0u8; FRAME_BYTES];
+        frame[0..4].copy_from_slice(&[0x11, 0x22, 0x33, 0x00]);
+        let mut out = Vec::new();
+        write_bmp(&frame, &mut out).unwrap();
+
+        assert_eq!(b"BM", &out[0..2]);
+        assert_eq!(out.len(), (HEADER_BYTES + COLS * ROWS * 3) as usize);
+        let start = HEADER_BYTES as usize;
+        assert_eq!(&[0x11, 0x22, 0x33, 0x00], &out[start..start + 4]);
+    }
+
+    #[test]
+    fn test_read_bmp() {
+        let mut frame = vec![0u8; FRAME_BYTES];
+        frame[4..8].copy_from_slice(&[0x11, 0x22, 0x33, 0x00]);
+        frame[FRAME_BYTES - 4..].copy_from_slice(&[0x44, 0x55, 0x66, 0x00]);
+        let mut bmp = Vec::new();
+        write_bmp(&frame, &mut bmp).unwrap();
+        assert_eq!(Ok(frame.clone()), read_bmp(&bmp[..]));
+
+        // Flipped to bottom up
+        let rows: Vec<&[u8]> = bmp[HEADER_BYTES as usize..]
+            .chunks(COLS as usize * 3)
+            .rev()
+            .collect();
+        let mut flipped = bmp[..HEADER_BYTES as usize].to_vec();
+        LittleEndian::write_i32(&mut flipped[22..26], ROWS as i32);
+        flipped.extend(rows.concat());
+        assert_eq!(Ok(frame), read_bmp(&flipped[..]));
+
+        assert!(read_bmp(&bmp[..100]).is_err());
+        assert!(read_bmp(&b"GIF89a"[..]).is_err());
+    }
+}
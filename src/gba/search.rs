@@ -0,0 +1,156 @@
+//! RAM search, for finding where a game keeps a value to make a cheat of it.
+//!
+//! A search starts with every aligned value of one size in EWRAM and IWRAM as
+//! a candidate.  Each step after keeps the candidates that compare true
+//! against a number, or against what they held at the step before, so lives
+//! can be found by starting a search, losing a life and keeping the values
+//! less than before, and repeating until a few are left.
+
+use gba_core::rules::Cmp;
+
+use state_diff::memories;
+
+use super::compare::read;
+use super::*;
+
+/// Listings stop after this many candidates
+const MAX_RESULTS: usize = 1000;
+
+/// What the candidates are compared against
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Against {
+    Value(u32),
+    /// What the candidate held at the last step
+    Prev,
+}
+
+struct Region {
+    base: u32,
+    last: Vec<u8>,
+    /// Offsets still matching
+    candidates: Vec<usize>,
+}
+
+pub struct RamSearch {
+    size: usize,
+    regions: Vec<Region>,
+}
+
+impl RamSearch {
+    /// Starts a search for `size` byte values over `memories`, each the
+    /// address it's at and its contents
+    pub fn new(size: usize, memories: &[(u32, &[u8])]) -> Self {
+        let regions = memories
+            .iter()
+            .map(|&(base, data)| Region {
+                base: base,
+                last: data.to_vec(),
+                candidates: (0..data.len() / size).map(|i| i * size).collect(),
+            })
+            .collect();
+        RamSearch {
+            size: size,
+            regions: regions,
+        }
+    }
+
+    /// Keeps the candidates for which `cmp` holds between their value in
+    /// `memories` now and `against`
+    pub fn filter(&mut self, memories: &[(u32, &[u8])], cmp: Cmp, against: Against) {
+        let size = self.size;
+        for (region, &(_, data)) in self.regions.iter_mut().zip(memories.iter()) {
+            {
+                let last = &region.last;
+                region.candidates.retain(|&off| {
+                    let rhs = match against {
+                        Against::Value(value) => value,
+                        Against::Prev => read(last, off, size),
+                    };
+                    cmp.eval(read(data, off, size), rhs)
+                });
+            }
+            region.last.copy_from_slice(data);
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|region| region.candidates.len())
+            .sum()
+    }
+
+    /// The first candidates' addresses and the values they held at the last
+    /// step
+    pub fn results(&self) -> Vec<(u32, u32)> {
+        self.regions
+            .iter()
+            .flat_map(|region| {
+                region
+                    .candidates
+                    .iter()
+                    .map(move |&off| (region.base + off as u32, read(&region.last, off, self.size)))
+            })
+            .take(MAX_RESULTS)
+            .collect()
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// EWRAM and IWRAM, where games keep their variables
+    fn searched(&self) -> Vec<(u32, &[u8])> {
+        memories(&self.core)[..2]
+            .iter()
+            .map(|&(_, base, ram)| (base, ram.as_slice()))
+            .collect()
+    }
+
+    /// Starts a new search for `size` byte values, returning the candidates
+    pub(super) fn start_search(&mut self, size: usize) -> usize {
+        let search = RamSearch::new(size, &self.searched());
+        let count = search.count();
+        self.search = Some(search);
+        count
+    }
+
+    /// Narrows the search, returning how many candidates are left
+    pub(super) fn filter_search(
+        &mut self,
+        cmp: Cmp,
+        against: Against,
+    ) -> ::std::result::Result<usize, String> {
+        let mut search = self.search.take().ok_or("no search started")?;
+        search.filter(&self.searched(), cmp, against);
+        let count = search.count();
+        self.search = Some(search);
+        Ok(count)
+    }
+
+    pub(super) fn search_results(&self) -> ::std::result::Result<Vec<(u32, u32)>, String> {
+        Ok(self.search.as_ref().ok_or("no search started")?.results())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let mut ram = vec![0u8; 8];
+        ram[2] = 3;
+        ram[6] = 3;
+        let mut search = RamSearch::new(2, &[(0x0200_0000, &ram)]);
+        assert_eq!(4, search.count());
+
+        search.filter(&[(0x0200_0000, &ram)], Cmp::Eq, Against::Value(3));
+        assert_eq!(vec![(0x0200_0002, 3), (0x0200_0006, 3)], search.results());
+
+        // Only one goes down
+        ram[6] = 2;
+        search.filter(&[(0x0200_0000, &ram)], Cmp::Lt, Against::Prev);
+        assert_eq!(vec![(0x0200_0006, 2)], search.results());
+        search.filter(&[(0x0200_0000, &ram)], Cmp::Eq, Against::Prev);
+        assert_eq!(1, search.count());
+    }
+}
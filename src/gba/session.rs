@@ -0,0 +1,128 @@
+//! Keeps track of what happened while the emulator was open, and writes it
+//! out as an HTML page when it closes.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use stats::format_duration;
+
+/// A screenshot taken during the session, with the state it belongs to
+pub struct Shot {
+    pub label: String,
+    pub image: PathBuf,
+    pub state: Option<PathBuf>,
+    pub frame: u64,
+}
+
+pub struct Session {
+    start: Instant,
+    pub frames: u64,
+    pub shots: Vec<Shot>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            start: Instant::now(),
+            frames: 0,
+            shots: Vec::new(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now() - self.start
+    }
+
+    pub fn add_shot(&mut self, label: String, image: PathBuf, state: Option<PathBuf>) {
+        self.shots.push(Shot {
+            label: label,
+            image: image,
+            state: state,
+            frame: self.frames,
+        });
+    }
+
+    pub fn to_html(&self, title: &str, playtime: Duration) -> String {
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str(&format!("<title>{} session</title>\n", escape(title)));
+        html.push_str("</head>\n<body>\n");
+        html.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+        html.push_str(&format!(
+            "<p>Played for {}, {} frames emulated</p>\n",
+            format_duration(playtime),
+            self.frames
+        ));
+        html.push_str("<table>\n<tr><th>Screenshot</th><th>Frame</th><th>State</th></tr>\n");
+        for shot in self.shots.iter() {
+            let state = match shot.state {
+                Some(ref path) => escape(&file_name(path)),
+                None => String::new(),
+            };
+            html.push_str(&format!(
+                "<tr><td><img src=\"{}\" alt=\"{}\" width=\"240\" height=\"160\"></td>\
+                 <td>{}</td><td>{}</td></tr>\n",
+                escape(&file_name(&shot.image)),
+                escape(&shot.label),
+                shot.frame,
+                state
+            ));
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+
+    /// Writes the summary to `path`, screenshots are linked relative to it
+    pub fn write_html(&self, title: &str, path: &Path) {
+        let html = self.to_html(title, self.elapsed());
+        match File::create(path).and_then(|mut f| f.write_all(html.as_bytes())) {
+            Ok(_) => info!("Wrote session summary {}", path.display()),
+            Err(err) => error!("Failed to write {}: {}", path.display(), err),
+        }
+    }
+}
+
+// Everything the session writes sits beside the summary
+fn file_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_html() {
+        let mut session = Session::new();
+        session.frames = 120;
+        session.add_shot(
+            "State 3".to_string(),
+            PathBuf::from("saves/zelda3.bmp"),
+            Some(PathBuf::from("saves/zelda3.sav")),
+        );
+        let html = session.to_html("A<B", Duration::from_secs(90));
+        assert!(html.contains("<h1>A&lt;B</h1>"));
+        assert!(html.contains("Played for 0:01:30, 120 frames"));
+        assert!(html.contains("<img src=\"zelda3.bmp\""));
+        assert!(html.contains("<td>zelda3.sav</td>"));
+    }
+}
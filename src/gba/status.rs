@@ -0,0 +1,76 @@
+//! Periodic reports of what is being played, for anything that wants to show
+//! it elsewhere, e.g. a chat status.
+
+use super::*;
+
+/// A snapshot of the running game
+#[derive(Clone, Debug)]
+#[cfg_attr(not(feature = "discord"), allow(dead_code))]
+pub struct Status {
+    pub title: String,
+    /// Time since the emulator was started
+    pub elapsed: Duration,
+    /// Emulation speed over the last report, 1.0 is full speed
+    pub speed: f64,
+}
+
+pub type StatusCallback = Box<FnMut(&Status)>;
+
+pub struct StatusReporter {
+    callback: Option<StatusCallback>,
+    last: Instant,
+    last_frames: u64,
+}
+
+impl StatusReporter {
+    pub fn new() -> Self {
+        StatusReporter {
+            callback: None,
+            last: Instant::now(),
+            last_frames: 0,
+        }
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Calls `callback` about once a second while the frontend is running
+    #[cfg_attr(not(feature = "discord"), allow(dead_code))]
+    pub fn on_status(&mut self, callback: StatusCallback) {
+        self.status.callback = Some(callback);
+    }
+
+    /// The title from the ROM header, or a placeholder if it has none
+    pub(super) fn title(&self) -> String {
+        let title = self.core.mmu.rom.title();
+        if title.is_empty() {
+            "gba-rs".to_string()
+        } else {
+            title
+        }
+    }
+
+    pub(super) fn report_status(&mut self) {
+        if self.status.callback.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        let since = now - self.status.last;
+        if since < Duration::from_secs(1) {
+            return;
+        }
+
+        let frames = self.session.frames - self.status.last_frames;
+        let secs = since.as_secs() as f64 + since.subsec_nanos() as f64 * 1e-9;
+        let fps = CYCLES_PER_SEC as f64 / CYCLES_PER_FRAME as f64;
+        let status = Status {
+            title: self.title(),
+            elapsed: self.session.elapsed(),
+            speed: frames as f64 / secs / fps,
+        };
+        self.status.last = now;
+        self.status.last_frames = self.session.frames;
+        if let Some(ref mut callback) = self.status.callback {
+            callback(&status);
+        }
+    }
+}
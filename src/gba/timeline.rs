@@ -0,0 +1,59 @@
+//! Exports of the core's per-frame timeline of DMAs, interrupts and timer
+//! overflows as JSON, e.g. to plot as a strip chart against the scanlines.
+//!
+//! The file has the frame number and a list of marks, each with the cycle
+//! since the frame started, the scanline (0-227), the dot within it (0-307)
+//! and what happened:
+//!
+//! ```text
+//! {"frame":120,"marks":[{"cycle":197120,"line":160,"dot":0,"kind":{"Irq":0}},
+//!   {"cycle":197124,"line":160,"dot":1,"kind":{"Dma":{"channel":3,"units":64}}}]}
+//! ```
+
+use std::fs::File;
+use std::io::BufWriter;
+
+use serde_json;
+
+use gba_core::io::timeline::Mark;
+
+use super::*;
+
+#[derive(Serialize)]
+struct Export<'m> {
+    frame: u64,
+    marks: &'m [Mark],
+}
+
+impl<'a> Gba<'a> {
+    /// Records the timeline of the next whole frame
+    pub(super) fn record_timeline(&mut self) {
+        self.core.io.timeline.record_next();
+        self.show_message("Recording timeline".to_string());
+    }
+
+    /// Writes out a recorded timeline once its frame has finished
+    pub(super) fn check_timeline(&mut self) {
+        let marks = match self.core.io.timeline.take() {
+            Some(marks) => marks,
+            None => return,
+        };
+        // The frame that just finished
+        let frame = self.session.frames.saturating_sub(1);
+        let mut path = self.opts.save_file.to_os_string();
+        path.push(format!("timeline-{}.json", frame));
+        let export = Export {
+            frame: frame,
+            marks: &marks,
+        };
+        let res = File::create(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|f| {
+                serde_json::to_writer(BufWriter::new(f), &export).map_err(|err| err.to_string())
+            });
+        match res {
+            Ok(()) => self.show_message(format!("Saved timeline {:?}", path)),
+            Err(err) => error!("Failed to save timeline {:?}: {}", path, err),
+        }
+    }
+}
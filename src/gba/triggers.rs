@@ -0,0 +1,201 @@
+//! User defined triggers that pause emulation or save a state when the game
+//! reaches a certain point.
+//!
+//! Triggers are read from a file with one trigger per line:
+//!
+//! ```text
+//! # pause when the main loop is reached
+//! exec 0x08000abc pause
+//! # save a state whenever the lives counter hits zero
+//! mem8 0x03001234 == 0 save pause
+//! ```
+//!
+//! Memory conditions compare with `==`, `!=`, `<`, `>`, `<=` or `>=` and read
+//! with `mem8`, `mem16` or `mem32`.  A trigger fires when its condition goes
+//! from false to true, and its actions run at the end of that frame.
+
+use gba_core::rules::{parse_num, Cmp, Width};
+
+use super::*;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Condition {
+    /// The CPU is about to execute the instruction at this address
+    Exec(u32),
+    Mem {
+        addr: u32,
+        width: Width,
+        cmp: Cmp,
+        value: u32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    pub cond: Condition,
+    pub pause: bool,
+    pub save: bool,
+    /// Whether the condition held the last time it was checked
+    active: bool,
+    /// Set when the trigger fires, cleared once its actions are run
+    fired: bool,
+    hits: u32,
+}
+
+fn parse_line(line: &str) -> ::std::result::Result<Trigger, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (cond, rest) = match words[0] {
+        "exec" if words.len() >= 2 => (Condition::Exec(parse_num(words[1])?), &words[2..]),
+        "mem8" | "mem16" | "mem32" if words.len() >= 4 => {
+            let width = Width::parse(words[0]).unwrap();
+            let cmp =
+                Cmp::parse(words[2]).ok_or_else(|| format!("unknown comparison '{}'", words[2]))?;
+            let cond = Condition::Mem {
+                addr: parse_num(words[1])?,
+                width: width,
+                cmp: cmp,
+                value: parse_num(words[3])?,
+            };
+            (cond, &words[4..])
+        }
+        _ => return Err(format!("could not parse condition in '{}'", line)),
+    };
+
+    let mut trigger = Trigger {
+        cond: cond,
+        pause: false,
+        save: false,
+        active: false,
+        fired: false,
+        hits: 0,
+    };
+    for action in rest {
+        match *action {
+            "pause" => trigger.pause = true,
+            "save" => trigger.save = true,
+            _ => return Err(format!("unknown action '{}'", action)),
+        }
+    }
+    if !trigger.pause && !trigger.save {
+        return Err(format!("trigger has no actions: '{}'", line));
+    }
+    Ok(trigger)
+}
+
+/// Parses a trigger file, ignoring blank lines and `#` comments
+pub fn parse_triggers(text: &str) -> ::std::result::Result<Vec<Trigger>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.split('#').next().unwrap().trim()))
+        .filter(|&(_, line)| !line.is_empty())
+        .map(|(i, line)| parse_line(line).map_err(|err| format!("line {}: {}", i + 1, err)))
+        .collect()
+}
+
+impl Trigger {
+    fn update(&mut self, now: bool) {
+        if now && !self.active {
+            self.fired = true;
+            self.hits += 1;
+        }
+        self.active = now;
+    }
+}
+
+impl<'a> Gba<'a> {
+    /// Checks execution triggers, called every cycle
+    pub(super) fn check_exec_triggers(&mut self) {
+        let pc = self.core.cpu.get_prefetch_addr();
+        for trigger in self.triggers.iter_mut() {
+            if let Condition::Exec(addr) = trigger.cond {
+                trigger.update(addr == pc);
+            }
+        }
+    }
+
+    /// Checks memory triggers and runs the actions of everything that fired
+    /// during the frame
+    pub(super) fn run_triggers(&mut self) {
+        let mut pause = false;
+        for i in 0..self.triggers.len() {
+            if let Condition::Mem {
+                addr,
+                width,
+                cmp,
+                value,
+            } = self.triggers[i].cond
+            {
+                let cur = width.read(&self.core.mmu, addr);
+                self.triggers[i].update(cmp.eval(cur, value));
+            }
+
+            if !self.triggers[i].fired {
+                continue;
+            }
+            self.triggers[i].fired = false;
+            let trigger = self.triggers[i].clone();
+            info!("Trigger {} fired: {:?}", i, trigger.cond);
+            if trigger.save {
+                let mut path = self.opts.save_file.to_os_string();
+                path.push(format!("trigger{}-{}.state", i, trigger.hits));
+                self.save_state(&path);
+            }
+            pause |= trigger.pause;
+        }
+        if pause {
+            info!("Paused by trigger, press the pause hotkey to resume or frame step to advance");
+            self.paused = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let triggers = parse_triggers(
+            "# comment\n\
+             exec 0x08000abc pause\n\
+             \n\
+             mem16 0x03001234 >= 10 save pause # trailing\n",
+        )
+        .unwrap();
+        assert_eq!(2, triggers.len());
+        assert_eq!(Condition::Exec(0x0800_0abc), triggers[0].cond);
+        assert!(triggers[0].pause && !triggers[0].save);
+        assert_eq!(
+            Condition::Mem {
+                addr: 0x0300_1234,
+                width: Width::Half,
+                cmp: Cmp::Ge,
+                value: 10,
+            },
+            triggers[1].cond
+        );
+        assert!(triggers[1].pause && triggers[1].save);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_triggers("exec 0x0800zzzz pause").is_err());
+        assert!(parse_triggers("exec 0x08000000").is_err());
+        assert!(parse_triggers("mem8 0x0 ~= 1 save").is_err());
+        assert!(parse_triggers("jump 0x0 save").is_err());
+    }
+
+    #[test]
+    fn test_edge_triggered() {
+        let mut trigger = parse_triggers("exec 0 save").unwrap().remove(0);
+        trigger.update(true);
+        assert!(trigger.fired);
+        trigger.fired = false;
+        trigger.update(true);
+        assert!(!trigger.fired);
+        trigger.update(false);
+        trigger.update(true);
+        assert!(trigger.fired);
+        assert_eq!(2, trigger.hits);
+    }
+}
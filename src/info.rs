@@ -0,0 +1,102 @@
+//! `info`, which prints what a ROM's cartridge header says about it without
+//! running it.
+
+use std::path::Path;
+
+use gba_core::rom::Backup;
+
+use romfile::{self, Kind, LoadError};
+
+/// Publishers by maker code, the ones with many GBA games
+const MAKERS: [(&'static str, &'static str); 9] = [
+    ("01", "Nintendo"),
+    ("08", "Capcom"),
+    ("41", "Ubisoft"),
+    ("52", "Activision"),
+    ("69", "Electronic Arts"),
+    ("78", "THQ"),
+    ("A4", "Konami"),
+    ("AF", "Namco"),
+    ("B2", "Bandai"),
+];
+
+/// Regions by the game code's last letter
+const REGIONS: [(char, &'static str); 7] = [
+    ('J', "Japan"),
+    ('E', "USA"),
+    ('P', "Europe"),
+    ('D', "Germany"),
+    ('F', "France"),
+    ('I', "Italy"),
+    ('S', "Spain"),
+];
+
+fn backup_name(backup: Backup) -> &'static str {
+    match backup {
+        Backup::None => "none",
+        Backup::Eeprom => "EEPROM",
+        Backup::Sram => "SRAM (32K)",
+        Backup::Flash => "Flash (64K)",
+        Backup::Flash1M => "Flash (128K)",
+    }
+}
+
+/// `size` bytes in the largest unit it's a whole number of
+fn size_name(size: usize) -> String {
+    if size >= 1 << 20 && size % (1 << 20) == 0 {
+        format!("{}M", size >> 20)
+    } else if size >= 1 << 10 && size % (1 << 10) == 0 {
+        format!("{}K", size >> 10)
+    } else {
+        format!("{} bytes", size)
+    }
+}
+
+/// Lines describing the ROM at `path`
+pub fn describe(path: &Path) -> Result<Vec<String>, LoadError> {
+    let rom = romfile::load(path, Kind::Rom)?;
+    // The ROM loaded, so it has room for a header
+    let header = rom.header().unwrap();
+    let lookup =
+        |found: Option<&'static str>| found.map_or(String::new(), |name| format!(" ({})", name));
+    let maker = MAKERS
+        .iter()
+        .find(|&&(code, _)| code == header.maker_code)
+        .map(|&(_, name)| name);
+    let region = header.game_code.chars().nth(3).and_then(|letter| {
+        REGIONS
+            .iter()
+            .find(|&&(code, _)| code == letter)
+            .map(|&(_, name)| name)
+    });
+    Ok(vec![
+        format!("Title:      {}", header.title),
+        format!("Game code:  {}{}", header.game_code, lookup(region)),
+        format!("Maker:      {}{}", header.maker_code, lookup(maker)),
+        format!("Version:    {}", header.version),
+        format!(
+            "Checksum:   {:#04x} ({})",
+            header.checksum,
+            if header.checksum_valid {
+                "valid"
+            } else {
+                "invalid"
+            }
+        ),
+        format!("Save type:  {}", backup_name(rom.backup())),
+        format!("ROM size:   {} ({} bytes)", size_name(rom.len()), rom.len()),
+        format!("CRC-32:     {:08x}", rom.crc32()),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_size_name() {
+        assert_eq!("16M", size_name(16 << 20));
+        assert_eq!("192K", size_name(192 << 10));
+        assert_eq!("1000 bytes", size_name(1000));
+    }
+}
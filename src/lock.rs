@@ -0,0 +1,191 @@
+//! Advisory lock files, so two instances running the same game can't
+//! overwrite each other's saves.
+//!
+//! A lock is a hidden file beside the saves holding the owner's process ID.
+//! The ID is written to a temporary file first and linked into place, so a
+//! lock never exists without its owner.  Locks are removed when the owner
+//! exits, and one left behind by an instance that was killed is taken over
+//! when the owner is known to be gone, which is only checked where `/proc`
+//! exists; elsewhere the file has to be deleted by hand.
+//!
+//! Locks that can't be created, e.g. beside ROMs on read-only media, are
+//! warned about and the saves used unlocked.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Instances of the same game that get their own save prefix
+const MAX_INSTANCES: u32 = 8;
+
+pub struct Lock {
+    path: PathBuf,
+}
+
+/// Whether a lock file containing `contents` was left behind by a process
+/// that isn't running
+fn stale<F: Fn(u32) -> bool>(contents: &str, alive: F) -> bool {
+    match contents.trim().parse() {
+        Ok(pid) => !alive(pid),
+        // Not written by a lock, so nothing can say it's safe to take over
+        Err(_) => false,
+    }
+}
+
+fn alive(pid: u32) -> bool {
+    if Path::new("/proc/self").exists() {
+        Path::new(&format!("/proc/{}", pid)).exists()
+    } else {
+        true
+    }
+}
+
+impl Lock {
+    /// Takes the lock file at `path`, or returns None if a running instance
+    /// holds it
+    pub fn acquire(path: &Path) -> io::Result<Option<Lock>> {
+        let temp = with_suffix(path, &format!(".{}", process::id()));
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)
+            .and_then(|mut file| write!(file, "{}", process::id()))?;
+        let taken = Lock::take(&temp, path);
+        if let Err(err) = fs::remove_file(&temp) {
+            warn!("Failed to remove {}: {}", temp.display(), err);
+        }
+        taken
+    }
+
+    /// Links the already written `temp` to `path` if no running instance
+    /// holds it
+    fn take(temp: &Path, path: &Path) -> io::Result<Option<Lock>> {
+        // Once to take over a stale lock, and again in case another
+        // instance took it over first
+        for _ in 0..2 {
+            // Fails instead of replacing a lock another instance holds
+            match fs::hard_link(temp, path) {
+                Ok(()) => {
+                    return Ok(Some(Lock {
+                        path: path.to_path_buf(),
+                    }));
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+                Err(err) => return Err(err),
+            }
+
+            let mut contents = String::new();
+            File::open(path)?.read_to_string(&mut contents)?;
+            if !stale(&contents, alive) {
+                return Ok(None);
+            }
+            warn!("Taking over {}, its owner has exited", path.display());
+            fs::remove_file(path)?;
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            error!("Failed to remove lock {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path: OsString = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// The lock for the files under `prefix`, hidden so it isn't one of them
+fn prefix_lock(prefix: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(prefix.file_name().unwrap_or_else(|| OsStr::new("save")));
+    name.push("lock");
+    prefix.with_file_name(name)
+}
+
+/// Locks the save prefix that states and other files are written under.  If
+/// other instances have it, a prefix with an instance number is locked
+/// instead so this instance's states are kept apart.
+pub fn lock_prefix(prefix: &Path) -> Result<(PathBuf, Option<Lock>), String> {
+    for instance in 1..MAX_INSTANCES + 1 {
+        let candidate = match instance {
+            1 => prefix.to_path_buf(),
+            _ => with_suffix(prefix, &format!("instance{}-", instance)),
+        };
+        let lock_path = prefix_lock(&candidate);
+        match Lock::acquire(&lock_path) {
+            Ok(Some(lock)) => {
+                if instance > 1 {
+                    warn!(
+                        "Another instance is using {}, saving states under {} instead",
+                        prefix.display(),
+                        candidate.display()
+                    );
+                }
+                return Ok((candidate, Some(lock)));
+            }
+            Ok(None) => (),
+            Err(err) => {
+                warn!("Could not lock {}: {}", lock_path.display(), err);
+                return Ok((candidate, None));
+            }
+        }
+    }
+    Err(format!(
+        "{} instances are already using {}",
+        MAX_INSTANCES,
+        prefix.display()
+    ))
+}
+
+/// Locks a battery save, along with whether this instance may write it,
+/// which it mustn't if another instance has it
+pub fn lock_battery(path: &Path) -> (Option<Lock>, bool) {
+    let lock_path = with_suffix(path, ".lock");
+    match Lock::acquire(&lock_path) {
+        Ok(Some(lock)) => (Some(lock), true),
+        Ok(None) => {
+            warn!(
+                "Another instance is using {}, this one won't write to it",
+                path.display()
+            );
+            (None, false)
+        }
+        Err(err) => {
+            warn!("Could not lock {}: {}", lock_path.display(), err);
+            (None, true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stale() {
+        let alive = |pid| pid == 10;
+        assert!(!stale("10", alive));
+        assert!(!stale("10\n", alive));
+        assert!(stale("11", alive));
+        assert!(!stale("", alive));
+        assert!(!stale("garbage", alive));
+    }
+
+    #[test]
+    fn test_prefix_lock() {
+        assert_eq!(
+            Path::new("games/.zelda-lock"),
+            prefix_lock(Path::new("games/zelda-"))
+        );
+        assert_eq!(Path::new(".save-lock"), prefix_lock(Path::new("save-")));
+    }
+}
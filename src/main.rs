@@ -1,36 +1,67 @@
-extern crate arm7tdmi_rs;
-extern crate arraydeque;
 extern crate bincode;
 extern crate byteorder;
 extern crate clap;
 #[macro_use]
 extern crate log;
 extern crate env_logger;
-extern crate memmap;
+extern crate gba_core;
 extern crate sdl2;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
 extern crate zstd;
 
+#[cfg(feature = "discord")]
+extern crate discord_rpc_client;
+#[cfg(feature = "retroachievements")]
+extern crate md5;
+#[cfg(feature = "http-server")]
+extern crate tiny_http;
+#[cfg(feature = "retroachievements")]
+extern crate ureq;
+
 extern crate flame;
 
 use std::default::Default;
-use std::error::Error;
+use std::ffi::OsStr;
 use std::fs::File;
-use std::path::Path;
+use std::io::{BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-mod bit_util;
-mod shared;
+use gba_core::cpu::breakpoint::{BreakEvent, Breakpoint};
+use gba_core::cpu::trace::{TraceFilter, Tracer};
+use gba_core::mmu::watch::Watchpoint;
+use gba_core::{rom, rules};
 
-mod cpu;
-mod io;
-mod mmu;
-mod rom;
+use settings::Settings;
 
+mod bench;
+mod bundle;
+mod config;
+mod determinism;
+mod disasm;
+#[cfg(feature = "discord")]
+mod discord;
+mod games;
 mod gba;
+mod info;
+mod lock;
+mod profile;
+#[cfg(feature = "retroachievements")]
+mod retro;
+mod romfile;
+mod screenshot_test;
+mod selftest;
+#[cfg(feature = "http-server")]
+mod server;
+mod settings;
+mod state_diff;
+mod stats;
 
 fn main() {
     env_logger::init();
@@ -38,33 +69,159 @@ fn main() {
     use GBAError::*;
     match run_emu() {
         Ok(_) => {}
-        Err(errcode) => match errcode {
-            RomLoadError(err) => println!("ROM failed to load: {:?}", err),
-        },
+        Err(errcode) => {
+            match errcode {
+                LoadError(err) => println!("{}", err),
+                MultibootTooLarge(size) => println!(
+                    "Multiboot image is {} bytes, but must fit in the 256KB of EWRAM",
+                    size
+                ),
+                TriggerLoadError(err) => println!("Triggers failed to load: {}", err),
+                RulesLoadError(err) => println!("Achievement rules failed to load: {}", err),
+                CheatsLoadError(err) => println!("Cheats failed to load: {}", err),
+                BadBreakpoint(err) => println!("Breakpoint is invalid: {}", err),
+                BadWatchpoint(err) => println!("Watchpoint is invalid: {}", err),
+                EmulationStopped(reason) => println!("Emulation stopped: {}", reason),
+                ProfileError(err) => println!("Save profile failed to load: {}", err),
+                BundleError(err) => println!("Save bundle failed: {}", err),
+                StatsError(err) => println!("Stats failed to load: {}", err),
+                StateDiffError(err) => println!("State diff failed: {}", err),
+                LockError(err) => println!("Saves could not be locked: {}", err),
+                GameConfigError(err) => println!("Game settings failed to load: {}", err),
+                SelftestFailed(count) => println!("{} self-test checks failed", count),
+                ConfigError(err) => println!("Config file failed to load: {}", err),
+                PipeError(err) => println!("Output pipe failed to open: {}", err),
+                TraceError(err) => println!("Trace failed to open: {}", err),
+                Nondeterministic(err) => println!("Determinism check failed: {}", err),
+                ScreenshotMismatch(err) => println!("Screenshot test failed: {}", err),
+                #[cfg(feature = "retroachievements")]
+                RetroError(err) => println!("RetroAchievements failed to load: {}", err),
+                #[cfg(feature = "http-server")]
+                ServerError(err) => println!("Control server failed to start: {}", err),
+            }
+            // So scripts, and packagers running the self-test, see the failure
+            std::process::exit(1);
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum GBAError {
-    RomLoadError(std::io::Error),
+    LoadError(romfile::LoadError),
+    MultibootTooLarge(usize),
+    TriggerLoadError(String),
+    RulesLoadError(String),
+    CheatsLoadError(String),
+    BadBreakpoint(String),
+    BadWatchpoint(String),
+    EmulationStopped(String),
+    ProfileError(String),
+    BundleError(String),
+    StatsError(String),
+    StateDiffError(String),
+    LockError(String),
+    GameConfigError(String),
+    SelftestFailed(usize),
+    ConfigError(String),
+    PipeError(String),
+    TraceError(String),
+    Nondeterministic(String),
+    ScreenshotMismatch(String),
+    #[cfg(feature = "retroachievements")]
+    RetroError(String),
+    #[cfg(feature = "http-server")]
+    ServerError(String),
 }
 
 pub type Result<T> = std::result::Result<T, GBAError>;
 
+const MULTIBOOT_MAX: usize = 256 * 1024;
+
 fn run_emu() -> Result<()> {
-    let app_m = App::new("gba-rs")
+    let app = App::new("gba-rs")
         .version("0.1")
         .about("Bad GBA Emulator")
         .author("Sean Purcell")
         .arg(
             Arg::with_name("bios")
-                .required(true)
-                .help("GBA bios rom to use"),
+                .help("GBA bios rom to use, can be left out if the config file sets it"),
         )
         .arg(
             Arg::with_name("rom")
-                .required(true)
-                .help("ROM file to emulate"),
+                .help("ROM file to emulate, a ZIP archive holding one, or an ELF executable"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("file")
+                .help("Settings to use when flags aren't given, by default config.toml in the config directory"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .takes_value(true)
+                .value_name("factor")
+                .default_value("3")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(scale) if scale >= 1 => Ok(()),
+                    _ => Err("must be a whole number from 1".to_string()),
+                })
+                .help("The window's size in multiples of the GBA screen"),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .possible_values(&gba::FILTERS)
+                .default_value("nearest")
+                .help("How the screen is scaled up, with sharp or smoothed pixels"),
+        )
+        .arg(
+            Arg::with_name("integer-scale")
+                .long("integer-scale")
+                .help("Only scale the screen by whole numbers, so every pixel is the same size"),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .takes_value(true)
+                .value_name("level")
+                .default_value("1")
+                .validator(|s| match s.parse::<f32>() {
+                    Ok(volume) if volume >= 0.0 && volume <= 1.0 => Ok(()),
+                    _ => Err("must be from 0 to 1".to_string()),
+                })
+                .help("Audio volume"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Start filling the screen, Alt+Enter or F11 switches back"),
+        )
+        .arg(
+            Arg::with_name("show-fps")
+                .long("show-fps")
+                .help("Show the frame rate and emulation speed in the corner, F3 toggles it"),
+        )
+        .arg(
+            Arg::with_name("post-filter")
+                .long("post-filter")
+                .takes_value(true)
+                .value_name("filters")
+                .default_value("none")
+                .validator(|s| gba::post::validate(&s))
+                .help("Filters to run over the screen, joined by +: lcd corrects colours to look like the GBA's screen, ghosting mixes each frame with the last like its slow LCD. F4 steps through them"),
+        )
+        .arg(
+            Arg::with_name("frame-blend")
+                .long("frame-blend")
+                .help("Enhancement: mix frames together in games that update every other frame, for smoother motion"),
+        )
+        .arg(
+            Arg::with_name("no-audio")
+                .long("no-audio")
+                .help("Don't open an audio device"),
         )
         .arg(
             Arg::with_name("profile")
@@ -87,25 +244,117 @@ fn run_emu() -> Result<()> {
                 .default_value("true")
                 .help("If true, limits the frame-rate to the GBA frame rate (~60fps)"),
         )
+        .arg(
+            Arg::with_name("speed")
+                .long("speed")
+                .takes_value(true)
+                .value_name("factor")
+                .default_value("1")
+                .validator(|s| gba::validate_speed(&s))
+                .help("Emulation speed, from 0.25 to 16, changed while running with - and ="),
+        )
+        .arg(
+            Arg::with_name("fast-forward")
+                .long("fast-forward")
+                .takes_value(true)
+                .value_name("factor")
+                .default_value("4")
+                .validator(|s| gba::validate_speed(&s))
+                .help("Emulation speed while Tab is held"),
+        )
         .arg(
             Arg::with_name("breakpoints")
                 .short("b")
                 .long("breaks")
+                .alias("break")
                 .required(false)
                 .takes_value(true)
                 .multiple(true)
                 .use_delimiter(true)
-                .validator(|s| match u32::from_str_radix(s.as_str(), 16) {
-                    Ok(_) => Ok(()),
-                    Err(err) => Err(err.description().to_string()),
-                })
-                .help("A list of addresses to warn when the CPU hits"),
+                .help(
+                    "A list of addresses, in hex, or with symbols function names, to stop the \
+                     CPU at.  A condition can follow, e.g. '8000f10 if r0 == 0x40 && hits > 3'",
+                ),
+        )
+        .arg(
+            Arg::with_name("break-events")
+                .long("break-on")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .help(
+                    "A list of events to stop the CPU after: irq, swi, vblank or dma, or one \
+                     interrupt, SWI or channel like irq:timer0, swi:0x0b or dma:3",
+                ),
+        )
+        .arg(
+            Arg::with_name("watches")
+                .long("watch")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .help(
+                    "A list of memory to log the accesses to, e.g. '3001234+4' or \
+                     'player_hp rw break' to stop after reads or writes",
+                ),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .value_name("file")
+                .help("Write each instruction run, with the registers it changed, to this file"),
+        )
+        .arg(
+            Arg::with_name("trace-ranges")
+                .long("trace-range")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .requires("trace")
+                .help("Only trace instructions in these ranges, e.g. 8000000-80001ff, or functions named by symbols"),
+        )
+        .arg(
+            Arg::with_name("trace-limit")
+                .long("trace-limit")
+                .takes_value(true)
+                .value_name("count")
+                .requires("trace")
+                .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Stop tracing after this many instructions"),
+        )
+        .arg(
+            Arg::with_name("compare-trace")
+                .long("compare-trace")
+                .takes_value(true)
+                .value_name("file")
+                .help("Compare the registers before each instruction with a reference emulator's trace, stopping where they differ"),
+        )
+        .arg(
+            Arg::with_name("coverage")
+                .long("coverage")
+                .help("Count the instructions run by ARM and Thumb format and mnemonic, and print them on exit"),
+        )
+        .arg(
+            Arg::with_name("cdl")
+                .long("cdl")
+                .takes_value(true)
+                .value_name("file")
+                .help("Log which ROM bytes run as ARM or Thumb code and which are read as data to this file, adding to it if it exists"),
+        )
+        .arg(
+            Arg::with_name("debugger")
+                .long("debugger")
+                .help(
+                    "Stop at a debugger console on stdin at breakpoints, or when F12 is \
+                     pressed",
+                ),
         )
         .arg(
             Arg::with_name("step-frames")
                 .short("S")
                 .long("step")
-                .help("Step through the frames step by step with the F key"),
+                .help("Start paused, to advance a frame at a time with the frame step hotkey (F)"),
         )
         .arg(
             Arg::with_name("quiet")
@@ -121,22 +370,422 @@ fn run_emu() -> Result<()> {
                 .help("Boot directly to the ROM instead of booting the BIOS"),
         )
         .arg(
-            Arg::with_name("save-file")
-                .short("s")
-                .long("save")
+            Arg::with_name("multiboot")
+                .short("m")
+                .long("multiboot")
+                .help(
+                    "Treat the ROM as a multiboot image to run from EWRAM (implied by .mb files)",
+                ),
+        )
+        .arg(save_file_arg())
+        .arg(save_profile_arg())
+        .arg(
+            Arg::with_name("state-compression")
+                .long("state-compression")
+                .takes_value(true)
+                .value_name("level")
+                .default_value("3")
+                .validator(|s| match s.parse::<i32>() {
+                    Ok(level) if level >= 1 && level <= 19 => Ok(()),
+                    _ => Err("must be a zstd level from 1 to 19".to_string()),
+                })
+                .help("How hard to compress save states, higher is smaller but slower"),
+        )
+        .arg(battery_arg())
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("Save the state on exit and pick up from it on the next launch"),
+        )
+        .arg(
+            Arg::with_name("triggers")
+                .short("t")
+                .long("triggers")
+                .required(false)
+                .takes_value(true)
+                .value_name("file")
+                .help("A file of triggers that pause or save a state on game events"),
+        )
+        .arg(
+            Arg::with_name("achievements")
+                .long("achievements")
+                .required(false)
+                .takes_value(true)
+                .value_name("file")
+                .help("A file of memory rules to announce when they become true"),
+        )
+        .arg(
+            Arg::with_name("cheats")
+                .long("cheats")
+                .required(false)
+                .takes_value(true)
+                .value_name("file")
+                .help("A file of GameShark, Action Replay or CodeBreaker codes, read again when it changes. F2 turns them off and on"),
+        )
+        .arg(
+            Arg::with_name("patch")
+                .long("patch")
+                .required(false)
+                .takes_value(true)
+                .value_name("file")
+                .help("An IPS, UPS or BPS patch to apply to the ROM, by default one named like it beside it"),
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .long("symbols")
+                .required(false)
+                .takes_value(true)
+                .value_name("file")
+                .help("A .sym or .map file naming addresses in traces and breakpoints, by default one named like the ROM beside it"),
+        )
+        .arg(
+            Arg::with_name("rom-patches")
+                .long("rom-patch")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .value_name("addr:value")
+                .validator(|s| rom::RomPatch::parse(&s).map(|_| ()))
+                .help("Patch ROM reads at a hex address with a 2 or 4 hex digit value"),
+        )
+        .arg(
+            Arg::with_name("video-pipe")
+                .long("video-pipe")
+                .takes_value(true)
+                .value_name("path")
+                .help("Write every frame to this file or named pipe, or - for stdout, to pipe into an encoder"),
+        )
+        .arg(
+            Arg::with_name("video-format")
+                .long("video-format")
+                .takes_value(true)
+                .possible_values(&gba::pipe::FORMATS)
+                .default_value("y4m")
+                .help("What --video-pipe writes, a YUV4MPEG2 stream or headerless RGB24 frames"),
+        )
+        .arg(
+            Arg::with_name("audio-pipe")
+                .long("audio-pipe")
+                .takes_value(true)
+                .value_name("path")
+                .help("Write the audio as 32768 Hz stereo s16le PCM to this file or named pipe, or - for stdout"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .value_name("file")
+                .help("Record the game with sound to this mp4 or mkv file through ffmpeg, until F6 or exit"),
+        )
+        .arg(
+            Arg::with_name("deterministic")
+                .long("deterministic")
+                .help("Leave out everything from the host but the input: saves from earlier runs, and features that read the clock. The same input then always plays out the same"),
+        )
+        .arg(
+            Arg::with_name("ram-fill")
+                .long("ram-fill")
+                .takes_value(true)
+                .value_name("byte")
+                .validator(|s| match rules::parse_num(&s) {
+                    Ok(byte) if byte <= 0xff => Ok(()),
+                    _ => Err("must be a byte, e.g. 0xff".to_string()),
+                })
+                .help("Start work RAM filled with this byte rather than zeroes, to find games reading it before writing"),
+        )
+        .arg(
+            Arg::with_name("determinism-check")
+                .long("determinism-check")
+                .takes_value(true)
+                .value_name("frames")
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("Run the game twice with the same input for this many frames, failing if they ever differ"),
+        )
+        .arg(
+            Arg::with_name("bench")
+                .long("bench")
+                .takes_value(true)
+                .value_name("frames")
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("Run this many frames as fast as possible without video or sound, then print how fast it was"),
+        )
+        .arg(
+            Arg::with_name("screenshot-test")
+                .long("screenshot-test")
+                .takes_value(true)
+                .value_name("frames")
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("Run for this many frames without input, failing if the last frame differs from the golden screenshot"),
+        )
+        .arg(
+            Arg::with_name("golden")
+                .long("golden")
+                .takes_value(true)
+                .value_name("file")
+                .requires("screenshot-test")
+                .help("The golden BMP for --screenshot-test, by default the ROM's path ending in .golden.bmp"),
+        )
+        .arg(
+            Arg::with_name("bless")
+                .long("bless")
+                .requires("screenshot-test")
+                .help("Save the last frame of --screenshot-test as the golden instead of comparing them"),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .required(false)
+                .takes_value(true)
+                .value_name("frames")
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("Run for this many frames without opening a window, then exit"),
+        )
+        .arg(
+            Arg::with_name("sio-loopback")
+                .long("sio-loopback")
+                .help("Loop the serial port back on itself instead of leaving it disconnected"),
+        )
+        .arg(Arg::with_name("flashcart").long("flashcart").help(
+            "Make the cartridge behave like a flashcart, for studying games that detect them",
+        ))
+        .arg(
+            Arg::with_name("ewram-size")
+                .long("ewram-size")
+                .takes_value(true)
+                .value_name("KB")
+                .validator(|s| validate_ram_kb(&s, 256))
+                .help("Development: enlarge EWRAM past the hardware's 256K"),
+        )
+        .arg(
+            Arg::with_name("debug-ram")
+                .long("debug-ram")
+                .takes_value(true)
+                .value_name("KB")
+                .validator(|s| validate_ram_kb(&s, 1))
+                .help("Development: map extra RAM at 0x01000000, which hardware doesn't have"),
+        )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("export-bundle")
+                .about("Pack the saves for a ROM into a single file to move to another machine")
+                .arg(
+                    Arg::with_name("rom")
+                        .required(true)
+                        .help("ROM the saves are for"),
+                )
+                .arg(
+                    Arg::with_name("bundle")
+                        .required(true)
+                        .help("Bundle file to write"),
+                )
+                .arg(save_file_arg())
+                .arg(save_profile_arg())
+                .arg(battery_arg())
+                .arg(bundle_cheats_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("import-bundle")
+                .about("Unpack a save bundle made by export-bundle")
+                .arg(
+                    Arg::with_name("rom")
+                        .required(true)
+                        .help("ROM the saves are for"),
+                )
+                .arg(
+                    Arg::with_name("bundle")
+                        .required(true)
+                        .help("Bundle file to read"),
+                )
+                .arg(save_file_arg())
+                .arg(save_profile_arg())
+                .arg(battery_arg())
+                .arg(bundle_cheats_arg())
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrite saves that already exist"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Print a ROM, or part of it, as ARM or Thumb assembly")
+                .arg(Arg::with_name("rom").required(true).help("ROM to disassemble"))
+                .arg(
+                    Arg::with_name("thumb")
+                        .long("thumb")
+                        .short("t")
+                        .help("Disassemble as Thumb rather than ARM"),
+                )
+                .arg(
+                    Arg::with_name("start")
+                        .long("start")
+                        .takes_value(true)
+                        .value_name("addr")
+                        .default_value("08000000")
+                        .validator(|s| match u32::from_str_radix(s.as_str(), 16) {
+                            Ok(_) => Ok(()),
+                            Err(err) => Err(err.to_string()),
+                        })
+                        .help("Address to start at, in hex"),
+                )
+                .arg(
+                    Arg::with_name("len")
+                        .long("len")
+                        .takes_value(true)
+                        .value_name("bytes")
+                        .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|err| err.to_string()))
+                        .help("How many bytes to disassemble, rather than to the end of the ROM"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show what a ROM's cartridge header says about it")
+                .arg(Arg::with_name("rom").required(true).help("ROM to describe")),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Show play time for each game")
+                .arg(Arg::with_name("rom").help("Only show this ROM")),
+        )
+        .subcommand(
+            SubCommand::with_name("state-diff")
+                .about("Show which components and memory ranges differ between two save states")
+                .arg(Arg::with_name("a").required(true).help("First save state"))
+                .arg(Arg::with_name("b").required(true).help("Second save state")),
+        )
+        .subcommand(
+            SubCommand::with_name("selftest")
+                .about("Check the CPU, PPU and SPU work, without a BIOS or ROM"),
+        );
+    #[cfg(feature = "retroachievements")]
+    let app = app
+        .arg(
+            Arg::with_name("ra-user")
+                .long("ra-user")
                 .required(false)
                 .takes_value(true)
-                .default_value("save")
-                .help("The save file prefix to save to"),
+                .value_name("name")
+                .requires("ra-token")
+                .help("RetroAchievements user name, enables achievements"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("ra-token")
+                .long("ra-token")
+                .required(false)
+                .takes_value(true)
+                .value_name("token")
+                .requires("ra-user")
+                .help("RetroAchievements API token for --ra-user"),
+        );
+    #[cfg(feature = "discord")]
+    let app = app.arg(
+        Arg::with_name("discord")
+            .long("discord")
+            .required(false)
+            .takes_value(true)
+            .value_name("app-id")
+            .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+            .conflicts_with("deterministic")
+            .help("Show the game as Discord Rich Presence using this application id"),
+    );
+    #[cfg(feature = "http-server")]
+    let app = app.arg(
+        Arg::with_name("http")
+            .long("http")
+            .required(false)
+            .takes_value(true)
+            .value_name("addr:port")
+            .help("Serve the HTTP control API on this address, e.g. 127.0.0.1:8080"),
+    );
+    #[cfg(feature = "http-server")]
+    let app = app
+        .arg(
+            Arg::with_name("crowd")
+                .long("crowd")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&["democracy", "anarchy"])
+                .requires("http")
+                .conflicts_with("deterministic")
+                .help("Play with keys voted for through the control server's /vote"),
+        )
+        .arg(
+            Arg::with_name("vote-window")
+                .long("vote-window")
+                .required(false)
+                .takes_value(true)
+                .value_name("frames")
+                .default_value("60")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("How many frames democracy votes are counted over"),
+        )
+        .arg(
+            Arg::with_name("rewind")
+                .long("rewind")
+                .required(false)
+                .takes_value(true)
+                .value_name("frames")
+                .validator(|s| {
+                    s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Keep checkpoints of this many frames for the control server's /reverse"),
+        );
+    let app_m = app.get_matches();
 
     for _ in 0..app_m.occurrences_of("quiet") {
         info!("Reduce logging");
         reduce_logging();
     }
 
-    let res = run_gba(&app_m);
+    let res = match app_m.subcommand() {
+        ("export-bundle", Some(sub_m)) => run_bundle(&app_m, sub_m, false),
+        ("import-bundle", Some(sub_m)) => run_bundle(&app_m, sub_m, true),
+        ("disasm", Some(sub_m)) => disasm::disassemble(
+            Path::new(sub_m.value_of_os("rom").unwrap()),
+            u32::from_str_radix(sub_m.value_of("start").unwrap(), 16).unwrap(),
+            sub_m.value_of("len").map(|len| len.parse().unwrap()),
+            sub_m.is_present("thumb"),
+        )
+        .map(|lines| {
+            for line in lines {
+                println!("{}", line);
+            }
+        })
+        .map_err(GBAError::LoadError),
+        ("info", Some(sub_m)) => info::describe(Path::new(sub_m.value_of_os("rom").unwrap()))
+            .map(|lines| {
+                for line in lines {
+                    println!("{}", line);
+                }
+            })
+            .map_err(GBAError::LoadError),
+        ("stats", Some(sub_m)) => run_stats(sub_m),
+        ("state-diff", Some(sub_m)) => state_diff::run(
+            Path::new(sub_m.value_of_os("a").unwrap()),
+            Path::new(sub_m.value_of_os("b").unwrap()),
+        )
+        .map_err(GBAError::StateDiffError),
+        ("selftest", Some(_)) => match selftest::run() {
+            0 => Ok(()),
+            failed => Err(GBAError::SelftestFailed(failed)),
+        },
+        _ => run_gba(&app_m),
+    };
 
     match app_m.value_of("profile") {
         Some("html") => flame::dump_html(&mut File::create("flame-graph.html").unwrap()).unwrap(),
@@ -149,32 +798,495 @@ fn run_emu() -> Result<()> {
     res
 }
 
+fn load_settings(app_m: &ArgMatches) -> Result<Settings> {
+    settings::load(app_m.value_of_os("config").map(Path::new)).map_err(GBAError::ConfigError)
+}
+
+/// A flag's value from the command line, then the config file, then the
+/// flag's default
+fn setting<T: FromStr>(app_m: &ArgMatches, name: &str, config: Option<T>) -> T {
+    match (app_m.occurrences_of(name), config) {
+        (0, Some(val)) => val,
+        _ => match app_m.value_of(name).unwrap().parse() {
+            Ok(val) => val,
+            Err(_) => unreachable!("{} was validated", name),
+        },
+    }
+}
+
+/// Like `setting`, for flags without a default
+fn optional<T: FromStr>(app_m: &ArgMatches, name: &str, config: Option<T>) -> Option<T> {
+    match app_m.value_of(name) {
+        Some(val) => val.parse().ok(),
+        None => config,
+    }
+}
+
 fn run_gba(app_m: &ArgMatches) -> Result<()> {
-    let bios_path = Path::new(app_m.value_of_os("bios").unwrap());
-    let game_path = Path::new(app_m.value_of_os("rom").unwrap());
+    let settings = load_settings(app_m)?;
+    // With the BIOS in the config file only the ROM has to be given, which
+    // clap sees as the first argument
+    let (bios_path, game_path) = match (app_m.value_of_os("bios"), app_m.value_of_os("rom")) {
+        (Some(bios), Some(rom)) => (PathBuf::from(bios), PathBuf::from(rom)),
+        (Some(rom), None) => match settings.bios {
+            Some(ref bios) => (bios.clone(), PathBuf::from(rom)),
+            None => {
+                return Err(GBAError::ConfigError(
+                    "no BIOS given, pass one before the ROM or set bios in the config file"
+                        .to_string(),
+                ))
+            }
+        },
+        (None, _) => {
+            return Err(GBAError::ConfigError(
+                "no ROM given, run with --help for usage".to_string(),
+            ))
+        }
+    };
+    let game_path = game_path.as_path();
 
-    let bios = rom::GameRom::new(&bios_path)?;
-    let rom = rom::GameRom::new(&game_path)?;
+    let bios = romfile::load(&bios_path, romfile::Kind::Bios).map_err(GBAError::LoadError)?;
+    // ELF files are already built for where they run, and bring symbols
+    let (rom, elf) = if romfile::is_elf(game_path) {
+        let (rom, elf) = romfile::load_elf(game_path).map_err(GBAError::LoadError)?;
+        (rom, Some(elf))
+    } else {
+        let rom = romfile::load(&game_path, romfile::Kind::Rom)
+            .and_then(|rom| {
+                romfile::patch(rom, game_path, app_m.value_of_os("patch").map(Path::new))
+            })
+            .map_err(GBAError::LoadError)?;
+        (rom, None)
+    };
+    let symbols_path = app_m.value_of_os("symbols").map(Path::new);
+    let symbols = match elf {
+        Some(ref elf) if symbols_path.is_none() => elf.symbols.clone(),
+        _ => romfile::symbols(game_path, symbols_path).map_err(GBAError::LoadError)?,
+    };
+
+    let multiboot = app_m.is_present("multiboot")
+        || game_path.extension().map_or(false, |ext| ext == "mb")
+        || elf.as_ref().map_or(false, |elf| elf.multiboot());
+    if multiboot && rom.len() > MULTIBOOT_MAX {
+        return Err(GBAError::MultibootTooLarge(rom.len()));
+    }
+
+    let breaks: Vec<Breakpoint> = match app_m.values_of("breakpoints") {
+        Some(v) => v
+            .map(|s| Breakpoint::parse(s, &symbols).map_err(GBAError::BadBreakpoint))
+            .collect::<Result<_>>()?,
+        None => vec![],
+    };
+
+    let break_events: Vec<BreakEvent> = match app_m.values_of("break-events") {
+        Some(v) => v
+            .map(|s| BreakEvent::parse(s).map_err(GBAError::BadBreakpoint))
+            .collect::<Result<_>>()?,
+        None => vec![],
+    };
 
-    let breaks: Vec<u32> = match app_m.values_of("breakpoints") {
-        Some(v) => v.map(|s| u32::from_str_radix(s, 16).unwrap()).collect(),
+    let watches: Vec<Watchpoint> = match app_m.values_of("watches") {
+        Some(v) => v
+            .map(|s| Watchpoint::parse(s, &symbols).map_err(GBAError::BadWatchpoint))
+            .collect::<Result<_>>()?,
         None => vec![],
     };
 
-    let opts = gba::Options {
-        fps_limit: app_m.value_of("fps-limit").unwrap() == "true",
-        breaks: breaks,
-        step_frames: app_m.is_present("step-frames"),
-        direct_boot: app_m.is_present("direct"),
-        save_file: app_m.value_of_os("save-file").unwrap().to_os_string(),
+    let trace_filter = TraceFilter {
+        ranges: match app_m.values_of("trace-ranges") {
+            Some(v) => v
+                .map(|s| TraceFilter::parse_range(s, &symbols).map_err(GBAError::TraceError))
+                .collect::<Result<_>>()?,
+            None => vec![],
+        },
+        limit: app_m.value_of("trace-limit").map(|s| s.parse().unwrap()),
+    };
+
+    let rom_patches: Vec<rom::RomPatch> = match app_m.values_of("rom-patches") {
+        Some(v) => v.map(|s| rom::RomPatch::parse(s).unwrap()).collect(),
+        None => vec![],
+    };
+
+    let triggers = match app_m.value_of_os("triggers") {
+        Some(path) => {
+            let mut text = String::new();
+            File::open(Path::new(path))
+                .and_then(|mut f| f.read_to_string(&mut text))
+                .map_err(|err| GBAError::TriggerLoadError(err.to_string()))?;
+            gba::triggers::parse_triggers(&text).map_err(GBAError::TriggerLoadError)?
+        }
+        None => vec![],
+    };
+
+    let rules = match app_m.value_of_os("achievements") {
+        Some(path) => {
+            let mut text = String::new();
+            File::open(Path::new(path))
+                .and_then(|mut f| f.read_to_string(&mut text))
+                .map_err(|err| GBAError::RulesLoadError(err.to_string()))?;
+            rules::Rules::parse(&text).map_err(GBAError::RulesLoadError)?
+        }
+        None => Default::default(),
+    };
+
+    let cheat_file = app_m.value_of_os("cheats").map(PathBuf::from);
+    let cheats = match cheat_file {
+        Some(ref path) => gba::cheats::load(path).map_err(GBAError::CheatsLoadError)?,
+        None => Default::default(),
+    };
+
+    #[cfg(feature = "retroachievements")]
+    let cheevos = match (
+        app_m
+            .value_of("ra-user")
+            .or(settings.ra_user.as_ref().map(|s| s.as_str())),
+        app_m
+            .value_of("ra-token")
+            .or(settings.ra_token.as_ref().map(|s| s.as_str())),
+    ) {
+        (Some(user), Some(token)) if !multiboot => {
+            retro::Cheevos::load(&rom, user, token).map_err(GBAError::RetroError)?
+        }
+        _ => None,
+    };
+
+    // Multiboot images aren't cartridges, so have no game code
+    let game = if multiboot {
+        Default::default()
+    } else {
+        games::load(&rom).map_err(GBAError::GameConfigError)?
+    };
+
+    let save_file = save_prefix(app_m, game_path, &settings)?;
+    // Multiboot images run without a cartridge to save to
+    let battery_file = match app_m.value_of_os("battery") {
+        None if multiboot => None,
+        _ => Some(battery_path(app_m, game_path, &settings)?),
+    };
+
+    // Held until the emulator exits, so other instances can't write over
+    // this one's saves
+    let (save_file, _prefix_lock) = lock::lock_prefix(&save_file).map_err(GBAError::LockError)?;
+    let (_battery_lock, battery_writable) = match battery_file {
+        Some(ref path) => lock::lock_battery(path),
+        None => (None, true),
+    };
+
+    let mut opts = gba::Options {
+        core: gba_core::Options {
+            breaks: breaks,
+            watches: watches,
+            break_events: break_events,
+            direct_boot: app_m.is_present("direct") || settings.direct == Some(true),
+            multiboot: multiboot,
+            sio_loopback: app_m.is_present("sio-loopback") || settings.sio_loopback == Some(true),
+            rom_patches: rom_patches,
+            ewram_size: optional(app_m, "ewram-size", settings.ewram_size).map(|kb| kb * 1024),
+            debug_ram_size: optional(app_m, "debug-ram", settings.debug_ram).map(|kb| kb * 1024),
+            flashcart: app_m.is_present("flashcart") || settings.flashcart == Some(true),
+            ram_fill: app_m
+                .value_of("ram-fill")
+                .map_or(0, |s| rules::parse_num(s).unwrap() as u8),
+            entry: elf.as_ref().map(|elf| elf.entry),
+            symbols: symbols,
+            ..Default::default()
+        },
+        fps_limit: setting(app_m, "fps-limit", settings.fps_limit),
+        speed: setting(app_m, "speed", settings.speed),
+        fast_forward: setting(app_m, "fast-forward", settings.fast_forward),
+        step_frames: app_m.is_present("step-frames") || settings.step_frames == Some(true),
+        save_file: save_file.into_os_string(),
+        state_level: setting(app_m, "state-compression", settings.state_compression),
+        resume: app_m.is_present("resume") || settings.resume == Some(true),
+        debugger: app_m.is_present("debugger"),
+        scale: setting(app_m, "scale", settings.scale),
+        filter: setting(app_m, "filter", settings.filter),
+        integer_scale: app_m.is_present("integer-scale") || settings.integer_scale == Some(true),
+        fullscreen: app_m.is_present("fullscreen") || settings.fullscreen == Some(true),
+        show_fps: app_m.is_present("show-fps") || settings.show_fps == Some(true),
+        post_filter: setting(app_m, "post-filter", settings.post_filter.clone()),
+        frame_blend: app_m.is_present("frame-blend") || settings.frame_blend == Some(true),
+        audio: !app_m.is_present("no-audio") && settings.audio.enabled != Some(false),
+        volume: setting(app_m, "volume", settings.audio.volume),
+        battery_file: battery_file,
+        battery_read_only: !battery_writable,
+        cdl_file: app_m.value_of_os("cdl").map(PathBuf::from),
+        coverage: app_m.is_present("coverage"),
+        triggers: triggers,
+        rules: rules,
+        cheat_file: cheat_file,
+        cheats: cheats,
+        #[cfg(feature = "retroachievements")]
+        cheevos: cheevos,
+        #[cfg(feature = "http-server")]
+        rewind_frames: optional(app_m, "rewind", settings.rewind).unwrap_or(0),
         ..Default::default()
     };
+    settings
+        .bindings(&mut opts.bindings)
+        .map_err(GBAError::ConfigError)?;
+    settings
+        .controller(&mut opts.controller)
+        .map_err(GBAError::ConfigError)?;
+    game.apply(&mut opts);
+    if app_m.is_present("deterministic") {
+        // The core's RAM starts filled with a fixed byte and there's no RTC
+        // or host clock in it, so only what's carried over from earlier runs
+        // can differ.  Crowd play and Discord presence, which go by the
+        // host's clock, are refused by the flags.
+        opts.battery_file = None;
+        opts.resume = false;
+    }
+    for (first, second) in opts.bindings.conflicts() {
+        warn!("{} and {} are bound to the same key", first, second);
+    }
+
+    if let Some(frames) = app_m.value_of("determinism-check") {
+        return determinism::run(rom, bios, &opts.core, frames.parse().unwrap())
+            .map_err(GBAError::Nondeterministic);
+    }
+
+    if let Some(frames) = app_m.value_of("bench") {
+        return bench::run(rom, bios, &opts.core, frames.parse().unwrap())
+            .map_err(GBAError::EmulationStopped);
+    }
+
+    if let Some(frames) = app_m.value_of("screenshot-test") {
+        let golden = match app_m.value_of_os("golden") {
+            Some(golden) => PathBuf::from(golden),
+            None => game_path.with_extension("golden.bmp"),
+        };
+        return screenshot_test::run(
+            rom,
+            bios,
+            &opts.core,
+            frames.parse().unwrap(),
+            &golden,
+            app_m.is_present("bless"),
+        )
+        .map_err(GBAError::ScreenshotMismatch);
+    }
+
+    if let Some(frames) = app_m.value_of("headless") {
+        let mut gba = gba::Gba::new_headless(rom, bios, opts);
+        open_pipes(&mut gba, app_m)?;
+        open_trace(&mut gba, app_m, trace_filter)?;
+        let res = gba.run_headless(frames.parse().unwrap());
+        gba.stop_recording();
+        gba.write_cdl();
+        gba.print_coverage();
+        return res;
+    }
 
     let mut gba = gba::Gba::new(rom, bios, opts);
+    open_pipes(&mut gba, app_m)?;
+    open_trace(&mut gba, app_m, trace_filter)?;
+
+    #[cfg(feature = "http-server")]
+    {
+        if let Some(addr) = app_m.value_of("http") {
+            gba.set_remote(server::start(addr).map_err(GBAError::ServerError)?);
+        }
+        if let Some(mode) = app_m.value_of("crowd") {
+            let window = app_m.value_of("vote-window").unwrap().parse().unwrap();
+            gba.set_crowd(gba::crowd::Crowd::new(
+                gba::crowd::Mode::parse(mode).unwrap(),
+                window,
+            ));
+        }
+    }
+
+    #[cfg(feature = "discord")]
+    {
+        if let Some(app_id) = app_m.value_of("discord") {
+            gba.on_status(discord::status_callback(app_id.parse().unwrap()));
+        }
+    }
 
     gba.run()
 }
 
+fn save_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("save-file")
+        .short("s")
+        .long("save")
+        .required(false)
+        .takes_value(true)
+        .help("The save file prefix to save to, by default the ROM's path without its extension")
+}
+
+fn battery_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("battery")
+        .long("battery")
+        .takes_value(true)
+        .value_name("file")
+        .help("The battery save file, by default the ROM's path with a .sav extension")
+}
+
+fn bundle_cheats_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("cheats")
+        .long("cheats")
+        .takes_value(true)
+        .value_name("file")
+        .help("The cheats file the game is run with")
+}
+
+fn save_profile_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("save-profile")
+        .long("save-profile")
+        .required(false)
+        .takes_value(true)
+        .value_name("name")
+        .validator(|s| profile::validate(&s))
+        .help("Keep saves in a separate directory for this profile")
+}
+
+/// Starts writing to the video and audio pipes asked for, and recording
+fn open_pipes(gba: &mut gba::Gba, app_m: &ArgMatches) -> Result<()> {
+    let video = app_m.value_of_os("video-pipe");
+    let audio = app_m.value_of_os("audio-pipe");
+    if video.is_some() && video == audio {
+        return Err(GBAError::PipeError(
+            "video and audio can't share an output".to_string(),
+        ));
+    }
+    let open = |path: &OsStr| {
+        gba::pipe::open(path)
+            .map_err(|err| GBAError::PipeError(format!("{}: {}", path.to_string_lossy(), err)))
+    };
+    if let Some(path) = video {
+        let format = app_m.value_of("video-format").unwrap();
+        gba.pipe_video(gba::pipe::video_sink(format, open(path)?));
+    }
+    if let Some(path) = audio {
+        gba.pipe_audio(open(path)?);
+    }
+    if let Some(path) = app_m.value_of_os("record") {
+        gba.start_recording(Path::new(path))
+            .map_err(|err| GBAError::PipeError(format!("recording: {}", err)))?;
+    }
+    Ok(())
+}
+
+/// Starts tracing instructions to the file asked for, and comparing them
+/// with a reference trace, if they were
+fn open_trace(gba: &mut gba::Gba, app_m: &ArgMatches, filter: TraceFilter) -> Result<()> {
+    let error = |path: &OsStr, err: std::io::Error| {
+        GBAError::TraceError(format!("{}: {}", path.to_string_lossy(), err))
+    };
+    if let Some(path) = app_m.value_of_os("trace") {
+        let file = File::create(path).map_err(|err| error(path, err))?;
+        gba.trace(Tracer::new(Box::new(BufWriter::new(file)), filter));
+    }
+    if let Some(path) = app_m.value_of_os("compare-trace") {
+        let lockstep =
+            gba::lockstep::Lockstep::open(Path::new(path)).map_err(|err| error(path, err))?;
+        gba.compare_trace(lockstep);
+    }
+    Ok(())
+}
+
+/// `rom`'s path, moved into the config file's save directory if it has one
+fn in_save_dir(rom: &Path, settings: &Settings) -> PathBuf {
+    match settings.save_dir {
+        Some(ref dir) => dir.join(rom.file_name().unwrap_or_else(|| OsStr::new("save"))),
+        None => rom.to_path_buf(),
+    }
+}
+
+/// The save profile from the save-profile arg, or the config file
+fn save_profile<'a>(app_m: &'a ArgMatches, settings: &'a Settings) -> Option<&'a str> {
+    app_m
+        .value_of("save-profile")
+        .or(settings.save_profile.as_ref().map(|s| s.as_str()))
+}
+
+/// The battery save from the battery arg, by default named like other
+/// emulators' saves so they can be shared, in the profile's directory if
+/// there is one
+fn battery_path(app_m: &ArgMatches, rom: &Path, settings: &Settings) -> Result<PathBuf> {
+    if let Some(path) = app_m.value_of_os("battery") {
+        return Ok(PathBuf::from(path));
+    }
+    let path = in_save_dir(rom, settings).with_extension("sav");
+    match save_profile(app_m, settings) {
+        Some(name) => profile::place(&path, name).map_err(GBAError::ProfileError),
+        None => Ok(path),
+    }
+}
+
+/// The save prefix from the save-file and save-profile args, or the config
+/// file
+fn save_prefix(app_m: &ArgMatches, rom: &Path, settings: &Settings) -> Result<PathBuf> {
+    let save_file = match app_m.value_of_os("save-file") {
+        Some(path) => PathBuf::from(path),
+        // Keep each game's saves apart, beside the ROM
+        None => {
+            let mut prefix = in_save_dir(rom, settings)
+                .with_extension("")
+                .into_os_string();
+            prefix.push("-");
+            PathBuf::from(prefix)
+        }
+    };
+    match save_profile(app_m, settings) {
+        Some(name) => profile::setup(&save_file, name).map_err(GBAError::ProfileError),
+        None => Ok(save_file),
+    }
+}
+
+fn run_bundle(top_m: &ArgMatches, app_m: &ArgMatches, import: bool) -> Result<()> {
+    let settings = load_settings(top_m)?;
+    let game_path = Path::new(app_m.value_of_os("rom").unwrap());
+    let bundle_path = Path::new(app_m.value_of_os("bundle").unwrap());
+    let rom = romfile::load(&game_path, romfile::Kind::Rom).map_err(GBAError::LoadError)?;
+    let prefix = save_prefix(app_m, game_path, &settings)?;
+    let code = rom.game_code();
+    let extras = bundle::Extras {
+        battery: Some(battery_path(app_m, game_path, &settings)?),
+        cheats: app_m.value_of_os("cheats").map(PathBuf::from),
+        game_config: if code.is_empty() {
+            None
+        } else {
+            Some(games::path(&code))
+        },
+    };
+
+    if import {
+        bundle::import(
+            &rom,
+            &prefix,
+            &extras,
+            bundle_path,
+            app_m.is_present("force"),
+        )
+    } else {
+        bundle::export(&rom, &prefix, &extras, bundle_path)
+    }
+    .map_err(GBAError::BundleError)
+}
+
+fn run_stats(app_m: &ArgMatches) -> Result<()> {
+    let crc = match app_m.value_of_os("rom") {
+        Some(path) => {
+            let rom =
+                romfile::load(Path::new(path), romfile::Kind::Rom).map_err(GBAError::LoadError)?;
+            Some(rom.crc32())
+        }
+        None => None,
+    };
+    stats::print(crc).map_err(GBAError::StatsError)
+}
+
+/// Development RAM sizes are powers of two from `min` KB up to the 16M a
+/// region can hold
+fn validate_ram_kb(s: &str, min: usize) -> std::result::Result<(), String> {
+    match s.parse::<usize>() {
+        Ok(kb) if kb.is_power_of_two() && kb >= min && kb <= 16 * 1024 => Ok(()),
+        _ => Err(format!("must be a power of two from {} to 16384 KB", min)),
+    }
+}
+
 fn reduce_logging() {
     use log::LevelFilter::*;
     log::set_max_level(match log::max_level() {
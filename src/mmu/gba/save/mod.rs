@@ -1,2 +0,0 @@
-mod eeprom;
-pub use self::eeprom::Eeprom;
@@ -0,0 +1,73 @@
+//! Named save profiles, so several people can play the same game without
+//! overwriting each other's saves.
+//!
+//! A profile moves everything written under the save prefix into its own
+//! directory: with `--save games/zelda --save-profile alice` states are written
+//! as `games/profiles/alice/zelda<N>.state`.  The battery save moves the same
+//! way, from beside the ROM to `profiles/alice/` beside it.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Checks the name can be used as a single directory name
+pub fn validate(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("invalid profile name '{}'", name));
+    }
+    if name.contains(|c: char| c == '/' || c == '\\' || c.is_control()) {
+        return Err(format!("profile name '{}' contains a path separator", name));
+    }
+    Ok(())
+}
+
+/// Returns the save prefix to use for `profile`
+pub fn prefix(save_file: &Path, profile: &str) -> PathBuf {
+    let parent = save_file.parent().unwrap_or_else(|| Path::new(""));
+    let name = save_file.file_name().unwrap_or_else(|| OsStr::new("save"));
+    parent.join("profiles").join(profile).join(name)
+}
+
+/// Makes sure the profile's directory beside `path` exists and returns where
+/// `path` moves to in it
+pub fn place(path: &Path, profile: &str) -> Result<PathBuf, String> {
+    validate(profile)?;
+    let path = prefix(path, profile);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("could not create {}: {}", dir.display(), err))?;
+    }
+    Ok(path)
+}
+
+/// Makes sure the profile's directory exists and returns its save prefix
+pub fn setup(save_file: &Path, profile: &str) -> Result<PathBuf, String> {
+    let prefix = place(save_file, profile)?;
+    info!("Using save profile '{}' at {}", profile, prefix.display());
+    Ok(prefix)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(
+            Path::new("profiles/alice/save"),
+            prefix(Path::new("save"), "alice")
+        );
+        assert_eq!(
+            Path::new("games/profiles/bob/zelda"),
+            prefix(Path::new("games/zelda"), "bob")
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("alice").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate("..").is_err());
+        assert!(validate("a/b").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! The few RetroAchievements web API requests needed to fetch a game's
+//! achievements.
+
+use serde_json;
+use ureq;
+
+const URL: &'static str = "https://retroachievements.org/dorequest.php";
+
+#[derive(Deserialize)]
+struct GameIdResponse {
+    #[serde(rename = "Success")]
+    success: bool,
+    #[serde(rename = "Error", default)]
+    error: Option<String>,
+    #[serde(rename = "GameID", default)]
+    game_id: u32,
+}
+
+#[derive(Deserialize)]
+struct PatchResponse {
+    #[serde(rename = "Success")]
+    success: bool,
+    #[serde(rename = "Error", default)]
+    error: Option<String>,
+    #[serde(rename = "PatchData")]
+    patch_data: Option<PatchData>,
+}
+
+#[derive(Deserialize)]
+pub struct PatchData {
+    #[serde(rename = "Title")]
+    pub title: String,
+    #[serde(rename = "Achievements")]
+    pub achievements: Vec<AchievementDef>,
+}
+
+#[derive(Deserialize)]
+pub struct AchievementDef {
+    #[serde(rename = "ID")]
+    pub id: u32,
+    #[serde(rename = "MemAddr")]
+    pub mem_addr: String,
+    #[serde(rename = "Title")]
+    pub title: String,
+    #[serde(rename = "Description")]
+    pub description: String,
+    #[serde(rename = "Points")]
+    pub points: u32,
+    /// 3 for official achievements, 5 for unofficial ones
+    #[serde(rename = "Flags")]
+    pub flags: u32,
+}
+
+pub const FLAG_OFFICIAL: u32 = 3;
+
+fn request(params: &[(&str, &str)]) -> Result<String, String> {
+    let mut req = ureq::get(URL);
+    for &(key, val) in params {
+        req.query(key, val);
+    }
+    let resp = req.call();
+    if !resp.ok() {
+        return Err(format!("request failed: {}", resp.status_line()));
+    }
+    resp.into_string().map_err(|err| err.to_string())
+}
+
+fn failed(error: Option<String>) -> String {
+    error.unwrap_or_else(|| "request was not successful".to_string())
+}
+
+/// Looks up the game with this ROM hash, returns None if it isn't known
+pub fn game_id(hash: &str) -> Result<Option<u32>, String> {
+    let body = request(&[("r", "gameid"), ("m", hash)])?;
+    let resp: GameIdResponse = serde_json::from_str(&body).map_err(|err| err.to_string())?;
+    if !resp.success {
+        return Err(failed(resp.error));
+    }
+    Ok(if resp.game_id == 0 {
+        None
+    } else {
+        Some(resp.game_id)
+    })
+}
+
+pub fn patch(user: &str, token: &str, game_id: u32) -> Result<PatchData, String> {
+    let game = game_id.to_string();
+    let body = request(&[("r", "patch"), ("u", user), ("t", token), ("g", &game)])?;
+    let resp: PatchResponse = serde_json::from_str(&body).map_err(|err| err.to_string())?;
+    match resp.patch_data {
+        Some(data) if resp.success => Ok(data),
+        _ => Err(failed(resp.error)),
+    }
+}
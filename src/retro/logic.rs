@@ -0,0 +1,451 @@
+//! Achievement conditions in the rcheevos `MemAddr` format, e.g.
+//! `0xH0010=5_d0x0020<0x0020.3.SR:0xH0030=1`.
+//!
+//! Conditions are joined with `_` into groups, the first group is the core
+//! and any others, separated with `S`, are alternatives of which one must be
+//! true alongside the core.  Only the standard, `R:` (reset if) and `P:`
+//! (pause if) condition types are supported.
+
+use gba_core::mmu::MemoryUnit;
+use gba_core::rules::Cmp;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Size {
+    Bit(u8),
+    Lower,
+    Upper,
+    Byte,
+    Half,
+    Tri,
+    Word,
+    BitCount,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Kind {
+    Value,
+    /// The value on the previous frame
+    Delta,
+    /// The value before it last changed
+    Prior,
+    Bcd,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct MemRef {
+    kind: Kind,
+    size: Size,
+    addr: u32,
+    cur: u32,
+    prev: u32,
+    prior: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Operand {
+    Const(u32),
+    Mem(MemRef),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Flag {
+    Standard,
+    ResetIf,
+    PauseIf,
+}
+
+#[derive(Clone, Debug)]
+struct Cond {
+    flag: Flag,
+    lhs: Operand,
+    cmp: Cmp,
+    rhs: Operand,
+    /// Hits needed before the condition counts as true, 0 for none
+    target: u32,
+    hits: u32,
+}
+
+/// Maps an rcheevos GBA address to the bus: IWRAM is first, then EWRAM
+fn bus_addr(addr: u32) -> Option<u32> {
+    if addr < 0x8000 {
+        Some(0x0300_0000 + addr)
+    } else if addr < 0x4_8000 {
+        Some(0x0200_0000 + addr - 0x8000)
+    } else {
+        None
+    }
+}
+
+fn read<M: MemoryUnit>(mmu: &M, size: Size, addr: u32) -> u32 {
+    let addr = match bus_addr(addr) {
+        Some(addr) => addr,
+        None => return 0,
+    };
+    let bytes = |n: u32| (0..n).fold(0, |v, i| v | (mmu.load8(addr + i) as u32) << (i * 8));
+    match size {
+        Size::Bit(bit) => (bytes(1) >> bit) & 1,
+        Size::Lower => bytes(1) & 0xf,
+        Size::Upper => bytes(1) >> 4,
+        Size::Byte => bytes(1),
+        Size::Half => bytes(2),
+        Size::Tri => bytes(3),
+        Size::Word => bytes(4),
+        Size::BitCount => bytes(1).count_ones(),
+    }
+}
+
+fn from_bcd(val: u32) -> u32 {
+    (0..8)
+        .rev()
+        .fold(0, |acc, i| acc * 10 + ((val >> (i * 4)) & 0xf))
+}
+
+impl MemRef {
+    fn update<M: MemoryUnit>(&mut self, mmu: &M) {
+        let val = read(mmu, self.size, self.addr);
+        self.prev = self.cur;
+        if val != self.cur {
+            self.prior = self.cur;
+        }
+        self.cur = val;
+    }
+
+    fn value(&self) -> u32 {
+        match self.kind {
+            Kind::Value => self.cur,
+            Kind::Delta => self.prev,
+            Kind::Prior => self.prior,
+            Kind::Bcd => from_bcd(self.cur),
+        }
+    }
+}
+
+impl Operand {
+    fn parse(s: &str) -> Result<Operand, String> {
+        let lower = s.to_ascii_lowercase();
+        let (kind, rest) = match lower.as_bytes().first() {
+            Some(&b'd') => (Kind::Delta, &s[1..]),
+            Some(&b'p') => (Kind::Prior, &s[1..]),
+            Some(&b'b') => (Kind::Bcd, &s[1..]),
+            _ => (Kind::Value, s),
+        };
+        if !rest.starts_with("0x") && !rest.starts_with("0X") {
+            if kind != Kind::Value {
+                return Err(format!("invalid operand '{}'", s));
+            }
+            let val = if s.starts_with('h') || s.starts_with('H') {
+                u32::from_str_radix(&s[1..], 16)
+            } else {
+                s.parse()
+            };
+            return val
+                .map(Operand::Const)
+                .map_err(|_| format!("invalid operand '{}'", s));
+        }
+
+        let rest = &rest[2..];
+        let letter = rest.chars().next().unwrap_or(' ').to_ascii_uppercase();
+        let (size, addr) = match letter {
+            'M'..='T' => (Size::Bit(letter as u8 - b'M'), &rest[1..]),
+            'L' => (Size::Lower, &rest[1..]),
+            'U' => (Size::Upper, &rest[1..]),
+            'H' => (Size::Byte, &rest[1..]),
+            'W' => (Size::Tri, &rest[1..]),
+            'X' => (Size::Word, &rest[1..]),
+            'K' => (Size::BitCount, &rest[1..]),
+            ' ' => (Size::Half, &rest[1..]),
+            c if c.is_digit(16) => (Size::Half, rest),
+            _ => return Err(format!("unsupported memory size in '{}'", s)),
+        };
+        let addr =
+            u32::from_str_radix(addr, 16).map_err(|_| format!("invalid address in '{}'", s))?;
+        Ok(Operand::Mem(MemRef {
+            kind: kind,
+            size: size,
+            addr: addr,
+            cur: 0,
+            prev: 0,
+            prior: 0,
+        }))
+    }
+
+    fn value(&self) -> u32 {
+        match *self {
+            Operand::Const(v) => v,
+            Operand::Mem(ref mem) => mem.value(),
+        }
+    }
+
+    fn update<M: MemoryUnit>(&mut self, mmu: &M) {
+        if let Operand::Mem(ref mut mem) = *self {
+            mem.update(mmu);
+        }
+    }
+}
+
+impl Cond {
+    fn parse(s: &str) -> Result<Cond, String> {
+        let (flag, s) = if s.len() > 2 && &s[1..2] == ":" {
+            let flag = match &s[..1] {
+                "R" => Flag::ResetIf,
+                "P" => Flag::PauseIf,
+                _ => return Err(format!("unsupported condition type in '{}'", s)),
+            };
+            (flag, &s[2..])
+        } else {
+            (Flag::Standard, s)
+        };
+
+        // Hit counts are written as a `.N.` suffix
+        let (s, target) = if s.ends_with('.') {
+            let body = &s[..s.len() - 1];
+            match body.rfind('.') {
+                Some(dot) => {
+                    let target = body[dot + 1..]
+                        .parse()
+                        .map_err(|_| format!("invalid hit count in '{}'", s))?;
+                    (&body[..dot], target)
+                }
+                None => return Err(format!("invalid hit count in '{}'", s)),
+            }
+        } else {
+            (s, 0)
+        };
+
+        let op_start = s
+            .find(|c| c == '=' || c == '!' || c == '<' || c == '>')
+            .ok_or_else(|| format!("no comparison in '{}'", s))?;
+        let ops = ["!=", "<=", ">=", "==", "=", "<", ">"];
+        let op = ops
+            .iter()
+            .find(|op| s[op_start..].starts_with(*op))
+            .ok_or_else(|| format!("invalid comparison in '{}'", s))?;
+        let cmp = match *op {
+            "=" => Cmp::Eq,
+            op => Cmp::parse(op).unwrap(),
+        };
+
+        Ok(Cond {
+            flag: flag,
+            lhs: Operand::parse(&s[..op_start])?,
+            cmp: cmp,
+            rhs: Operand::parse(&s[op_start + op.len()..])?,
+            target: target,
+            hits: 0,
+        })
+    }
+
+    /// Tests the condition and counts a hit, returns whether it is satisfied
+    fn check(&mut self) -> bool {
+        let now = self.cmp.eval(self.lhs.value(), self.rhs.value());
+        if self.target == 0 {
+            return now;
+        }
+        if now && self.hits < self.target {
+            self.hits += 1;
+        }
+        self.hits >= self.target
+    }
+}
+
+/// Returns whether the group is true and whether it asked for a reset
+fn check_group(group: &mut [Cond]) -> (bool, bool) {
+    let mut paused = false;
+    for cond in group.iter_mut().filter(|c| c.flag == Flag::PauseIf) {
+        paused |= cond.check();
+    }
+    if paused {
+        return (false, false);
+    }
+
+    let mut all = true;
+    let mut reset = false;
+    for cond in group.iter_mut().filter(|c| c.flag != Flag::PauseIf) {
+        let ok = cond.check();
+        match cond.flag {
+            Flag::ResetIf => reset |= ok,
+            _ => all &= ok,
+        }
+    }
+    (all && !reset, reset)
+}
+
+#[derive(Clone, Debug)]
+pub struct Achievement {
+    pub id: u32,
+    pub title: String,
+    pub description: String,
+    pub points: u32,
+    pub unlocked: bool,
+    groups: Vec<Vec<Cond>>,
+    /// An achievement has to be seen false before it can unlock, so loading
+    /// into a finished game doesn't unlock everything at once
+    primed: bool,
+}
+
+impl Achievement {
+    pub fn parse(
+        id: u32,
+        title: &str,
+        description: &str,
+        points: u32,
+        mem_addr: &str,
+    ) -> Result<Achievement, String> {
+        let groups = mem_addr
+            .split('S')
+            .map(|group| {
+                if group.is_empty() {
+                    return Ok(Vec::new());
+                }
+                group.split('_').map(Cond::parse).collect()
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Achievement {
+            id: id,
+            title: title.to_string(),
+            description: description.to_string(),
+            points: points,
+            unlocked: false,
+            groups: groups,
+            primed: false,
+        })
+    }
+
+    /// Evaluates the achievement for this frame, returns true if it has just
+    /// been unlocked
+    pub fn check<M: MemoryUnit>(&mut self, mmu: &M) -> bool {
+        if self.unlocked {
+            return false;
+        }
+        for cond in self.groups.iter_mut().flat_map(|g| g.iter_mut()) {
+            cond.lhs.update(mmu);
+            cond.rhs.update(mmu);
+        }
+
+        let results: Vec<(bool, bool)> = self
+            .groups
+            .iter_mut()
+            .map(|group| check_group(group))
+            .collect();
+        if results.iter().any(|&(_, reset)| reset) {
+            for cond in self.groups.iter_mut().flat_map(|g| g.iter_mut()) {
+                cond.hits = 0;
+            }
+        }
+        let core = results[0].0;
+        let alts = &results[1..];
+        let triggered = core && (alts.is_empty() || alts.iter().any(|&(ok, _)| ok));
+
+        if !self.primed {
+            self.primed = !triggered;
+            return false;
+        }
+        self.unlocked = triggered;
+        triggered
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use gba_core::mmu::ram::{Ram, RamUnit};
+
+    // Stands in for the bus, with IWRAM at 0 so rcheevos addresses line up
+    struct Iwram(RamUnit);
+
+    impl MemoryUnit for Iwram {
+        fn load8(&self, addr: u32) -> u8 {
+            self.0.load8(addr - 0x0300_0000)
+        }
+        fn set8(&mut self, addr: u32, val: u8) {
+            self.0.set8(addr - 0x0300_0000, val)
+        }
+        fn load16(&self, addr: u32) -> u16 {
+            self.0.load16(addr - 0x0300_0000)
+        }
+        fn set16(&mut self, addr: u32, val: u16) {
+            self.0.set16(addr - 0x0300_0000, val)
+        }
+        fn load32(&self, addr: u32) -> u32 {
+            self.0.load32(addr - 0x0300_0000)
+        }
+        fn set32(&mut self, addr: u32, val: u32) {
+            self.0.set32(addr - 0x0300_0000, val)
+        }
+    }
+
+    fn iwram() -> Iwram {
+        Iwram(RamUnit {
+            ram: Ram::new(0x100),
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let cond = Cond::parse("R:d0xH0010>=h1f.3.").unwrap();
+        assert_eq!(Flag::ResetIf, cond.flag);
+        assert_eq!(Cmp::Ge, cond.cmp);
+        assert_eq!(3, cond.target);
+        assert_eq!(Operand::Const(0x1f), cond.rhs);
+        match cond.lhs {
+            Operand::Mem(mem) => {
+                assert_eq!(Kind::Delta, mem.kind);
+                assert_eq!(Size::Byte, mem.size);
+                assert_eq!(0x10, mem.addr);
+            }
+            _ => panic!("expected memory operand"),
+        }
+        assert_eq!(Cmp::Eq, Cond::parse("0x1234=5").unwrap().cmp);
+        assert!(Cond::parse("A:0xH0010=1").is_err());
+        assert!(Cond::parse("0xH0010~1").is_err());
+        assert!(Achievement::parse(1, "", "", 5, "0xH0010=1S0xM0011=1S0xX0012!=0").is_ok());
+    }
+
+    #[test]
+    fn test_unlock() {
+        let mut mem = iwram();
+        let mut ach = Achievement::parse(1, "Up", "", 5, "0xH0010>d0xH0010").unwrap();
+        mem.set8(0x0300_0010, 0);
+        assert!(!ach.check(&mem));
+        mem.set8(0x0300_0010, 1);
+        assert!(ach.check(&mem));
+        assert!(ach.unlocked);
+        mem.set8(0x0300_0010, 2);
+        assert!(!ach.check(&mem));
+    }
+
+    #[test]
+    fn test_not_unlocked_on_load() {
+        let mut mem = iwram();
+        mem.set8(0x0300_0010, 8);
+        let mut ach = Achievement::parse(1, "", "", 5, "0xH0010=8").unwrap();
+        assert!(!ach.check(&mem));
+        assert!(!ach.check(&mem));
+        mem.set8(0x0300_0010, 0);
+        assert!(!ach.check(&mem));
+        mem.set8(0x0300_0010, 8);
+        assert!(ach.check(&mem));
+    }
+
+    #[test]
+    fn test_hits_and_reset() {
+        let mut mem = iwram();
+        let mut ach = Achievement::parse(1, "", "", 5, "0xH0010=1.3._R:0xH0011=1").unwrap();
+        mem.set8(0x0300_0010, 1);
+        assert!(!ach.check(&mem));
+        assert!(!ach.check(&mem));
+        // A reset clears the hits, so it takes three more frames
+        mem.set8(0x0300_0011, 1);
+        assert!(!ach.check(&mem));
+        mem.set8(0x0300_0011, 0);
+        assert!(!ach.check(&mem));
+        assert!(!ach.check(&mem));
+        assert!(ach.check(&mem));
+    }
+
+    #[test]
+    fn test_bcd() {
+        assert_eq!(1234, from_bcd(0x1234));
+    }
+}
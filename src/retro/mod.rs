@@ -0,0 +1,72 @@
+//! RetroAchievements support, built with the `retroachievements` feature.
+//!
+//! The ROM is identified by its MD5 hash, its achievements are fetched from
+//! the RetroAchievements API at startup and their conditions are checked
+//! against memory every frame.  Unlocks are only shown locally, nothing is
+//! submitted back to the server.
+
+use md5;
+
+use gba_core::mmu::MemoryUnit;
+use gba_core::rom::GameRom;
+
+mod api;
+mod logic;
+
+pub use self::logic::Achievement;
+
+#[derive(Clone, Debug)]
+pub struct Cheevos {
+    pub achievements: Vec<Achievement>,
+}
+
+impl Cheevos {
+    /// Fetches the achievements for `rom`, returns None if the game isn't in
+    /// the RetroAchievements database
+    pub fn load(rom: &GameRom, user: &str, token: &str) -> Result<Option<Cheevos>, String> {
+        let hash = format!("{:x}", md5::compute(&rom[..]));
+        let game_id = match api::game_id(&hash)? {
+            Some(id) => id,
+            None => {
+                warn!("ROM {} is not known to RetroAchievements", hash);
+                return Ok(None);
+            }
+        };
+        let patch = api::patch(user, token, game_id)?;
+
+        let mut achievements = Vec::new();
+        for def in patch.achievements.iter() {
+            if def.flags != api::FLAG_OFFICIAL {
+                continue;
+            }
+            match Achievement::parse(
+                def.id,
+                &def.title,
+                &def.description,
+                def.points,
+                &def.mem_addr,
+            ) {
+                Ok(ach) => achievements.push(ach),
+                Err(err) => warn!("Skipping achievement '{}': {}", def.title, err),
+            }
+        }
+        info!(
+            "Loaded {} achievements for {}",
+            achievements.len(),
+            patch.title
+        );
+        Ok(Some(Cheevos {
+            achievements: achievements,
+        }))
+    }
+
+    /// Checks every locked achievement, calling `unlocked` for each one that
+    /// was earned this frame
+    pub fn check<M: MemoryUnit, F: FnMut(&Achievement)>(&mut self, mmu: &M, mut unlocked: F) {
+        for ach in self.achievements.iter_mut() {
+            if ach.check(mmu) {
+                unlocked(ach);
+            }
+        }
+    }
+}
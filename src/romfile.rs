@@ -0,0 +1,285 @@
+//! Loading the ROM and BIOS with errors that say what is wrong with the file,
+//! rather than failing somewhere inside the emulator.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use gba_core::elf::Elf;
+use gba_core::rom::{self, GameRom};
+use gba_core::symbols::Symbols;
+
+const BIOS_SIZE: usize = 16 * 1024;
+/// The header is needed to identify the game, a ROM without one is not a ROM
+const HEADER_SIZE: usize = 0xc0;
+/// The gamepak address space is 32M
+const ROM_MAX: usize = 32 * 1024 * 1024;
+
+/// Extensions of files that are commonly passed by mistake, with what they are
+const WRONG_EXTENSIONS: [(&'static str, &'static str); 7] = [
+    ("gb", "a Game Boy game"),
+    ("gbc", "a Game Boy Color game"),
+    ("nds", "a Nintendo DS game"),
+    ("7z", "an archive, extract the ROM from it first"),
+    ("sav", "a save file"),
+    ("sgm", "a save state"),
+    ("state", "a save state"),
+];
+
+/// Patches found beside the ROM with its name, in the order looked for
+const PATCH_EXTENSIONS: [&'static str; 3] = ["ips", "ups", "bps"];
+/// The same for symbol files
+const SYMBOL_EXTENSIONS: [&'static str; 2] = ["sym", "map"];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Rom,
+    Bios,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Kind::Rom => write!(f, "ROM"),
+            Kind::Bios => write!(f, "BIOS"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Missing(Kind, PathBuf),
+    Unreadable(Kind, PathBuf, io::Error),
+    WrongSize(Kind, PathBuf, usize),
+    WrongExtension(Kind, PathBuf, &'static str),
+    /// A BIOS image was given where the ROM should be
+    BiosAsRom(PathBuf),
+    /// A game was given where the BIOS should be
+    RomAsBios(PathBuf),
+    /// The patch couldn't be read or didn't apply
+    BadPatch(PathBuf, String),
+    /// The ELF file couldn't be parsed or laid out
+    BadElf(PathBuf, String),
+    /// The symbol file couldn't be read or parsed
+    BadSymbols(PathBuf, String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::LoadError::*;
+        match *self {
+            Missing(kind, ref path) => write!(
+                f,
+                "{} file {} does not exist (the BIOS is the first argument and the ROM the second)",
+                kind,
+                path.display()
+            ),
+            Unreadable(kind, ref path, ref err) => {
+                write!(f, "could not read {} file {}: {}", kind, path.display(), err)
+            }
+            WrongSize(Kind::Bios, ref path, size) => write!(
+                f,
+                "BIOS file {} is {} bytes, but the GBA BIOS is exactly {} bytes",
+                path.display(),
+                size,
+                BIOS_SIZE
+            ),
+            WrongSize(Kind::Rom, ref path, size) => write!(
+                f,
+                "ROM file {} is {} bytes, but a GBA ROM is between {} bytes and 32MB",
+                path.display(),
+                size,
+                HEADER_SIZE
+            ),
+            WrongExtension(kind, ref path, what) => write!(
+                f,
+                "{} file {} looks like {}, not a GBA {}",
+                kind,
+                path.display(),
+                what,
+                kind
+            ),
+            BiosAsRom(ref path) => write!(
+                f,
+                "ROM file {} looks like a BIOS, the BIOS is the first argument and the ROM the second",
+                path.display()
+            ),
+            RomAsBios(ref path) => write!(
+                f,
+                "BIOS file {} looks like a game, the BIOS is the first argument and the ROM the second",
+                path.display()
+            ),
+            BadPatch(ref path, ref err) => {
+                write!(f, "could not apply patch {}: {}", path.display(), err)
+            }
+            BadElf(ref path, ref err) => {
+                write!(f, "could not load ELF file {}: {}", path.display(), err)
+            }
+            BadSymbols(ref path, ref err) => {
+                write!(f, "could not load symbols {}: {}", path.display(), err)
+            }
+        }
+    }
+}
+
+/// Checks `data` from `path` is plausibly a `kind` file
+pub fn check(kind: Kind, path: &Path, data: &[u8]) -> Result<(), LoadError> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    if let Some(ext) = ext {
+        if let Some(&(_, what)) = WRONG_EXTENSIONS.iter().find(|&&(bad, _)| bad == ext) {
+            return Err(LoadError::WrongExtension(kind, path.to_path_buf(), what));
+        }
+    }
+
+    let has_header = rom::header_valid(data);
+    match kind {
+        Kind::Bios if data.len() != BIOS_SIZE => {
+            if has_header {
+                Err(LoadError::RomAsBios(path.to_path_buf()))
+            } else {
+                Err(LoadError::WrongSize(kind, path.to_path_buf(), data.len()))
+            }
+        }
+        Kind::Rom if data.len() == BIOS_SIZE && !has_header => {
+            Err(LoadError::BiosAsRom(path.to_path_buf()))
+        }
+        Kind::Rom if data.len() < HEADER_SIZE || data.len() > ROM_MAX => {
+            Err(LoadError::WrongSize(kind, path.to_path_buf(), data.len()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Loads and checks the `kind` file at `path`
+pub fn load(path: &Path, kind: Kind) -> Result<GameRom, LoadError> {
+    let rom = GameRom::new(path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => LoadError::Missing(kind, path.to_path_buf()),
+        _ => LoadError::Unreadable(kind, path.to_path_buf(), err),
+    })?;
+    check(kind, path, &rom)?;
+    if kind == Kind::Rom && !rom::header_valid(&rom) {
+        warn!("{} has a bad header checksum", path.display());
+    }
+    Ok(rom)
+}
+
+/// Whether `path` is an ELF executable to load with `load_elf`
+pub fn is_elf(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("elf"))
+}
+
+/// Loads the ELF executable at `path`, with its segments laid out as a ROM
+/// or multiboot image
+pub fn load_elf(path: &Path) -> Result<(GameRom, Elf), LoadError> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => LoadError::Missing(Kind::Rom, path.to_path_buf()),
+            _ => LoadError::Unreadable(Kind::Rom, path.to_path_buf(), err),
+        })?;
+    let bad_elf = |err| LoadError::BadElf(path.to_path_buf(), err);
+    let elf = Elf::parse(&data).map_err(&bad_elf)?;
+    let image = elf.image().map_err(&bad_elf)?;
+    let rom = GameRom::from_bytes(&image)
+        .map_err(|err| LoadError::Unreadable(Kind::Rom, path.to_path_buf(), err))?;
+    Ok((rom, elf))
+}
+
+/// Applies the patch at `patch` to `rom`, or without one the patch named
+/// like the ROM beside it, if there is one
+pub fn patch(rom: GameRom, game_path: &Path, patch: Option<&Path>) -> Result<GameRom, LoadError> {
+    let path = match patch {
+        Some(path) => path.to_path_buf(),
+        None => match PATCH_EXTENSIONS
+            .iter()
+            .map(|ext| game_path.with_extension(ext))
+            .find(|path| path.exists())
+        {
+            Some(path) => path,
+            None => return Ok(rom),
+        },
+    };
+    let mut data = Vec::new();
+    File::open(&path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|err| LoadError::BadPatch(path.clone(), err.to_string()))?;
+    let rom = rom
+        .with_patch(&data)
+        .map_err(|err| LoadError::BadPatch(path.clone(), err))?;
+    info!("Applied patch {}", path.display());
+    Ok(rom)
+}
+
+/// Reads the symbol file at `path`, or without one the `.sym` or `.map` file
+/// named like the ROM beside it, if there is one
+pub fn symbols(game_path: &Path, path: Option<&Path>) -> Result<Symbols, LoadError> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match SYMBOL_EXTENSIONS
+            .iter()
+            .map(|ext| game_path.with_extension(ext))
+            .find(|path| path.exists())
+        {
+            Some(path) => path,
+            None => return Ok(Default::default()),
+        },
+    };
+    let mut text = String::new();
+    File::open(&path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|err| LoadError::BadSymbols(path.clone(), err.to_string()))?;
+    let map = path
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("map"));
+    let symbols = if map {
+        Symbols::parse_map(&text)
+    } else {
+        Symbols::parse_sym(&text).map_err(|err| LoadError::BadSymbols(path.clone(), err))?
+    };
+    info!("Loaded symbols from {}", path.display());
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom(size: usize) -> Vec<u8> {
+        let mut data = vec![0u8; size];
+        let sum = data[0xa0..0xbd]
+            .iter()
+            .fold(0u8, |sum, &b| sum.wrapping_sub(b));
+        data[0xbd] = sum.wrapping_sub(0x19);
+        data
+    }
+
+    #[test]
+    fn test_check() {
+        let path = Path::new("game.gba");
+        assert!(check(Kind::Rom, path, &rom(0x1000)).is_ok());
+        assert!(check(Kind::Bios, Path::new("bios.bin"), &vec![0; BIOS_SIZE]).is_ok());
+
+        match check(Kind::Rom, path, &vec![0; BIOS_SIZE]) {
+            Err(LoadError::BiosAsRom(_)) => (),
+            res => panic!("expected BiosAsRom, got {:?}", res),
+        }
+        match check(Kind::Bios, path, &rom(0x1000)) {
+            Err(LoadError::RomAsBios(_)) => (),
+            res => panic!("expected RomAsBios, got {:?}", res),
+        }
+        match check(Kind::Bios, path, &vec![0; 100]) {
+            Err(LoadError::WrongSize(Kind::Bios, _, 100)) => (),
+            res => panic!("expected WrongSize, got {:?}", res),
+        }
+        match check(Kind::Rom, Path::new("game.GBC"), &rom(0x1000)) {
+            Err(LoadError::WrongExtension(Kind::Rom, _, _)) => (),
+            res => panic!("expected WrongExtension, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! `--screenshot-test`, which runs a game headlessly for a number of frames
+//! and compares the last frame with a golden screenshot, to catch PPU
+//! regressions that the CPU tests can't.
+//!
+//! No buttons are pressed, so the same build always draws the same frame.
+//! With `--bless` the frame is saved as the new golden instead, once it's
+//! been checked by eye.  When the frame differs it's saved beside the golden
+//! to compare them.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use gba_core;
+use gba_core::io::ppu::COLS;
+use gba_core::rom::GameRom;
+
+use gba::screenshot;
+
+/// Where the frame is saved when it doesn't match `golden`
+fn actual_path(golden: &Path) -> PathBuf {
+    let mut name = golden.file_stem().unwrap_or_default().to_os_string();
+    name.push(".actual.bmp");
+    golden.with_file_name(name)
+}
+
+/// Describes how `frame` differs from `golden`, if it does
+fn compare(frame: &[u8], golden: &[u8]) -> Option<String> {
+    let mut differ = frame
+        .chunks(4)
+        .zip(golden.chunks(4))
+        .enumerate()
+        .filter(|&(_, (a, b))| a[..3] != b[..3])
+        .map(|(i, _)| i as u32);
+    let first = differ.next()?;
+    Some(format!(
+        "{} pixels differ, the first at ({}, {})",
+        differ.count() + 1,
+        first % COLS,
+        first / COLS
+    ))
+}
+
+/// Runs `rom` for `frames` frames and checks its last frame matches the BMP
+/// at `golden`, or saves it there if `bless`
+pub fn run(
+    rom: GameRom,
+    bios: GameRom,
+    opts: &gba_core::Options,
+    frames: u64,
+    golden: &Path,
+    bless: bool,
+) -> Result<(), String> {
+    let mut gba = gba_core::Gba::new(rom, bios, opts);
+    for frame in 0..frames {
+        if !gba.run_frame() {
+            return Err(format!("CPU stopped at a breakpoint on frame {}", frame));
+        }
+    }
+
+    if bless {
+        File::create(golden)
+            .and_then(|file| screenshot::write_bmp(gba.frame(), BufWriter::new(file)))
+            .map_err(|err| format!("{}: {}", golden.display(), err))?;
+        println!("Saved golden {}", golden.display());
+        return Ok(());
+    }
+
+    let expected = File::open(golden)
+        .map_err(|err| err.to_string())
+        .and_then(|file| screenshot::read_bmp(BufReader::new(file)))
+        .map_err(|err| format!("{}: {}", golden.display(), err))?;
+    match compare(gba.frame(), &expected) {
+        None => {
+            println!("Frame {} matches {}", frames, golden.display());
+            Ok(())
+        }
+        Some(diff) => {
+            let actual = actual_path(golden);
+            screenshot::save(gba.frame(), &actual);
+            Err(format!(
+                "{} from {}, the frame is in {}",
+                diff,
+                golden.display(),
+                actual.display()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use gba_core::io::ppu::FRAME_BYTES;
+
+    #[test]
+    fn test_compare() {
+        let golden = vec![0u8; FRAME_BYTES];
+        let mut frame = golden.clone();
+        // The padding byte isn't part of the picture
+        frame[3] = 0xff;
+        assert_eq!(None, compare(&frame, &golden));
+
+        let at = |x: u32, y: u32| ((y * COLS + x) * 4) as usize;
+        frame[at(5, 2)] = 1;
+        frame[at(7, 100) + 2] = 1;
+        assert_eq!(
+            Some("2 pixels differ, the first at (5, 2)".to_string()),
+            compare(&frame, &golden)
+        );
+    }
+
+    #[test]
+    fn test_actual_path() {
+        assert_eq!(
+            Path::new("goldens/mode4.actual.bmp"),
+            actual_path(Path::new("goldens/mode4.bmp"))
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! `gba-rs selftest`, which checks the build works without needing a BIOS or
+//! a game, for packagers and for users before they file bugs.
+//!
+//! The CPU runs a small program built in, and the PPU and SPU are checked on
+//! their own with the CPU spinning.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gba_core::io::ppu::COLS;
+use gba_core::mmu::MemoryUnit;
+use gba_core::rom::GameRom;
+use gba_core::{Gba, Options, CYCLES_PER_FRAME};
+
+/// Sums 1 to 100 in an ARM loop, squares the sum, then switches to Thumb
+/// to shift a value, storing each result in IWRAM
+const CPU_PROGRAM: [u32; 13] = [
+    0xe3a0_0000, // mov r0, #0
+    0xe3a0_1064, // mov r1, #100
+    0xe080_0001, // loop: add r0, r0, r1
+    0xe251_1001, // subs r1, r1, #1
+    0x1aff_fffc, // bne loop
+    0xe3a0_2403, // mov r2, #0x03000000
+    0xe582_0000, // str r0, [r2]
+    0xe003_0090, // mul r3, r0, r0
+    0xe582_3004, // str r3, [r2, #4]
+    0xe28f_4001, // add r4, pc, #1
+    0xe12f_ff14, // bx r4
+    0x0100_2007, // movs r0, #7; lsls r0, r0, #4
+    0xe7fe_6090, // str r0, [r2, #8]; b .
+];
+/// What the program leaves in IWRAM
+const CPU_RESULTS: [(&'static str, u32); 3] = [
+    ("ARM loop", 5050),
+    ("ARM multiply", 5050 * 5050),
+    ("Thumb", 7 << 4),
+];
+
+/// Spins, for checks that set up the hardware themselves
+const SPIN_PROGRAM: [u32; 1] = [0xeaff_fffe]; // b .
+
+const RED: u32 = 0xf8_00_00;
+const GREEN: u32 = 0x00_f8_00;
+const BLUE: u32 = 0x00_00_f8;
+
+fn boot(program: &[u32]) -> Result<Box<Gba<'static>>, String> {
+    let mut data = vec![0u8; program.len() * 4];
+    for (bytes, &word) in data.chunks_mut(4).zip(program.iter()) {
+        LittleEndian::write_u32(bytes, word);
+    }
+    let rom = GameRom::from_bytes(&data).map_err(|err| err.to_string())?;
+    let opts = Options {
+        direct_boot: true,
+        ..Default::default()
+    };
+    Ok(Gba::new(rom, Default::default(), &opts))
+}
+
+fn check_cpu() -> Result<(), String> {
+    let mut gba = boot(&CPU_PROGRAM)?;
+    gba.run_frame();
+    let mut failed = Vec::new();
+    for (i, &(name, expected)) in CPU_RESULTS.iter().enumerate() {
+        let got = gba.mmu.load32(0x0300_0000 + i as u32 * 4);
+        if got != expected {
+            failed.push(format!("{} gave {}, expected {}", name, got, expected));
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed.join(", "))
+    }
+}
+
+/// Draws a tile on BG0 and a sprite over the backdrop in mode 0
+fn check_ppu() -> Result<(), String> {
+    let mut gba = boot(&SPIN_PROGRAM)?;
+    // DISPCNT: mode 0, BG0 and OBJ on, 1D sprite tiles
+    gba.mmu.set16(0x0400_0000, 0x1140);
+    // BG0CNT: tiles at 0x06000000, map at 0x06000800
+    gba.mmu.set16(0x0400_0008, 0x0100);
+    gba.mmu.set16(0x0500_0000, 0x7c00);
+    gba.mmu.set16(0x0500_0002, 0x001f);
+    gba.mmu.set16(0x0500_0202, 0x03e0);
+    for off in (0..32).step_by(4) {
+        gba.mmu.set32(0x0600_0020 + off, 0x1111_1111);
+        gba.mmu.set32(0x0601_0020 + off, 0x1111_1111);
+    }
+    gba.mmu.set16(0x0600_0800, 1);
+    // An 8x8 sprite at (16, 16)
+    gba.mmu.set16(0x0700_0000, 16);
+    gba.mmu.set16(0x0700_0002, 16);
+    gba.mmu.set16(0x0700_0004, 1);
+
+    for _ in 0..2 {
+        gba.run_frame();
+    }
+    let frame = gba.frame();
+    let pixel = |x: u32, y: u32| {
+        let off = ((y * COLS + x) * 4) as usize;
+        LittleEndian::read_u32(&frame[off..off + 4]) & 0xff_ffff
+    };
+    let expected = [
+        ("BG0 tile", 0, 0, RED),
+        ("backdrop", 8, 0, BLUE),
+        ("sprite", 16, 16, GREEN),
+        ("backdrop beside sprite", 24, 16, BLUE),
+    ];
+    for &(name, x, y, colour) in expected.iter() {
+        let got = pixel(x, y);
+        if got != colour {
+            return Err(format!(
+                "{} at ({}, {}) is {:06x}, expected {:06x}",
+                name, x, y, got, colour
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a frame's samples all reach the output, in range
+fn check_spu() -> Result<(), String> {
+    let mut gba = boot(&SPIN_PROGRAM)?;
+    let mut buf = gba.spu.tap();
+    gba.run_frame();
+
+    // A sample every 512 cycles
+    let samples = (CYCLES_PER_FRAME / 512) as usize;
+    let mut out = vec![0f32; samples * 2];
+    buf.fill(&mut out);
+    if let Some(idx) = out.iter().position(|&s| s == 0.0) {
+        return Err(format!("sample {} of {} is silent", idx / 2, samples));
+    }
+    if let Some(&s) = out.iter().find(|&&s| s.abs() > 1.0) {
+        return Err(format!("sample {} is out of range", s));
+    }
+    Ok(())
+}
+
+const CHECKS: [(&'static str, fn() -> Result<(), String>); 3] =
+    [("cpu", check_cpu), ("ppu", check_ppu), ("spu", check_spu)];
+
+/// Runs every check, printing each result, and returns how many failed
+pub fn run() -> usize {
+    let mut failed = 0;
+    for &(name, check) in CHECKS.iter() {
+        let res = match panic::catch_unwind(AssertUnwindSafe(check)) {
+            Ok(res) => res,
+            Err(_) => Err("panicked".to_string()),
+        };
+        match res {
+            Ok(()) => println!("{}: ok", name),
+            Err(err) => {
+                println!("{}: FAILED: {}", name, err);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ppu() {
+        assert_eq!(Ok(()), check_ppu());
+    }
+
+    #[test]
+    fn test_spu() {
+        assert_eq!(Ok(()), check_spu());
+    }
+}
@@ -0,0 +1,355 @@
+//! An HTTP server for driving the emulator from other programs, built with
+//! the `http-server` feature.
+//!
+//! | Request                     | Action                                    |
+//! |-----------------------------|-------------------------------------------|
+//! | `POST /pause`, `/resume`    | pause or resume emulation                 |
+//! | `POST /state/save/<slot>`   | save a state to slot 0-9                  |
+//! | `POST /state/load/<slot>`   | load a state from slot 0-9                |
+//! | `GET /memory/<addr>?len=N`  | read N bytes, returned as hex             |
+//! | `POST /memory/<addr>`       | write the hex bytes in the body           |
+//! | `GET /screenshot`           | the current frame as a BMP                |
+//! | `POST /layers`              | dump the next frame's layers, see below   |
+//! | `POST /timeline`            | export the next frame's DMAs and IRQs     |
+//! | `POST /input?keys=a,start`  | hold keys down until the next input       |
+//! | `GET /status`               | title, pause state and frame count (JSON) |
+//! | `POST /vote?key=a`          | vote for a key, with `--crowd`            |
+//! | `POST /step?n=N`            | run N cycles while paused                 |
+//! | `POST /reverse/step?n=N`    | step N cycles backwards, with `--rewind`  |
+//! | `POST /reverse/continue`    | run backwards to the last breakpoint hit  |
+//! | `GET /reverse/write/<addr>` | the PC that last wrote an address         |
+//! | `POST /reference/<slot>`    | load a slot to compare against            |
+//! | `GET /reference/diff`       | what differs from the reference           |
+//! | `GET /reference/search`     | search against the reference, see below   |
+//! | `POST /search?size=N`       | start a RAM search, see below             |
+//! | `POST /search/filter`       | narrow the RAM search                     |
+//! | `GET /search`               | the RAM search's candidates and values    |
+//!
+//! Key names are `a`, `b`, `select`, `start`, `right`, `left`, `up`, `down`,
+//! `r` and `l`.  Addresses are hex.
+//!
+//! `/reference/search?value=N&size=2&in=live` lists the addresses holding the
+//! `size` byte value `N` in the running game (or, with `in=reference`, in the
+//! reference state) but not in the other one, along with the other one's
+//! value there.
+//!
+//! `/search?size=2` starts a search over the `size` byte values in EWRAM
+//! and IWRAM, as described in `gba::search`.  `/search/filter?cmp=lt&value=prev`
+//! keeps the values less than they were at the last step, and `value=N`
+//! compares against a number instead.  The comparisons are `eq`, `ne`, `lt`,
+//! `gt`, `le` and `ge`.
+//!
+//! `/layers` writes each layer of the next frame, the windows and the blend
+//! chosen for every pixel next to the save file, as described in
+//! `gba::layers`.  `/timeline` writes the cycle and scanline of every DMA,
+//! interrupt and timer overflow in the next frame as JSON, as described in
+//! `gba::timeline`.
+
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use tiny_http::{Header, Method, Response, Server};
+
+use gba_core::io::key::KeyState;
+use gba_core::rules::{parse_num, Cmp};
+
+use gba::compare::Side;
+use gba::crowd;
+use gba::remote::{Command, Reply, Request};
+use gba::search::Against;
+
+const MAX_PEEK: u32 = 0x10000;
+
+fn parse_addr(s: &str) -> Result<u32, Reply> {
+    let s = if s.starts_with("0x") { &s[2..] } else { s };
+    u32::from_str_radix(s, 16).map_err(|_| Reply::text(400, "invalid address\n"))
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, Reply> {
+    let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(Reply::text(400, "odd number of hex digits\n"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            ::std::str::from_utf8(pair)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(|| Reply::text(400, "invalid hex\n"))
+        })
+        .collect()
+}
+
+fn parse_size(params: &str) -> Result<usize, Reply> {
+    match query(params, "size").unwrap_or("1") {
+        "1" => Ok(1),
+        "2" => Ok(2),
+        "4" => Ok(4),
+        _ => Err(Reply::text(400, "size must be 1, 2 or 4\n")),
+    }
+}
+
+fn parse_cmp(name: &str) -> Result<Cmp, Reply> {
+    match name {
+        "eq" => Ok(Cmp::Eq),
+        "ne" => Ok(Cmp::Ne),
+        "lt" => Ok(Cmp::Lt),
+        "gt" => Ok(Cmp::Gt),
+        "le" => Ok(Cmp::Le),
+        "ge" => Ok(Cmp::Ge),
+        _ => Err(Reply::text(
+            400,
+            &format!("unknown comparison '{}'\n", name),
+        )),
+    }
+}
+
+fn parse_keys(list: &str) -> Result<KeyState, Reply> {
+    let mut keys = KeyState::default();
+    for name in list.split(',').filter(|name| !name.is_empty()) {
+        match name {
+            "a" => keys.a = true,
+            "b" => keys.b = true,
+            "select" => keys.select = true,
+            "start" => keys.start = true,
+            "right" => keys.r = true,
+            "left" => keys.l = true,
+            "up" => keys.u = true,
+            "down" => keys.d = true,
+            "r" => keys.br = true,
+            "l" => keys.bl = true,
+            _ => return Err(Reply::text(400, &format!("unknown key '{}'\n", name))),
+        }
+    }
+    Ok(keys)
+}
+
+fn query<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) if k == key => Some(v),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn parse_slot(s: &str) -> Result<u32, Reply> {
+    match s.parse() {
+        Ok(slot) if slot < 10 => Ok(slot),
+        _ => Err(Reply::text(400, "slot must be 0-9\n")),
+    }
+}
+
+fn parse_count(params: &str) -> Result<u64, Reply> {
+    match query(params, "n") {
+        Some(n) => n.parse().map_err(|_| Reply::text(400, "invalid count\n")),
+        None => Ok(1),
+    }
+}
+
+/// Works out which command a request is for
+fn route(post: bool, url: &str, body: &[u8]) -> Result<Command, Reply> {
+    let mut parts = url.splitn(2, '?');
+    let path = parts.next().unwrap();
+    let params = parts.next().unwrap_or("");
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match (post, segments.as_slice()) {
+        (true, ["pause"]) => Ok(Command::Pause),
+        (true, ["resume"]) => Ok(Command::Resume),
+        (true, ["state", "save", slot]) => Ok(Command::SaveState(parse_slot(slot)?)),
+        (true, ["state", "load", slot]) => Ok(Command::LoadState(parse_slot(slot)?)),
+        (false, ["memory", addr]) => {
+            let len = match query(params, "len") {
+                Some(len) => len
+                    .parse()
+                    .map_err(|_| Reply::text(400, "invalid length\n"))?,
+                None => 1,
+            };
+            if len > MAX_PEEK {
+                return Err(Reply::text(400, "length too large\n"));
+            }
+            Ok(Command::Peek {
+                addr: parse_addr(addr)?,
+                len: len,
+            })
+        }
+        (true, ["memory", addr]) => {
+            let text =
+                ::std::str::from_utf8(body).map_err(|_| Reply::text(400, "invalid hex\n"))?;
+            Ok(Command::Poke {
+                addr: parse_addr(addr)?,
+                data: parse_hex(text)?,
+            })
+        }
+        (false, ["screenshot"]) => Ok(Command::Screenshot),
+        (true, ["layers"]) => Ok(Command::CaptureLayers),
+        (true, ["timeline"]) => Ok(Command::RecordTimeline),
+        (true, ["input"]) => Ok(Command::Input(parse_keys(
+            query(params, "keys").unwrap_or(""),
+        )?)),
+        (true, ["vote"]) => {
+            let name = query(params, "key").unwrap_or("");
+            match crowd::parse_key(name) {
+                Some(key) => Ok(Command::Vote(key)),
+                None => Err(Reply::text(400, &format!("unknown key '{}'\n", name))),
+            }
+        }
+        (true, ["step"]) => Ok(Command::Step(parse_count(params)?)),
+        (true, ["reverse", "step"]) => Ok(Command::ReverseStep(parse_count(params)?)),
+        (true, ["reverse", "continue"]) => Ok(Command::ReverseContinue),
+        (false, ["reverse", "write", addr]) => Ok(Command::LastWrite(parse_addr(addr)?)),
+        (true, ["reference", slot]) => Ok(Command::LoadReference(parse_slot(slot)?)),
+        (false, ["reference", "diff"]) => Ok(Command::CompareReference),
+        (false, ["reference", "search"]) => {
+            let value = parse_num(query(params, "value").unwrap_or(""))
+                .map_err(|err| Reply::text(400, &format!("{}\n", err)))?;
+            let size = parse_size(params)?;
+            let side = match query(params, "in").unwrap_or("live") {
+                "live" => Side::Live,
+                "reference" => Side::Reference,
+                _ => return Err(Reply::text(400, "in must be live or reference\n")),
+            };
+            Ok(Command::SearchReference {
+                value: value,
+                size: size,
+                side: side,
+            })
+        }
+        (true, ["search"]) => Ok(Command::StartSearch(parse_size(params)?)),
+        (true, ["search", "filter"]) => {
+            let cmp = parse_cmp(query(params, "cmp").unwrap_or(""))?;
+            let against = match query(params, "value").unwrap_or("prev") {
+                "prev" => Against::Prev,
+                value => Against::Value(
+                    parse_num(value).map_err(|err| Reply::text(400, &format!("{}\n", err)))?,
+                ),
+            };
+            Ok(Command::FilterSearch(cmp, against))
+        }
+        (false, ["search"]) => Ok(Command::SearchResults),
+        (false, ["status"]) => Ok(Command::Status),
+        _ => Err(Reply::text(404, "not found\n")),
+    }
+}
+
+/// Starts serving on `addr`, requests are passed on through the returned
+/// channel and answered once the emulator has run them
+pub fn start(addr: &str) -> Result<Receiver<Request>, String> {
+    let server = Server::http(addr).map_err(|err| err.to_string())?;
+    info!("Control server listening on {}", addr);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let mut body = Vec::new();
+            let reply = match request.as_reader().read_to_end(&mut body) {
+                Err(err) => Reply::text(400, &format!("{}\n", err)),
+                Ok(_) => {
+                    let post = *request.method() == Method::Post;
+                    match route(post, request.url(), &body) {
+                        Ok(command) => {
+                            let (reply_tx, reply_rx) = mpsc::channel();
+                            if tx.send((command, reply_tx)).is_err() {
+                                break;
+                            }
+                            reply_rx
+                                .recv()
+                                .unwrap_or_else(|_| Reply::text(503, "emulator stopped\n"))
+                        }
+                        Err(reply) => reply,
+                    }
+                }
+            };
+            let header =
+                Header::from_bytes(&b"Content-Type"[..], reply.content_type.as_bytes()).unwrap();
+            let response = Response::from_data(reply.body)
+                .with_status_code(reply.status)
+                .with_header(header);
+            if let Err(err) = request.respond(response) {
+                warn!("Failed to respond to control request: {}", err);
+            }
+        }
+    });
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        match route(false, "/memory/0x02000010?len=4", b"") {
+            Ok(Command::Peek { addr, len }) => {
+                assert_eq!(0x0200_0010, addr);
+                assert_eq!(4, len);
+            }
+            _ => panic!("expected peek"),
+        }
+        match route(true, "/memory/3000000", b"de ad\n") {
+            Ok(Command::Poke { addr, data }) => {
+                assert_eq!(0x0300_0000, addr);
+                assert_eq!(vec![0xde, 0xad], data);
+            }
+            _ => panic!("expected poke"),
+        }
+        match route(true, "/input?keys=a,up,r", b"") {
+            Ok(Command::Input(keys)) => assert!(keys.a && keys.u && keys.br && !keys.r),
+            _ => panic!("expected input"),
+        }
+        assert!(route(true, "/state/load/3", b"").is_ok());
+        assert!(route(true, "/layers", b"").is_ok());
+        match route(true, "/reverse/step?n=20", b"") {
+            Ok(Command::ReverseStep(n)) => assert_eq!(20, n),
+            _ => panic!("expected reverse step"),
+        }
+        match route(true, "/vote?key=start", b"") {
+            Ok(Command::Vote(key)) => assert_eq!("start", crowd::KEYS[key]),
+            _ => panic!("expected vote"),
+        }
+
+        assert_eq!(404, route(false, "/pause", b"").err().unwrap().status);
+        assert_eq!(
+            400,
+            route(true, "/state/save/10", b"").err().unwrap().status
+        );
+        assert_eq!(400, route(true, "/memory/0", b"abc").err().unwrap().status);
+        assert_eq!(400, route(true, "/input?keys=x", b"").err().unwrap().status);
+        assert_eq!(400, route(true, "/vote?key=x", b"").err().unwrap().status);
+        match route(
+            false,
+            "/reference/search?value=0x1234&size=2&in=reference",
+            b"",
+        ) {
+            Ok(Command::SearchReference { value, size, side }) => {
+                assert_eq!((0x1234, 2, Side::Reference), (value, size, side));
+            }
+            _ => panic!("expected search"),
+        }
+        match route(true, "/search/filter?cmp=lt", b"") {
+            Ok(Command::FilterSearch(cmp, against)) => {
+                assert_eq!((Cmp::Lt, Against::Prev), (cmp, against));
+            }
+            _ => panic!("expected search filter"),
+        }
+        assert_eq!(
+            400,
+            route(true, "/search/filter?cmp=lt&value=x", b"")
+                .err()
+                .unwrap()
+                .status
+        );
+        assert_eq!(
+            400,
+            route(false, "/reference/search?value=1&size=3", b"")
+                .err()
+                .unwrap()
+                .status
+        );
+    }
+}
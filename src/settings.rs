@@ -0,0 +1,231 @@
+//! The config file, for settings that would otherwise have to be passed as
+//! flags on every run.  Flags given on the command line win over the file.
+//!
+//! The file is `config.toml` in the config directory unless `--config` names
+//! another:
+//!
+//! ```toml
+//! # Only the ROM has to be given on the command line
+//! bios = "/home/me/gba/bios.bin"
+//! save-dir = "/home/me/gba/saves"
+//! speed = 1.5
+//! scale = 4
+//!
+//! [audio]
+//! volume = 0.5
+//!
+//! [bindings]
+//! a = "J"
+//! b = "H"
+//! frame-step = "N"
+//! log-level = "none"
+//!
+//! # SDL's names for an Xbox style pad's buttons
+//! [controller]
+//! a = "a"
+//! b = "x"
+//!
+//! [controller-hotkeys]
+//! save-state = "back+a"
+//! faster = "back+rightstick"
+//! ```
+//!
+//! Settings are named like the flags they stand in for.  Per-run flags such
+//! as breakpoints, triggers and ROM patches are left out, as are the
+//! settings for a particular game, see `games`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use toml;
+
+use config;
+use gba::bindings::{self, Bindings};
+use gba::controller::ControllerBindings;
+use gba::{post, validate_speed, Filter};
+use profile;
+use validate_ram_kb;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    /// Used when only the ROM is given
+    pub bios: Option<PathBuf>,
+    /// Keep saves here rather than beside the ROM
+    pub save_dir: Option<PathBuf>,
+    pub save_profile: Option<String>,
+    pub fps_limit: Option<bool>,
+    pub speed: Option<f64>,
+    pub fast_forward: Option<f64>,
+    pub step_frames: Option<bool>,
+    pub direct: Option<bool>,
+    pub sio_loopback: Option<bool>,
+    pub flashcart: Option<bool>,
+    pub ewram_size: Option<usize>,
+    pub debug_ram: Option<usize>,
+    pub state_compression: Option<i32>,
+    pub resume: Option<bool>,
+    #[cfg(feature = "http-server")]
+    pub rewind: Option<usize>,
+    #[cfg(feature = "retroachievements")]
+    pub ra_user: Option<String>,
+    #[cfg(feature = "retroachievements")]
+    pub ra_token: Option<String>,
+    /// The window's size in multiples of the screen
+    pub scale: Option<u32>,
+    /// `nearest` or `linear`
+    pub filter: Option<Filter>,
+    pub integer_scale: Option<bool>,
+    pub fullscreen: Option<bool>,
+    pub show_fps: Option<bool>,
+    /// Filters joined by `+`, see `gba::post`
+    pub post_filter: Option<String>,
+    /// An enhancement, off by default
+    pub frame_blend: Option<bool>,
+    pub audio: Audio,
+    /// SDL key names by button or hotkey
+    pub bindings: BTreeMap<String, String>,
+    /// SDL controller button names by button
+    pub controller: BTreeMap<String, String>,
+    /// Whether a controller's left stick works as the D-pad
+    pub controller_stick: Option<bool>,
+    /// Controller chords by hotkey
+    pub controller_hotkeys: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Audio {
+    pub enabled: Option<bool>,
+    /// From 0 to 1
+    pub volume: Option<f32>,
+}
+
+impl Settings {
+    /// Checks the settings the way the flags are checked
+    fn validate(&self) -> Result<(), String> {
+        let check =
+            |name: &str, res: Result<(), String>| res.map_err(|err| format!("{} {}", name, err));
+        if let Some(speed) = self.speed {
+            check("speed", validate_speed(&speed.to_string()))?;
+        }
+        if let Some(speed) = self.fast_forward {
+            check("fast-forward", validate_speed(&speed.to_string()))?;
+        }
+        if let Some(kb) = self.ewram_size {
+            check("ewram-size", validate_ram_kb(&kb.to_string(), 256))?;
+        }
+        if let Some(kb) = self.debug_ram {
+            check("debug-ram", validate_ram_kb(&kb.to_string(), 1))?;
+        }
+        match self.state_compression {
+            Some(level) if level < 1 || level > 19 => {
+                return Err("state-compression must be a zstd level from 1 to 19".to_string())
+            }
+            _ => (),
+        }
+        if let Some(ref name) = self.save_profile {
+            check("save-profile", profile::validate(name))?;
+        }
+        if let Some(ref chain) = self.post_filter {
+            check("post-filter", post::validate(chain))?;
+        }
+        if self.scale == Some(0) {
+            return Err("scale must be at least 1".to_string());
+        }
+        match self.audio.volume {
+            Some(volume) if volume < 0.0 || volume > 1.0 => {
+                return Err("audio volume must be from 0 to 1".to_string())
+            }
+            _ => (),
+        }
+        self.bindings(&mut Default::default())?;
+        self.controller(&mut Default::default())
+    }
+
+    /// Applies the key bindings onto `bindings`
+    pub fn bindings(&self, bindings: &mut Bindings) -> Result<(), String> {
+        for (name, key) in self.bindings.iter() {
+            bindings.set(name, bindings::parse_key(key)?)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the controller bindings onto `bindings`
+    pub fn controller(&self, bindings: &mut ControllerBindings) -> Result<(), String> {
+        for (button, name) in self.controller.iter() {
+            bindings.set(button, name)?;
+        }
+        for (hotkey, chord) in self.controller_hotkeys.iter() {
+            bindings.set_hotkey(hotkey, chord)?;
+        }
+        if let Some(stick) = self.controller_stick {
+            bindings.stick = stick;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the settings from `path`, or from `config.toml` in the config
+/// directory if it exists
+pub fn load(path: Option<&Path>) -> Result<Settings, String> {
+    let default = config::dir().join("config.toml");
+    let path = path.unwrap_or(&default);
+    let mut text = String::new();
+    match File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+        Ok(_) => (),
+        Err(ref err) if path == default && err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Default::default())
+        }
+        Err(err) => return Err(format!("{}: {}", path.display(), err)),
+    }
+    let settings: Settings =
+        toml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+    settings
+        .validate()
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    info!("Using settings from {}", path.display());
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(Settings::default().validate().is_ok());
+
+        let settings = Settings {
+            speed: Some(2.0),
+            ewram_size: Some(512),
+            state_compression: Some(19),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+
+        let invalid = [
+            Settings {
+                speed: Some(100.0),
+                ..Default::default()
+            },
+            Settings {
+                ewram_size: Some(300),
+                ..Default::default()
+            },
+            Settings {
+                state_compression: Some(0),
+                ..Default::default()
+            },
+            Settings {
+                scale: Some(0),
+                ..Default::default()
+            },
+        ];
+        for settings in invalid.iter() {
+            assert!(settings.validate().is_err(), "{:?}", settings);
+        }
+    }
+}
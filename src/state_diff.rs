@@ -0,0 +1,127 @@
+//! Compares two save states, to narrow down where runs that should match
+//! (replays, netplay, states loaded across versions) went apart.
+
+use std::path::Path;
+
+use bincode;
+use serde::Serialize;
+
+use gba_core;
+use gba_core::mmu::ram::Ram;
+
+use gba;
+
+/// Runs of differing bytes closer than this are reported as one range
+const MERGE_GAP: usize = 16;
+
+/// The ranges `[start, end)` where `a` and `b` differ, merging nearby ones
+pub fn diff_ranges(a: &[u8], b: &[u8]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for i in (0..a.len().min(b.len())).filter(|&i| a[i] != b[i]) {
+        match ranges.last_mut() {
+            Some(last) if i - last.1 < MERGE_GAP => last.1 = i + 1,
+            _ => ranges.push((i, i + 1)),
+        }
+    }
+    if a.len() != b.len() {
+        ranges.push((a.len().min(b.len()), a.len().max(b.len())));
+    }
+    ranges
+}
+
+/// The memories of `gba` that hold game state, with their names and where
+/// they start on the bus
+pub fn memories<'a, 'b>(gba: &'b gba_core::Gba<'a>) -> [(&'static str, u32, &'b Ram); 6] {
+    [
+        ("ewram", 0x0200_0000, &gba.mmu.bram),
+        ("iwram", 0x0300_0000, &gba.mmu.cram),
+        ("palette", 0x0500_0000, &gba.mmu.pram),
+        ("vram", 0x0600_0000, &gba.mmu.vram),
+        ("oam", 0x0700_0000, &gba.mmu.oam),
+        ("sram", 0x0e00_0000, &gba.mmu.gram),
+    ]
+}
+
+fn same<T: Serialize>(a: &T, b: &T) -> bool {
+    bincode::serialize(a).ok() == bincode::serialize(b).ok()
+}
+
+/// Describes how the states `a` and `b` differ, one line per difference
+pub fn diff<'a>(a: &gba_core::Gba<'a>, b: &gba_core::Gba<'a>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if !same(&a.cpu, &b.cpu) {
+        lines.push("cpu: differs".to_string());
+    }
+    if !same(&a.io, &b.io) {
+        lines.push("io: differs".to_string());
+    }
+    if !same(&a.ppu, &b.ppu) {
+        lines.push("ppu: differs".to_string());
+    }
+    if !same(&a.mmu.ee, &b.mmu.ee) {
+        lines.push("eeprom: differs".to_string());
+    }
+
+    for (&(name, base, ram_a), &(_, _, ram_b)) in memories(a).iter().zip(memories(b).iter()) {
+        let ranges = diff_ranges(ram_a.as_slice(), ram_b.as_slice());
+        if ranges.is_empty() {
+            continue;
+        }
+        let bytes: usize = ranges.iter().map(|&(start, end)| end - start).sum();
+        lines.push(format!(
+            "{}: {} ranges, {} bytes differ",
+            name,
+            ranges.len(),
+            bytes
+        ));
+        for (start, end) in ranges {
+            lines.push(format!(
+                "  {:#010x}-{:#010x}",
+                base + start as u32,
+                base + end as u32 - 1
+            ));
+        }
+    }
+    lines
+}
+
+/// Prints the differences between the states at `path_a` and `path_b`
+pub fn run(path_a: &Path, path_b: &Path) -> Result<(), String> {
+    let load = |path: &Path| {
+        gba::read_state(path).map_err(|err| format!("could not read {}: {}", path.display(), err))
+    };
+    let (frame_a, a) = load(path_a)?;
+    let (frame_b, b) = load(path_b)?;
+
+    let mut lines = diff(&a, &b);
+    if frame_a != frame_b {
+        lines.insert(0, "frame: differs".to_string());
+    }
+    if lines.is_empty() {
+        println!("States are identical");
+    }
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff_ranges() {
+        let a = vec![0u8; 64];
+        let mut b = a.clone();
+        assert!(diff_ranges(&a, &b).is_empty());
+
+        b[2] = 1;
+        b[10] = 1;
+        b[40] = 1;
+        assert_eq!(vec![(2, 11), (40, 41)], diff_ranges(&a, &b));
+
+        b.push(0);
+        assert_eq!(vec![(2, 11), (40, 41), (64, 65)], diff_ranges(&a, &b));
+    }
+}
@@ -0,0 +1,176 @@
+//! Play time and other per-game statistics, kept in `stats.json` in the config
+//! directory and keyed by the ROM's CRC-32.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use config;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct GameStats {
+    pub title: String,
+    /// Seconds the emulator was open with this game
+    pub play_secs: u64,
+    pub frames: u64,
+    /// Unix time the game was last closed
+    pub last_played: u64,
+    pub sessions: u64,
+}
+
+pub type Stats = BTreeMap<String, GameStats>;
+
+pub fn key(crc32: u32) -> String {
+    format!("{:08x}", crc32)
+}
+
+/// Adds a session's worth of play to `stats`
+pub fn add_session(
+    stats: &mut Stats,
+    crc32: u32,
+    title: &str,
+    played: Duration,
+    frames: u64,
+    now: u64,
+) {
+    let entry = stats.entry(key(crc32)).or_insert_with(Default::default);
+    entry.title = title.to_string();
+    entry.play_secs += played.as_secs();
+    entry.frames += frames;
+    entry.last_played = now;
+    entry.sessions += 1;
+}
+
+pub fn load(path: &Path) -> Result<Stats, String> {
+    if !path.exists() {
+        return Ok(Stats::new());
+    }
+    File::open(path)
+        .map_err(|err| err.to_string())
+        .and_then(|f| serde_json::from_reader(f).map_err(|err| err.to_string()))
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))
+}
+
+pub fn save(path: &Path, stats: &Stats) -> Result<(), String> {
+    File::create(path)
+        .map_err(|err| err.to_string())
+        .and_then(|f| serde_json::to_writer_pretty(f, stats).map_err(|err| err.to_string()))
+        .map_err(|err| format!("could not write {}: {}", path.display(), err))
+}
+
+/// Records a session in the stats file in the config directory
+pub fn record(crc32: u32, title: &str, played: Duration, frames: u64) -> Result<(), String> {
+    let path = config::file("stats.json")?;
+    let mut stats = load(&path)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    add_session(&mut stats, crc32, title, played, frames, now);
+    save(&path, &stats)
+}
+
+/// Prints the stats for every game, most recently played first, or only the
+/// game with the given CRC
+pub fn print(crc32: Option<u32>) -> Result<(), String> {
+    let stats = load(&config::dir().join("stats.json"))?;
+    let mut games: Vec<(&String, &GameStats)> = stats
+        .iter()
+        .filter(|&(k, _)| crc32.map_or(true, |crc| *k == key(crc)))
+        .collect();
+    if games.is_empty() {
+        println!("No games played yet");
+        return Ok(());
+    }
+    games.sort_by(|a, b| b.1.last_played.cmp(&a.1.last_played));
+
+    println!(
+        "{:<12} {:<8} {:>10} {:>12} {:>8}  {}",
+        "Title", "CRC", "Play time", "Frames", "Sessions", "Last played"
+    );
+    for (crc, game) in games {
+        println!(
+            "{:<12} {:<8} {:>10} {:>12} {:>8}  {}",
+            game.title,
+            crc,
+            format_duration(Duration::from_secs(game.play_secs)),
+            game.frames,
+            game.sessions,
+            format_date(game.last_played)
+        );
+    }
+    Ok(())
+}
+
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Formats a unix time as a UTC date and time
+pub fn format_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Howard Hinnant's days_from_civil, inverted
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_session() {
+        let mut stats = Stats::new();
+        add_session(
+            &mut stats,
+            0xabcd,
+            "ZELDA",
+            Duration::from_secs(60),
+            3600,
+            10,
+        );
+        add_session(
+            &mut stats,
+            0xabcd,
+            "ZELDA",
+            Duration::from_secs(30),
+            1800,
+            20,
+        );
+        let game = &stats["0000abcd"];
+        assert_eq!(90, game.play_secs);
+        assert_eq!(5400, game.frames);
+        assert_eq!(20, game.last_played);
+        assert_eq!(2, game.sessions);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!("0:00:59", format_duration(Duration::from_secs(59)));
+        assert_eq!("2:01:05", format_duration(Duration::from_secs(7265)));
+        assert_eq!("1970-01-01 00:00 UTC", format_date(0));
+        assert_eq!("2000-02-29 12:34 UTC", format_date(951_827_640));
+    }
+}
@@ -1,3 +1,5 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
 use std::sync::{Once, ONCE_INIT};
 
 use env_logger;
@@ -9,3 +11,45 @@ pub fn setup() {
         env_logger::init();
     });
 }
+
+/// Counts the allocations made by each thread, so tests can check that a
+/// path doesn't allocate without seeing tests running alongside it
+struct CountingAlloc;
+
+thread_local!(static ALLOCATIONS: Cell<usize> = Cell::new(0));
+
+fn count() {
+    // Thread locals are gone while a thread is being torn down
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// The number of allocations `f` makes on this thread
+pub fn allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.with(|count| count.get());
+    f();
+    ALLOCATIONS.with(|count| count.get()) - before
+}